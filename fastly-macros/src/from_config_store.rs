@@ -0,0 +1,183 @@
+//! Implementation of `#[derive(FromConfigStore)]`.
+
+use {
+    proc_macro2::TokenStream,
+    quote::quote,
+    syn::{
+        spanned::Spanned, Attribute, Data, DeriveInput, Fields, GenericArgument, Lit, LitStr, Meta,
+        NestedMeta, PathArguments, Type,
+    },
+};
+
+/// The options given to a field with `#[config_store(...)]`.
+#[derive(Default)]
+struct FieldOptions {
+    rename: Option<LitStr>,
+    json: bool,
+    default: bool,
+}
+
+/// Expand `#[derive(FromConfigStore)]` for the given struct.
+pub(crate) fn derive(input: DeriveInput) -> Result<TokenStream, syn::Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.ident.span(),
+                    "`FromConfigStore` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "`FromConfigStore` can only be derived for structs",
+            ))
+        }
+    };
+    let store_name = parse_store_name(&input)?;
+
+    let mut inits = Vec::with_capacity(fields.len());
+    for field in fields {
+        let ident = field.ident.as_ref().expect("fields are named");
+        let options = parse_field_options(&field.attrs)?;
+        let key = options
+            .rename
+            .unwrap_or_else(|| LitStr::new(&ident.to_string(), ident.span()));
+        let getter = if options.json {
+            quote!(get_json)
+        } else {
+            quote!(get_parsed)
+        };
+        let init = match option_inner(&field.ty) {
+            Some(inner) => quote! { store.#getter::<#inner>(#key)? },
+            None if options.default => {
+                let ty = &field.ty;
+                quote! { store.#getter::<#ty>(#key)?.unwrap_or_default() }
+            }
+            None => {
+                let ty = &field.ty;
+                quote! {
+                    store.#getter::<#ty>(#key)?.ok_or_else(|| fastly::error::ValueError::Missing {
+                        key: ::std::string::String::from(#key),
+                    })?
+                }
+            }
+        };
+        inits.push(quote! { #ident: #init });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics fastly::config_store::FromConfigStore for #ident #ty_generics
+        #where_clause
+        {
+            const STORE_NAME: &'static str = #store_name;
+
+            fn from_config_store(
+                store: &fastly::ConfigStore,
+            ) -> ::std::result::Result<Self, fastly::error::ValueError> {
+                ::std::result::Result::Ok(Self { #(#inits,)* })
+            }
+        }
+    })
+}
+
+/// Get the arguments of the `#[config_store(...)]` attributes in a list.
+fn config_store_args(attrs: &[Attribute]) -> Result<Vec<NestedMeta>, syn::Error> {
+    let mut args = vec![];
+    for attr in attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("config_store"))
+    {
+        match attr.parse_meta()? {
+            Meta::List(list) => args.extend(list.nested),
+            meta => {
+                return Err(syn::Error::new(
+                    meta.span(),
+                    "expected arguments, as in `#[config_store(...)]`",
+                ))
+            }
+        }
+    }
+    Ok(args)
+}
+
+/// Get a string literal argument, as in `name = "..."`.
+fn lit_str(lit: Lit) -> Result<LitStr, syn::Error> {
+    match lit {
+        Lit::Str(s) => Ok(s),
+        lit => Err(syn::Error::new(lit.span(), "expected a string")),
+    }
+}
+
+/// Parse the `#[config_store(name = "...")]` attribute of the struct.
+fn parse_store_name(input: &DeriveInput) -> Result<LitStr, syn::Error> {
+    let mut name = None;
+    for arg in config_store_args(&input.attrs)? {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => {
+                let span = nv.span();
+                if name.replace(lit_str(nv.lit)?).is_some() {
+                    return Err(syn::Error::new(span, "`name` may only be given once"));
+                }
+            }
+            arg => {
+                return Err(syn::Error::new(
+                    arg.span(),
+                    "unknown argument; expected `name = \"...\"`",
+                ))
+            }
+        }
+    }
+    name.ok_or_else(|| {
+        syn::Error::new(
+            input.ident.span(),
+            "`FromConfigStore` requires the name of the config store, as in \
+             `#[config_store(name = \"...\")]`",
+        )
+    })
+}
+
+/// Parse the `#[config_store(...)]` attributes of a field.
+fn parse_field_options(attrs: &[Attribute]) -> Result<FieldOptions, syn::Error> {
+    let mut options = FieldOptions::default();
+    for arg in config_store_args(attrs)? {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("rename") => {
+                options.rename = Some(lit_str(nv.lit)?);
+            }
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("json") => options.json = true,
+            NestedMeta::Meta(Meta::Path(path)) if path.is_ident("default") => {
+                options.default = true
+            }
+            arg => {
+                return Err(syn::Error::new(
+                    arg.span(),
+                    "unknown argument; expected `rename = \"...\"`, `json`, or `default`",
+                ))
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// If the type is written as `Option<T>`, get `T`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if path.qself.is_some() || segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
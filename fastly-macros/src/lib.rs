@@ -14,11 +14,14 @@ use {
     proc_macro2::Span,
     quote::quote_spanned,
     syn::{
-        parse_macro_input, parse_quote, punctuated::Punctuated, spanned::Spanned, Attribute, Ident,
-        ItemFn, ReturnType, Signature, Visibility,
+        parse_macro_input, parse_quote, punctuated::Punctuated, spanned::Spanned, Attribute,
+        AttributeArgs, DeriveInput, ExprPath, Ident, ItemFn, Lit, Meta, NestedMeta, ReturnType,
+        Signature, Visibility,
     },
 };
 
+mod from_config_store;
+
 /// Main function attribute for a Compute@Edge program.
 ///
 /// ## Usage
@@ -36,13 +39,50 @@ use {
 /// ```
 ///
 /// You can apply `#[fastly::main]` to any function that takes `Request` as its sole argument, and
-/// returns a `Result<Response, Error>`.
+/// returns one of:
+///
+/// - `Response`;
+///
+/// - `Result<Response, E>`, where `E` implements `Into<Response>`. This includes `fastly::Error`,
+///   which becomes a `500 Internal Server Error` response containing the error message.
+///
+/// The function may also be an `async fn`, in which case it is run to completion by
+/// `fastly::async_io::block_on()`:
+///
+/// ```rust,no_run
+/// use fastly::{Error, Request, Response};
+///
+/// #[fastly::main]
+/// async fn main(ds_req: Request) -> Result<Response, Error> {
+///     Ok(ds_req.send_async("example_backend")?.await?)
+/// }
+/// ```
+///
+/// ## Handling errors
+///
+/// To choose the response sent to the client when the function returns an error, name a function
+/// that converts the error into a `Response` with the `error_handler` argument. The error type then
+/// does not need to implement `Into<Response>`:
+///
+/// ```rust,no_run
+/// use fastly::http::StatusCode;
+/// use fastly::{Error, Request, Response};
+///
+/// fn handle_error(e: Error) -> Response {
+///     Response::from_status(StatusCode::BAD_GATEWAY).with_body_text_plain(&format!("{}\n", e))
+/// }
+///
+/// #[fastly::main(error_handler = "handle_error")]
+/// fn main(ds_req: Request) -> Result<Response, Error> {
+///     Ok(ds_req.send("example_backend")?)
+/// }
+/// ```
 ///
 /// ## More Information
 ///
 /// This is a convenience to abstract over the common usage of `Request::from_client()` and
 /// `Response::send_to_client()` at the beginning and end of a program's `main()` function. The
-/// macro use above is equivalent to the following code:
+/// first macro use above is equivalent to the following code:
 ///
 /// ```rust,no_run
 /// use fastly::{Error, Request};
@@ -55,10 +95,17 @@ use {
 /// }
 /// ```
 #[proc_macro_attribute]
-pub fn main(_: TokenStream, input: TokenStream) -> TokenStream {
-    // Parse the input token stream as a free-standing function, or return an error.
+pub fn main(args: TokenStream, input: TokenStream) -> TokenStream {
+    // Parse the arguments of the attribute, and the input token stream as a free-standing function,
+    // or return an error.
+    let args = parse_macro_input!(args as AttributeArgs);
     let raw_main = parse_macro_input!(input as ItemFn);
 
+    let error_handler = match parse_error_handler(args) {
+        Ok(error_handler) => error_handler,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
     // Check that the function signature looks okay-ish. If we have the wrong number of arguments,
     // or no return type is specified , print a friendly spanned error with the expected signature.
     if !check_impl_signature(&raw_main.sig) {
@@ -70,6 +117,9 @@ pub fn main(_: TokenStream, input: TokenStream) -> TokenStream {
 fn main (request: Request) -> Result<Response, Error> {
     ...
 }
+
+The function may be `async`, and may return `Response`, or `Result<Response, E>` where `E`
+implements `Into<Response>`.
 ",
         )
         .to_compile_error()
@@ -79,10 +129,31 @@ fn main (request: Request) -> Result<Response, Error> {
     // Get the attributes, visibility, and signature of our outer function. Then, update the
     // attributes and visibility of the inner function that we will inline.
     let (attrs, vis, sig) = outer_main_info(&raw_main);
+    let is_async = raw_main.sig.asyncness.is_some();
     let (name, inner_fn) = inner_fn_info(raw_main);
 
+    // Call the inner function, running it to completion if it is asynchronous.
+    let call = if is_async {
+        quote_spanned! {inner_fn.span() => fastly::async_io::block_on(#name(ds_req)) }
+    } else {
+        quote_spanned! {inner_fn.span() => #name(ds_req) }
+    };
+
+    // Turn the output of the inner function into the response for the client.
+    let ds_resp = match error_handler {
+        Some(error_handler) => quote_spanned! {error_handler.span() =>
+            match #call {
+                Ok(ds_resp) => ds_resp,
+                Err(e) => #error_handler(e),
+            }
+        },
+        None => quote_spanned! {inner_fn.span() =>
+            fastly::MainOutput::into_response(#call)
+        },
+    };
+
     // Define our raw main function, which will provide the downstream request to our main function
-    // implementation as its argument, and then send the `ResponseExt` result downstream.
+    // implementation as its argument, and then send the resulting response downstream.
     let output = quote_spanned! {inner_fn.span() =>
         #(#attrs)*
         #vis
@@ -91,14 +162,8 @@ fn main (request: Request) -> Result<Response, Error> {
             #inner_fn
             fastly::init();
             let ds_req = fastly::Request::from_client();
-            match #name(ds_req) {
-                Ok(ds_resp) => ds_resp.send_to_client(),
-                Err(e) => {
-                    fastly::Response::from_body(e.to_string())
-                        .with_status(fastly::http::StatusCode::INTERNAL_SERVER_ERROR)
-                        .send_to_client()
-                }
-            };
+            let ds_resp: fastly::Response = #ds_resp;
+            ds_resp.send_to_client();
             Ok(())
         }
     };
@@ -106,6 +171,52 @@ fn main (request: Request) -> Result<Response, Error> {
     output.into()
 }
 
+/// Derive `fastly::config_store::FromConfigStore` for a struct with named fields.
+///
+/// Each field is loaded from the config store entry with the same key. See the documentation of
+/// `FromConfigStore` in the `fastly` crate for the `#[config_store(...)]` attributes this accepts.
+#[proc_macro_derive(FromConfigStore, attributes(config_store))]
+pub fn derive_from_config_store(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_config_store::derive(input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Parse the arguments of the `#[main]` attribute, returning the path of the error handler if one
+/// was given.
+fn parse_error_handler(args: AttributeArgs) -> Result<Option<ExprPath>, syn::Error> {
+    let mut error_handler = None;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("error_handler") => {
+                let path = match &nv.lit {
+                    Lit::Str(s) => s.parse::<ExprPath>()?,
+                    lit => {
+                        return Err(syn::Error::new(
+                            lit.span(),
+                            "expected the path of a function as a string",
+                        ))
+                    }
+                };
+                if error_handler.replace(path).is_some() {
+                    return Err(syn::Error::new(
+                        nv.span(),
+                        "`error_handler` may only be given once",
+                    ));
+                }
+            }
+            arg => {
+                return Err(syn::Error::new(
+                    arg.span(),
+                    "unknown argument; expected `error_handler = \"path::to::function\"`",
+                ))
+            }
+        }
+    }
+    Ok(error_handler)
+}
+
 /// Check if the signature of the `#[main]` function seems correct.
 ///
 /// Unfortunately, we cannot precisely typecheck in a procedural macro attribute, because we are
@@ -132,14 +243,15 @@ fn check_impl_signature(sig: &Signature) -> bool {
 /// The outer main function will use the same attributes and visibility as our raw main function.
 ///
 /// The signature of the outer function will be changed to have inputs and outputs of the form
-/// `fn main() -> Result<(), fastly::Error>`. The name of the outer main will always be just that,
-/// `main`.
+/// `fn main() -> Result<(), fastly::Error>`, and is never `async`. The name of the outer main will
+/// always be just that, `main`.
 fn outer_main_info(inner_main: &ItemFn) -> (Vec<Attribute>, Visibility, Signature) {
     let attrs = inner_main.attrs.clone();
     let vis = Visibility::Inherited;
     let sig = {
         let mut sig = inner_main.sig.clone();
         sig.ident = Ident::new("main", Span::call_site());
+        sig.asyncness = None;
         sig.inputs = Punctuated::new();
         sig.output = parse_quote!(-> ::std::result::Result<(), fastly::Error>);
         sig
@@ -24,6 +24,10 @@ pub const INVALID_DICTIONARY_HANDLE: u32 = std::u32::MAX - 1;
 #[deprecated(since = "0.9.3", note = "renamed to KV Store")]
 pub const INVALID_OBJECT_STORE_HANDLE: u32 = INVALID_KV_STORE_HANDLE;
 pub const INVALID_KV_STORE_HANDLE: u32 = std::u32::MAX - 1;
+pub const INVALID_KV_LOOKUP_HANDLE: u32 = std::u32::MAX - 1;
+pub const INVALID_KV_INSERT_HANDLE: u32 = std::u32::MAX - 1;
+pub const INVALID_KV_DELETE_HANDLE: u32 = std::u32::MAX - 1;
+pub const INVALID_KV_LIST_HANDLE: u32 = std::u32::MAX - 1;
 pub const INVALID_SECRET_STORE_HANDLE: u32 = std::u32::MAX - 1;
 pub const INVALID_SECRET_HANDLE: u32 = std::u32::MAX - 1;
 pub const INVALID_CACHE_HANDLE: u32 = std::u32::MAX - 1;
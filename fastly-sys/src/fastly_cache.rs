@@ -79,6 +79,10 @@ bitflags::bitflags! {
     }
 }
 
+#[cfg(feature = "testing")]
+pub use crate::testing::fastly_cache::*;
+
+#[cfg(not(feature = "testing"))]
 #[link(wasm_import_module = "fastly_cache")]
 extern "C" {
     #[link_name = "lookup"]
@@ -1,5 +1,8 @@
 // TODO ACF 2020-12-01: remove once this is fixed: https://github.com/rust-lang/rust/issues/79581
 #![allow(clashing_extern_declarations)]
+// With the `testing` feature, the hostcall declarations are replaced by re-exports of the mock host,
+// leaving the imports they use behind.
+#![cfg_attr(feature = "testing", allow(unused_imports))]
 
 //! FFI bindings to the Fastly Compute@Edge ABI.
 //!
@@ -18,6 +21,8 @@
 use fastly_shared::FastlyStatus;
 
 pub mod fastly_cache;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 // The following type aliases are used for readability of definitions in this module. They should
 // not be confused with types of similar names in the `fastly` crate which are used to provide safe
@@ -31,6 +36,10 @@ pub type DictionaryHandle = u32;
 #[deprecated(since = "0.9.3", note = "renamed to KV Store")]
 pub type ObjectStoreHandle = u32;
 pub type KVStoreHandle = u32;
+pub type KVStoreLookupHandle = u32;
+pub type KVStoreInsertHandle = u32;
+pub type KVStoreDeleteHandle = u32;
+pub type KVStoreListHandle = u32;
 pub type SecretStoreHandle = u32;
 pub type SecretHandle = u32;
 pub type AsyncItemHandle = u32;
@@ -115,6 +124,10 @@ bitflags::bitflags! {
 pub mod fastly_abi {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_abi::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_abi")]
     extern "C" {
         #[link_name = "init"]
@@ -126,6 +139,10 @@ pub mod fastly_abi {
 pub mod fastly_uap {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_uap::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_uap")]
     extern "C" {
         #[link_name = "parse"]
@@ -151,6 +168,10 @@ pub mod fastly_uap {
 pub mod fastly_http_body {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_http_body::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_http_body")]
     extern "C" {
         #[link_name = "append"]
@@ -187,6 +208,10 @@ pub mod fastly_http_body {
 pub mod fastly_log {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_log::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_log")]
     extern "C" {
         #[link_name = "endpoint_get"]
@@ -212,6 +237,10 @@ pub mod fastly_log {
 pub mod fastly_http_req {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_http_req::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_http_req")]
     extern "C" {
         #[link_name = "body_downstream_get"]
@@ -504,6 +533,10 @@ pub mod fastly_http_req {
 pub mod fastly_http_resp {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_http_resp::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_http_resp")]
     extern "C" {
         #[link_name = "header_append"]
@@ -615,6 +648,10 @@ pub mod fastly_http_resp {
 pub mod fastly_dictionary {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_dictionary::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_dictionary")]
     extern "C" {
         #[link_name = "open"]
@@ -639,6 +676,10 @@ pub mod fastly_dictionary {
 pub mod fastly_geo {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_geo::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_geo")]
     extern "C" {
         #[link_name = "lookup"]
@@ -658,6 +699,144 @@ pub use fastly_kv_store as fastly_object_store;
 pub mod fastly_kv_store {
     use super::*;
 
+    /// The outcome of a KV Store operation, reported separately from the hostcall status.
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(u32)]
+    pub enum KvError {
+        #[default]
+        Uninitialized,
+        Ok,
+        BadRequest,
+        NotFound,
+        PreconditionFailed,
+        PayloadTooLarge,
+        InternalError,
+        TooManyRequests,
+    }
+
+    /// How an insert combines the new value with any existing value for the key.
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(u32)]
+    pub enum InsertMode {
+        #[default]
+        Overwrite,
+        Add,
+        Append,
+        Prepend,
+    }
+
+    bitflags::bitflags! {
+        #[derive(Default)]
+        #[repr(transparent)]
+        pub struct InsertConfigOptions: u32 {
+            const RESERVED = 1 << 0;
+            const BACKGROUND_FETCH = 1 << 1;
+            const IF_GENERATION_MATCH = 1 << 2;
+            const METADATA = 1 << 3;
+            const TIME_TO_LIVE_SEC = 1 << 4;
+        }
+    }
+
+    #[derive(Debug)]
+    #[repr(C)]
+    pub struct InsertConfig {
+        pub mode: InsertMode,
+        pub unused: u32,
+        pub metadata: *const u8,
+        pub metadata_len: u32,
+        pub time_to_live_sec: u32,
+        pub if_generation_match: u64,
+    }
+
+    impl Default for InsertConfig {
+        fn default() -> Self {
+            InsertConfig {
+                mode: InsertMode::Overwrite,
+                unused: 0,
+                metadata: std::ptr::null(),
+                metadata_len: 0,
+                time_to_live_sec: 0,
+                if_generation_match: 0,
+            }
+        }
+    }
+
+    bitflags::bitflags! {
+        #[derive(Default)]
+        #[repr(transparent)]
+        pub struct LookupConfigOptions: u32 {
+            const RESERVED = 1 << 0;
+        }
+    }
+
+    #[derive(Debug, Default)]
+    #[repr(C)]
+    pub struct LookupConfig {
+        pub reserved: u32,
+    }
+
+    bitflags::bitflags! {
+        #[derive(Default)]
+        #[repr(transparent)]
+        pub struct DeleteConfigOptions: u32 {
+            const RESERVED = 1 << 0;
+        }
+    }
+
+    #[derive(Debug, Default)]
+    #[repr(C)]
+    pub struct DeleteConfig {
+        pub reserved: u32,
+    }
+
+    /// The consistency of a listing.
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    #[repr(u32)]
+    pub enum ListMode {
+        #[default]
+        Strong,
+        Eventual,
+    }
+
+    bitflags::bitflags! {
+        #[derive(Default)]
+        #[repr(transparent)]
+        pub struct ListConfigOptions: u32 {
+            const RESERVED = 1 << 0;
+            const CURSOR = 1 << 1;
+            const LIMIT = 1 << 2;
+            const PREFIX = 1 << 3;
+        }
+    }
+
+    #[derive(Debug)]
+    #[repr(C)]
+    pub struct ListConfig {
+        pub mode: ListMode,
+        pub cursor: *const u8,
+        pub cursor_len: u32,
+        pub limit: u32,
+        pub prefix: *const u8,
+        pub prefix_len: u32,
+    }
+
+    impl Default for ListConfig {
+        fn default() -> Self {
+            ListConfig {
+                mode: ListMode::Strong,
+                cursor: std::ptr::null(),
+                cursor_len: 0,
+                limit: 0,
+                prefix: std::ptr::null(),
+                prefix_len: 0,
+            }
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_kv_store::*;
+
+    #[cfg(not(feature = "testing"))]
     // TODO ACF 2023-04-11: keep the object store name here until the ABI is updated
     #[link(wasm_import_module = "fastly_object_store")]
     extern "C" {
@@ -684,11 +863,110 @@ pub mod fastly_kv_store {
             body_handle: BodyHandle,
         ) -> FastlyStatus;
     }
+
+    #[cfg(not(feature = "testing"))]
+    #[link(wasm_import_module = "fastly_kv_store")]
+    extern "C" {
+        #[link_name = "open"]
+        pub fn open_v2(
+            name_ptr: *const u8,
+            name_len: usize,
+            kv_store_handle_out: *mut KVStoreHandle,
+        ) -> FastlyStatus;
+
+        /// Start a lookup, whose results are collected with `pending_lookup_wait_v2`.
+        ///
+        /// The lookup handle can also be selected with `fastly_async_io`.
+        #[link_name = "lookup"]
+        pub fn lookup_v2(
+            kv_store_handle: KVStoreHandle,
+            key_ptr: *const u8,
+            key_len: usize,
+            lookup_config_mask: LookupConfigOptions,
+            lookup_config: *const LookupConfig,
+            lookup_handle_out: *mut KVStoreLookupHandle,
+        ) -> FastlyStatus;
+
+        /// Wait for a lookup to complete, returning the value along with its metadata and
+        /// generation.
+        ///
+        /// If the metadata does not fit in the buffer, this returns `BUFLEN` without creating a
+        /// body, and sets `nwritten_out` to the needed length.
+        #[link_name = "lookup_wait"]
+        pub fn pending_lookup_wait_v2(
+            lookup_handle: KVStoreLookupHandle,
+            body_handle_out: *mut BodyHandle,
+            metadata_buf: *mut u8,
+            metadata_buf_len: usize,
+            nwritten_out: *mut usize,
+            generation_out: *mut u32,
+            kv_error_out: *mut KvError,
+        ) -> FastlyStatus;
+
+        /// Start an insert, whose outcome is collected with `pending_insert_wait_v2`.
+        ///
+        /// The insert handle can also be selected with `fastly_async_io`.
+        #[link_name = "insert"]
+        pub fn insert_v2(
+            kv_store_handle: KVStoreHandle,
+            key_ptr: *const u8,
+            key_len: usize,
+            body_handle: BodyHandle,
+            insert_config_mask: InsertConfigOptions,
+            insert_config: *const InsertConfig,
+            insert_handle_out: *mut KVStoreInsertHandle,
+        ) -> FastlyStatus;
+
+        #[link_name = "insert_wait"]
+        pub fn pending_insert_wait_v2(
+            insert_handle: KVStoreInsertHandle,
+            kv_error_out: *mut KvError,
+        ) -> FastlyStatus;
+
+        /// Start a delete, whose outcome is collected with `pending_delete_wait_v2`.
+        #[link_name = "delete"]
+        pub fn delete_v2(
+            kv_store_handle: KVStoreHandle,
+            key_ptr: *const u8,
+            key_len: usize,
+            delete_config_mask: DeleteConfigOptions,
+            delete_config: *const DeleteConfig,
+            delete_handle_out: *mut KVStoreDeleteHandle,
+        ) -> FastlyStatus;
+
+        #[link_name = "delete_wait"]
+        pub fn pending_delete_wait_v2(
+            delete_handle: KVStoreDeleteHandle,
+            kv_error_out: *mut KvError,
+        ) -> FastlyStatus;
+
+        /// Start listing the keys of a store, a page of which is collected with
+        /// `pending_list_wait_v2`.
+        #[link_name = "list"]
+        pub fn list_v2(
+            kv_store_handle: KVStoreHandle,
+            list_config_mask: ListConfigOptions,
+            list_config: *const ListConfig,
+            list_handle_out: *mut KVStoreListHandle,
+        ) -> FastlyStatus;
+
+        /// Wait for a listing to complete, returning the page of keys as JSON in a new body.
+        #[link_name = "list_wait"]
+        pub fn pending_list_wait_v2(
+            list_handle: KVStoreListHandle,
+            body_handle_out: *mut BodyHandle,
+            kv_error_out: *mut KvError,
+        ) -> FastlyStatus;
+    }
 }
 
 pub mod fastly_secret_store {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_secret_store::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_secret_store")]
     extern "C" {
         #[link_name = "open"]
@@ -734,6 +1012,10 @@ pub mod fastly_backend {
         Unhealthy,
     }
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_backend::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_backend")]
     extern "C" {
         #[link_name = "exists"]
@@ -825,6 +1107,10 @@ pub mod fastly_backend {
 pub mod fastly_async_io {
     use super::*;
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_async_io::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_async_io")]
     extern "C" {
         #[link_name = "select"]
@@ -860,6 +1146,10 @@ pub mod fastly_purge {
         pub ret_buf_nwritten_out: *mut usize,
     }
 
+    #[cfg(feature = "testing")]
+    pub use crate::testing::fastly_purge::*;
+
+    #[cfg(not(feature = "testing"))]
     #[link(wasm_import_module = "fastly_purge")]
     extern "C" {
         #[link_name = "purge_surrogate_key"]
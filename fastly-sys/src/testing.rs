@@ -0,0 +1,682 @@
+//! An in-process implementation of the Compute@Edge ABI, for running programs under `cargo test`.
+//!
+//! When the `testing` feature is enabled, each hostcall module in this crate (such as
+//! [`fastly_http_req`][crate::fastly_http_req]) is backed by the functions in this module rather
+//! than by imports from the Compute@Edge host. Code written against the `fastly` crate can then run
+//! on a native target, against in-memory bodies, requests, responses, stores, and backends.
+//!
+//! Host state is kept per thread, so tests running in parallel do not observe each other's
+//! requests, stores, or backends. Call [`reset()`] to return the current thread's host to its
+//! initial state.
+//!
+//! Most programs should use the `fastly::testing` module rather than calling these functions
+//! directly.
+// The mock hostcalls mirror the `extern` declarations they replace, so they keep the same argument
+// lists and do not document the safety contract of each raw pointer individually.
+#![allow(clippy::missing_safety_doc, clippy::too_many_arguments)]
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+use fastly_shared::{FastlyStatus, FramingHeadersMode, HttpKeepaliveMode, HttpVersion};
+
+use crate::{BodyHandle, ContentEncodings, RequestHandle, ResponseHandle};
+
+mod cache;
+
+pub(crate) mod fastly_abi;
+pub(crate) mod fastly_async_io;
+pub(crate) mod fastly_backend;
+pub(crate) mod fastly_cache;
+pub(crate) mod fastly_dictionary;
+pub(crate) mod fastly_geo;
+pub(crate) mod fastly_http_body;
+pub(crate) mod fastly_http_req;
+pub(crate) mod fastly_http_resp;
+pub(crate) mod fastly_kv_store;
+pub(crate) mod fastly_log;
+pub(crate) mod fastly_purge;
+pub(crate) mod fastly_secret_store;
+pub(crate) mod fastly_uap;
+
+/// A function that handles requests sent to a backend.
+///
+/// The handler takes ownership of the request and body handles, and returns a [`BackendReply`].
+pub type BackendHandler = Box<dyn FnMut(RequestHandle, BodyHandle) -> BackendReply>;
+
+/// The reply of a backend to a request.
+pub struct BackendReply {
+    /// Handles to the response and its body, or the status that the send hostcall fails with.
+    pub result: Result<(ResponseHandle, BodyHandle), FastlyStatus>,
+    /// How long the backend takes to reply, as measured by the host's clock.
+    ///
+    /// If this exceeds the first byte timeout of the backend, the request fails with
+    /// `FastlyStatus::ERROR` once the timeout has elapsed.
+    pub latency: Duration,
+}
+
+impl BackendReply {
+    /// A reply that is available immediately.
+    pub fn immediate(result: Result<(ResponseHandle, BodyHandle), FastlyStatus>) -> Self {
+        BackendReply {
+            result,
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+thread_local! {
+    static HOST: RefCell<Host> = RefCell::new(Host::new(1));
+}
+
+/// Run a closure against the current thread's host.
+///
+/// # Panics
+///
+/// Panics if called from within another call to `with_host()`.
+pub(crate) fn with_host<R>(f: impl FnOnce(&mut Host) -> R) -> R {
+    HOST.with(|host| f(&mut host.borrow_mut()))
+}
+
+/// Run a closure against the current thread's host, if it is still available.
+///
+/// Handles are closed from `Drop` implementations, which can run while the thread-local host is
+/// being torn down. Those paths use this function so they can treat a missing host as success.
+pub(crate) fn try_with_host<R>(f: impl FnOnce(&mut Host) -> R) -> Option<R> {
+    HOST.try_with(|host| host.try_borrow_mut().ok().map(|mut host| f(&mut host)))
+        .ok()
+        .flatten()
+}
+
+/// The state of the in-process host for a single thread.
+pub(crate) struct Host {
+    /// The first handle allocated by this host; smaller handles were allocated before a reset.
+    first_handle: u32,
+    next_handle: u32,
+    pub(crate) clock_ns: u64,
+    pub(crate) bodies: HashMap<u32, BodyData>,
+    pub(crate) requests: HashMap<u32, RequestData>,
+    pub(crate) responses: HashMap<u32, ResponseData>,
+    pub(crate) pending: HashMap<u32, PendingData>,
+    pub(crate) downstream: Downstream,
+    pub(crate) backends: HashMap<String, BackendData>,
+    pub(crate) handlers: HashMap<String, BackendHandler>,
+    pub(crate) dictionaries: HashMap<String, HashMap<String, String>>,
+    pub(crate) dictionary_handles: HashMap<u32, String>,
+    pub(crate) kv_stores: HashMap<String, BTreeMap<String, fastly_kv_store::KvEntry>>,
+    pub(crate) kv_store_handles: HashMap<u32, String>,
+    pub(crate) kv_pending: HashMap<u32, fastly_kv_store::KvPending>,
+    pub(crate) secret_stores: HashMap<String, HashMap<String, Vec<u8>>>,
+    pub(crate) secret_store_handles: HashMap<u32, String>,
+    pub(crate) secrets: HashMap<u32, Vec<u8>>,
+    pub(crate) log_endpoints: HashMap<u32, String>,
+    pub(crate) logs: HashMap<String, Vec<Vec<u8>>>,
+    pub(crate) geo: HashMap<IpAddr, String>,
+    pub(crate) cache: cache::Cache,
+}
+
+impl Host {
+    fn new(first_handle: u32) -> Self {
+        Host {
+            first_handle,
+            next_handle: first_handle,
+            clock_ns: 0,
+            bodies: HashMap::new(),
+            requests: HashMap::new(),
+            responses: HashMap::new(),
+            pending: HashMap::new(),
+            downstream: Downstream::default(),
+            backends: HashMap::new(),
+            handlers: HashMap::new(),
+            dictionaries: HashMap::new(),
+            dictionary_handles: HashMap::new(),
+            kv_stores: HashMap::new(),
+            kv_store_handles: HashMap::new(),
+            kv_pending: HashMap::new(),
+            secret_stores: HashMap::new(),
+            secret_store_handles: HashMap::new(),
+            secrets: HashMap::new(),
+            log_endpoints: HashMap::new(),
+            logs: HashMap::new(),
+            geo: HashMap::new(),
+            cache: cache::Cache::default(),
+        }
+    }
+
+    /// Allocate a fresh handle.
+    ///
+    /// Handles of every kind are drawn from the same sequence, so that a handle passed to
+    /// `fastly_async_io` identifies a single item regardless of its kind.
+    pub(crate) fn new_handle(&mut self) -> u32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        handle
+    }
+
+    /// Returns `true` if the handle was allocated before the host was last reset.
+    pub(crate) fn is_stale_handle(&self, handle: u32) -> bool {
+        handle < self.first_handle
+    }
+
+    pub(crate) fn new_body(&mut self, data: Vec<u8>) -> u32 {
+        let handle = self.new_handle();
+        self.bodies.insert(
+            handle,
+            BodyData {
+                data,
+                pos: 0,
+                closed: false,
+            },
+        );
+        handle
+    }
+
+    pub(crate) fn new_request(&mut self, data: RequestData) -> u32 {
+        let handle = self.new_handle();
+        self.requests.insert(handle, data);
+        handle
+    }
+
+    pub(crate) fn new_response(&mut self, data: ResponseData) -> u32 {
+        let handle = self.new_handle();
+        self.responses.insert(handle, data);
+        handle
+    }
+
+    /// Copy the unread contents of a body into a new, open body.
+    ///
+    /// This is used to hand bodies that the guest has already closed, such as a finished streaming
+    /// body, to a backend handler or a test.
+    pub(crate) fn reopen_body(&mut self, handle: u32) -> Option<u32> {
+        let data = self.bodies.get(&handle)?.unread().to_vec();
+        Some(self.new_body(data))
+    }
+}
+
+/// An in-memory body.
+pub(crate) struct BodyData {
+    pub(crate) data: Vec<u8>,
+    /// The offset of the first unread byte.
+    pub(crate) pos: usize,
+    pub(crate) closed: bool,
+}
+
+impl BodyData {
+    pub(crate) fn unread(&self) -> &[u8] {
+        &self.data[self.pos..]
+    }
+
+    /// Remove and return the unread contents of the body.
+    pub(crate) fn take_unread(&mut self) -> Vec<u8> {
+        let rest = self.data.split_off(self.pos);
+        self.data.clear();
+        self.pos = 0;
+        rest
+    }
+}
+
+/// An ordered header map with case-insensitive names.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Headers(Vec<(Vec<u8>, Vec<u8>)>);
+
+impl Headers {
+    /// The distinct header names, in the order they were first added.
+    pub(crate) fn names(&self) -> Vec<&[u8]> {
+        let mut names: Vec<&[u8]> = vec![];
+        for (name, _) in &self.0 {
+            if !names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// All of the values for the given header name, in order.
+    pub(crate) fn values(&self, name: &[u8]) -> Vec<&[u8]> {
+        self.0
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_slice())
+            .collect()
+    }
+
+    pub(crate) fn get(&self, name: &[u8]) -> Option<&[u8]> {
+        self.values(name).into_iter().next()
+    }
+
+    pub(crate) fn append(&mut self, name: &[u8], value: &[u8]) {
+        self.0.push((name.to_ascii_lowercase(), value.to_vec()));
+    }
+
+    pub(crate) fn insert(&mut self, name: &[u8], value: &[u8]) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Replace the values for the given name with the nul-terminated values in `values`.
+    pub(crate) fn set_values(&mut self, name: &[u8], values: &[u8]) {
+        self.remove(name);
+        if values.is_empty() {
+            return;
+        }
+        let values = values.strip_suffix(b"\0").unwrap_or(values);
+        for value in values.split(|b| *b == b'\0') {
+            self.append(name, value);
+        }
+    }
+
+    /// Remove all values for the given name, returning whether any were present.
+    pub(crate) fn remove(&mut self, name: &[u8]) -> bool {
+        let before = self.0.len();
+        self.0.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.0.len() != before
+    }
+}
+
+/// An in-memory request.
+#[derive(Clone, Debug)]
+pub(crate) struct RequestData {
+    pub(crate) method: Vec<u8>,
+    pub(crate) uri: Vec<u8>,
+    pub(crate) version: u32,
+    pub(crate) headers: Headers,
+    pub(crate) cache_override: (u32, u32, u32, Vec<u8>),
+    pub(crate) framing_headers_mode: FramingHeadersMode,
+    pub(crate) auto_decompress_encodings: ContentEncodings,
+}
+
+impl Default for RequestData {
+    fn default() -> Self {
+        RequestData {
+            method: b"GET".to_vec(),
+            uri: b"http://localhost/".to_vec(),
+            version: HttpVersion::Http11 as u32,
+            headers: Headers::default(),
+            cache_override: (0, 0, 0, vec![]),
+            framing_headers_mode: FramingHeadersMode::Automatic,
+            auto_decompress_encodings: ContentEncodings::empty(),
+        }
+    }
+}
+
+/// An in-memory response.
+#[derive(Clone, Debug)]
+pub(crate) struct ResponseData {
+    pub(crate) status: u16,
+    pub(crate) version: u32,
+    pub(crate) headers: Headers,
+    pub(crate) framing_headers_mode: FramingHeadersMode,
+    pub(crate) http_keepalive_mode: HttpKeepaliveMode,
+}
+
+impl Default for ResponseData {
+    fn default() -> Self {
+        ResponseData {
+            status: 200,
+            version: HttpVersion::Http11 as u32,
+            headers: Headers::default(),
+            framing_headers_mode: FramingHeadersMode::Automatic,
+            http_keepalive_mode: HttpKeepaliveMode::Automatic,
+        }
+    }
+}
+
+/// A request sent with `send_async` or `send_async_streaming`.
+pub(crate) struct PendingData {
+    pub(crate) state: PendingState,
+    /// The host clock reading at which the response becomes available.
+    pub(crate) ready_at_ns: u64,
+}
+
+pub(crate) enum PendingState {
+    /// A streaming request whose backend has not yet been called, because the guest may still be
+    /// writing the body.
+    Streaming {
+        req: u32,
+        body: u32,
+        backend: String,
+    },
+    /// The backend has produced a response or an error.
+    Done(Result<(u32, u32), FastlyStatus>),
+}
+
+/// The downstream client request, and the response sent to it.
+pub(crate) struct Downstream {
+    pub(crate) request: Option<(u32, u32)>,
+    pub(crate) original_header_names: Vec<Vec<u8>>,
+    pub(crate) client_ip_addr: IpAddr,
+    pub(crate) fastly_key_is_valid: bool,
+    pub(crate) response: Option<(u32, u32)>,
+}
+
+impl Default for Downstream {
+    fn default() -> Self {
+        Downstream {
+            request: None,
+            original_header_names: vec![],
+            client_ip_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            fastly_key_is_valid: false,
+            response: None,
+        }
+    }
+}
+
+/// The configuration of a backend.
+#[derive(Clone, Debug)]
+pub(crate) struct BackendData {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) override_host: Option<Vec<u8>>,
+    pub(crate) connect_timeout_ms: u32,
+    pub(crate) first_byte_timeout_ms: u32,
+    pub(crate) between_bytes_timeout_ms: u32,
+    pub(crate) use_ssl: bool,
+    pub(crate) ssl_min_version: Option<u32>,
+    pub(crate) ssl_max_version: Option<u32>,
+    pub(crate) is_dynamic: bool,
+}
+
+impl BackendData {
+    fn for_name(name: &str) -> Self {
+        BackendData {
+            host: name.to_owned(),
+            port: 443,
+            override_host: None,
+            connect_timeout_ms: 1_000,
+            first_byte_timeout_ms: 15_000,
+            between_bytes_timeout_ms: 10_000,
+            use_ssl: true,
+            ssl_min_version: None,
+            ssl_max_version: None,
+            is_dynamic: false,
+        }
+    }
+}
+
+/// Send a request to a backend, calling its handler.
+///
+/// Returns the result of the request, and how many nanoseconds it takes to arrive. Replies slower
+/// than the backend's first byte timeout are replaced by an error once the timeout elapses.
+///
+/// The handler is removed from the host while it runs, so that it may itself make hostcalls.
+pub(crate) fn dispatch(
+    req: u32,
+    body: u32,
+    backend: &str,
+) -> (Result<(u32, u32), FastlyStatus>, u64) {
+    let handler = with_host(|host| {
+        if !host.requests.contains_key(&req) || !host.bodies.contains_key(&body) {
+            return Err(FastlyStatus::BADF);
+        }
+        if !host.backends.contains_key(backend) {
+            return Err(FastlyStatus::INVAL);
+        }
+        host.handlers.remove(backend).ok_or(FastlyStatus::ERROR)
+    });
+    let mut handler = match handler {
+        Ok(handler) => handler,
+        Err(status) => return (Err(status), 0),
+    };
+    let reply = handler(req, body);
+    with_host(|host| {
+        host.handlers.entry(backend.to_owned()).or_insert(handler);
+        let latency_ns = u64::try_from(reply.latency.as_nanos()).unwrap_or(u64::MAX);
+        let timeout_ns = host
+            .backends
+            .get(backend)
+            .map_or(u64::MAX, |b| u64::from(b.first_byte_timeout_ms) * 1_000_000);
+        if latency_ns <= timeout_ns {
+            return (reply.result, latency_ns);
+        }
+        if let Ok((resp, resp_body)) = reply.result {
+            host.responses.remove(&resp);
+            host.bodies.remove(&resp_body);
+        }
+        (Err(FastlyStatus::ERROR), timeout_ns)
+    })
+}
+
+/// Borrow the bytes of a hostcall argument.
+pub(crate) unsafe fn arg_bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// Borrow a hostcall argument as a string, or fail with `INVAL` if it is not UTF-8.
+pub(crate) unsafe fn arg_str<'a>(ptr: *const u8, len: usize) -> Result<&'a str, FastlyStatus> {
+    std::str::from_utf8(arg_bytes(ptr, len)).map_err(|_| FastlyStatus::INVAL)
+}
+
+/// Write a single value to a guest buffer.
+///
+/// If the buffer is too small, this returns `BUFLEN` and sets `nwritten` to the needed length.
+pub(crate) unsafe fn write_bytes(
+    bytes: &[u8],
+    buf: *mut u8,
+    buf_len: usize,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    if !nwritten.is_null() {
+        *nwritten = bytes.len();
+    }
+    if bytes.len() > buf_len {
+        return FastlyStatus::BUFLEN;
+    }
+    if !bytes.is_empty() {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), buf, bytes.len());
+    }
+    FastlyStatus::OK
+}
+
+/// Write as many values as will fit to a guest buffer, each followed by a nul byte, starting from
+/// the value at index `cursor`.
+///
+/// This follows the protocol expected by the multi-value hostcalls: `ending_cursor` is set to the
+/// index of the next value to write, or `-1` if every value was written.
+pub(crate) unsafe fn write_multi_value<T: AsRef<[u8]>>(
+    values: &[T],
+    buf: *mut u8,
+    buf_len: usize,
+    cursor: u32,
+    ending_cursor: *mut i64,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    let mut written = 0;
+    let mut ix = cursor as usize;
+    while let Some(value) = values.get(ix) {
+        let value = value.as_ref();
+        let needed = value.len() + 1;
+        if written + needed > buf_len {
+            if written == 0 {
+                *nwritten = needed;
+                return FastlyStatus::BUFLEN;
+            }
+            break;
+        }
+        std::ptr::copy_nonoverlapping(value.as_ptr(), buf.add(written), value.len());
+        *buf.add(written + value.len()) = b'\0';
+        written += needed;
+        ix += 1;
+    }
+    *nwritten = written;
+    *ending_cursor = if ix >= values.len() { -1 } else { ix as i64 };
+    FastlyStatus::OK
+}
+
+/// Reset the current thread's host to its initial state.
+///
+/// Handles allocated before the reset are no longer valid, but closing them succeeds, so that
+/// values from a previous test can be dropped safely.
+pub fn reset() {
+    let old = HOST.with(|host| {
+        let next = host.borrow().next_handle;
+        host.replace(Host::new(next))
+    });
+    // Dropping backend handlers may close handles, so the old host must be dropped only after the
+    // new one is in place.
+    drop(old);
+}
+
+/// The current reading of the host's clock.
+///
+/// The clock starts at zero and only moves when a test advances it, or when a hostcall waits for a
+/// pending item that becomes ready in the future.
+pub fn now() -> Duration {
+    with_host(|host| Duration::from_nanos(host.clock_ns))
+}
+
+/// Move the host's clock forward.
+pub fn advance_clock(by: Duration) {
+    with_host(|host| host.clock_ns += by.as_nanos() as u64);
+}
+
+/// Set the request returned by `fastly_http_req::body_downstream_get`.
+///
+/// The host takes ownership of both handles. The original header names reported for the client
+/// request are the names of the request's headers at the time of this call.
+///
+/// If this is never called, the client request is an empty `GET` request for
+/// `http://localhost/`.
+pub fn set_client_request(req: RequestHandle, body: BodyHandle) {
+    with_host(|host| {
+        let names = host
+            .requests
+            .get(&req)
+            .map(|r| r.headers.names().into_iter().map(<[u8]>::to_vec).collect())
+            .unwrap_or_default();
+        host.downstream.request = Some((req, body));
+        host.downstream.original_header_names = names;
+    });
+}
+
+/// Set the IP address reported for the client. The default is `127.0.0.1`.
+pub fn set_client_ip_addr(addr: IpAddr) {
+    with_host(|host| host.downstream.client_ip_addr = addr);
+}
+
+/// Set whether the client request carried a valid Fastly-Key. The default is `false`.
+pub fn set_fastly_key_is_valid(is_valid: bool) {
+    with_host(|host| host.downstream.fastly_key_is_valid = is_valid);
+}
+
+/// Take the response sent to the client, if one has been sent.
+///
+/// The caller takes ownership of the returned handles. The body contains everything written to the
+/// response body so far, including writes to a streaming body.
+pub fn take_client_response() -> Option<(ResponseHandle, BodyHandle)> {
+    with_host(|host| {
+        let (resp, body) = host.downstream.response.take()?;
+        let body = host.reopen_body(body)?;
+        Some((resp, body))
+    })
+}
+
+/// Register a backend with the given name, replacing any existing backend of the same name.
+///
+/// Requests sent to the backend are passed to `handler`. The backend reports its host as `name`,
+/// and uses TLS on port 443.
+pub fn register_backend(name: &str, handler: BackendHandler) {
+    with_host(|host| {
+        host.backends
+            .insert(name.to_owned(), BackendData::for_name(name));
+        host.handlers.insert(name.to_owned(), handler)
+    });
+}
+
+/// Set the handler for requests sent to the backend with the given name, without defining the
+/// backend.
+///
+/// This allows handling requests to a dynamic backend that the program registers itself.
+pub fn set_backend_handler(name: &str, handler: BackendHandler) {
+    with_host(|host| host.handlers.insert(name.to_owned(), handler));
+}
+
+/// Set the first byte timeout of a backend.
+///
+/// Replies that take longer than this to arrive fail with `FastlyStatus::ERROR`. The default for
+/// backends registered with [`register_backend()`] is 15 seconds.
+pub fn set_first_byte_timeout(name: &str, timeout: Duration) {
+    with_host(|host| {
+        if let Some(backend) = host.backends.get_mut(name) {
+            backend.first_byte_timeout_ms = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+        }
+    });
+}
+
+/// Remove a backend and its handler.
+pub fn remove_backend(name: &str) {
+    let handler = with_host(|host| {
+        host.backends.remove(name);
+        host.handlers.remove(name)
+    });
+    drop(handler);
+}
+
+/// Set an entry in a config store, creating the store if it does not exist.
+pub fn insert_config_store_entry(store: &str, key: &str, value: &str) {
+    with_host(|host| {
+        host.dictionaries
+            .entry(store.to_owned())
+            .or_default()
+            .insert(key.to_owned(), value.to_owned())
+    });
+}
+
+/// Create an empty config store, if it does not already exist.
+pub fn create_config_store(store: &str) {
+    with_host(|host| {
+        host.dictionaries.entry(store.to_owned()).or_default();
+    });
+}
+
+/// Set an entry in a KV store, creating the store if it does not exist.
+pub fn insert_kv_store_entry(store: &str, key: &str, value: &[u8]) {
+    with_host(|host| {
+        host.kv_stores.entry(store.to_owned()).or_default().insert(
+            key.to_owned(),
+            fastly_kv_store::KvEntry::new(value.to_vec()),
+        )
+    });
+}
+
+/// Create an empty KV store, if it does not already exist.
+pub fn create_kv_store(store: &str) {
+    with_host(|host| {
+        host.kv_stores.entry(store.to_owned()).or_default();
+    });
+}
+
+/// Get the value of an entry in a KV store.
+pub fn kv_store_entry(store: &str, key: &str) -> Option<Vec<u8>> {
+    with_host(|host| {
+        let entry = host.kv_stores.get(store)?.get(key)?;
+        Some(entry.value.clone()).filter(|_| entry.is_live(host.clock_ns))
+    })
+}
+
+/// Set a secret in a secret store, creating the store if it does not exist.
+pub fn insert_secret(store: &str, name: &str, plaintext: &[u8]) {
+    with_host(|host| {
+        host.secret_stores
+            .entry(store.to_owned())
+            .or_default()
+            .insert(name.to_owned(), plaintext.to_vec())
+    });
+}
+
+/// Set the raw geolocation data returned for an IP address.
+///
+/// `json` must be in the format produced by the Compute@Edge geolocation hostcall.
+pub fn set_geo(addr: IpAddr, json: &str) {
+    with_host(|host| host.geo.insert(addr, json.to_owned()));
+}
+
+/// The messages written to a log endpoint, in order.
+pub fn log_entries(endpoint: &str) -> Vec<Vec<u8>> {
+    with_host(|host| host.logs.get(endpoint).cloned().unwrap_or_default())
+}
@@ -0,0 +1,241 @@
+//! The state of the in-process cache.
+
+use std::collections::{HashMap, HashSet};
+
+use super::Headers;
+use crate::fastly_cache::CacheLookupState;
+
+#[derive(Default)]
+pub(crate) struct Cache {
+    next_id: u64,
+    pub(crate) entries: HashMap<u64, Entry>,
+    pub(crate) handles: HashMap<u32, Lookup>,
+    /// Keys for which some transaction currently holds the obligation to insert or update.
+    obligations: HashSet<Vec<u8>>,
+}
+
+/// A cached object.
+///
+/// Entries that are replaced or purged stay in the map, with `live` cleared, so that handles to
+/// them can still read them.
+pub(crate) struct Entry {
+    pub(crate) key: Vec<u8>,
+    pub(crate) live: bool,
+    /// The handle of the body that the object is written to.
+    pub(crate) body: u32,
+    pub(crate) options: WriteOptions,
+    pub(crate) inserted_at_ns: u64,
+    /// The headers named by the vary rule, with their values in the request the object was
+    /// inserted for.
+    pub(crate) vary: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    pub(crate) hits: u64,
+}
+
+impl Entry {
+    pub(crate) fn age_ns(&self, now_ns: u64) -> u64 {
+        now_ns.saturating_sub(self.inserted_at_ns) + self.options.initial_age_ns
+    }
+
+    fn matches(&self, headers: &Headers) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| vary_value(headers, name) == *value)
+    }
+}
+
+/// The options an object is inserted or updated with.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct WriteOptions {
+    pub(crate) max_age_ns: u64,
+    pub(crate) request_headers: Option<Headers>,
+    pub(crate) vary_rule: Option<String>,
+    pub(crate) initial_age_ns: u64,
+    pub(crate) stale_while_revalidate_ns: u64,
+    pub(crate) surrogate_keys: Vec<String>,
+    pub(crate) length: Option<u64>,
+    pub(crate) user_metadata: Vec<u8>,
+}
+
+/// The result of a lookup, referred to by a cache handle.
+pub(crate) struct Lookup {
+    pub(crate) key: Vec<u8>,
+    pub(crate) request_headers: Headers,
+    pub(crate) entry: Option<u64>,
+    pub(crate) state: CacheLookupState,
+    /// Whether the lookup is waiting for another transaction to insert or update the object.
+    pub(crate) pending: bool,
+}
+
+fn vary_value(headers: &Headers, name: &[u8]) -> Option<Vec<u8>> {
+    let values = headers.values(name);
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(&b", "[..]))
+    }
+}
+
+impl Cache {
+    /// Find the most recently inserted live object for a key that matches the request headers,
+    /// and is still usable.
+    fn find(&self, key: &[u8], headers: &Headers, now_ns: u64) -> Option<u64> {
+        self.entries
+            .iter()
+            .filter(|(_, e)| e.live && e.key == key && e.matches(headers))
+            .filter(|(_, e)| {
+                e.age_ns(now_ns) < e.options.max_age_ns + e.options.stale_while_revalidate_ns
+            })
+            .max_by_key(|(id, _)| **id)
+            .map(|(id, _)| *id)
+    }
+
+    /// Look up an object, returning the lookup to associate with a new cache handle.
+    ///
+    /// A transactional lookup that finds no fresh object is given the obligation to insert or
+    /// update it, unless another transaction already holds that obligation. In that case, a lookup
+    /// that found a stale object completes without the obligation, and one that found nothing is
+    /// collapsed: it stays pending until the other transaction inserts the object or gives up.
+    pub(crate) fn lookup(
+        &mut self,
+        key: &[u8],
+        request_headers: Headers,
+        transactional: bool,
+        now_ns: u64,
+    ) -> Lookup {
+        let mut lookup = Lookup {
+            key: key.to_vec(),
+            request_headers,
+            entry: None,
+            state: CacheLookupState::empty(),
+            pending: false,
+        };
+        self.complete(&mut lookup, transactional, now_ns);
+        lookup
+    }
+
+    /// Complete a lookup, or leave it pending if it is collapsed onto another transaction.
+    fn complete(&mut self, lookup: &mut Lookup, transactional: bool, now_ns: u64) {
+        let key = &lookup.key;
+        let entry = self.find(key, &lookup.request_headers, now_ns);
+        lookup.pending = transactional && entry.is_none() && self.obligations.contains(key);
+        if lookup.pending {
+            return;
+        }
+        let mut state = CacheLookupState::empty();
+        let mut stale = false;
+        if let Some(id) = entry {
+            let entry = self.entries.get_mut(&id).expect("found above");
+            entry.hits += 1;
+            state |= CacheLookupState::FOUND | CacheLookupState::USABLE;
+            if entry.age_ns(now_ns) >= entry.options.max_age_ns {
+                state |= CacheLookupState::STALE;
+                stale = true;
+            }
+        }
+        if transactional && (entry.is_none() || (stale && !self.obligations.contains(key))) {
+            self.obligations.insert(key.clone());
+            state |= CacheLookupState::MUST_INSERT_OR_UPDATE;
+        }
+        lookup.entry = entry;
+        lookup.state = state;
+    }
+
+    /// Retry the lookups collapsed onto a transaction for `key`, in the order they were made.
+    fn wake(&mut self, key: &[u8], now_ns: u64) {
+        let mut waiting: Vec<u32> = self
+            .handles
+            .iter()
+            .filter(|(_, lookup)| lookup.pending && lookup.key == key)
+            .map(|(handle, _)| *handle)
+            .collect();
+        waiting.sort_unstable();
+        for handle in waiting {
+            let mut lookup = self.handles.remove(&handle).expect("collected above");
+            self.complete(&mut lookup, true, now_ns);
+            self.handles.insert(handle, lookup);
+        }
+    }
+
+    /// Insert an object whose contents will be written to `body`, returning its identifier.
+    pub(crate) fn insert(
+        &mut self,
+        key: &[u8],
+        options: WriteOptions,
+        lookup_headers: &Headers,
+        body: u32,
+        now_ns: u64,
+    ) -> u64 {
+        let headers = options.request_headers.as_ref().unwrap_or(lookup_headers);
+        let vary: Vec<_> = options
+            .vary_rule
+            .iter()
+            .flat_map(|rule| rule.split_ascii_whitespace())
+            .map(|name| {
+                let name = name.as_bytes().to_ascii_lowercase();
+                let value = vary_value(headers, &name);
+                (name, value)
+            })
+            .collect();
+        // The new object replaces any previous object for the same variant.
+        for entry in self.entries.values_mut() {
+            if entry.key == key && entry.vary == vary {
+                entry.live = false;
+            }
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.insert(
+            id,
+            Entry {
+                key: key.to_vec(),
+                live: true,
+                body,
+                options,
+                inserted_at_ns: now_ns,
+                vary,
+                hits: 0,
+            },
+        );
+        self.wake(key, now_ns);
+        id
+    }
+
+    /// Release the obligation held by a cache handle, if any, passing it on to a collapsed lookup.
+    pub(crate) fn release(&mut self, handle: u32, now_ns: u64) -> bool {
+        match self.handles.get_mut(&handle) {
+            Some(lookup)
+                if lookup
+                    .state
+                    .contains(CacheLookupState::MUST_INSERT_OR_UPDATE) =>
+            {
+                lookup.state.remove(CacheLookupState::MUST_INSERT_OR_UPDATE);
+                let key = lookup.key.clone();
+                self.obligations.remove(&key);
+                self.wake(&key, now_ns);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Purge the objects with the given surrogate key.
+    ///
+    /// A soft purge marks the objects as stale, rather than removing them.
+    pub(crate) fn purge(&mut self, surrogate_key: &str, soft: bool, now_ns: u64) {
+        for entry in self.entries.values_mut() {
+            if !entry
+                .options
+                .surrogate_keys
+                .iter()
+                .any(|k| k == surrogate_key)
+            {
+                continue;
+            }
+            if soft {
+                entry.options.max_age_ns = entry.options.max_age_ns.min(entry.age_ns(now_ns));
+            } else {
+                entry.live = false;
+            }
+        }
+    }
+}
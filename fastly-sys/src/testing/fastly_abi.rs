@@ -0,0 +1,9 @@
+use fastly_shared::{FastlyStatus, FASTLY_ABI_VERSION};
+
+pub unsafe extern "C" fn init(abi_version: u64) -> FastlyStatus {
+    if abi_version == FASTLY_ABI_VERSION {
+        FastlyStatus::OK
+    } else {
+        FastlyStatus::UNSUPPORTED
+    }
+}
@@ -0,0 +1,91 @@
+use fastly_shared::FastlyStatus;
+
+use super::fastly_http_req::pending_is_ready;
+use super::{with_host, Host};
+use crate::AsyncItemHandle;
+
+/// Returns whether an async item is ready, or `None` if the handle is not an async item.
+///
+/// Bodies and KV store operations complete synchronously in the in-process host, so only pending
+/// requests and collapsed cache lookups can be waiting.
+fn is_item_ready(host: &Host, handle: AsyncItemHandle) -> Option<bool> {
+    if let Some(pending) = host.pending.get(&handle) {
+        Some(pending_is_ready(host, pending))
+    } else if let Some(lookup) = host.cache.handles.get(&handle) {
+        Some(!lookup.pending)
+    } else if host.bodies.contains_key(&handle) || host.kv_pending.contains_key(&handle) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+pub unsafe extern "C" fn select(
+    async_item_handles: *const AsyncItemHandle,
+    async_item_handles_len: usize,
+    timeout_ms: u32,
+    done_index_out: *mut u32,
+) -> FastlyStatus {
+    let handles = if async_item_handles_len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(async_item_handles, async_item_handles_len)
+    };
+    if handles.is_empty() && timeout_ms == 0 {
+        return FastlyStatus::INVAL;
+    }
+    with_host(|host| {
+        let mut soonest: Option<(u64, usize)> = None;
+        for (ix, handle) in handles.iter().enumerate() {
+            match is_item_ready(host, *handle) {
+                Some(true) => {
+                    *done_index_out = ix as u32;
+                    return FastlyStatus::OK;
+                }
+                Some(false) => {
+                    if let Some(pending) = host.pending.get(handle) {
+                        if let super::PendingState::Done(_) = pending.state {
+                            let at = (pending.ready_at_ns, ix);
+                            soonest = Some(soonest.map_or(at, |s| s.min(at)));
+                        }
+                    }
+                }
+                None => return FastlyStatus::BADF,
+            }
+        }
+        // Nothing is ready yet, so move the clock to whichever comes first: the soonest item, or
+        // the timeout. A timeout of zero means there is no timeout.
+        let deadline = (timeout_ms != 0).then(|| host.clock_ns + u64::from(timeout_ms) * 1_000_000);
+        match (soonest, deadline) {
+            (Some((at, ix)), Some(deadline)) if at <= deadline => {
+                host.clock_ns = at;
+                *done_index_out = ix as u32;
+            }
+            (_, Some(deadline)) => {
+                host.clock_ns = deadline;
+                *done_index_out = u32::MAX;
+            }
+            (Some((at, ix)), None) => {
+                host.clock_ns = at;
+                *done_index_out = ix as u32;
+            }
+            // Only streaming requests with open bodies remain; no amount of waiting will complete
+            // them.
+            (None, None) => return FastlyStatus::INVAL,
+        }
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn is_ready(
+    async_item_handle: AsyncItemHandle,
+    ready_out: *mut u32,
+) -> FastlyStatus {
+    with_host(|host| match is_item_ready(host, async_item_handle) {
+        Some(ready) => {
+            *ready_out = ready as u32;
+            FastlyStatus::OK
+        }
+        None => FastlyStatus::BADF,
+    })
+}
@@ -0,0 +1,170 @@
+use fastly_shared::FastlyStatus;
+
+use super::{arg_str, with_host, write_bytes, BackendData};
+use crate::fastly_backend::BackendHealth;
+
+/// Run a closure against the configuration of a backend, or fail with `INVAL` if there is no
+/// backend with the given name.
+unsafe fn with_backend(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    f: impl FnOnce(&BackendData) -> FastlyStatus,
+) -> FastlyStatus {
+    let name = match arg_str(backend_ptr, backend_len) {
+        Ok(name) => name,
+        Err(status) => return status,
+    };
+    with_host(|host| match host.backends.get(name) {
+        Some(backend) => f(backend),
+        None => FastlyStatus::INVAL,
+    })
+}
+
+pub unsafe extern "C" fn exists(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    backend_exists_out: *mut u32,
+) -> FastlyStatus {
+    let name = match arg_str(backend_ptr, backend_len) {
+        Ok(name) => name,
+        Err(status) => return status,
+    };
+    *backend_exists_out = with_host(|host| host.backends.contains_key(name)) as u32;
+    FastlyStatus::OK
+}
+
+pub unsafe extern "C" fn is_healthy(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    backend_health_out: *mut BackendHealth,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |_| {
+        *backend_health_out = BackendHealth::Unknown;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn is_dynamic(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u32,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        *value = backend.is_dynamic as u32;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_host(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u8,
+    value_max_len: usize,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        write_bytes(backend.host.as_bytes(), value, value_max_len, nwritten)
+    })
+}
+
+pub unsafe extern "C" fn get_override_host(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u8,
+    value_max_len: usize,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        match &backend.override_host {
+            Some(host) => write_bytes(host, value, value_max_len, nwritten),
+            None => FastlyStatus::NONE,
+        }
+    })
+}
+
+pub unsafe extern "C" fn get_port(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u16,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        *value = backend.port;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_connect_timeout_ms(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u32,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        *value = backend.connect_timeout_ms;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_first_byte_timeout_ms(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u32,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        *value = backend.first_byte_timeout_ms;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_between_bytes_timeout_ms(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u32,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        *value = backend.between_bytes_timeout_ms;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn is_ssl(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u32,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        *value = backend.use_ssl as u32;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_ssl_min_version(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u32,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        match backend.ssl_min_version {
+            Some(version) => {
+                *value = version;
+                FastlyStatus::OK
+            }
+            None => FastlyStatus::NONE,
+        }
+    })
+}
+
+pub unsafe extern "C" fn get_ssl_max_version(
+    backend_ptr: *const u8,
+    backend_len: usize,
+    value: *mut u32,
+) -> FastlyStatus {
+    with_backend(backend_ptr, backend_len, |backend| {
+        match backend.ssl_max_version {
+            Some(version) => {
+                *value = version;
+                FastlyStatus::OK
+            }
+            None => FastlyStatus::NONE,
+        }
+    })
+}
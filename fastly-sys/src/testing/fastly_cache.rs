@@ -0,0 +1,422 @@
+use fastly_shared::FastlyStatus;
+
+use super::cache::{Lookup, WriteOptions};
+use super::{arg_bytes, arg_str, try_with_host, with_host, write_bytes, Headers, Host};
+use crate::fastly_cache::{
+    CacheDurationNs, CacheGetBodyOptions, CacheGetBodyOptionsMask, CacheHandle, CacheHitCount,
+    CacheLookupOptions, CacheLookupOptionsMask, CacheLookupState, CacheObjectLength,
+    CacheWriteOptions, CacheWriteOptionsMask,
+};
+use crate::{BodyHandle, RequestHandle};
+
+unsafe fn lookup_headers(
+    host: &Host,
+    options_mask: CacheLookupOptionsMask,
+    options: *const CacheLookupOptions,
+) -> Result<Headers, FastlyStatus> {
+    if !options_mask.contains(CacheLookupOptionsMask::REQUEST_HEADERS) {
+        return Ok(Headers::default());
+    }
+    request_headers(host, (*options).request_headers)
+}
+
+fn request_headers(host: &Host, req_handle: RequestHandle) -> Result<Headers, FastlyStatus> {
+    match host.requests.get(&req_handle) {
+        Some(req) => Ok(req.headers.clone()),
+        None => Err(FastlyStatus::BADF),
+    }
+}
+
+unsafe fn write_options(
+    host: &Host,
+    options_mask: CacheWriteOptionsMask,
+    options: *const CacheWriteOptions,
+) -> Result<WriteOptions, FastlyStatus> {
+    use CacheWriteOptionsMask as Mask;
+
+    let options = &*options;
+    let mut parsed = WriteOptions {
+        max_age_ns: options.max_age_ns,
+        ..WriteOptions::default()
+    };
+    if options_mask.contains(Mask::REQUEST_HEADERS) {
+        parsed.request_headers = Some(request_headers(host, options.request_headers)?);
+    }
+    if options_mask.contains(Mask::VARY_RULE) {
+        parsed.vary_rule = Some(arg_str(options.vary_rule_ptr, options.vary_rule_len)?.to_owned());
+    }
+    if options_mask.contains(Mask::INITIAL_AGE_NS) {
+        parsed.initial_age_ns = options.initial_age_ns;
+    }
+    if options_mask.contains(Mask::STALE_WHILE_REVALIDATE_NS) {
+        parsed.stale_while_revalidate_ns = options.stale_while_revalidate_ns;
+    }
+    if options_mask.contains(Mask::SURROGATE_KEYS) {
+        parsed.surrogate_keys = arg_str(options.surrogate_keys_ptr, options.surrogate_keys_len)?
+            .split_ascii_whitespace()
+            .map(str::to_owned)
+            .collect();
+    }
+    if options_mask.contains(Mask::LENGTH) {
+        parsed.length = Some(options.length);
+    }
+    if options_mask.contains(Mask::USER_METADATA) {
+        parsed.user_metadata =
+            arg_bytes(options.user_metadata_ptr, options.user_metadata_len).to_vec();
+    }
+    Ok(parsed)
+}
+
+unsafe fn do_lookup(
+    cache_key_ptr: *const u8,
+    cache_key_len: usize,
+    options_mask: CacheLookupOptionsMask,
+    options: *const CacheLookupOptions,
+    cache_handle_out: *mut CacheHandle,
+    transactional: bool,
+) -> FastlyStatus {
+    let key = arg_bytes(cache_key_ptr, cache_key_len);
+    with_host(|host| {
+        let headers = match lookup_headers(host, options_mask, options) {
+            Ok(headers) => headers,
+            Err(status) => return status,
+        };
+        let now = host.clock_ns;
+        let lookup = host.cache.lookup(key, headers, transactional, now);
+        let handle = host.new_handle();
+        host.cache.handles.insert(handle, lookup);
+        *cache_handle_out = handle;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn lookup(
+    cache_key_ptr: *const u8,
+    cache_key_len: usize,
+    options_mask: CacheLookupOptionsMask,
+    options: *const CacheLookupOptions,
+    cache_handle_out: *mut CacheHandle,
+) -> FastlyStatus {
+    do_lookup(
+        cache_key_ptr,
+        cache_key_len,
+        options_mask,
+        options,
+        cache_handle_out,
+        false,
+    )
+}
+
+pub unsafe extern "C" fn insert(
+    cache_key_ptr: *const u8,
+    cache_key_len: usize,
+    options_mask: CacheWriteOptionsMask,
+    options: *const CacheWriteOptions,
+    body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    let key = arg_bytes(cache_key_ptr, cache_key_len);
+    with_host(|host| {
+        let options = match write_options(host, options_mask, options) {
+            Ok(options) => options,
+            Err(status) => return status,
+        };
+        let body = host.new_body(vec![]);
+        let now = host.clock_ns;
+        host.cache
+            .insert(key, options, &Headers::default(), body, now);
+        *body_handle_out = body;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn transaction_lookup(
+    cache_key_ptr: *const u8,
+    cache_key_len: usize,
+    options_mask: CacheLookupOptionsMask,
+    options: *const CacheLookupOptions,
+    cache_handle_out: *mut CacheHandle,
+) -> FastlyStatus {
+    do_lookup(
+        cache_key_ptr,
+        cache_key_len,
+        options_mask,
+        options,
+        cache_handle_out,
+        true,
+    )
+}
+
+/// Insert an object on behalf of a transaction, returning the handle of the body to write it to
+/// and the identifier of the new entry.
+unsafe fn do_transaction_insert(
+    host: &mut Host,
+    handle: CacheHandle,
+    options_mask: CacheWriteOptionsMask,
+    options: *const CacheWriteOptions,
+) -> Result<(BodyHandle, u64), FastlyStatus> {
+    let options = write_options(host, options_mask, options)?;
+    let (key, headers) = match host.cache.handles.get(&handle) {
+        Some(lookup)
+            if lookup
+                .state
+                .contains(CacheLookupState::MUST_INSERT_OR_UPDATE) =>
+        {
+            (lookup.key.clone(), lookup.request_headers.clone())
+        }
+        _ => return Err(FastlyStatus::BADF),
+    };
+    let body = host.new_body(vec![]);
+    let now = host.clock_ns;
+    let entry = host.cache.insert(&key, options, &headers, body, now);
+    host.cache.release(handle, now);
+    Ok((body, entry))
+}
+
+pub unsafe extern "C" fn transaction_insert(
+    handle: CacheHandle,
+    options_mask: CacheWriteOptionsMask,
+    options: *const CacheWriteOptions,
+    body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    with_host(
+        |host| match do_transaction_insert(host, handle, options_mask, options) {
+            Ok((body, _)) => {
+                *body_handle_out = body;
+                FastlyStatus::OK
+            }
+            Err(status) => status,
+        },
+    )
+}
+
+pub unsafe extern "C" fn transaction_insert_and_stream_back(
+    handle: CacheHandle,
+    options_mask: CacheWriteOptionsMask,
+    options: *const CacheWriteOptions,
+    body_handle_out: *mut BodyHandle,
+    cache_handle_out: *mut CacheHandle,
+) -> FastlyStatus {
+    with_host(
+        |host| match do_transaction_insert(host, handle, options_mask, options) {
+            Ok((body, entry)) => {
+                let lookup = host.cache.handles.get(&handle).expect("checked on insert");
+                let lookup = Lookup {
+                    key: lookup.key.clone(),
+                    request_headers: lookup.request_headers.clone(),
+                    entry: Some(entry),
+                    state: CacheLookupState::FOUND | CacheLookupState::USABLE,
+                    pending: false,
+                };
+                let cache_handle = host.new_handle();
+                host.cache.handles.insert(cache_handle, lookup);
+                *body_handle_out = body;
+                *cache_handle_out = cache_handle;
+                FastlyStatus::OK
+            }
+            Err(status) => status,
+        },
+    )
+}
+
+pub unsafe extern "C" fn transaction_update(
+    handle: CacheHandle,
+    options_mask: CacheWriteOptionsMask,
+    options: *const CacheWriteOptions,
+) -> FastlyStatus {
+    with_host(|host| {
+        let options = match write_options(host, options_mask, options) {
+            Ok(options) => options,
+            Err(status) => return status,
+        };
+        let id = match host.cache.handles.get(&handle) {
+            Some(Lookup {
+                entry: Some(id),
+                state,
+                ..
+            }) if state.contains(CacheLookupState::MUST_INSERT_OR_UPDATE) => *id,
+            _ => return FastlyStatus::BADF,
+        };
+        let now = host.clock_ns;
+        let entry = host
+            .cache
+            .entries
+            .get_mut(&id)
+            .expect("entries are never removed");
+        // The length of the object can't change without a new body.
+        let length = entry.options.length;
+        entry.options = WriteOptions { length, ..options };
+        entry.inserted_at_ns = now;
+        host.cache.release(handle, now);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn transaction_cancel(handle: CacheHandle) -> FastlyStatus {
+    with_host(|host| {
+        if !host.cache.handles.contains_key(&handle) {
+            return FastlyStatus::BADF;
+        }
+        let now = host.clock_ns;
+        host.cache.release(handle, now);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn close(handle: CacheHandle) -> FastlyStatus {
+    try_with_host(|host| {
+        if host.is_stale_handle(handle) {
+            return FastlyStatus::OK;
+        }
+        let now = host.clock_ns;
+        host.cache.release(handle, now);
+        match host.cache.handles.remove(&handle) {
+            Some(_) => FastlyStatus::OK,
+            None => FastlyStatus::BADF,
+        }
+    })
+    .unwrap_or(FastlyStatus::OK)
+}
+
+pub unsafe extern "C" fn get_state(
+    handle: CacheHandle,
+    cache_lookup_state_out: *mut CacheLookupState,
+) -> FastlyStatus {
+    with_host(|host| match host.cache.handles.get(&handle) {
+        // The in-process host runs a single request, so nothing else can complete a collapsed
+        // lookup while this call waits for it.
+        Some(lookup) if lookup.pending => FastlyStatus::ERROR,
+        Some(lookup) => {
+            *cache_lookup_state_out = lookup.state;
+            FastlyStatus::OK
+        }
+        None => FastlyStatus::BADF,
+    })
+}
+
+/// Run a function on the entry found by a lookup.
+///
+/// Returns `BADF` if the handle is invalid, or `NONE` if the lookup found nothing.
+fn with_entry(handle: CacheHandle, f: impl FnOnce(&mut Host, u64) -> FastlyStatus) -> FastlyStatus {
+    with_host(|host| match host.cache.handles.get(&handle) {
+        Some(Lookup {
+            entry: Some(id), ..
+        }) => {
+            let id = *id;
+            f(host, id)
+        }
+        Some(_) => FastlyStatus::NONE,
+        None => FastlyStatus::BADF,
+    })
+}
+
+pub unsafe extern "C" fn get_user_metadata(
+    handle: CacheHandle,
+    user_metadata_out_ptr: *mut u8,
+    user_metadata_out_len: usize,
+    nwritten_out: *mut usize,
+) -> FastlyStatus {
+    with_entry(handle, |host, id| {
+        write_bytes(
+            &host.cache.entries[&id].options.user_metadata,
+            user_metadata_out_ptr,
+            user_metadata_out_len,
+            nwritten_out,
+        )
+    })
+}
+
+pub unsafe extern "C" fn get_body(
+    handle: CacheHandle,
+    options_mask: CacheGetBodyOptionsMask,
+    options: *const CacheGetBodyOptions,
+    body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    with_entry(handle, |host, id| {
+        let data = match host.bodies.get(&host.cache.entries[&id].body) {
+            Some(body) => &body.data,
+            None => return FastlyStatus::NONE,
+        };
+        let len = data.len() as u64;
+        let from = if options_mask.contains(CacheGetBodyOptionsMask::FROM) {
+            (*options).from
+        } else {
+            0
+        };
+        // `to` is inclusive; like the host, return the whole object if the range is invalid.
+        let to = if options_mask.contains(CacheGetBodyOptionsMask::TO) {
+            (*options).to.saturating_add(1)
+        } else {
+            len
+        };
+        let data = if from < to && to <= len {
+            data[from as usize..to as usize].to_vec()
+        } else {
+            data.clone()
+        };
+        *body_handle_out = host.new_body(data);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_length(
+    handle: CacheHandle,
+    length_out: *mut CacheObjectLength,
+) -> FastlyStatus {
+    with_entry(handle, |host, id| {
+        let entry = &host.cache.entries[&id];
+        let length = entry
+            .options
+            .length
+            .or_else(|| match host.bodies.get(&entry.body) {
+                Some(body) if body.closed => Some(body.data.len() as u64),
+                _ => None,
+            });
+        match length {
+            Some(length) => {
+                *length_out = length;
+                FastlyStatus::OK
+            }
+            None => FastlyStatus::NONE,
+        }
+    })
+}
+
+pub unsafe extern "C" fn get_max_age_ns(
+    handle: CacheHandle,
+    duration_out: *mut CacheDurationNs,
+) -> FastlyStatus {
+    with_entry(handle, |host, id| {
+        *duration_out = host.cache.entries[&id].options.max_age_ns;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_stale_while_revalidate_ns(
+    handle: CacheHandle,
+    duration_out: *mut CacheDurationNs,
+) -> FastlyStatus {
+    with_entry(handle, |host, id| {
+        *duration_out = host.cache.entries[&id].options.stale_while_revalidate_ns;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_age_ns(
+    handle: CacheHandle,
+    duration_out: *mut CacheDurationNs,
+) -> FastlyStatus {
+    with_entry(handle, |host, id| {
+        *duration_out = host.cache.entries[&id].age_ns(host.clock_ns);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get_hits(
+    handle: CacheHandle,
+    hits_out: *mut CacheHitCount,
+) -> FastlyStatus {
+    with_entry(handle, |host, id| {
+        *hits_out = host.cache.entries[&id].hits;
+        FastlyStatus::OK
+    })
+}
@@ -0,0 +1,53 @@
+use fastly_shared::FastlyStatus;
+
+use super::{arg_str, with_host, write_bytes};
+use crate::DictionaryHandle;
+
+pub unsafe extern "C" fn open(
+    name: *const u8,
+    name_len: usize,
+    dict_handle_out: *mut DictionaryHandle,
+) -> FastlyStatus {
+    let name = match arg_str(name, name_len) {
+        Ok("") => return FastlyStatus::NONE,
+        Ok(name) => name,
+        Err(status) => return status,
+    };
+    with_host(|host| {
+        if !host.dictionaries.contains_key(name) {
+            return FastlyStatus::BADF;
+        }
+        let handle = host.new_handle();
+        host.dictionary_handles.insert(handle, name.to_owned());
+        *dict_handle_out = handle;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get(
+    dict_handle: DictionaryHandle,
+    key: *const u8,
+    key_len: usize,
+    value: *mut u8,
+    value_max_len: usize,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    let key = match arg_str(key, key_len) {
+        Ok(key) => key,
+        Err(status) => return status,
+    };
+    with_host(|host| {
+        let entries = match host
+            .dictionary_handles
+            .get(&dict_handle)
+            .and_then(|name| host.dictionaries.get(name))
+        {
+            Some(entries) => entries,
+            None => return FastlyStatus::BADF,
+        };
+        match entries.get(key) {
+            Some(v) => write_bytes(v.as_bytes(), value, value_max_len, nwritten),
+            None => FastlyStatus::NONE,
+        }
+    })
+}
@@ -0,0 +1,26 @@
+use std::net::IpAddr;
+
+use fastly_shared::FastlyStatus;
+
+use super::{arg_bytes, with_host, write_bytes};
+
+pub unsafe extern "C" fn lookup(
+    addr_octets: *const u8,
+    addr_len: usize,
+    buf: *mut u8,
+    buf_len: usize,
+    nwritten_out: *mut usize,
+) -> FastlyStatus {
+    let octets = arg_bytes(addr_octets, addr_len);
+    let addr = if let Ok(v4) = <[u8; 4]>::try_from(octets) {
+        IpAddr::from(v4)
+    } else if let Ok(v6) = <[u8; 16]>::try_from(octets) {
+        IpAddr::from(v6)
+    } else {
+        return FastlyStatus::INVAL;
+    };
+    with_host(|host| match host.geo.get(&addr) {
+        Some(json) => write_bytes(json.as_bytes(), buf, buf_len, nwritten_out),
+        None => FastlyStatus::NONE,
+    })
+}
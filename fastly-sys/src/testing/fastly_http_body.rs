@@ -0,0 +1,91 @@
+use fastly_shared::{BodyWriteEnd, FastlyStatus};
+
+use super::{arg_bytes, try_with_host, with_host};
+use crate::BodyHandle;
+
+pub unsafe extern "C" fn append(dst_handle: BodyHandle, src_handle: BodyHandle) -> FastlyStatus {
+    with_host(|host| {
+        let src = match host.bodies.get(&src_handle) {
+            Some(src) if !src.closed && src_handle != dst_handle => src,
+            _ => return FastlyStatus::BADF,
+        };
+        if !matches!(host.bodies.get(&dst_handle), Some(dst) if !dst.closed) {
+            return FastlyStatus::BADF;
+        }
+        let data = src.unread().to_vec();
+        host.bodies.remove(&src_handle);
+        let dst = host.bodies.get_mut(&dst_handle).expect("checked above");
+        dst.data.extend_from_slice(&data);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn new(handle_out: *mut BodyHandle) -> FastlyStatus {
+    *handle_out = with_host(|host| host.new_body(vec![]));
+    FastlyStatus::OK
+}
+
+pub unsafe extern "C" fn read(
+    body_handle: BodyHandle,
+    buf: *mut u8,
+    buf_len: usize,
+    nread_out: *mut usize,
+) -> FastlyStatus {
+    with_host(|host| {
+        let body = match host.bodies.get_mut(&body_handle) {
+            Some(body) if !body.closed => body,
+            _ => return FastlyStatus::BADF,
+        };
+        let unread = body.unread();
+        let n = std::cmp::min(unread.len(), buf_len);
+        std::ptr::copy_nonoverlapping(unread.as_ptr(), buf, n);
+        body.pos += n;
+        *nread_out = n;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn write(
+    body_handle: BodyHandle,
+    buf: *const u8,
+    buf_len: usize,
+    end: BodyWriteEnd,
+    nwritten_out: *mut usize,
+) -> FastlyStatus {
+    let bytes = arg_bytes(buf, buf_len);
+    with_host(|host| {
+        let body = match host.bodies.get_mut(&body_handle) {
+            Some(body) if !body.closed => body,
+            _ => return FastlyStatus::BADF,
+        };
+        match end {
+            BodyWriteEnd::Back => body.data.extend_from_slice(bytes),
+            BodyWriteEnd::Front => {
+                let pos = body.pos;
+                body.data.splice(pos..pos, bytes.iter().copied());
+            }
+        }
+        *nwritten_out = bytes.len();
+        FastlyStatus::OK
+    })
+}
+
+/// Close a body.
+///
+/// The contents of a closed body are kept, because a closed body may still be read by the host:
+/// for example, a streaming response body sent to the client, or an object inserted into the cache.
+pub unsafe extern "C" fn close(body_handle: BodyHandle) -> FastlyStatus {
+    try_with_host(|host| {
+        if host.is_stale_handle(body_handle) {
+            return FastlyStatus::OK;
+        }
+        match host.bodies.get_mut(&body_handle) {
+            Some(body) if !body.closed => {
+                body.closed = true;
+                FastlyStatus::OK
+            }
+            _ => FastlyStatus::BADF,
+        }
+    })
+    .unwrap_or(FastlyStatus::OK)
+}
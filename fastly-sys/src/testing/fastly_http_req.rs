@@ -0,0 +1,723 @@
+use std::net::IpAddr;
+
+use fastly_shared::{FastlyStatus, FramingHeadersMode};
+
+use super::{
+    arg_bytes, arg_str, dispatch, with_host, write_bytes, write_multi_value, BackendData, Host,
+    PendingData, PendingState, RequestData,
+};
+use crate::{
+    BackendConfigOptions, BodyHandle, ContentEncodings, DynamicBackendConfig, PendingRequestHandle,
+    RequestHandle, ResponseHandle,
+};
+
+/// Run a closure against a request, or fail with `BADF` if the handle is not a request.
+fn with_request(
+    req_handle: RequestHandle,
+    f: impl FnOnce(&mut RequestData) -> FastlyStatus,
+) -> FastlyStatus {
+    with_host(|host| match host.requests.get_mut(&req_handle) {
+        Some(req) => f(req),
+        None => FastlyStatus::BADF,
+    })
+}
+
+pub unsafe extern "C" fn body_downstream_get(
+    req_handle_out: *mut RequestHandle,
+    body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    with_host(|host| {
+        let (req, body) = match host.downstream.request {
+            Some(handles) => handles,
+            None => {
+                let handles = (
+                    host.new_request(RequestData::default()),
+                    host.new_body(vec![]),
+                );
+                host.downstream.request = Some(handles);
+                handles
+            }
+        };
+        if !req_handle_out.is_null() {
+            *req_handle_out = req;
+        }
+        if !body_handle_out.is_null() {
+            *body_handle_out = body;
+        }
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn cache_override_set(
+    req_handle: RequestHandle,
+    tag: u32,
+    ttl: u32,
+    swr: u32,
+) -> FastlyStatus {
+    with_request(req_handle, |req| {
+        req.cache_override = (tag, ttl, swr, vec![]);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn cache_override_v2_set(
+    req_handle: RequestHandle,
+    tag: u32,
+    ttl: u32,
+    swr: u32,
+    sk: *const u8,
+    sk_len: usize,
+) -> FastlyStatus {
+    let sk = arg_bytes(sk, sk_len);
+    with_request(req_handle, |req| {
+        req.cache_override = (tag, ttl, swr, sk.to_vec());
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn framing_headers_mode_set(
+    req_handle: RequestHandle,
+    mode: FramingHeadersMode,
+) -> FastlyStatus {
+    with_request(req_handle, |req| {
+        req.framing_headers_mode = mode;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn downstream_client_ip_addr(
+    addr_octets_out: *mut u8,
+    nwritten_out: *mut usize,
+) -> FastlyStatus {
+    let octets = match with_host(|host| host.downstream.client_ip_addr) {
+        IpAddr::V4(addr) => addr.octets().to_vec(),
+        IpAddr::V6(addr) => addr.octets().to_vec(),
+    };
+    write_bytes(&octets, addr_octets_out, 16, nwritten_out)
+}
+
+// The client connection to the in-process host is never secured with TLS, so the following
+// hostcalls fail in the same way as they do for a plaintext client connection.
+
+pub unsafe extern "C" fn downstream_client_h2_fingerprint(
+    _h2fp_out: *mut u8,
+    _h2fp_max_len: usize,
+    _nwritten: *mut usize,
+) -> FastlyStatus {
+    FastlyStatus::ERROR
+}
+
+pub unsafe extern "C" fn downstream_client_request_id(
+    _reqid_out: *mut u8,
+    _reqid_max_len: usize,
+    _nwritten: *mut usize,
+) -> FastlyStatus {
+    FastlyStatus::ERROR
+}
+
+pub unsafe extern "C" fn downstream_tls_cipher_openssl_name(
+    _cipher_out: *mut u8,
+    _cipher_max_len: usize,
+    _nwritten: *mut usize,
+) -> FastlyStatus {
+    FastlyStatus::ERROR
+}
+
+pub unsafe extern "C" fn downstream_tls_protocol(
+    _protocol_out: *mut u8,
+    _protocol_max_len: usize,
+    _nwritten: *mut usize,
+) -> FastlyStatus {
+    FastlyStatus::ERROR
+}
+
+pub unsafe extern "C" fn downstream_tls_client_hello(
+    _client_hello_out: *mut u8,
+    _client_hello_max_len: usize,
+    _nwritten: *mut usize,
+) -> FastlyStatus {
+    FastlyStatus::ERROR
+}
+
+pub unsafe extern "C" fn downstream_tls_ja3_md5(
+    _ja3_md5_out: *mut u8,
+    _nwritten_out: *mut usize,
+) -> FastlyStatus {
+    FastlyStatus::ERROR
+}
+
+pub unsafe extern "C" fn downstream_tls_raw_client_certificate(
+    _client_hello_out: *mut u8,
+    _client_hello_max_len: usize,
+    _nwritten: *mut usize,
+) -> FastlyStatus {
+    FastlyStatus::ERROR
+}
+
+pub unsafe extern "C" fn downstream_tls_client_cert_verify_result(
+    _verify_result_out: *mut u32,
+) -> FastlyStatus {
+    FastlyStatus::ERROR
+}
+
+pub unsafe extern "C" fn header_append(
+    req_handle: RequestHandle,
+    name: *const u8,
+    name_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> FastlyStatus {
+    let (name, value) = (arg_bytes(name, name_len), arg_bytes(value, value_len));
+    with_request(req_handle, |req| {
+        req.headers.append(name, value);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn header_insert(
+    req_handle: RequestHandle,
+    name: *const u8,
+    name_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> FastlyStatus {
+    let (name, value) = (arg_bytes(name, name_len), arg_bytes(value, value_len));
+    with_request(req_handle, |req| {
+        req.headers.insert(name, value);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn original_header_names_get(
+    buf: *mut u8,
+    buf_len: usize,
+    cursor: u32,
+    ending_cursor: *mut i64,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    with_host(|host| {
+        write_multi_value(
+            &host.downstream.original_header_names,
+            buf,
+            buf_len,
+            cursor,
+            ending_cursor,
+            nwritten,
+        )
+    })
+}
+
+pub unsafe extern "C" fn original_header_count(count_out: *mut u32) -> FastlyStatus {
+    *count_out = with_host(|host| host.downstream.original_header_names.len() as u32);
+    FastlyStatus::OK
+}
+
+pub unsafe extern "C" fn header_names_get(
+    req_handle: RequestHandle,
+    buf: *mut u8,
+    buf_len: usize,
+    cursor: u32,
+    ending_cursor: *mut i64,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    with_request(req_handle, |req| {
+        let names = req.headers.names();
+        write_multi_value(&names, buf, buf_len, cursor, ending_cursor, nwritten)
+    })
+}
+
+pub unsafe extern "C" fn header_values_get(
+    req_handle: RequestHandle,
+    name: *const u8,
+    name_len: usize,
+    buf: *mut u8,
+    buf_len: usize,
+    cursor: u32,
+    ending_cursor: *mut i64,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    let name = arg_bytes(name, name_len);
+    with_request(req_handle, |req| {
+        let values = req.headers.values(name);
+        write_multi_value(&values, buf, buf_len, cursor, ending_cursor, nwritten)
+    })
+}
+
+pub unsafe extern "C" fn header_values_set(
+    req_handle: RequestHandle,
+    name: *const u8,
+    name_len: usize,
+    values: *const u8,
+    values_len: usize,
+) -> FastlyStatus {
+    let (name, values) = (arg_bytes(name, name_len), arg_bytes(values, values_len));
+    with_request(req_handle, |req| {
+        req.headers.set_values(name, values);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn header_value_get(
+    req_handle: RequestHandle,
+    name: *const u8,
+    name_len: usize,
+    value: *mut u8,
+    value_max_len: usize,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    let name = arg_bytes(name, name_len);
+    with_request(req_handle, |req| match req.headers.get(name) {
+        Some(v) => write_bytes(v, value, value_max_len, nwritten),
+        None => FastlyStatus::INVAL,
+    })
+}
+
+pub unsafe extern "C" fn header_remove(
+    req_handle: RequestHandle,
+    name: *const u8,
+    name_len: usize,
+) -> FastlyStatus {
+    let name = arg_bytes(name, name_len);
+    with_request(req_handle, |req| {
+        if req.headers.remove(name) {
+            FastlyStatus::OK
+        } else {
+            FastlyStatus::INVAL
+        }
+    })
+}
+
+pub unsafe extern "C" fn method_get(
+    req_handle: RequestHandle,
+    method: *mut u8,
+    method_max_len: usize,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    with_request(req_handle, |req| {
+        write_bytes(&req.method, method, method_max_len, nwritten)
+    })
+}
+
+pub unsafe extern "C" fn method_set(
+    req_handle: RequestHandle,
+    method: *const u8,
+    method_len: usize,
+) -> FastlyStatus {
+    let method = arg_bytes(method, method_len);
+    if method.is_empty() {
+        return FastlyStatus::HTTPINVALID;
+    }
+    with_request(req_handle, |req| {
+        req.method = method.to_vec();
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn new(req_handle_out: *mut RequestHandle) -> FastlyStatus {
+    *req_handle_out = with_host(|host| host.new_request(RequestData::default()));
+    FastlyStatus::OK
+}
+
+pub unsafe extern "C" fn send(
+    req_handle: RequestHandle,
+    body_handle: BodyHandle,
+    backend: *const u8,
+    backend_len: usize,
+    resp_handle_out: *mut ResponseHandle,
+    resp_body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    let backend = match arg_str(backend, backend_len) {
+        Ok(backend) => backend,
+        Err(status) => return status,
+    };
+    let (result, latency_ns) = dispatch(req_handle, body_handle, backend);
+    with_host(|host| host.clock_ns = host.clock_ns.saturating_add(latency_ns));
+    match result {
+        Ok((resp, resp_body)) => {
+            *resp_handle_out = resp;
+            *resp_body_handle_out = resp_body;
+            FastlyStatus::OK
+        }
+        Err(status) => status,
+    }
+}
+
+pub unsafe extern "C" fn send_async(
+    req_handle: RequestHandle,
+    body_handle: BodyHandle,
+    backend: *const u8,
+    backend_len: usize,
+    pending_req_handle_out: *mut PendingRequestHandle,
+) -> FastlyStatus {
+    let backend = match arg_str(backend, backend_len) {
+        Ok(backend) => backend,
+        Err(status) => return status,
+    };
+    let (result, latency_ns) = dispatch(req_handle, body_handle, backend);
+    if let Err(FastlyStatus::BADF | FastlyStatus::INVAL) = result {
+        // Requests to backends that do not exist fail immediately, rather than when polled.
+        return result.unwrap_err();
+    }
+    *pending_req_handle_out = with_host(|host| {
+        let handle = host.new_handle();
+        let ready_at_ns = host.clock_ns.saturating_add(latency_ns);
+        host.pending.insert(
+            handle,
+            PendingData {
+                state: PendingState::Done(result),
+                ready_at_ns,
+            },
+        );
+        handle
+    });
+    FastlyStatus::OK
+}
+
+pub unsafe extern "C" fn send_async_streaming(
+    req_handle: RequestHandle,
+    body_handle: BodyHandle,
+    backend: *const u8,
+    backend_len: usize,
+    pending_req_handle_out: *mut PendingRequestHandle,
+) -> FastlyStatus {
+    let backend = match arg_str(backend, backend_len) {
+        Ok(backend) => backend,
+        Err(status) => return status,
+    };
+    with_host(|host| {
+        if !host.requests.contains_key(&req_handle) || !host.bodies.contains_key(&body_handle) {
+            return FastlyStatus::BADF;
+        }
+        if !host.backends.contains_key(backend) {
+            return FastlyStatus::INVAL;
+        }
+        let handle = host.new_handle();
+        let ready_at_ns = host.clock_ns;
+        host.pending.insert(
+            handle,
+            PendingData {
+                state: PendingState::Streaming {
+                    req: req_handle,
+                    body: body_handle,
+                    backend: backend.to_owned(),
+                },
+                ready_at_ns,
+            },
+        );
+        *pending_req_handle_out = handle;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn upgrade_websocket(
+    _backend: *const u8,
+    _backend_len: usize,
+) -> FastlyStatus {
+    FastlyStatus::UNSUPPORTED
+}
+
+pub unsafe extern "C" fn redirect_to_websocket_proxy(
+    _backend: *const u8,
+    _backend_len: usize,
+) -> FastlyStatus {
+    FastlyStatus::UNSUPPORTED
+}
+
+pub unsafe extern "C" fn redirect_to_grip_proxy(
+    _backend: *const u8,
+    _backend_len: usize,
+) -> FastlyStatus {
+    FastlyStatus::UNSUPPORTED
+}
+
+pub unsafe extern "C" fn register_dynamic_backend(
+    name_prefix: *const u8,
+    name_prefix_len: usize,
+    target: *const u8,
+    target_len: usize,
+    config_mask: BackendConfigOptions,
+    config: *const DynamicBackendConfig,
+) -> FastlyStatus {
+    let (name, target) = match (
+        arg_str(name_prefix, name_prefix_len),
+        arg_str(target, target_len),
+    ) {
+        (Ok(name), Ok(target)) if !name.is_empty() && !target.is_empty() => (name, target),
+        _ => return FastlyStatus::INVAL,
+    };
+    let config = &*config;
+    let use_ssl = config_mask.contains(BackendConfigOptions::USE_SSL);
+    let (host, port) = match target.rsplit_once(':') {
+        Some((host, port)) => match port.parse() {
+            Ok(port) => (host, port),
+            Err(_) => return FastlyStatus::INVAL,
+        },
+        None => (target, if use_ssl { 443 } else { 80 }),
+    };
+    let mut backend = BackendData {
+        host: host.to_owned(),
+        port,
+        use_ssl,
+        is_dynamic: true,
+        ..BackendData::for_name(name)
+    };
+    if config_mask.contains(BackendConfigOptions::HOST_OVERRIDE) {
+        backend.override_host =
+            Some(arg_bytes(config.host_override, config.host_override_len as usize).to_vec());
+    }
+    if config_mask.contains(BackendConfigOptions::CONNECT_TIMEOUT) {
+        backend.connect_timeout_ms = config.connect_timeout_ms;
+    }
+    if config_mask.contains(BackendConfigOptions::FIRST_BYTE_TIMEOUT) {
+        backend.first_byte_timeout_ms = config.first_byte_timeout_ms;
+    }
+    if config_mask.contains(BackendConfigOptions::BETWEEN_BYTES_TIMEOUT) {
+        backend.between_bytes_timeout_ms = config.between_bytes_timeout_ms;
+    }
+    if use_ssl {
+        if config_mask.contains(BackendConfigOptions::SSL_MIN_VERSION) {
+            backend.ssl_min_version = Some(config.ssl_min_version);
+        }
+        if config_mask.contains(BackendConfigOptions::SSL_MAX_VERSION) {
+            backend.ssl_max_version = Some(config.ssl_max_version);
+        }
+    }
+    with_host(|host| {
+        if host.backends.contains_key(name) {
+            return FastlyStatus::ERROR;
+        }
+        host.backends.insert(name.to_owned(), backend);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn uri_get(
+    req_handle: RequestHandle,
+    uri: *mut u8,
+    uri_max_len: usize,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    with_request(req_handle, |req| {
+        write_bytes(&req.uri, uri, uri_max_len, nwritten)
+    })
+}
+
+pub unsafe extern "C" fn uri_set(
+    req_handle: RequestHandle,
+    uri: *const u8,
+    uri_len: usize,
+) -> FastlyStatus {
+    let uri = arg_bytes(uri, uri_len);
+    with_request(req_handle, |req| {
+        req.uri = uri.to_vec();
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn version_get(req_handle: RequestHandle, version: *mut u32) -> FastlyStatus {
+    with_request(req_handle, |req| {
+        *version = req.version;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn version_set(req_handle: RequestHandle, version: u32) -> FastlyStatus {
+    with_request(req_handle, |req| {
+        req.version = version;
+        FastlyStatus::OK
+    })
+}
+
+/// Call the backend for a streaming request, once its body has been closed or if `force` is set.
+fn start_streaming(pending_req_handle: PendingRequestHandle, force: bool) -> FastlyStatus {
+    let started = with_host(|host| {
+        let (req, body, backend) = match host.pending.get(&pending_req_handle) {
+            Some(PendingData {
+                state: PendingState::Streaming { req, body, backend },
+                ..
+            }) => (*req, *body, backend.clone()),
+            Some(_) => return Ok(None),
+            None => return Err(FastlyStatus::BADF),
+        };
+        if !force && matches!(host.bodies.get(&body), Some(b) if !b.closed) {
+            return Ok(None);
+        }
+        // The guest keeps its handle to a streaming body, so the backend receives a copy.
+        let body = host
+            .reopen_body(body)
+            .unwrap_or_else(|| host.new_body(vec![]));
+        Ok(Some((req, body, backend)))
+    });
+    let (req, body, backend) = match started {
+        Ok(Some(started)) => started,
+        Ok(None) => return FastlyStatus::OK,
+        Err(status) => return status,
+    };
+    let (result, latency_ns) = dispatch(req, body, &backend);
+    with_host(|host| {
+        if let Some(pending) = host.pending.get_mut(&pending_req_handle) {
+            pending.state = PendingState::Done(result);
+            pending.ready_at_ns = host.clock_ns.saturating_add(latency_ns);
+        }
+    });
+    FastlyStatus::OK
+}
+
+/// Returns `true` if a pending request can complete without advancing the clock.
+pub(crate) fn pending_is_ready(host: &Host, pending: &PendingData) -> bool {
+    match &pending.state {
+        PendingState::Streaming { body, .. } => {
+            !matches!(host.bodies.get(body), Some(b) if !b.closed)
+        }
+        PendingState::Done(_) => pending.ready_at_ns <= host.clock_ns,
+    }
+}
+
+/// Remove a completed pending request, advancing the clock to the time at which it completed.
+fn take_pending(
+    host: &mut Host,
+    pending_req_handle: PendingRequestHandle,
+) -> Result<(ResponseHandle, BodyHandle), FastlyStatus> {
+    match host.pending.remove(&pending_req_handle) {
+        Some(PendingData {
+            state: PendingState::Done(result),
+            ready_at_ns,
+        }) => {
+            host.clock_ns = host.clock_ns.max(ready_at_ns);
+            result
+        }
+        _ => Err(FastlyStatus::BADF),
+    }
+}
+
+pub unsafe extern "C" fn pending_req_poll(
+    pending_req_handle: PendingRequestHandle,
+    is_done_out: *mut i32,
+    resp_handle_out: *mut ResponseHandle,
+    resp_body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    let status = start_streaming(pending_req_handle, false);
+    if status.is_err() {
+        return status;
+    }
+    with_host(|host| {
+        let ready = matches!(
+            host.pending.get(&pending_req_handle),
+            Some(pending @ PendingData { state: PendingState::Done(_), .. })
+                if pending_is_ready(host, pending)
+        );
+        if !ready {
+            *is_done_out = 0;
+            return FastlyStatus::OK;
+        }
+        *is_done_out = 1;
+        match take_pending(host, pending_req_handle) {
+            Ok((resp, body)) => {
+                *resp_handle_out = resp;
+                *resp_body_handle_out = body;
+                FastlyStatus::OK
+            }
+            Err(status) => status,
+        }
+    })
+}
+
+pub unsafe extern "C" fn pending_req_select(
+    pending_req_handles: *const PendingRequestHandle,
+    pending_req_handles_len: usize,
+    done_index_out: *mut i32,
+    resp_handle_out: *mut ResponseHandle,
+    resp_body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    if pending_req_handles.is_null() || pending_req_handles_len == 0 {
+        return FastlyStatus::INVAL;
+    }
+    let handles = std::slice::from_raw_parts(pending_req_handles, pending_req_handles_len);
+    for handle in handles {
+        let status = start_streaming(*handle, false);
+        if status.is_err() {
+            return status;
+        }
+    }
+    // Complete the request that becomes ready soonest. If every request is a streaming request
+    // whose body is still open, nothing can complete, so start the first one regardless.
+    let next = with_host(|host| {
+        handles
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, handle)| match host.pending.get(handle) {
+                Some(PendingData {
+                    state: PendingState::Done(_),
+                    ready_at_ns,
+                }) => Some((*ready_at_ns, ix)),
+                _ => None,
+            })
+            .min()
+            .map(|(_, ix)| ix)
+    });
+    let done_index = match next {
+        Some(ix) => ix,
+        None => {
+            let status = start_streaming(handles[0], true);
+            if status.is_err() {
+                return status;
+            }
+            0
+        }
+    };
+    with_host(|host| {
+        *done_index_out = done_index as i32;
+        let (resp, body) = take_pending(host, handles[done_index]).unwrap_or((
+            fastly_shared::INVALID_RESPONSE_HANDLE,
+            fastly_shared::INVALID_BODY_HANDLE,
+        ));
+        *resp_handle_out = resp;
+        *resp_body_handle_out = body;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn pending_req_wait(
+    pending_req_handle: PendingRequestHandle,
+    resp_handle_out: *mut ResponseHandle,
+    resp_body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    let status = start_streaming(pending_req_handle, true);
+    if status.is_err() {
+        return status;
+    }
+    with_host(|host| match take_pending(host, pending_req_handle) {
+        Ok((resp, body)) => {
+            *resp_handle_out = resp;
+            *resp_body_handle_out = body;
+            FastlyStatus::OK
+        }
+        Err(status) => status,
+    })
+}
+
+pub unsafe extern "C" fn fastly_key_is_valid(is_valid_out: *mut u32) -> FastlyStatus {
+    *is_valid_out = with_host(|host| host.downstream.fastly_key_is_valid) as u32;
+    FastlyStatus::OK
+}
+
+pub unsafe extern "C" fn close(req_handle: RequestHandle) -> FastlyStatus {
+    super::try_with_host(|host| {
+        if host.is_stale_handle(req_handle) || host.requests.remove(&req_handle).is_some() {
+            FastlyStatus::OK
+        } else {
+            FastlyStatus::BADF
+        }
+    })
+    .unwrap_or(FastlyStatus::OK)
+}
+
+pub unsafe extern "C" fn auto_decompress_response_set(
+    req_handle: RequestHandle,
+    encodings: ContentEncodings,
+) -> FastlyStatus {
+    with_request(req_handle, |req| {
+        req.auto_decompress_encodings = encodings;
+        FastlyStatus::OK
+    })
+}
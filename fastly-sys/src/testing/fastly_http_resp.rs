@@ -0,0 +1,215 @@
+use fastly_shared::{FastlyStatus, FramingHeadersMode, HttpKeepaliveMode};
+
+use super::{arg_bytes, try_with_host, with_host, write_bytes, write_multi_value, ResponseData};
+use crate::{BodyHandle, ResponseHandle};
+
+/// Run a closure against a response, or fail with `BADF` if the handle is not a response.
+fn with_response(
+    resp_handle: ResponseHandle,
+    f: impl FnOnce(&mut ResponseData) -> FastlyStatus,
+) -> FastlyStatus {
+    with_host(|host| match host.responses.get_mut(&resp_handle) {
+        Some(resp) => f(resp),
+        None => FastlyStatus::BADF,
+    })
+}
+
+pub unsafe extern "C" fn header_append(
+    resp_handle: ResponseHandle,
+    name: *const u8,
+    name_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> FastlyStatus {
+    let (name, value) = (arg_bytes(name, name_len), arg_bytes(value, value_len));
+    with_response(resp_handle, |resp| {
+        resp.headers.append(name, value);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn header_insert(
+    resp_handle: ResponseHandle,
+    name: *const u8,
+    name_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> FastlyStatus {
+    let (name, value) = (arg_bytes(name, name_len), arg_bytes(value, value_len));
+    with_response(resp_handle, |resp| {
+        resp.headers.insert(name, value);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn header_names_get(
+    resp_handle: ResponseHandle,
+    buf: *mut u8,
+    buf_len: usize,
+    cursor: u32,
+    ending_cursor: *mut i64,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    with_response(resp_handle, |resp| {
+        let names = resp.headers.names();
+        write_multi_value(&names, buf, buf_len, cursor, ending_cursor, nwritten)
+    })
+}
+
+pub unsafe extern "C" fn header_value_get(
+    resp_handle: ResponseHandle,
+    name: *const u8,
+    name_len: usize,
+    value: *mut u8,
+    value_max_len: usize,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    let name = arg_bytes(name, name_len);
+    with_response(resp_handle, |resp| match resp.headers.get(name) {
+        Some(v) => write_bytes(v, value, value_max_len, nwritten),
+        None => FastlyStatus::INVAL,
+    })
+}
+
+pub unsafe extern "C" fn header_values_get(
+    resp_handle: ResponseHandle,
+    name: *const u8,
+    name_len: usize,
+    buf: *mut u8,
+    buf_len: usize,
+    cursor: u32,
+    ending_cursor: *mut i64,
+    nwritten: *mut usize,
+) -> FastlyStatus {
+    let name = arg_bytes(name, name_len);
+    with_response(resp_handle, |resp| {
+        let values = resp.headers.values(name);
+        write_multi_value(&values, buf, buf_len, cursor, ending_cursor, nwritten)
+    })
+}
+
+pub unsafe extern "C" fn header_values_set(
+    resp_handle: ResponseHandle,
+    name: *const u8,
+    name_len: usize,
+    values: *const u8,
+    values_len: usize,
+) -> FastlyStatus {
+    let (name, values) = (arg_bytes(name, name_len), arg_bytes(values, values_len));
+    with_response(resp_handle, |resp| {
+        resp.headers.set_values(name, values);
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn header_remove(
+    resp_handle: ResponseHandle,
+    name: *const u8,
+    name_len: usize,
+) -> FastlyStatus {
+    let name = arg_bytes(name, name_len);
+    with_response(resp_handle, |resp| {
+        if resp.headers.remove(name) {
+            FastlyStatus::OK
+        } else {
+            FastlyStatus::INVAL
+        }
+    })
+}
+
+pub unsafe extern "C" fn new(resp_handle_out: *mut ResponseHandle) -> FastlyStatus {
+    *resp_handle_out = with_host(|host| host.new_response(ResponseData::default()));
+    FastlyStatus::OK
+}
+
+pub unsafe extern "C" fn send_downstream(
+    resp_handle: ResponseHandle,
+    body_handle: BodyHandle,
+    _streaming: u32,
+) -> FastlyStatus {
+    with_host(|host| {
+        if !host.responses.contains_key(&resp_handle) || !host.bodies.contains_key(&body_handle) {
+            return FastlyStatus::BADF;
+        }
+        if host.downstream.response.is_some() {
+            return FastlyStatus::ERROR;
+        }
+        // Keep the body handle rather than its contents, so that writes to a streaming body are
+        // part of the response that a test sees.
+        host.downstream.response = Some((resp_handle, body_handle));
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn status_get(resp_handle: ResponseHandle, status: *mut u16) -> FastlyStatus {
+    with_response(resp_handle, |resp| {
+        *status = resp.status;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn status_set(resp_handle: ResponseHandle, status: u16) -> FastlyStatus {
+    if !(100..=999).contains(&status) {
+        return FastlyStatus::HTTPINVALIDSTATUS;
+    }
+    with_response(resp_handle, |resp| {
+        resp.status = status;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn version_get(
+    resp_handle: ResponseHandle,
+    version: *mut u32,
+) -> FastlyStatus {
+    with_response(resp_handle, |resp| {
+        *version = resp.version;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn version_set(resp_handle: ResponseHandle, version: u32) -> FastlyStatus {
+    with_response(resp_handle, |resp| {
+        resp.version = version;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn framing_headers_mode_set(
+    resp_handle: ResponseHandle,
+    mode: FramingHeadersMode,
+) -> FastlyStatus {
+    with_response(resp_handle, |resp| {
+        resp.framing_headers_mode = mode;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn http_keepalive_mode_set(
+    resp_handle: ResponseHandle,
+    mode: HttpKeepaliveMode,
+) -> FastlyStatus {
+    with_response(resp_handle, |resp| {
+        resp.http_keepalive_mode = mode;
+        FastlyStatus::OK
+    })
+}
+
+/// Close a response.
+///
+/// A response sent downstream stays in the host until a test takes it.
+pub unsafe extern "C" fn close(resp_handle: ResponseHandle) -> FastlyStatus {
+    try_with_host(|host| {
+        if host.is_stale_handle(resp_handle) {
+            return FastlyStatus::OK;
+        }
+        if !host.responses.contains_key(&resp_handle) {
+            return FastlyStatus::BADF;
+        }
+        if host.downstream.response.map(|(resp, _)| resp) != Some(resp_handle) {
+            host.responses.remove(&resp_handle);
+        }
+        FastlyStatus::OK
+    })
+    .unwrap_or(FastlyStatus::OK)
+}
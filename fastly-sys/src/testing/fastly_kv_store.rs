@@ -0,0 +1,484 @@
+use fastly_shared::{FastlyStatus, INVALID_BODY_HANDLE};
+
+use super::{arg_bytes, arg_str, with_host, write_bytes, Host};
+use crate::fastly_kv_store::{
+    DeleteConfig, DeleteConfigOptions, InsertConfig, InsertConfigOptions, InsertMode, KvError,
+    ListConfig, ListConfigOptions, LookupConfig, LookupConfigOptions,
+};
+use crate::{
+    BodyHandle, KVStoreDeleteHandle, KVStoreHandle, KVStoreInsertHandle, KVStoreListHandle,
+    KVStoreLookupHandle,
+};
+
+/// The number of keys listed per page when no limit is given.
+const DEFAULT_LIST_LIMIT: u32 = 100;
+/// The most keys that can be listed per page.
+const MAX_LIST_LIMIT: u32 = 1000;
+
+/// An item in a KV store.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct KvEntry {
+    pub(crate) value: Vec<u8>,
+    pub(crate) metadata: Vec<u8>,
+    pub(crate) generation: u32,
+    /// The host clock reading at which the item expires, if it was inserted with a time to live.
+    pub(crate) expires_at_ns: Option<u64>,
+}
+
+impl KvEntry {
+    pub(crate) fn new(value: Vec<u8>) -> Self {
+        KvEntry {
+            value,
+            ..KvEntry::default()
+        }
+    }
+
+    pub(crate) fn is_live(&self, now_ns: u64) -> bool {
+        !matches!(self.expires_at_ns, Some(at) if at <= now_ns)
+    }
+}
+
+pub unsafe extern "C" fn open(
+    name_ptr: *const u8,
+    name_len: usize,
+    kv_store_handle_out: *mut KVStoreHandle,
+) -> FastlyStatus {
+    let name = match arg_str(name_ptr, name_len) {
+        Ok(name) => name,
+        Err(status) => return status,
+    };
+    with_host(|host| {
+        if !host.kv_stores.contains_key(name) {
+            return FastlyStatus::INVAL;
+        }
+        let handle = host.new_handle();
+        host.kv_store_handles.insert(handle, name.to_owned());
+        *kv_store_handle_out = handle;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn open_v2(
+    name_ptr: *const u8,
+    name_len: usize,
+    kv_store_handle_out: *mut KVStoreHandle,
+) -> FastlyStatus {
+    open(name_ptr, name_len, kv_store_handle_out)
+}
+
+/// A KV store operation that has been started, but not yet waited on.
+///
+/// Operations on the in-process stores complete when they are started, so these hold their results
+/// until the guest waits on them.
+pub(crate) enum KvPending {
+    Lookup(Option<KvEntry>),
+    Insert(KvError),
+    Delete(KvError),
+    List(Result<Vec<u8>, KvError>),
+}
+
+/// Register the result of an operation, returning the handle to wait on it with.
+fn start(host: &mut Host, pending: KvPending) -> u32 {
+    let handle = host.new_handle();
+    host.kv_pending.insert(handle, pending);
+    handle
+}
+
+/// Find the name of the store behind a handle, if the store exists.
+fn store_name(host: &Host, kv_store_handle: KVStoreHandle) -> Option<String> {
+    match host.kv_store_handles.get(&kv_store_handle) {
+        Some(name) if host.kv_stores.contains_key(name) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Find the live item for a key, if any.
+fn live_entry<'a>(host: &'a Host, name: &str, key: &str) -> Option<&'a KvEntry> {
+    host.kv_stores[name]
+        .get(key)
+        .filter(|entry| entry.is_live(host.clock_ns))
+}
+
+pub unsafe extern "C" fn lookup(
+    kv_store_handle: KVStoreHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    body_handle_out: *mut BodyHandle,
+) -> FastlyStatus {
+    let key = match arg_str(key_ptr, key_len) {
+        Ok(key) if !key.is_empty() => key,
+        _ => return FastlyStatus::INVAL,
+    };
+    with_host(|host| {
+        let Some(name) = store_name(host, kv_store_handle) else {
+            return FastlyStatus::BADF;
+        };
+        let value = live_entry(host, &name, key).map(|entry| entry.value.clone());
+        *body_handle_out = match value {
+            Some(value) => host.new_body(value),
+            None => INVALID_BODY_HANDLE,
+        };
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn insert(
+    kv_store_handle: KVStoreHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    body_handle: BodyHandle,
+) -> FastlyStatus {
+    let args = match insert_args(
+        key_ptr,
+        key_len,
+        InsertConfigOptions::empty(),
+        std::ptr::null(),
+    ) {
+        Ok(args) => args,
+        Err(status) => return status,
+    };
+    with_host(
+        |host| match perform_insert(host, kv_store_handle, body_handle, args) {
+            Ok(_) => FastlyStatus::OK,
+            Err(status) => status,
+        },
+    )
+}
+
+pub unsafe extern "C" fn lookup_v2(
+    kv_store_handle: KVStoreHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    _lookup_config_mask: LookupConfigOptions,
+    _lookup_config: *const LookupConfig,
+    lookup_handle_out: *mut KVStoreLookupHandle,
+) -> FastlyStatus {
+    let key = match arg_str(key_ptr, key_len) {
+        Ok(key) if !key.is_empty() => key,
+        _ => return FastlyStatus::INVAL,
+    };
+    with_host(|host| {
+        let Some(name) = store_name(host, kv_store_handle) else {
+            return FastlyStatus::BADF;
+        };
+        let entry = live_entry(host, &name, key).cloned();
+        *lookup_handle_out = start(host, KvPending::Lookup(entry));
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn pending_lookup_wait_v2(
+    lookup_handle: KVStoreLookupHandle,
+    body_handle_out: *mut BodyHandle,
+    metadata_buf: *mut u8,
+    metadata_buf_len: usize,
+    nwritten_out: *mut usize,
+    generation_out: *mut u32,
+    kv_error_out: *mut KvError,
+) -> FastlyStatus {
+    with_host(|host| {
+        let entry = match host.kv_pending.get(&lookup_handle) {
+            Some(KvPending::Lookup(entry)) => entry.clone(),
+            _ => return FastlyStatus::BADF,
+        };
+        *body_handle_out = INVALID_BODY_HANDLE;
+        *kv_error_out = match entry {
+            Some(entry) => {
+                // Leave the lookup pending if the metadata does not fit, so it can be retried.
+                let status = write_bytes(
+                    &entry.metadata,
+                    metadata_buf,
+                    metadata_buf_len,
+                    nwritten_out,
+                );
+                if status != FastlyStatus::OK {
+                    return status;
+                }
+                *generation_out = entry.generation;
+                *body_handle_out = host.new_body(entry.value);
+                KvError::Ok
+            }
+            None => KvError::NotFound,
+        };
+        host.kv_pending.remove(&lookup_handle);
+        FastlyStatus::OK
+    })
+}
+
+/// The arguments of an insert, read from guest memory.
+struct InsertArgs<'a> {
+    key: &'a str,
+    mode: InsertMode,
+    metadata: Vec<u8>,
+    time_to_live_sec: Option<u32>,
+    if_generation_match: Option<u64>,
+}
+
+unsafe fn insert_args<'a>(
+    key_ptr: *const u8,
+    key_len: usize,
+    insert_config_mask: InsertConfigOptions,
+    insert_config: *const InsertConfig,
+) -> Result<InsertArgs<'a>, FastlyStatus> {
+    let key = match arg_str(key_ptr, key_len) {
+        Ok(key) if !key.is_empty() => key,
+        _ => return Err(FastlyStatus::INVAL),
+    };
+    let default_config = InsertConfig::default();
+    let config = if insert_config.is_null() {
+        &default_config
+    } else {
+        &*insert_config
+    };
+    let metadata = if insert_config_mask.contains(InsertConfigOptions::METADATA) {
+        arg_bytes(config.metadata, config.metadata_len as usize).to_vec()
+    } else {
+        vec![]
+    };
+    Ok(InsertArgs {
+        key,
+        mode: config.mode,
+        metadata,
+        time_to_live_sec: insert_config_mask
+            .contains(InsertConfigOptions::TIME_TO_LIVE_SEC)
+            .then_some(config.time_to_live_sec),
+        if_generation_match: insert_config_mask
+            .contains(InsertConfigOptions::IF_GENERATION_MATCH)
+            .then_some(config.if_generation_match),
+    })
+}
+
+/// Insert a body into a store, returning the outcome of the insert.
+fn perform_insert(
+    host: &mut Host,
+    kv_store_handle: KVStoreHandle,
+    body_handle: BodyHandle,
+    args: InsertArgs<'_>,
+) -> Result<KvError, FastlyStatus> {
+    let name = store_name(host, kv_store_handle).ok_or(FastlyStatus::BADF)?;
+    let mut body = host.bodies.remove(&body_handle).ok_or(FastlyStatus::BADF)?;
+    let value = body.take_unread();
+    let existing = live_entry(host, &name, args.key);
+    let precondition_failed = match args.mode {
+        InsertMode::Add => existing.is_some(),
+        _ => false,
+    } || (args.if_generation_match.is_some()
+        && existing.map(|entry| u64::from(entry.generation)) != args.if_generation_match);
+    if precondition_failed {
+        return Ok(KvError::PreconditionFailed);
+    }
+    let value = match (args.mode, existing) {
+        (InsertMode::Append, Some(existing)) => [&existing.value[..], &value].concat(),
+        (InsertMode::Prepend, Some(existing)) => [&value[..], &existing.value].concat(),
+        _ => value,
+    };
+    let expires_at_ns = args
+        .time_to_live_sec
+        .map(|ttl| host.clock_ns + u64::from(ttl) * 1_000_000_000);
+    // Generations only need to be unique, so draw them from the handle sequence.
+    let generation = host.new_handle();
+    host.kv_stores
+        .get_mut(&name)
+        .expect("checked above")
+        .insert(
+            args.key.to_owned(),
+            KvEntry {
+                value,
+                metadata: args.metadata,
+                generation,
+                expires_at_ns,
+            },
+        );
+    Ok(KvError::Ok)
+}
+
+pub unsafe extern "C" fn insert_v2(
+    kv_store_handle: KVStoreHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    body_handle: BodyHandle,
+    insert_config_mask: InsertConfigOptions,
+    insert_config: *const InsertConfig,
+    insert_handle_out: *mut KVStoreInsertHandle,
+) -> FastlyStatus {
+    let args = match insert_args(key_ptr, key_len, insert_config_mask, insert_config) {
+        Ok(args) => args,
+        Err(status) => return status,
+    };
+    with_host(
+        |host| match perform_insert(host, kv_store_handle, body_handle, args) {
+            Ok(kv_error) => {
+                *insert_handle_out = start(host, KvPending::Insert(kv_error));
+                FastlyStatus::OK
+            }
+            Err(status) => status,
+        },
+    )
+}
+
+pub unsafe extern "C" fn pending_insert_wait_v2(
+    insert_handle: KVStoreInsertHandle,
+    kv_error_out: *mut KvError,
+) -> FastlyStatus {
+    with_host(|host| match host.kv_pending.get(&insert_handle) {
+        Some(KvPending::Insert(kv_error)) => {
+            *kv_error_out = *kv_error;
+            host.kv_pending.remove(&insert_handle);
+            FastlyStatus::OK
+        }
+        _ => FastlyStatus::BADF,
+    })
+}
+
+pub unsafe extern "C" fn delete_v2(
+    kv_store_handle: KVStoreHandle,
+    key_ptr: *const u8,
+    key_len: usize,
+    _delete_config_mask: DeleteConfigOptions,
+    _delete_config: *const DeleteConfig,
+    delete_handle_out: *mut KVStoreDeleteHandle,
+) -> FastlyStatus {
+    let key = match arg_str(key_ptr, key_len) {
+        Ok(key) if !key.is_empty() => key,
+        _ => return FastlyStatus::INVAL,
+    };
+    with_host(|host| {
+        let Some(name) = store_name(host, kv_store_handle) else {
+            return FastlyStatus::BADF;
+        };
+        let now_ns = host.clock_ns;
+        let removed = host
+            .kv_stores
+            .get_mut(&name)
+            .and_then(|entries| entries.remove(key));
+        let kv_error = match removed {
+            Some(entry) if entry.is_live(now_ns) => KvError::Ok,
+            _ => KvError::NotFound,
+        };
+        *delete_handle_out = start(host, KvPending::Delete(kv_error));
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn pending_delete_wait_v2(
+    delete_handle: KVStoreDeleteHandle,
+    kv_error_out: *mut KvError,
+) -> FastlyStatus {
+    with_host(|host| match host.kv_pending.get(&delete_handle) {
+        Some(KvPending::Delete(kv_error)) => {
+            *kv_error_out = *kv_error;
+            host.kv_pending.remove(&delete_handle);
+            FastlyStatus::OK
+        }
+        _ => FastlyStatus::BADF,
+    })
+}
+
+pub unsafe extern "C" fn list_v2(
+    kv_store_handle: KVStoreHandle,
+    list_config_mask: ListConfigOptions,
+    list_config: *const ListConfig,
+    list_handle_out: *mut KVStoreListHandle,
+) -> FastlyStatus {
+    let default_config = ListConfig::default();
+    let config = if list_config.is_null() {
+        &default_config
+    } else {
+        &*list_config
+    };
+    let arg = |mask, ptr, len: u32| {
+        if list_config_mask.contains(mask) {
+            arg_str(ptr, len as usize).map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    let (cursor, prefix) = match (
+        arg(ListConfigOptions::CURSOR, config.cursor, config.cursor_len),
+        arg(ListConfigOptions::PREFIX, config.prefix, config.prefix_len),
+    ) {
+        (Ok(cursor), Ok(prefix)) => (cursor, prefix.unwrap_or_default()),
+        _ => return FastlyStatus::INVAL,
+    };
+    let limit = if list_config_mask.contains(ListConfigOptions::LIMIT) {
+        config.limit
+    } else {
+        DEFAULT_LIST_LIMIT
+    };
+    with_host(|host| {
+        let Some(name) = store_name(host, kv_store_handle) else {
+            return FastlyStatus::BADF;
+        };
+        if limit == 0 || limit > MAX_LIST_LIMIT {
+            *list_handle_out = start(host, KvPending::List(Err(KvError::BadRequest)));
+            return FastlyStatus::OK;
+        }
+        // The cursor is the last key of the previous page.
+        let mut keys = host.kv_stores[&name]
+            .iter()
+            .filter(|(key, entry)| {
+                key.starts_with(prefix)
+                    && !matches!(cursor, Some(cursor) if key.as_str() <= cursor)
+                    && entry.is_live(host.clock_ns)
+            })
+            .map(|(key, _)| key.as_str());
+        let page: Vec<_> = keys.by_ref().take(limit as usize).collect();
+        let next_cursor = match keys.next() {
+            Some(_) => page.last().copied(),
+            None => None,
+        };
+        let mut json = String::from("{\"data\":[");
+        for (i, key) in page.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            push_json_str(&mut json, key);
+        }
+        json.push_str(&format!("],\"meta\":{{\"limit\":{limit},\"prefix\":"));
+        push_json_str(&mut json, prefix);
+        if let Some(next_cursor) = next_cursor {
+            json.push_str(",\"next_cursor\":");
+            push_json_str(&mut json, next_cursor);
+        }
+        json.push_str("}}");
+        *list_handle_out = start(host, KvPending::List(Ok(json.into_bytes())));
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn pending_list_wait_v2(
+    list_handle: KVStoreListHandle,
+    body_handle_out: *mut BodyHandle,
+    kv_error_out: *mut KvError,
+) -> FastlyStatus {
+    with_host(|host| {
+        let result = match host.kv_pending.get(&list_handle) {
+            Some(KvPending::List(result)) => result.clone(),
+            _ => return FastlyStatus::BADF,
+        };
+        host.kv_pending.remove(&list_handle);
+        *body_handle_out = INVALID_BODY_HANDLE;
+        *kv_error_out = match result {
+            Ok(page) => {
+                *body_handle_out = host.new_body(page);
+                KvError::Ok
+            }
+            Err(kv_error) => kv_error,
+        };
+        FastlyStatus::OK
+    })
+}
+
+/// Append a string to a JSON document as a string literal.
+fn push_json_str(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if u32::from(c) < 0x20 => json.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
@@ -0,0 +1,38 @@
+use fastly_shared::FastlyStatus;
+
+use super::{arg_bytes, arg_str, with_host};
+
+pub unsafe extern "C" fn endpoint_get(
+    name: *const u8,
+    name_len: usize,
+    endpoint_handle_out: *mut u32,
+) -> FastlyStatus {
+    let name = match arg_str(name, name_len) {
+        Ok(name) if !name.is_empty() => name,
+        _ => return FastlyStatus::INVAL,
+    };
+    with_host(|host| {
+        let handle = host.new_handle();
+        host.log_endpoints.insert(handle, name.to_owned());
+        *endpoint_handle_out = handle;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn write(
+    endpoint_handle: u32,
+    msg: *const u8,
+    msg_len: usize,
+    nwritten_out: *mut usize,
+) -> FastlyStatus {
+    let msg = arg_bytes(msg, msg_len);
+    with_host(|host| {
+        let name = match host.log_endpoints.get(&endpoint_handle) {
+            Some(name) => name.clone(),
+            None => return FastlyStatus::BADF,
+        };
+        host.logs.entry(name).or_default().push(msg.to_vec());
+        *nwritten_out = msg.len();
+        FastlyStatus::OK
+    })
+}
@@ -0,0 +1,31 @@
+use fastly_shared::FastlyStatus;
+
+use super::{arg_str, with_host, write_bytes};
+use crate::fastly_purge::{PurgeOptions, PurgeOptionsMask};
+
+pub unsafe extern "C" fn purge_surrogate_key(
+    surrogate_key_ptr: *const u8,
+    surrogate_key_len: usize,
+    options_mask: PurgeOptionsMask,
+    options: *mut PurgeOptions,
+) -> FastlyStatus {
+    let key = match arg_str(surrogate_key_ptr, surrogate_key_len) {
+        Ok(key) if !key.is_empty() => key,
+        _ => return FastlyStatus::INVAL,
+    };
+    let soft = options_mask.contains(PurgeOptionsMask::SOFT_PURGE);
+    with_host(|host| {
+        let now = host.clock_ns;
+        host.cache.purge(key, soft, now);
+    });
+    if options_mask.contains(PurgeOptionsMask::RET_BUF) {
+        let options = &*options;
+        return write_bytes(
+            b"{}",
+            options.ret_buf_ptr,
+            options.ret_buf_len,
+            options.ret_buf_nwritten_out,
+        );
+    }
+    FastlyStatus::OK
+}
@@ -0,0 +1,79 @@
+use fastly_shared::FastlyStatus;
+
+use super::{arg_bytes, arg_str, with_host, write_bytes};
+use crate::{SecretHandle, SecretStoreHandle};
+
+pub unsafe extern "C" fn open(
+    secret_store_name_ptr: *const u8,
+    secret_store_name_len: usize,
+    secret_store_handle_out: *mut SecretStoreHandle,
+) -> FastlyStatus {
+    let name = match arg_str(secret_store_name_ptr, secret_store_name_len) {
+        Ok(name) if !name.is_empty() => name,
+        _ => return FastlyStatus::INVAL,
+    };
+    with_host(|host| {
+        if !host.secret_stores.contains_key(name) {
+            return FastlyStatus::NONE;
+        }
+        let handle = host.new_handle();
+        host.secret_store_handles.insert(handle, name.to_owned());
+        *secret_store_handle_out = handle;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn get(
+    secret_store_handle: SecretStoreHandle,
+    secret_name_ptr: *const u8,
+    secret_name_len: usize,
+    secret_handle_out: *mut SecretHandle,
+) -> FastlyStatus {
+    let name = match arg_str(secret_name_ptr, secret_name_len) {
+        Ok(name) if !name.is_empty() => name,
+        _ => return FastlyStatus::INVAL,
+    };
+    with_host(|host| {
+        let plaintext = match host
+            .secret_store_handles
+            .get(&secret_store_handle)
+            .and_then(|store| host.secret_stores.get(store))
+        {
+            Some(secrets) => match secrets.get(name) {
+                Some(plaintext) => plaintext.clone(),
+                None => return FastlyStatus::NONE,
+            },
+            None => return FastlyStatus::BADF,
+        };
+        let handle = host.new_handle();
+        host.secrets.insert(handle, plaintext);
+        *secret_handle_out = handle;
+        FastlyStatus::OK
+    })
+}
+
+pub unsafe extern "C" fn plaintext(
+    secret_handle: SecretHandle,
+    plaintext_buf: *mut u8,
+    plaintext_max_len: usize,
+    nwritten_out: *mut usize,
+) -> FastlyStatus {
+    with_host(|host| match host.secrets.get(&secret_handle) {
+        Some(plaintext) => write_bytes(plaintext, plaintext_buf, plaintext_max_len, nwritten_out),
+        None => FastlyStatus::BADF,
+    })
+}
+
+pub unsafe extern "C" fn from_bytes(
+    plaintext_buf: *const u8,
+    plaintext_len: usize,
+    secret_handle_out: *mut SecretHandle,
+) -> FastlyStatus {
+    let plaintext = arg_bytes(plaintext_buf, plaintext_len).to_vec();
+    with_host(|host| {
+        let handle = host.new_handle();
+        host.secrets.insert(handle, plaintext);
+        *secret_handle_out = handle;
+        FastlyStatus::OK
+    })
+}
@@ -0,0 +1,21 @@
+use fastly_shared::FastlyStatus;
+
+/// User agent parsing is not provided by the in-process host.
+pub unsafe extern "C" fn parse(
+    _user_agent: *const u8,
+    _user_agent_max_len: usize,
+    _family: *mut u8,
+    _family_max_len: usize,
+    _family_written: *mut usize,
+    _major: *mut u8,
+    _major_max_len: usize,
+    _major_written: *mut usize,
+    _minor: *mut u8,
+    _minor_max_len: usize,
+    _minor_written: *mut usize,
+    _patch: *mut u8,
+    _patch_max_len: usize,
+    _patch_written: *mut usize,
+) -> FastlyStatus {
+    FastlyStatus::UNSUPPORTED
+}
@@ -0,0 +1,206 @@
+//! Check that the hostcalls imported by this crate match the host's witx definitions.
+//!
+//! A mismatch is only caught by the host when it instantiates a program, so compare the signatures
+//! as the host sees them: lowered to core WebAssembly types.
+
+use std::collections::HashMap;
+
+/// The witx definitions to check the imports of this crate against.
+const WITX: &[&str] = &[include_str!("witx/kv_store.witx")];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ValType {
+    I32,
+    I64,
+}
+
+/// The parameter and return types of a hostcall.
+type Signature = (Vec<ValType>, Vec<ValType>);
+
+enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    fn atom(&self) -> Option<&str> {
+        match self {
+            Sexp::Atom(atom) => Some(atom),
+            Sexp::List(_) => None,
+        }
+    }
+
+    fn list(&self) -> Option<&[Sexp]> {
+        match self {
+            Sexp::Atom(_) => None,
+            Sexp::List(list) => Some(list),
+        }
+    }
+
+    /// Returns the elements of a list that starts with the given atom, after that atom.
+    fn tagged(&self, tag: &str) -> Option<&[Sexp]> {
+        match self.list()? {
+            [head, rest @ ..] if head.atom() == Some(tag) => Some(rest),
+            _ => None,
+        }
+    }
+}
+
+fn parse_witx(src: &str) -> Vec<Sexp> {
+    let mut stack = vec![Vec::new()];
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                chars.by_ref().find(|&c| c == '\n');
+            }
+            '(' => stack.push(Vec::new()),
+            ')' => {
+                let list = stack.pop().expect("unbalanced witx");
+                stack
+                    .last_mut()
+                    .expect("unbalanced witx")
+                    .push(Sexp::List(list));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut atom = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    atom.push(c);
+                    chars.next();
+                }
+                stack
+                    .last_mut()
+                    .expect("unbalanced witx")
+                    .push(Sexp::Atom(atom));
+            }
+        }
+    }
+    assert_eq!(stack.len(), 1, "unbalanced witx");
+    stack.pop().unwrap()
+}
+
+/// Lower a witx parameter type. Handles, enums, and flags are all 32 bits wide.
+fn lower_witx_param(ty: &Sexp, out: &mut Vec<ValType>) {
+    match ty.atom() {
+        Some("string") => out.extend([ValType::I32, ValType::I32]),
+        Some("u64" | "s64") => out.push(ValType::I64),
+        // Other atoms are 32-bit types; lists are pointers, `usize`, or `char8`.
+        _ => out.push(ValType::I32),
+    }
+}
+
+/// Collect the signatures of the functions in the witx modules, by module and function name.
+fn witx_signatures() -> HashMap<(String, String), Signature> {
+    let mut signatures = HashMap::new();
+    for module in WITX.iter().flat_map(|src| parse_witx(src)) {
+        let [name, funcs @ ..] = module.tagged("module").expect("expected a module") else {
+            panic!("module without a name");
+        };
+        let module_name = name.atom().unwrap().trim_start_matches('$');
+        for func in funcs {
+            let Some([func_tag, export, items @ ..]) = func.tagged("@interface") else {
+                continue;
+            };
+            assert_eq!(func_tag.atom(), Some("func"));
+            let func_name = export.tagged("export").unwrap()[0]
+                .atom()
+                .unwrap()
+                .trim_matches('"');
+            let mut params = Vec::new();
+            let mut results = Vec::new();
+            for item in items {
+                if let Some([_, ty]) = item.tagged("param") {
+                    lower_witx_param(ty, &mut params);
+                } else if let Some([_, expected]) = item.tagged("result") {
+                    // The `fastly_status` is returned, and any value is written through a pointer.
+                    let expected = expected.tagged("expected").unwrap();
+                    if expected.len() == 2 {
+                        params.push(ValType::I32);
+                    }
+                    results.push(ValType::I32);
+                }
+            }
+            signatures.insert(
+                (module_name.to_owned(), func_name.to_owned()),
+                (params, results),
+            );
+        }
+    }
+    signatures
+}
+
+/// Lower a Rust parameter type from an `extern` block of this crate.
+fn lower_rust_param(ty: &str) -> ValType {
+    match ty.trim() {
+        "u64" | "i64" => ValType::I64,
+        // Everything else is a pointer, a `usize`, or a 32-bit integer, handle, enum, or flags.
+        _ => ValType::I32,
+    }
+}
+
+/// Collect the signatures of the functions imported by this crate from the given modules, by module
+/// and `link_name`.
+fn rust_signatures(modules: &[String]) -> HashMap<(String, String), Signature> {
+    let src = include_str!("../src/lib.rs");
+    let mut signatures = HashMap::new();
+    for (start, _) in src.match_indices("#[link(wasm_import_module = \"") {
+        let rest = &src[start + "#[link(wasm_import_module = \"".len()..];
+        let module_name = &rest[..rest.find('"').unwrap()];
+        if !modules.iter().any(|m| m == module_name) {
+            continue;
+        }
+        // Take the body of the `extern` block, without comments.
+        let body = &rest[rest.find('{').unwrap() + 1..];
+        let body: String = body[..body.find("\n    }").unwrap()]
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("//"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        for item in body.split("#[link_name = \"").skip(1) {
+            let link_name = &item[..item.find('"').unwrap()];
+            let args = &item[item.find('(').unwrap() + 1..item.find(')').unwrap()];
+            let params = args
+                .split(',')
+                .filter(|arg| !arg.trim().is_empty())
+                .map(|arg| lower_rust_param(arg.split_once(':').unwrap().1))
+                .collect();
+            assert!(
+                item.contains("-> FastlyStatus;"),
+                "`{link_name}` should return a `FastlyStatus`"
+            );
+            signatures.insert(
+                (module_name.to_owned(), link_name.to_owned()),
+                (params, vec![ValType::I32]),
+            );
+        }
+    }
+    signatures
+}
+
+#[test]
+fn kv_store_imports_match_witx() {
+    let witx = witx_signatures();
+    let mut modules: Vec<_> = witx.keys().map(|(module, _)| module.clone()).collect();
+    modules.sort();
+    modules.dedup();
+    let rust = rust_signatures(&modules);
+    assert!(rust.keys().any(|(module, _)| module == "fastly_kv_store"));
+
+    let mut mismatches = Vec::new();
+    for (key @ (module, name), signature) in &rust {
+        match witx.get(key) {
+            Some(expected) if expected == signature => {}
+            Some(expected) => mismatches.push(format!(
+                "{module}::{name} takes {:?}, but the host expects {:?}",
+                signature.0, expected.0
+            )),
+            None => mismatches.push(format!("{module}::{name} is not provided by the host")),
+        }
+    }
+    mismatches.sort();
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
@@ -0,0 +1,465 @@
+//! Asynchronous operations, and an executor to run them.
+//!
+//! Several Compute@Edge operations complete in the background while the program continues to run:
+//!
+//! - A [`PendingRequest`][crate::http::request::PendingRequest] is a [`Future`] that resolves to
+//!   the response from the backend.
+//!
+//! - [`TransactionLookupBuilder::execute_async()`][crate::cache::core::TransactionLookupBuilder::execute_async()]
+//!   returns a [`Future`] that resolves once a transactional cache lookup has completed.
+//!
+//! - [`Body::read_async()`][crate::Body::read_async()] returns a [`Future`] that resolves once data
+//!   from a body, such as a response body still arriving from a backend, can be read.
+//!
+//! - A [`PendingLookup`][crate::kv_store::PendingLookup] or
+//!   [`PendingInsert`][crate::kv_store::PendingInsert] is a [`Future`] that resolves to the result
+//!   of a KV Store operation.
+//!
+//! These futures can be composed with `async` blocks, [`join()`], and [`join_all()`], and then run
+//! with [`block_on()`]. While the futures are waiting, the executor sleeps in the host until one of
+//! the operations they are waiting on is ready.
+//!
+//! # Examples
+//!
+//! Fetching from two backends concurrently:
+//!
+//! ```no_run
+//! use fastly::async_io::{block_on, join};
+//! use fastly::{Error, Request};
+//! # fn f() -> Result<(), Error> {
+//!
+//! let (users, products) = block_on(async {
+//!     let users = Request::get("https://example.com/users").send_async("users")?;
+//!     let products = Request::get("https://example.com/products").send_async("products")?;
+//!     Ok::<_, Error>(join(users, products).await)
+//! })?;
+//! let (mut users, mut products) = (users?, products?);
+//! println!("{} {}", users.take_body_str(), products.take_body_str());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The futures in this crate can only be driven by [`block_on()`]; they do not work with other
+//! executors.
+//!
+//! # Selecting across handles
+//!
+//! Programs that work with the low-level [`handle`][crate::handle] interfaces can instead wait on
+//! the operations directly with [`select()`], which accepts the [`AsyncHandle`] of any
+//! [`AsyncItem`]: a [`BodyHandle`][crate::handle::BodyHandle] whose data is still arriving, a
+//! [`PendingRequestHandle`][crate::handle::PendingRequestHandle], a cache lookup, or a KV Store
+//! operation.
+//!
+//! ```no_run
+//! use fastly::async_io::{select, AsyncItem};
+//! use fastly::cache::core::{CacheKey, Transaction};
+//! use fastly::{Error, Request};
+//! use std::time::Duration;
+//! # fn f() -> Result<(), Error> {
+//!
+//! let (_, body) = Request::get("https://example.com/")
+//!     .send("origin")?
+//!     .into_handles();
+//! let lookup = Transaction::lookup(CacheKey::from_static(b"key")).execute_async();
+//! match select([body.async_handle(), lookup.async_handle()], Some(Duration::from_secs(1))) {
+//!     Some(0) => println!("the origin body has data to read"),
+//!     Some(_) => println!("the cache lookup has completed"),
+//!     None => println!("neither was ready within a second"),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use crate::abi;
+use std::cell::RefCell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+thread_local! {
+    /// The handles that pending futures are waiting on, and the wakers to call when they are ready.
+    ///
+    /// Futures register their handles each time they are polled, so the list is cleared before
+    /// every poll of the top-level future.
+    static WAITING: RefCell<Vec<(u32, Waker)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns `true` if the async item with the given handle is ready.
+///
+/// # Panics
+///
+/// Panics if the handle is not a valid async item.
+pub(crate) fn is_ready(handle: u32) -> bool {
+    let mut ready_out = 0;
+    let status = unsafe { abi::fastly_async_io::is_ready(handle, &mut ready_out) };
+    if status.is_err() {
+        panic!("fastly_async_io::is_ready failed: {:?}", status);
+    }
+    ready_out != 0
+}
+
+/// Check whether the async item with the given handle is ready, and if not, arrange for the task
+/// in `cx` to be woken once it is.
+pub(crate) fn poll_ready(handle: u32, cx: &mut Context<'_>) -> Poll<()> {
+    if is_ready(handle) {
+        Poll::Ready(())
+    } else {
+        WAITING.with(|waiting| waiting.borrow_mut().push((handle, cx.waker().clone())));
+        Poll::Pending
+    }
+}
+
+/// A waker that records whether it has been woken.
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Run a future to completion, sleeping in the host while it waits on asynchronous operations.
+///
+/// # Panics
+///
+/// Panics if the future is pending, but is neither waiting on an asynchronous operation in the host
+/// nor has woken itself, as it would never complete.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = Box::pin(fut);
+    let flag = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    // A future may itself call `block_on`, so keep the registrations of the outer executor aside.
+    let outer = WAITING.with(|waiting| waiting.take());
+    let output = loop {
+        flag.0.store(false, Ordering::SeqCst);
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            break output;
+        }
+        let waiting = WAITING.with(|waiting| waiting.take());
+        if flag.0.load(Ordering::SeqCst) {
+            // The future woke itself while being polled, so it can make progress right away.
+            continue;
+        }
+        if waiting.is_empty() {
+            panic!("future is pending, but is not waiting on any asynchronous operation");
+        }
+        let handles: Vec<u32> = waiting.iter().map(|(handle, _)| *handle).collect();
+        let mut done_index = u32::MAX;
+        let status = unsafe {
+            abi::fastly_async_io::select(handles.as_ptr(), handles.len(), 0, &mut done_index)
+        };
+        if status.is_err() || done_index == u32::MAX {
+            panic!("fastly_async_io::select failed: {:?}", status);
+        }
+        waiting[done_index as usize].1.wake_by_ref();
+    };
+    WAITING.with(|waiting| *waiting.borrow_mut() = outer);
+    output
+}
+
+/// A future that polls two futures concurrently.
+///
+/// This `struct` is created by [`join()`].
+#[must_use = "futures do nothing unless polled"]
+pub struct Join<A: Future, B: Future> {
+    a: MaybeDone<A>,
+    b: MaybeDone<B>,
+}
+
+/// Wait for two futures to complete, polling them concurrently.
+pub fn join<A: Future, B: Future>(a: A, b: B) -> Join<A, B> {
+    Join {
+        a: MaybeDone::Pending(Box::pin(a)),
+        b: MaybeDone::Pending(Box::pin(b)),
+    }
+}
+
+impl<A: Future, B: Future> Future for Join<A, B> {
+    type Output = (A::Output, B::Output);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let a_done = self.a.poll(cx);
+        let b_done = self.b.poll(cx);
+        if a_done && b_done {
+            Poll::Ready((self.a.take(), self.b.take()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A future that polls a collection of futures concurrently.
+///
+/// This `struct` is created by [`join_all()`].
+#[must_use = "futures do nothing unless polled"]
+pub struct JoinAll<F: Future> {
+    futures: Vec<MaybeDone<F>>,
+}
+
+/// Wait for all of a collection of futures to complete, polling them concurrently.
+///
+/// The outputs are returned in the same order as the futures.
+pub fn join_all<F: Future>(futures: impl IntoIterator<Item = F>) -> JoinAll<F> {
+    JoinAll {
+        futures: futures
+            .into_iter()
+            .map(|fut| MaybeDone::Pending(Box::pin(fut)))
+            .collect(),
+    }
+}
+
+impl<F: Future> Future for JoinAll<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut all_done = true;
+        for fut in self.futures.iter_mut() {
+            all_done &= fut.poll(cx);
+        }
+        if all_done {
+            Poll::Ready(self.futures.iter_mut().map(MaybeDone::take).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// A future, or its output once it has completed.
+enum MaybeDone<F: Future> {
+    Pending(Pin<Box<F>>),
+    Done(F::Output),
+    Taken,
+}
+
+// The future is pinned in its own allocation, and the output is never pinned.
+impl<F: Future> Unpin for MaybeDone<F> {}
+
+impl<F: Future> MaybeDone<F> {
+    /// Poll the future if it has not yet completed, and return whether it has.
+    fn poll(&mut self, cx: &mut Context<'_>) -> bool {
+        if let MaybeDone::Pending(fut) = self {
+            match fut.as_mut().poll(cx) {
+                Poll::Ready(output) => *self = MaybeDone::Done(output),
+                Poll::Pending => return false,
+            }
+        }
+        true
+    }
+
+    fn take(&mut self) -> F::Output {
+        match std::mem::replace(self, MaybeDone::Taken) {
+            MaybeDone::Done(output) => output,
+            _ => panic!("future polled after completion"),
+        }
+    }
+}
+
+/// A handle to an operation that may complete in the background, borrowed from its [`AsyncItem`].
+///
+/// Handles are passed to [`select()`] to wait until one of several operations is ready.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AsyncHandle<'a> {
+    handle: u32,
+    item: PhantomData<&'a ()>,
+}
+
+impl AsyncHandle<'_> {
+    /// Make a handle from its underlying representation.
+    ///
+    /// An invalid handle stands for an operation that failed before it could be started, and is
+    /// always ready.
+    pub(crate) fn from_u32(handle: u32) -> Self {
+        Self {
+            handle,
+            item: PhantomData,
+        }
+    }
+
+    /// Get the underlying representation of the handle.
+    ///
+    /// This should only be used when calling the raw ABI directly, and care should be taken not to
+    /// reuse or alias handle values.
+    pub fn as_u32(&self) -> u32 {
+        self.handle
+    }
+
+    fn is_invalid(&self) -> bool {
+        // All kinds of async item share the same invalid handle value.
+        self.handle == fastly_shared::INVALID_BODY_HANDLE
+    }
+
+    /// Returns `true` if the operation is ready, without blocking.
+    pub fn is_ready(&self) -> bool {
+        self.is_invalid() || is_ready(self.handle)
+    }
+}
+
+/// An operation that may complete in the background, such as a pending request or a cache lookup.
+///
+/// This is implemented by:
+///
+/// - [`BodyHandle`][crate::handle::BodyHandle], which is ready once data can be read from it, or it
+///   has reached its end.
+///
+/// - [`PendingRequestHandle`][crate::handle::PendingRequestHandle] and
+///   [`PendingRequest`][crate::http::request::PendingRequest], which are ready once the response
+///   has arrived, or the request has failed.
+///
+/// - [`PendingTransaction`][crate::cache::core::PendingTransaction], which is ready once the cache
+///   lookup has completed.
+pub trait AsyncItem {
+    /// Get a handle to the operation, to pass to [`select()`].
+    fn async_handle(&self) -> AsyncHandle<'_>;
+
+    /// Returns `true` if the operation is ready, without blocking.
+    fn is_ready(&self) -> bool {
+        self.async_handle().is_ready()
+    }
+}
+
+/// Block until one of the given operations is ready, or until the timeout has elapsed.
+///
+/// Returns the index of the handle in the argument collection that became ready, or `None` if the
+/// timeout elapsed first. If `timeout` is `None`, this waits for as long as it takes.
+///
+/// The operations themselves are left untouched: the result of a ready operation is retrieved from
+/// its item as usual, for example with
+/// [`PendingRequestHandle::wait()`][crate::handle::PendingRequestHandle::wait()], which will then
+/// not block.
+///
+/// ### Panics
+///
+/// Panics if the argument collection is empty and there is no timeout, or if any of the handles
+/// have been closed.
+pub fn select<'a, I>(handles: I, timeout: Option<Duration>) -> Option<usize>
+where
+    I: IntoIterator<Item = AsyncHandle<'a>>,
+{
+    let handles = handles.into_iter().collect::<Vec<_>>();
+    if let Some(ix) = handles.iter().position(AsyncHandle::is_invalid) {
+        return Some(ix);
+    }
+    let timeout_ms = match timeout {
+        // The host treats a timeout of zero as no timeout at all, so only poll the handles.
+        Some(timeout) if timeout.is_zero() => {
+            return handles.iter().position(AsyncHandle::is_ready);
+        }
+        // Round up, so that a short timeout is not mistaken for no timeout.
+        Some(timeout) => {
+            let ms = timeout.as_nanos().saturating_add(999_999) / 1_000_000;
+            u32::try_from(ms).unwrap_or(u32::MAX)
+        }
+        None if handles.is_empty() => panic!("cannot select on zero handles without a timeout"),
+        None => 0,
+    };
+    let raw_handles = handles.iter().map(AsyncHandle::as_u32).collect::<Vec<_>>();
+    let mut done_index = u32::MAX;
+    let status = unsafe {
+        abi::fastly_async_io::select(
+            raw_handles.as_ptr(),
+            raw_handles.len(),
+            timeout_ms,
+            &mut done_index,
+        )
+    };
+    if status.is_err() {
+        panic!("fastly_async_io::select failed: {:?}", status);
+    }
+    if done_index == u32::MAX {
+        None
+    } else {
+        Some(done_index as usize)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::cache::core::{CacheKey, Transaction};
+    use crate::testing::{self, Backends, Reply};
+    use crate::{Body, Request, Response};
+    use std::time::Duration;
+
+    #[test]
+    fn join_waits_for_requests_concurrently() {
+        testing::reset();
+        let reply = |body: &str, ms| {
+            Reply::from(Response::from_body(body.to_owned())).with_delay(Duration::from_millis(ms))
+        };
+        Backends::new()
+            .reply("a", reply("a", 100))
+            .reply("b", reply("b", 300))
+            .reply("c", reply("c", 200));
+        let (a, rest) = block_on(async {
+            let a = Request::get("http://example.com/").send_async("a").unwrap();
+            let rest = ["b", "c"].iter().map(|backend| {
+                Request::get("http://example.com/")
+                    .send_async(*backend)
+                    .unwrap()
+            });
+            join(a, join_all(rest)).await
+        });
+        assert_eq!(a.unwrap().take_body_str(), "a");
+        let bodies: Vec<_> = rest
+            .into_iter()
+            .map(|r| r.unwrap().take_body_str())
+            .collect();
+        assert_eq!(bodies, ["b", "c"]);
+        assert_eq!(testing::now(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn cache_lookups_and_body_reads() {
+        testing::reset();
+        let tx = block_on(Transaction::lookup(CacheKey::from_static(b"key")).execute_async());
+        assert!(tx.unwrap().must_insert());
+
+        let mut body = Body::from("hello");
+        let mut buf = [0; 16];
+        let n = block_on(body.read_async(&mut buf)).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+
+    #[test]
+    fn select_races_requests_and_lookups() {
+        testing::reset();
+        Backends::new().reply(
+            "slow",
+            Reply::from(Response::new()).with_delay(Duration::from_millis(300)),
+        );
+        let pending = Request::get("http://example.com/")
+            .send_async("slow")
+            .unwrap();
+        let lookup = Transaction::lookup(CacheKey::from_static(b"key")).execute_async();
+        let ready = select([pending.async_handle(), lookup.async_handle()], None);
+        assert_eq!(ready, Some(1));
+        assert!(!pending.is_ready());
+        assert_eq!(testing::now(), Duration::ZERO);
+    }
+
+    #[test]
+    fn select_times_out() {
+        testing::reset();
+        let reply = |ms| Reply::from(Response::new()).with_delay(Duration::from_millis(ms));
+        Backends::new()
+            .reply("a", reply(300))
+            .reply("b", reply(200));
+        let a = Request::get("http://example.com/").send_async("a").unwrap();
+        let b = Request::get("http://example.com/").send_async("b").unwrap();
+        let handles = [a.async_handle(), b.async_handle()];
+        assert_eq!(select(handles, Some(Duration::ZERO)), None);
+        assert_eq!(select(handles, Some(Duration::from_millis(100))), None);
+        assert_eq!(testing::now(), Duration::from_millis(100));
+        assert_eq!(select(handles, Some(Duration::from_secs(1))), Some(1));
+        assert_eq!(testing::now(), Duration::from_millis(200));
+        assert!(b.is_ready());
+    }
+}
@@ -0,0 +1,6 @@
+//! Authentication of requests at the edge.
+
+pub mod aws;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod signed_url;
@@ -0,0 +1,986 @@
+//! Verification of JSON Web Tokens.
+//!
+//! A [`Validator`] checks the signature of a token, as defined by
+//! [RFC 7515](https://www.rfc-editor.org/rfc/rfc7515), against a [`KeySet`], and then validates
+//! its `exp`, `nbf`, `iss` and `aud` claims as defined by
+//! [RFC 7519](https://www.rfc-editor.org/rfc/rfc7519). The `HS256`, `RS256`, `ES256` and `EdDSA`
+//! (Ed25519) algorithms are supported.
+//!
+//! Keys can be loaded from a [`SecretStore`], from a JSON Web Key Set in a [`ConfigStore`], or
+//! fetched from a JWKS URL through a backend, in which case the key set is kept in the
+//! [Simple Cache][crate::cache::simple].
+//!
+//! This module is only available with the `jwt` feature.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use fastly::auth::jwt::{KeySet, Validator};
+//! use fastly::{Request, Response};
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), fastly::Error> {
+//! let keys = KeySet::fetch(
+//!     "https://auth.example.com/.well-known/jwks.json",
+//!     "auth_backend",
+//!     Duration::from_secs(3600),
+//! )?;
+//! let validator = Validator::new(keys)
+//!     .with_issuer("https://auth.example.com/")
+//!     .with_audience("my-api")
+//!     .with_cookie("id_token");
+//!
+//! let req = Request::from_client();
+//! match validator.verify_request(&req) {
+//!     Ok(token) => {
+//!         let user = token.claims().subject().unwrap_or_default().to_owned();
+//!         req.with_header("x-user", user).send("origin")?.send_to_client();
+//!     }
+//!     Err(e) => Response::from_status(401).with_body(e.to_string()).send_to_client(),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::cache::simple::{self, CacheEntry, CacheError};
+use crate::config_store::ConfigStore;
+use crate::convert::ToBackend;
+use crate::error::ValueError;
+use crate::http::header::AUTHORIZATION;
+use crate::http::request::SendError;
+use crate::http::StatusCode;
+use crate::secret_store::plaintext::decode_base64;
+use crate::secret_store::{self, HmacKey, Jwk, JwkSet, ParseError, Secret, SecretStore};
+use crate::Request;
+use base64ct::{Base64UrlUnpadded, Encoding};
+use p256::ecdsa::signature::Verifier;
+use rsa::traits::SignatureScheme as _;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// A JWS signature algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Algorithm {
+    /// HMAC with SHA-256.
+    Hs256,
+    /// RSASSA-PKCS1-v1_5 with SHA-256.
+    Rs256,
+    /// ECDSA with the P-256 curve and SHA-256.
+    Es256,
+    /// EdDSA with the Ed25519 curve.
+    EdDsa,
+}
+
+impl Algorithm {
+    /// Every supported algorithm.
+    pub const ALL: [Algorithm; 4] = [Self::Hs256, Self::Rs256, Self::Es256, Self::EdDsa];
+
+    /// The name of the algorithm in a token's `alg` header, such as `HS256`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hs256 => "HS256",
+            Self::Rs256 => "RS256",
+            Self::Es256 => "ES256",
+            Self::EdDsa => "EdDSA",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|alg| alg.as_str() == name)
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A key for verifying token signatures.
+///
+/// Each key verifies signatures of a single [`Algorithm`], so a token can't be verified with a key
+/// meant for a different algorithm.
+pub struct Key {
+    kid: Option<String>,
+    kind: KeyKind,
+}
+
+enum KeyKind {
+    Hmac(HmacKey),
+    Rsa(rsa::RsaPublicKey),
+    Es256(p256::ecdsa::VerifyingKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+impl Key {
+    /// Use a shared secret as an `HS256` key.
+    pub fn hmac(key: impl Into<HmacKey>) -> Self {
+        Self {
+            kid: None,
+            kind: KeyKind::Hmac(key.into()),
+        }
+    }
+
+    /// Use a JSON Web Key.
+    ///
+    /// `oct` keys are used for `HS256`, `RSA` keys for `RS256`, `EC` keys on the `P-256` curve for
+    /// `ES256`, and `OKP` keys on the `Ed25519` curve for `EdDSA`.
+    pub fn from_jwk(jwk: &Jwk) -> Result<Self, JwtError> {
+        let param = |name: &'static str| {
+            let value = jwk
+                .param(name)
+                .and_then(Value::as_str)
+                .ok_or(JwtError::InvalidKey(name))?;
+            decode_base64(value.as_bytes()).map_err(|_| JwtError::InvalidKey(name))
+        };
+        let curve = || jwk.param("crv").and_then(Value::as_str);
+        let kind = match jwk.kty() {
+            "oct" => KeyKind::Hmac(HmacKey::new(param("k")?)),
+            "RSA" => {
+                let n = rsa::BigUint::from_bytes_be(&param("n")?);
+                let e = rsa::BigUint::from_bytes_be(&param("e")?);
+                KeyKind::Rsa(rsa::RsaPublicKey::new(n, e).map_err(|_| JwtError::InvalidKey("n"))?)
+            }
+            "EC" if curve() == Some("P-256") => {
+                let (x, y) = (param("x")?, param("y")?);
+                if x.len() != 32 || y.len() != 32 {
+                    return Err(JwtError::InvalidKey("x"));
+                }
+                let point = p256::EncodedPoint::from_affine_coordinates(
+                    p256::FieldBytes::from_slice(&x),
+                    p256::FieldBytes::from_slice(&y),
+                    false,
+                );
+                KeyKind::Es256(
+                    p256::ecdsa::VerifyingKey::from_encoded_point(&point)
+                        .map_err(|_| JwtError::InvalidKey("x"))?,
+                )
+            }
+            "OKP" if curve() == Some("Ed25519") => {
+                let x = param("x")?;
+                let x = <&[u8; 32]>::try_from(&*x).map_err(|_| JwtError::InvalidKey("x"))?;
+                KeyKind::Ed25519(
+                    ed25519_dalek::VerifyingKey::from_bytes(x)
+                        .map_err(|_| JwtError::InvalidKey("x"))?,
+                )
+            }
+            _ => return Err(JwtError::UnsupportedKey(jwk.kty().to_owned())),
+        };
+        Ok(Self {
+            kid: jwk.kid().map(str::to_owned),
+            kind,
+        })
+    }
+
+    /// Set the key ID, which is matched against the `kid` header of tokens.
+    pub fn with_kid(mut self, kid: impl Into<String>) -> Self {
+        self.kid = Some(kid.into());
+        self
+    }
+
+    /// The key ID.
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    /// The algorithm this key verifies.
+    pub fn algorithm(&self) -> Algorithm {
+        match self.kind {
+            KeyKind::Hmac(_) => Algorithm::Hs256,
+            KeyKind::Rsa(_) => Algorithm::Rs256,
+            KeyKind::Es256(_) => Algorithm::Es256,
+            KeyKind::Ed25519(_) => Algorithm::EdDsa,
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match &self.kind {
+            KeyKind::Hmac(key) => signature.len() == 32 && key.verify(message, signature),
+            KeyKind::Rsa(key) => rsa::Pkcs1v15Sign::new::<Sha256>()
+                .verify(key, &Sha256::digest(message), signature)
+                .is_ok(),
+            KeyKind::Es256(key) => p256::ecdsa::Signature::from_slice(signature)
+                .is_ok_and(|sig| key.verify(message, &sig).is_ok()),
+            KeyKind::Ed25519(key) => ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|sig| key.verify_strict(message, &sig).is_ok()),
+        }
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A set of keys for verifying token signatures.
+#[derive(Debug, Default)]
+pub struct KeySet {
+    keys: Vec<Key>,
+}
+
+impl KeySet {
+    /// An empty key set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a key to the set.
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Use the keys of a JSON Web Key Set.
+    ///
+    /// Keys of unsupported types and curves are skipped, so that key sets shared with other
+    /// services can be used.
+    pub fn from_jwks(jwks: &JwkSet) -> Result<Self, JwtError> {
+        let mut keys = Vec::with_capacity(jwks.keys().len());
+        for jwk in jwks.keys() {
+            match Key::from_jwk(jwk) {
+                Ok(key) => keys.push(key),
+                Err(JwtError::UnsupportedKey(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    /// Parse a JSON Web Key Set, and use its keys as with [`KeySet::from_jwks()`].
+    pub fn from_jwks_json(json: &[u8]) -> Result<Self, JwtError> {
+        Self::from_jwks(&serde_json::from_slice(json).map_err(ParseError::Jwk)?)
+    }
+
+    /// Use the keys in a secret.
+    ///
+    /// A secret that starts with `{` is parsed as a JSON Web Key Set. Any other secret is used as
+    /// a shared `HS256` key.
+    pub fn from_secret(secret: &Secret) -> Result<Self, JwtError> {
+        // Read the secret once, so that both branches see the same plaintext.
+        let plaintext = secret.plaintext_zeroizing();
+        if plaintext.first() == Some(&b'{') {
+            Self::from_jwks_json(&plaintext)
+        } else {
+            Ok(Self::new().with_key(Key::hmac(HmacKey::new(plaintext))))
+        }
+    }
+
+    /// Use the keys in the named secret, as with [`KeySet::from_secret()`].
+    pub fn from_secret_store(store: &SecretStore, name: &str) -> Result<Self, JwtError> {
+        let secret = store
+            .try_get(name)?
+            .ok_or_else(|| JwtError::MissingKeys(name.to_owned()))?;
+        Self::from_secret(&secret)
+    }
+
+    /// Use the JSON Web Key Set stored under the given key of a config store.
+    ///
+    /// Config stores are not encrypted, so they should only hold public keys.
+    pub fn from_config_store(store: &ConfigStore, key: &str) -> Result<Self, JwtError> {
+        let jwks: JwkSet = store
+            .get_json(key)?
+            .ok_or_else(|| JwtError::MissingKeys(key.to_owned()))?;
+        Self::from_jwks(&jwks)
+    }
+
+    /// Fetch a JSON Web Key Set from a URL through a backend.
+    ///
+    /// The key set is kept in the [Simple Cache][crate::cache::simple] for `ttl`, so it is only
+    /// fetched when it is not already cached in the POP. Responses that are not successful or do
+    /// not contain a valid key set are not cached.
+    pub fn fetch(url: &str, backend: impl ToBackend, ttl: Duration) -> Result<Self, JwtError> {
+        let mut failure = None;
+        let body = simple::get_or_set_with(format!("fastly-jwks:{url}"), || {
+            match fetch_jwks(url, backend) {
+                Ok(body) => Ok(CacheEntry::new(body, ttl)),
+                Err(e) => {
+                    let msg = e.to_string();
+                    failure = Some(e);
+                    Err(anyhow::anyhow!(msg))
+                }
+            }
+        });
+        match (body, failure) {
+            (Ok(body), _) => Self::from_jwks_json(&body.expect("closure never fails").into_bytes()),
+            (Err(_), Some(e)) => Err(e),
+            (Err(e), None) => Err(e.into()),
+        }
+    }
+
+    /// The keys in this set.
+    pub fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+}
+
+/// Decode a part of a token, which must be unpadded URL-safe base64 with no other characters.
+fn decode_base64_url(part: &str) -> Option<Vec<u8>> {
+    Base64UrlUnpadded::decode_vec(part).ok()
+}
+
+fn fetch_jwks(url: &str, backend: impl ToBackend) -> Result<Vec<u8>, JwtError> {
+    let resp = Request::get(url)
+        .send(backend)
+        .map_err(|e| JwtError::Fetch(Box::new(e)))?;
+    if !resp.get_status().is_success() {
+        return Err(JwtError::FetchStatus(resp.get_status()));
+    }
+    let body = resp.into_body_bytes();
+    KeySet::from_jwks_json(&body)?;
+    Ok(body)
+}
+
+/// The claims of a verified token.
+#[derive(Clone, Debug)]
+pub struct Claims {
+    map: Map<String, Value>,
+}
+
+impl Claims {
+    /// The value of any claim.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.map.get(name)
+    }
+
+    /// The `iss` (issuer) claim.
+    pub fn issuer(&self) -> Option<&str> {
+        self.get("iss").and_then(Value::as_str)
+    }
+
+    /// The `sub` (subject) claim.
+    pub fn subject(&self) -> Option<&str> {
+        self.get("sub").and_then(Value::as_str)
+    }
+
+    /// The `aud` (audience) claim, which may be a single string or an array of them.
+    pub fn audience(&self) -> Vec<&str> {
+        match self.get("aud") {
+            Some(Value::String(aud)) => vec![aud.as_str()],
+            Some(Value::Array(auds)) => auds.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The `exp` (expiration time) claim.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.time("exp")
+    }
+
+    /// The `nbf` (not before) claim.
+    pub fn not_before(&self) -> Option<SystemTime> {
+        self.time("nbf")
+    }
+
+    /// The `iat` (issued at) claim.
+    pub fn issued_at(&self) -> Option<SystemTime> {
+        self.time("iat")
+    }
+
+    /// Deserialize all of the claims into a custom type.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        T::deserialize(Value::Object(self.map.clone()))
+    }
+
+    fn time(&self, name: &str) -> Option<SystemTime> {
+        let secs = self.get(name)?.as_f64()?;
+        Duration::try_from_secs_f64(secs)
+            .ok()
+            .map(|since_epoch| SystemTime::UNIX_EPOCH + since_epoch)
+    }
+}
+
+/// A token whose signature and claims have been verified.
+#[derive(Clone, Debug)]
+pub struct Token {
+    algorithm: Algorithm,
+    kid: Option<String>,
+    claims: Claims,
+}
+
+impl Token {
+    /// The algorithm the token was signed with.
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// The `kid` header of the token.
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    /// The claims of the token.
+    pub fn claims(&self) -> &Claims {
+        &self.claims
+    }
+
+    /// Take the claims of the token.
+    pub fn into_claims(self) -> Claims {
+        self.claims
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+/// Verifies tokens and validates their claims.
+///
+/// By default, tokens signed with any supported algorithm are accepted, must have an `exp`
+/// claim, and may have any issuer and audience. A leeway of one minute is allowed when checking
+/// the `exp` and `nbf` claims, to account for clock skew.
+#[derive(Debug)]
+pub struct Validator {
+    keys: KeySet,
+    algorithms: Vec<Algorithm>,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    leeway: Duration,
+    require_exp: bool,
+    cookie: Option<String>,
+}
+
+impl Validator {
+    /// Verify tokens with the given keys.
+    pub fn new(keys: KeySet) -> Self {
+        Self {
+            keys,
+            algorithms: Algorithm::ALL.to_vec(),
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            leeway: Duration::from_secs(60),
+            require_exp: true,
+            cookie: None,
+        }
+    }
+
+    /// Only accept tokens signed with the given algorithms.
+    pub fn with_algorithms(mut self, algorithms: &[Algorithm]) -> Self {
+        self.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Accept tokens from the given issuer.
+    ///
+    /// Once any issuer is given, tokens must have an `iss` claim matching one of them.
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuers.push(issuer.into());
+        self
+    }
+
+    /// Accept tokens for the given audience.
+    ///
+    /// Once any audience is given, tokens must have an `aud` claim including one of them.
+    pub fn with_audience(mut self, audience: impl Into<String>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    /// Set the leeway allowed when checking the `exp` and `nbf` claims.
+    pub fn with_leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    /// Set whether tokens must have an `exp` claim.
+    pub fn with_exp_required(mut self, required: bool) -> Self {
+        self.require_exp = required;
+        self
+    }
+
+    /// Look for tokens in the cookie with the given name, when a request has no `Authorization`
+    /// header.
+    pub fn with_cookie(mut self, name: impl Into<String>) -> Self {
+        self.cookie = Some(name.into());
+        self
+    }
+
+    /// Verify a token from a request.
+    ///
+    /// The token is taken from a `Bearer` `Authorization` header or, if the request has no
+    /// `Authorization` header and a cookie was given with [`Validator::with_cookie()`], from that
+    /// cookie.
+    pub fn verify_request(&self, req: &Request) -> Result<Token, JwtError> {
+        self.verify(self.token_from(req).ok_or(JwtError::MissingToken)?)
+    }
+
+    /// Verify a token.
+    pub fn verify(&self, token: &str) -> Result<Token, JwtError> {
+        self.verify_at(token, SystemTime::now())
+    }
+
+    fn token_from<'a>(&self, req: &'a Request) -> Option<&'a str> {
+        match req.get_header(AUTHORIZATION) {
+            Some(auth) => {
+                let (scheme, token) = auth.to_str().ok()?.trim().split_once(' ')?;
+                scheme
+                    .eq_ignore_ascii_case("bearer")
+                    .then(|| token.trim_start())
+            }
+            None => req.get_cookie(self.cookie.as_deref()?),
+        }
+    }
+
+    fn verify_at(&self, token: &str, now: SystemTime) -> Result<Token, JwtError> {
+        let mut parts = token.split('.');
+        let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(payload), Some(signature)) if parts.next().is_none() => {
+                (header, payload, signature)
+            }
+            _ => return Err(JwtError::Malformed("not three parts")),
+        };
+        let decode = |part: &str, what| decode_base64_url(part).ok_or(JwtError::Malformed(what));
+
+        let header: Header = serde_json::from_slice(&decode(header, "header")?)
+            .map_err(|_| JwtError::Malformed("header"))?;
+        let algorithm = Algorithm::from_name(&header.alg)
+            .ok_or_else(|| JwtError::UnsupportedAlgorithm(header.alg.clone()))?;
+        if !self.algorithms.contains(&algorithm) {
+            return Err(JwtError::AlgorithmNotAllowed(algorithm));
+        }
+
+        let signing_input = &token[..token.rfind('.').expect("token has three parts")];
+        let signature = decode(signature, "signature")?;
+        let mut candidates = self.keys.keys.iter().filter(|key| {
+            key.algorithm() == algorithm
+                && match (&header.kid, &key.kid) {
+                    (Some(kid), Some(key_kid)) => kid == key_kid,
+                    _ => true,
+                }
+        });
+        let mut found = false;
+        let verified = candidates.any(|key| {
+            found = true;
+            key.verify(signing_input.as_bytes(), &signature)
+        });
+        if !verified {
+            return Err(if found {
+                JwtError::InvalidSignature
+            } else {
+                JwtError::NoMatchingKey
+            });
+        }
+
+        let claims = Claims {
+            map: serde_json::from_slice(&decode(payload, "payload")?)
+                .map_err(|_| JwtError::Malformed("payload"))?,
+        };
+        self.validate(&claims, now)?;
+        Ok(Token {
+            algorithm,
+            kid: header.kid,
+            claims,
+        })
+    }
+
+    fn validate(&self, claims: &Claims, now: SystemTime) -> Result<(), JwtError> {
+        let time = |name| match claims.get(name) {
+            None => Ok(None),
+            Some(_) => claims.time(name).map(Some).ok_or(JwtError::Malformed(name)),
+        };
+        match time("exp")? {
+            Some(exp) if now >= exp + self.leeway => return Err(JwtError::Expired),
+            None if self.require_exp => return Err(JwtError::MissingExpiry),
+            _ => {}
+        }
+        if let Some(nbf) = time("nbf")? {
+            if now + self.leeway < nbf {
+                return Err(JwtError::NotYetValid);
+            }
+        }
+        if !self.issuers.is_empty()
+            && !claims
+                .issuer()
+                .is_some_and(|iss| self.issuers.iter().any(|issuer| issuer == iss))
+        {
+            return Err(JwtError::InvalidIssuer);
+        }
+        if !self.audiences.is_empty()
+            && !claims
+                .audience()
+                .into_iter()
+                .any(|aud| self.audiences.iter().any(|audience| audience == aud))
+        {
+            return Err(JwtError::InvalidAudience);
+        }
+        Ok(())
+    }
+}
+
+/// Errors loading keys or verifying tokens.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum JwtError {
+    /// The request has no token.
+    #[error("no token found in request")]
+    MissingToken,
+
+    /// The token is not a well-formed JWS in compact serialization.
+    #[error("malformed token: invalid {0}")]
+    Malformed(&'static str),
+
+    /// The token's `alg` header names an unsupported algorithm, such as `none`.
+    #[error("unsupported algorithm `{0}`")]
+    UnsupportedAlgorithm(String),
+
+    /// The token's algorithm was excluded with [`Validator::with_algorithms()`].
+    #[error("algorithm {0} is not allowed")]
+    AlgorithmNotAllowed(Algorithm),
+
+    /// No key matches the token's algorithm and `kid` header.
+    #[error("no key found for token")]
+    NoMatchingKey,
+
+    /// The token's signature is invalid.
+    #[error("invalid token signature")]
+    InvalidSignature,
+
+    /// The token has expired.
+    #[error("token has expired")]
+    Expired,
+
+    /// The token is not valid yet.
+    #[error("token is not valid yet")]
+    NotYetValid,
+
+    /// The token has no `exp` claim, which is required by default.
+    #[error("token has no expiration time")]
+    MissingExpiry,
+
+    /// The token's issuer is not one of the accepted issuers.
+    #[error("token issuer is not accepted")]
+    InvalidIssuer,
+
+    /// The token's audience does not include any of the accepted audiences.
+    #[error("token audience is not accepted")]
+    InvalidAudience,
+
+    /// A JSON Web Key has a missing or invalid parameter.
+    #[error("invalid key parameter `{0}`")]
+    InvalidKey(&'static str),
+
+    /// A JSON Web Key has an unsupported type or curve.
+    #[error("unsupported key type `{0}`")]
+    UnsupportedKey(String),
+
+    /// No keys were found under the given secret or config store key.
+    #[error("no keys found under `{0}`")]
+    MissingKeys(String),
+
+    /// A secret holding keys could not be looked up.
+    #[error("key lookup failed: {0}")]
+    SecretLookup(#[from] secret_store::LookupError),
+
+    /// A secret or key set could not be parsed.
+    #[error("invalid keys: {0}")]
+    Parse(#[from] ParseError),
+
+    /// A key set could not be read from a config store.
+    #[error("invalid keys: {0}")]
+    ConfigStore(#[from] ValueError),
+
+    /// A key set could not be fetched from its backend.
+    #[error("key set fetch failed: {0}")]
+    Fetch(#[source] Box<SendError>),
+
+    /// A key set's backend responded with an unsuccessful status.
+    #[error("key set fetch failed with status {0}")]
+    FetchStatus(StatusCode),
+
+    /// A fetched key set could not be cached.
+    #[error("key set cache failed: {0}")]
+    Cache(#[from] CacheError),
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::http::header::HeaderValue;
+    use crate::{testing, Response};
+    use p256::ecdsa::signature::{SignatureEncoding, Signer};
+    use serde_json::json;
+
+    fn sign(header: Value, claims: Value, signer: impl Fn(&[u8]) -> Vec<u8>) -> String {
+        let input = format!(
+            "{}.{}",
+            Base64UrlUnpadded::encode_string(header.to_string().as_bytes()),
+            Base64UrlUnpadded::encode_string(claims.to_string().as_bytes())
+        );
+        let signature = Base64UrlUnpadded::encode_string(&signer(input.as_bytes()));
+        format!("{input}.{signature}")
+    }
+
+    fn unix(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    struct Rng;
+
+    impl rsa::rand_core::RngCore for Rng {
+        fn next_u32(&mut self) -> u32 {
+            rsa::rand_core::impls::next_u32_via_fill(self)
+        }
+        fn next_u64(&mut self) -> u64 {
+            rsa::rand_core::impls::next_u64_via_fill(self)
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            getrandom::getrandom(dest).unwrap();
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rsa::rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl rsa::rand_core::CryptoRng for Rng {}
+
+    #[test]
+    fn claims_validation() {
+        let secret = b"a shared secret that is long enough".to_vec();
+        let hmac = HmacKey::new(secret.clone());
+        let validator = Validator::new(KeySet::new().with_key(Key::hmac(secret)))
+            .with_issuer("https://auth.example.com/")
+            .with_audience("api");
+        let token = |claims| {
+            sign(json!({"alg": "HS256", "typ": "JWT"}), claims, |input| {
+                hmac.sign(input).to_vec()
+            })
+        };
+        let now = unix(1_700_000_000);
+
+        let good = json!({
+            "iss": "https://auth.example.com/",
+            "aud": ["other", "api"],
+            "sub": "user-1",
+            "exp": 1_700_000_100,
+            "nbf": 1_699_999_000,
+        });
+        let verified = validator.verify_at(&token(good.clone()), now).unwrap();
+        assert_eq!(verified.algorithm(), Algorithm::Hs256);
+        assert_eq!(verified.claims().subject(), Some("user-1"));
+        assert_eq!(verified.claims().expires_at(), Some(unix(1_700_000_100)));
+
+        let with = |name: &str, value: Value| {
+            let mut claims = good.clone();
+            claims[name] = value;
+            validator.verify_at(&token(claims), now).unwrap_err()
+        };
+        assert!(matches!(
+            with("exp", json!(1_699_999_900)),
+            JwtError::Expired
+        ));
+        assert!(matches!(
+            with("exp", json!("soon")),
+            JwtError::Malformed("exp")
+        ));
+        assert!(matches!(
+            with("nbf", json!(1_700_000_100)),
+            JwtError::NotYetValid
+        ));
+        assert!(matches!(
+            with("iss", json!("evil")),
+            JwtError::InvalidIssuer
+        ));
+        assert!(matches!(
+            with("aud", json!("other")),
+            JwtError::InvalidAudience
+        ));
+        // Within the leeway.
+        assert!(validator
+            .verify_at(&token(good.clone()), unix(1_700_000_150))
+            .is_ok());
+
+        let mut unsigned = token(good.clone());
+        unsigned.truncate(unsigned.rfind('.').unwrap() + 1);
+        assert!(matches!(
+            validator.verify_at(&unsigned, now),
+            Err(JwtError::InvalidSignature)
+        ));
+        let none = sign(json!({"alg": "none"}), good.clone(), |_| Vec::new());
+        assert!(matches!(
+            validator.verify_at(&none, now),
+            Err(JwtError::UnsupportedAlgorithm(_))
+        ));
+        // Parts must be unpadded URL-safe base64.
+        let valid = token(good.clone());
+        let (input, signature) = valid.rsplit_once('.').unwrap();
+        let padded = format!("{input}.{signature}=");
+        let spaced = format!("{input}.{signature} ");
+        let standard = format!("{input}.{}", signature.replace('-', "+").replace('_', "/"));
+        for token in [padded, spaced, standard] {
+            if token == valid {
+                continue;
+            }
+            assert!(matches!(
+                validator.verify_at(&token, now),
+                Err(JwtError::Malformed("signature"))
+            ));
+        }
+        let hs_only = Validator::new(KeySet::new()).with_algorithms(&[Algorithm::Rs256]);
+        assert!(matches!(
+            hs_only.verify_at(&token(good), now),
+            Err(JwtError::AlgorithmNotAllowed(Algorithm::Hs256))
+        ));
+    }
+
+    #[test]
+    fn asymmetric_algorithms() {
+        let ec = p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap();
+        let ec_point = ec.verifying_key().to_encoded_point(false);
+        let ed = ed25519_dalek::SigningKey::from_bytes(&[9; 32]);
+        let rsa = rsa::RsaPrivateKey::new(&mut Rng, 1024).unwrap();
+        let rsa_public = rsa::traits::PublicKeyParts::n(&rsa.to_public_key()).to_bytes_be();
+        let jwks = json!({"keys": [
+            {"kty": "EC", "crv": "P-256", "kid": "ec",
+             "x": Base64UrlUnpadded::encode_string(ec_point.x().unwrap()),
+             "y": Base64UrlUnpadded::encode_string(ec_point.y().unwrap())},
+            {"kty": "OKP", "crv": "Ed25519", "kid": "ed",
+             "x": Base64UrlUnpadded::encode_string(ed.verifying_key().as_bytes())},
+            {"kty": "RSA", "kid": "rsa", "n": Base64UrlUnpadded::encode_string(&rsa_public), "e": "AQAB"},
+            {"kty": "OKP", "crv": "X25519", "kid": "ignored", "x": "AAAA"},
+        ]});
+        let keys = KeySet::from_jwks_json(jwks.to_string().as_bytes()).unwrap();
+        assert_eq!(keys.keys().len(), 3);
+        let validator = Validator::new(keys);
+        let claims = json!({"exp": 2_000_000_000});
+        let now = unix(1_700_000_000);
+
+        let token = sign(
+            json!({"alg": "ES256", "kid": "ec"}),
+            claims.clone(),
+            |input| {
+                let sig: p256::ecdsa::Signature = ec.sign(input);
+                sig.to_vec()
+            },
+        );
+        assert_eq!(validator.verify_at(&token, now).unwrap().kid(), Some("ec"));
+
+        let token = sign(json!({"alg": "EdDSA"}), claims.clone(), |input| {
+            ed.sign(input).to_vec()
+        });
+        assert!(validator.verify_at(&token, now).is_ok());
+
+        let rsa = rsa::pkcs1v15::SigningKey::<Sha256>::new(rsa);
+        let token = sign(
+            json!({"alg": "RS256", "kid": "rsa"}),
+            claims.clone(),
+            |input| rsa.sign(input).to_vec(),
+        );
+        assert!(validator.verify_at(&token, now).is_ok());
+
+        // Tokens must be signed with the key named by their `kid` header.
+        let other = p256::ecdsa::SigningKey::from_slice(&[8; 32]).unwrap();
+        let token = sign(
+            json!({"alg": "ES256", "kid": "ec"}),
+            claims.clone(),
+            |input| {
+                let sig: p256::ecdsa::Signature = other.sign(input);
+                sig.to_vec()
+            },
+        );
+        assert!(matches!(
+            validator.verify_at(&token, now),
+            Err(JwtError::InvalidSignature)
+        ));
+        let token = sign(json!({"alg": "ES256", "kid": "other"}), claims, |input| {
+            let sig: p256::ecdsa::Signature = ec.sign(input);
+            sig.to_vec()
+        });
+        assert!(matches!(
+            validator.verify_at(&token, now),
+            Err(JwtError::NoMatchingKey)
+        ));
+    }
+
+    #[test]
+    fn key_sources() {
+        testing::reset();
+        testing::insert_secret("secrets", "hs", "shared secret");
+        testing::insert_secret(
+            "secrets",
+            "jwks",
+            r#"{"keys": [{"kty": "oct", "k": "c2VjcmV0"}]}"#,
+        );
+        testing::insert_config_store_entry("config", "jwks", r#"{"keys": []}"#);
+        let secrets = SecretStore::open("secrets").unwrap();
+        for name in ["hs", "jwks"] {
+            let keys = KeySet::from_secret_store(&secrets, name).unwrap();
+            assert_eq!(keys.keys()[0].algorithm(), Algorithm::Hs256);
+        }
+        assert!(matches!(
+            KeySet::from_secret_store(&secrets, "missing"),
+            Err(JwtError::MissingKeys(_))
+        ));
+        let config = ConfigStore::open("config");
+        assert!(KeySet::from_config_store(&config, "jwks")
+            .unwrap()
+            .keys()
+            .is_empty());
+
+        let fetches = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = fetches.clone();
+        testing::register_backend("auth", move |req| {
+            counter.set(counter.get() + 1);
+            assert_eq!(req.get_path(), "/jwks.json");
+            Response::from_body(r#"{"keys": [{"kty": "oct", "k": "c2VjcmV0"}]}"#)
+        });
+        for _ in 0..2 {
+            let keys = KeySet::fetch(
+                "https://auth.example.com/jwks.json",
+                "auth",
+                Duration::from_secs(60),
+            )
+            .unwrap();
+            assert_eq!(keys.keys().len(), 1);
+        }
+        assert_eq!(fetches.get(), 1);
+
+        testing::register_backend("broken", |_| Response::from_status(503));
+        assert!(matches!(
+            KeySet::fetch(
+                "https://broken.example.com/",
+                "broken",
+                Duration::from_secs(60)
+            ),
+            Err(JwtError::FetchStatus(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+    }
+
+    #[test]
+    fn tokens_from_requests() {
+        let validator = Validator::new(KeySet::new()).with_cookie("id_token");
+        let req = Request::get("http://example.com/").with_header(AUTHORIZATION, "Bearer a.b.c");
+        assert_eq!(validator.token_from(&req), Some("a.b.c"));
+        let req =
+            Request::get("http://example.com/").with_header(AUTHORIZATION, "Basic dXNlcjpwYXNz");
+        assert_eq!(validator.token_from(&req), None);
+        let req = Request::get("http://example.com/").with_header("cookie", "id_token=x.y.z");
+        assert_eq!(validator.token_from(&req), Some("x.y.z"));
+        assert!(matches!(
+            validator.verify_request(&Request::get("http://example.com/")),
+            Err(JwtError::MissingToken)
+        ));
+
+        let auth = HeaderValue::from_bytes(b"Bearer \xffa.b.c").unwrap();
+        let req = Request::get("http://example.com/")
+            .with_header(AUTHORIZATION, auth)
+            .with_header("cookie", "id_token=x.y.z");
+        assert_eq!(validator.token_from(&req), None);
+        assert!(matches!(
+            validator.verify_request(&req),
+            Err(JwtError::MissingToken)
+        ));
+    }
+}
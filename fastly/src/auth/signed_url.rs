@@ -0,0 +1,734 @@
+//! Expiring signed URLs and edge authorization tokens.
+//!
+//! Two schemes are supported, both based on HMAC-SHA256 with keys that are usually loaded from a
+//! [`SecretStore`]:
+//!
+//! - A [`UrlSigner`] signs a URL's path, a chosen subset of its query parameters, and an expiry
+//!   time, and adds the expiry and signature to the URL's query.
+//! - A [`TokenAuth`] generates and verifies tokens in the format used by Akamai EdgeAuth, such as
+//!   `st=1700000000~exp=1700003600~acl=/videos/*~hmac=…`. A token can authorize every path
+//!   matching an access control list (ACL) of wildcard patterns, which lets one token cover every
+//!   segment of a stream. Tokens are read from a query parameter, cookie or header.
+//!
+//! Signatures are always compared in constant time.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use fastly::auth::signed_url::TokenAuth;
+//! use fastly::secret_store::SecretStore;
+//! use fastly::{Request, Response};
+//!
+//! # fn main() -> Result<(), fastly::Error> {
+//! let secrets = SecretStore::open("secrets")?;
+//! let tokens = TokenAuth::from_secret_store(&secrets, &["edge_auth_key"])?;
+//!
+//! let req = Request::from_client();
+//! match tokens.verify_request(&req) {
+//!     Ok(_) => req.send("media_origin")?.send_to_client(),
+//!     Err(e) => Response::from_status(403).with_body(e.to_string()).send_to_client(),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::http::header::HeaderName;
+use crate::http::Url;
+use crate::secret_store::plaintext::encode_hex;
+use crate::secret_store::{HmacKey, LookupError, SecretStore};
+use crate::Request;
+use std::net::IpAddr;
+use std::time::SystemTime;
+use thiserror::Error;
+
+/// Signs and verifies URLs that expire.
+///
+/// The signature covers the URL's path, the values of the query parameters chosen with
+/// [`UrlSigner::with_signed_params()`], and the expiry time. The expiry time and signature are
+/// added to the query as the `expires` and `signature` parameters, unless other names are set with
+/// [`UrlSigner::with_param_names()`].
+#[derive(Debug)]
+pub struct UrlSigner {
+    keys: Vec<HmacKey>,
+    signed_params: Vec<String>,
+    expires_param: String,
+    signature_param: String,
+}
+
+impl UrlSigner {
+    /// Sign URLs with the given key.
+    pub fn new(key: impl Into<HmacKey>) -> Self {
+        Self {
+            keys: vec![key.into()],
+            signed_params: Vec::new(),
+            expires_param: "expires".to_owned(),
+            signature_param: "signature".to_owned(),
+        }
+    }
+
+    /// Sign URLs with keys from a secret store.
+    ///
+    /// The first of the named secrets that is found is used to sign URLs, and all of them are used
+    /// to verify URLs, so that keys can be rotated.
+    pub fn from_secret_store(store: &SecretStore, names: &[&str]) -> Result<Self, SignedUrlError> {
+        let mut keys = load_keys(store, names, |secret| Ok(secret.to_vec().into()))?.into_iter();
+        let mut signer = Self::new(keys.next().ok_or(SignedUrlError::NoKey)?);
+        signer.keys.extend(keys);
+        Ok(signer)
+    }
+
+    /// Also verify URLs signed with the given key.
+    pub fn with_previous_key(mut self, key: impl Into<HmacKey>) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+
+    /// Include the values of the given query parameters in signatures.
+    ///
+    /// Other query parameters are not signed, and may be added or changed without invalidating the
+    /// signature.
+    pub fn with_signed_params(mut self, params: &[&str]) -> Self {
+        self.signed_params = params.iter().map(|&param| param.to_owned()).collect();
+        self
+    }
+
+    /// Set the names of the query parameters that hold the expiry time and signature.
+    pub fn with_param_names(mut self, expires: &str, signature: &str) -> Self {
+        self.expires_param = expires.to_owned();
+        self.signature_param = signature.to_owned();
+        self
+    }
+
+    /// Sign a URL so that it is valid until `expires`.
+    ///
+    /// Any expiry time or signature parameters already in the URL are replaced.
+    pub fn sign(&self, url: &Url, expires: SystemTime) -> Url {
+        let expires = unix_secs(expires).to_string();
+        let mut signed = url.clone();
+        signed
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(
+                url.query_pairs().filter(|(name, _)| {
+                    name != &self.expires_param && name != &self.signature_param
+                }),
+            )
+            .append_pair(&self.expires_param, &expires);
+        let signature = encode_hex(&self.keys[0].sign(&self.message(&signed, &expires)));
+        signed
+            .query_pairs_mut()
+            .append_pair(&self.signature_param, &signature);
+        signed
+    }
+
+    /// Verify that a URL was signed with one of the keys and has not expired.
+    pub fn verify(&self, url: &Url) -> Result<(), SignedUrlError> {
+        self.verify_at(url, SystemTime::now())
+    }
+
+    /// Verify the URL of a request, as with [`UrlSigner::verify()`].
+    pub fn verify_request(&self, req: &Request) -> Result<(), SignedUrlError> {
+        self.verify(req.get_url())
+    }
+
+    fn verify_at(&self, url: &Url, now: SystemTime) -> Result<(), SignedUrlError> {
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(param, _)| param == name)
+                .map(|(_, value)| value)
+        };
+        let expires = param(&self.expires_param).ok_or(SignedUrlError::MissingSignature)?;
+        let signature = param(&self.signature_param).ok_or(SignedUrlError::MissingSignature)?;
+        let expiry: u64 = expires
+            .parse()
+            .map_err(|_| SignedUrlError::Malformed("expires"))?;
+        let signature = unhex(&signature).ok_or(SignedUrlError::Malformed("signature"))?;
+        let message = self.message(url, &expires);
+        if !self.keys.iter().any(|key| key.verify(&message, &signature)) {
+            return Err(SignedUrlError::InvalidSignature);
+        }
+        if unix_secs(now) >= expiry {
+            return Err(SignedUrlError::Expired);
+        }
+        Ok(())
+    }
+
+    /// The signed message: the path, the signed query parameters in name order, and the expiry
+    /// time, separated by newlines.
+    fn message(&self, url: &Url, expires: &str) -> Vec<u8> {
+        let mut params: Vec<_> = url
+            .query_pairs()
+            .filter(|(name, _)| self.signed_params.iter().any(|param| param == name))
+            .collect();
+        params.sort();
+        let mut message = url.path().to_owned();
+        message.push('\n');
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params)
+            .finish();
+        message.push_str(&query);
+        message.push('\n');
+        message.push_str(expires);
+        message.into_bytes()
+    }
+}
+
+/// Generates and verifies edge authorization tokens in the Akamai EdgeAuth format.
+///
+/// A token is a list of `~`-separated fields, ending with an `hmac` field that signs the others:
+///
+/// - `ip`: the client IP address the token is valid for,
+/// - `st`: the time the token is valid from,
+/// - `exp`: the time the token expires,
+/// - `acl`: `!`-separated path patterns the token is valid for, where `*` matches any sequence of
+///   characters and `?` matches any single character,
+/// - `id` and `data`: opaque strings included in the signature.
+///
+/// Tokens without an `acl` are valid for a single URL path, which is signed but not included in
+/// the token.
+///
+/// Following EdgeAuth, keys are hex-encoded.
+#[derive(Debug)]
+pub struct TokenAuth {
+    keys: Vec<HmacKey>,
+    token_name: String,
+}
+
+impl TokenAuth {
+    /// Generate and verify tokens with the given key.
+    pub fn new(key: impl Into<HmacKey>) -> Self {
+        Self {
+            keys: vec![key.into()],
+            token_name: "__token__".to_owned(),
+        }
+    }
+
+    /// Generate and verify tokens with the given hex-encoded key.
+    pub fn from_hex_key(key: &str) -> Result<Self, SignedUrlError> {
+        Ok(Self::new(unhex(key).ok_or(SignedUrlError::InvalidKey)?))
+    }
+
+    /// Generate and verify tokens with hex-encoded keys from a secret store.
+    ///
+    /// The first of the named secrets that is found is used to generate tokens, and all of them
+    /// are used to verify tokens, so that keys can be rotated.
+    pub fn from_secret_store(store: &SecretStore, names: &[&str]) -> Result<Self, SignedUrlError> {
+        let mut keys = load_keys(store, names, |secret| {
+            let hex = std::str::from_utf8(secret).map_err(|_| SignedUrlError::InvalidKey)?;
+            unhex(hex.trim())
+                .map(HmacKey::new)
+                .ok_or(SignedUrlError::InvalidKey)
+        })?
+        .into_iter();
+        let mut auth = Self::new(keys.next().ok_or(SignedUrlError::NoKey)?);
+        auth.keys.extend(keys);
+        Ok(auth)
+    }
+
+    /// Also verify tokens generated with the given key.
+    pub fn with_previous_key(mut self, key: impl Into<HmacKey>) -> Self {
+        self.keys.push(key.into());
+        self
+    }
+
+    /// Set the name of the query parameter, cookie and header that tokens are read from. The
+    /// default is `__token__`.
+    pub fn with_token_name(mut self, name: impl Into<String>) -> Self {
+        self.token_name = name.into();
+        self
+    }
+
+    /// Generate a token.
+    pub fn generate(&self, token: &EdgeToken) -> String {
+        let fields = token.fields();
+        let mac = self.keys[0].sign(token.message(&fields, None).as_bytes());
+        format!("{fields}~hmac={}", encode_hex(&mac))
+    }
+
+    /// Verify a token for the given path and client IP address.
+    ///
+    /// Returns the fields of the token if its signature is valid, it is valid at the current time,
+    /// and it is valid for the path and IP address.
+    pub fn verify(
+        &self,
+        token: &str,
+        path: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<EdgeToken, SignedUrlError> {
+        self.verify_at(token, path, client_ip, SystemTime::now())
+    }
+
+    /// Verify the token in a request's query, cookies or headers, for the request's path and
+    /// client IP address.
+    pub fn verify_request(&self, req: &Request) -> Result<EdgeToken, SignedUrlError> {
+        let token = req
+            .get_query_parameter(&self.token_name)
+            .or_else(|| req.get_cookie(&self.token_name))
+            .or_else(|| {
+                let name = HeaderName::from_bytes(self.token_name.as_bytes()).ok()?;
+                req.get_header(name)?.to_str().ok()
+            })
+            .ok_or(SignedUrlError::MissingSignature)?;
+        self.verify(token, req.get_path(), req.get_client_ip_addr())
+    }
+
+    fn verify_at(
+        &self,
+        token: &str,
+        path: &str,
+        client_ip: Option<IpAddr>,
+        now: SystemTime,
+    ) -> Result<EdgeToken, SignedUrlError> {
+        let (fields, mac) = token
+            .rsplit_once("~hmac=")
+            .ok_or(SignedUrlError::MissingSignature)?;
+        let mac = unhex(mac).ok_or(SignedUrlError::Malformed("hmac"))?;
+        let parsed = EdgeToken::parse(fields)?;
+        let url = parsed.acl.is_empty().then_some(path);
+        let message = parsed.message(fields, url);
+        if mac.len() != 32
+            || !self
+                .keys
+                .iter()
+                .any(|key| key.verify(message.as_bytes(), &mac))
+        {
+            return Err(SignedUrlError::InvalidSignature);
+        }
+
+        let now = unix_secs(now);
+        if now >= parsed.expires {
+            return Err(SignedUrlError::Expired);
+        }
+        if parsed.start.is_some_and(|start| now < start) {
+            return Err(SignedUrlError::NotYetValid);
+        }
+        if let Some(ip) = &parsed.ip {
+            if client_ip.map(|client_ip| client_ip.to_string()).as_ref() != Some(ip) {
+                return Err(SignedUrlError::IpMismatch);
+            }
+        }
+        if !parsed.acl.is_empty() && !parsed.acl.iter().any(|acl| acl_matches(acl, path)) {
+            return Err(SignedUrlError::PathNotAllowed);
+        }
+        Ok(parsed)
+    }
+}
+
+/// The fields of an edge authorization token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EdgeToken {
+    ip: Option<String>,
+    start: Option<u64>,
+    expires: u64,
+    acl: Vec<String>,
+    url: Option<String>,
+    id: Option<String>,
+    data: Option<String>,
+}
+
+impl EdgeToken {
+    /// A token valid for every path matching any of the given patterns until `expires`.
+    pub fn for_acl(acl: &[&str], expires: SystemTime) -> Self {
+        Self {
+            acl: acl.iter().map(|&pattern| pattern.to_owned()).collect(),
+            ..Self::empty(expires)
+        }
+    }
+
+    /// A token valid for a single URL path until `expires`.
+    pub fn for_path(path: impl Into<String>, expires: SystemTime) -> Self {
+        Self {
+            url: Some(path.into()),
+            ..Self::empty(expires)
+        }
+    }
+
+    fn empty(expires: SystemTime) -> Self {
+        Self {
+            ip: None,
+            start: None,
+            expires: unix_secs(expires),
+            acl: Vec::new(),
+            url: None,
+            id: None,
+            data: None,
+        }
+    }
+
+    /// Make the token valid from the given time, rather than as soon as it is generated.
+    pub fn with_start(mut self, start: SystemTime) -> Self {
+        self.start = Some(unix_secs(start));
+        self
+    }
+
+    /// Make the token valid only for the given client IP address.
+    pub fn with_ip(mut self, ip: IpAddr) -> Self {
+        self.ip = Some(ip.to_string());
+        self
+    }
+
+    /// Set the `id` field of the token.
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Set the `data` field of the token.
+    pub fn with_data(mut self, data: impl Into<String>) -> Self {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// The client IP address the token is valid for.
+    pub fn ip(&self) -> Option<&str> {
+        self.ip.as_deref()
+    }
+
+    /// The time the token is valid from, in seconds since the Unix epoch.
+    pub fn start(&self) -> Option<u64> {
+        self.start
+    }
+
+    /// The time the token expires, in seconds since the Unix epoch.
+    pub fn expires(&self) -> u64 {
+        self.expires
+    }
+
+    /// The path patterns the token is valid for.
+    pub fn acl(&self) -> &[String] {
+        &self.acl
+    }
+
+    /// The `id` field of the token.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// The `data` field of the token.
+    pub fn data(&self) -> Option<&str> {
+        self.data.as_deref()
+    }
+
+    /// The fields of the token, other than the `hmac`.
+    fn fields(&self) -> String {
+        let mut fields = Vec::new();
+        if let Some(ip) = &self.ip {
+            fields.push(format!("ip={ip}"));
+        }
+        if let Some(start) = self.start {
+            fields.push(format!("st={start}"));
+        }
+        fields.push(format!("exp={}", self.expires));
+        if !self.acl.is_empty() {
+            fields.push(format!("acl={}", self.acl.join("!")));
+        }
+        if let Some(id) = &self.id {
+            fields.push(format!("id={id}"));
+        }
+        if let Some(data) = &self.data {
+            fields.push(format!("data={data}"));
+        }
+        fields.join("~")
+    }
+
+    /// The signed message: the fields of the token, followed by the path for tokens without an
+    /// ACL.
+    fn message(&self, fields: &str, url: Option<&str>) -> String {
+        match url.or(self.url.as_deref()) {
+            Some(url) if self.acl.is_empty() => format!("{fields}~url={url}"),
+            _ => fields.to_owned(),
+        }
+    }
+
+    fn parse(fields: &str) -> Result<Self, SignedUrlError> {
+        let mut token = Self::empty(SystemTime::UNIX_EPOCH);
+        let mut expires = None;
+        for field in fields.split('~') {
+            let (name, value) = field
+                .split_once('=')
+                .ok_or(SignedUrlError::Malformed("field"))?;
+            let time = |name| value.parse().map_err(|_| SignedUrlError::Malformed(name));
+            match name {
+                "ip" => token.ip = Some(value.to_owned()),
+                "st" => token.start = Some(time("st")?),
+                "exp" => expires = Some(time("exp")?),
+                "acl" => token.acl = value.split('!').map(str::to_owned).collect(),
+                "id" => token.id = Some(value.to_owned()),
+                "data" => token.data = Some(value.to_owned()),
+                _ => return Err(SignedUrlError::Malformed("field")),
+            }
+        }
+        token.expires = expires.ok_or(SignedUrlError::Malformed("exp"))?;
+        Ok(token)
+    }
+}
+
+/// Match a path against an ACL pattern, where `*` matches any sequence of characters and `?`
+/// matches any single character.
+fn acl_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, path) = (pattern.as_bytes(), path.as_bytes());
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack = None;
+    while s < path.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, s));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == path[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    s = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn load_keys(
+    store: &SecretStore,
+    names: &[&str],
+    parse: impl Fn(&[u8]) -> Result<HmacKey, SignedUrlError>,
+) -> Result<Vec<HmacKey>, SignedUrlError> {
+    let mut keys = Vec::with_capacity(names.len());
+    for name in names {
+        if let Some(secret) = store.try_get(name)? {
+            keys.push(parse(&secret.plaintext_zeroizing())?);
+        }
+    }
+    Ok(keys)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Errors verifying signed URLs and tokens.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SignedUrlError {
+    /// The URL or request has no signature or token.
+    #[error("no signature found")]
+    MissingSignature,
+
+    /// A field of the signature or token is malformed.
+    #[error("malformed signature: invalid {0}")]
+    Malformed(&'static str),
+
+    /// The signature is not valid for any of the keys.
+    #[error("invalid signature")]
+    InvalidSignature,
+
+    /// The URL or token has expired.
+    #[error("signature has expired")]
+    Expired,
+
+    /// The token is not valid yet.
+    #[error("token is not valid yet")]
+    NotYetValid,
+
+    /// The token is not valid for the client's IP address.
+    #[error("token is not valid for this client")]
+    IpMismatch,
+
+    /// The token is not valid for the path.
+    #[error("token is not valid for this path")]
+    PathNotAllowed,
+
+    /// A key is not valid hex.
+    #[error("invalid key")]
+    InvalidKey,
+
+    /// None of the named keys were found in the secret store.
+    #[error("no signing key found")]
+    NoKey,
+
+    /// A key could not be looked up.
+    #[error("signing key lookup failed: {0}")]
+    KeyLookup(#[from] LookupError),
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::time::Duration;
+
+    fn unix(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn signed_urls() {
+        let signer = UrlSigner::new(b"key".to_vec()).with_signed_params(&["quality", "user"]);
+        let url = Url::parse("https://cdn.example.com/video.mp4?quality=hd&cb=1").unwrap();
+        let signed = signer.sign(&url, unix(1000));
+        assert!(signed.as_str().starts_with(
+            "https://cdn.example.com/video.mp4?quality=hd&cb=1&expires=1000&signature="
+        ));
+        assert!(signer.verify_at(&signed, unix(999)).is_ok());
+        assert!(matches!(
+            signer.verify_at(&signed, unix(1000)),
+            Err(SignedUrlError::Expired)
+        ));
+
+        let with_query = |query: &str| {
+            let mut url = signed.clone();
+            url.set_query(Some(&signed.query().unwrap().replace("quality=hd", query)));
+            signer.verify_at(&url, unix(0))
+        };
+        // Unsigned parameters may change; signed ones may not be changed or added.
+        assert!(with_query("quality=hd&cb=2").is_ok());
+        assert!(matches!(
+            with_query("quality=4k"),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+        assert!(matches!(
+            with_query("quality=hd&user=admin"),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+        let mut moved = signed.clone();
+        moved.set_path("/other.mp4");
+        assert!(matches!(
+            signer.verify_at(&moved, unix(0)),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+        assert!(matches!(
+            signer.verify_at(&url, unix(0)),
+            Err(SignedUrlError::MissingSignature)
+        ));
+
+        // URLs signed with a previous key stay valid during rotation.
+        let rotated = UrlSigner::new(b"new key".to_vec())
+            .with_previous_key(b"key".to_vec())
+            .with_signed_params(&["quality", "user"]);
+        assert!(rotated.verify_at(&signed, unix(0)).is_ok());
+    }
+
+    #[test]
+    fn edge_tokens() {
+        let auth = TokenAuth::from_hex_key("a1b2c3d4e5f6").unwrap();
+        let token = EdgeToken::for_acl(&["/videos/*/seg-??.ts", "/live/*"], unix(2000))
+            .with_start(unix(1000))
+            .with_id("abc");
+        let generated = auth.generate(&token);
+        assert!(
+            generated.starts_with("st=1000~exp=2000~acl=/videos/*/seg-??.ts!/live/*~id=abc~hmac=")
+        );
+
+        let verify = |path, now| auth.verify_at(&generated, path, None, unix(now));
+        assert_eq!(verify("/videos/a/b/seg-01.ts", 1500).unwrap(), token);
+        assert!(verify("/live/stream.m3u8", 1500).is_ok());
+        assert!(matches!(
+            verify("/videos/a/seg-1.ts", 1500),
+            Err(SignedUrlError::PathNotAllowed)
+        ));
+        assert!(matches!(
+            verify("/live/x", 999),
+            Err(SignedUrlError::NotYetValid)
+        ));
+        assert!(matches!(
+            verify("/live/x", 2000),
+            Err(SignedUrlError::Expired)
+        ));
+        let tampered = generated.replace("exp=2000", "exp=3000");
+        assert!(matches!(
+            auth.verify_at(&tampered, "/live/x", None, unix(1500)),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+
+        // Path tokens sign the path without including it in the token.
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let path_token = auth.generate(&EdgeToken::for_path("/file.zip", unix(2000)).with_ip(ip));
+        assert!(path_token.starts_with("ip=192.0.2.1~exp=2000~hmac="));
+        assert!(auth
+            .verify_at(&path_token, "/file.zip", Some(ip), unix(1500))
+            .is_ok());
+        assert!(matches!(
+            auth.verify_at(&path_token, "/other.zip", Some(ip), unix(1500)),
+            Err(SignedUrlError::InvalidSignature)
+        ));
+        assert!(matches!(
+            auth.verify_at(&path_token, "/file.zip", None, unix(1500)),
+            Err(SignedUrlError::IpMismatch)
+        ));
+    }
+
+    #[test]
+    fn tokens_from_requests() {
+        use crate::http::header::HeaderValue;
+
+        let auth = TokenAuth::from_hex_key("a1b2c3d4e5f6").unwrap();
+        let token = auth.generate(&EdgeToken::for_acl(&["/*"], unix(u64::from(u32::MAX))));
+        let req = Request::get("http://example.com/a").with_header("__token__", token.as_str());
+        assert!(auth.verify_request(&req).is_ok());
+
+        let unreadable = HeaderValue::from_bytes(b"exp=1~hmac=\xff").unwrap();
+        let req = Request::get("http://example.com/a").with_header("__token__", unreadable);
+        assert!(matches!(
+            auth.verify_request(&req),
+            Err(SignedUrlError::MissingSignature)
+        ));
+        let auth = auth.with_token_name("my token");
+        assert!(matches!(
+            auth.verify_request(&req),
+            Err(SignedUrlError::MissingSignature)
+        ));
+    }
+
+    #[test]
+    fn keys_from_secret_store() {
+        testing::reset();
+        testing::insert_secret("secrets", "edge_auth_v1", "00ff");
+        testing::insert_secret("secrets", "edge_auth_v2", "0102\n");
+        testing::insert_secret("secrets", "bad", "xyz");
+        let secrets = SecretStore::open("secrets").unwrap();
+        let old = TokenAuth::new(vec![0x00, 0xff]);
+        let auth =
+            TokenAuth::from_secret_store(&secrets, &["edge_auth_v2", "edge_auth_v1"]).unwrap();
+        let token = old.generate(&EdgeToken::for_acl(&["/*"], unix(2000)));
+        assert!(auth.verify_at(&token, "/a", None, unix(0)).is_ok());
+        let token = auth.generate(&EdgeToken::for_acl(&["/*"], unix(2000)));
+        assert!(TokenAuth::new(vec![0x01, 0x02])
+            .verify_at(&token, "/a", None, unix(0))
+            .is_ok());
+        assert!(matches!(
+            TokenAuth::from_secret_store(&secrets, &["bad"]),
+            Err(SignedUrlError::InvalidKey)
+        ));
+        assert!(matches!(
+            UrlSigner::from_secret_store(&secrets, &["missing"]),
+            Err(SignedUrlError::NoKey)
+        ));
+    }
+
+    #[test]
+    fn acl_wildcards() {
+        assert!(acl_matches("/*", "/"));
+        assert!(acl_matches("*", "/a/b"));
+        assert!(acl_matches("/a/*.ts", "/a/b/c.ts"));
+        assert!(acl_matches("/a/*b*c", "/a/xbyc"));
+        assert!(!acl_matches("/a/*.ts", "/a/b.m3u8"));
+        assert!(!acl_matches("/a/?", "/a/bc"));
+        assert!(acl_matches("/a/?", "/a/b"));
+    }
+}
@@ -13,6 +13,13 @@
 //! This interface provides the full benefits of Fastly's purging, request collapsing, and
 //! revalidation capabilities, and is recommended for most users who need to cache HTTP responses.
 //!
+//! ## HTTP Cache API
+//!
+//! The [`http`] module applies the caching semantics of HTTP to responses, and uses them to drive
+//! the [Core Cache API][core]. It can perform read-through caching of responses that are fetched by
+//! other means than [`Request::send()`][crate::Request::send()], or that need to be cached
+//! differently, in a single call.
+//!
 //! ## Simple Cache API
 //!
 //! The [`simple`] module contains a non-durable key-value API backed by the same cache platform as
@@ -27,4 +34,5 @@
 //! of request collapsing and revalidation control flow.
 
 pub mod core;
+pub mod http;
 pub mod simple;
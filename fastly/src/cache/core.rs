@@ -8,10 +8,9 @@
 //! purging](https://docs.fastly.com/en/guides/purging-api-cache-with-surrogate-keys).
 //!
 //! While this API contains affordances for some HTTP caching concepts such as `Vary` headers and
-//! `stale-while-revalidate`, this API is **not** suitable for HTTP caching out-of-the-box. Future
-//! SDK releases will add a more customizable HTTP Cache API with support for customizable
-//! read-through caching, freshness lifetime inference, conditional request evaluation, automatic
-//! revalidation, and more.
+//! `stale-while-revalidate`, this API is **not** suitable for HTTP caching out-of-the-box. The
+//! [`http`][super::http] module builds HTTP caching semantics on top of this API, including
+//! freshness lifetime inference and automatic revalidation.
 //!
 //! Cached items in this API consist of:
 //!
@@ -40,6 +39,7 @@ use self::handle::{
     WriteOptions as HandleWriteOptions,
 };
 use crate::{
+    async_io::{AsyncHandle, AsyncItem},
     convert::{ToHeaderName, ToHeaderValue},
     handle::RequestHandle,
     http::{
@@ -48,12 +48,19 @@ use crate::{
     },
 };
 use bytes::Bytes;
-use fastly_shared::FastlyStatus;
-use std::{sync::Arc, time::Duration};
+use fastly_shared::{FastlyStatus, INVALID_CACHE_HANDLE};
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
 
 mod handle;
 pub use handle::CacheKey;
-use handle::CacheLookupState;
+pub use handle::CacheLookupState;
 
 /// Errors arising from cache operations.
 #[derive(Debug, thiserror::Error)]
@@ -168,12 +175,14 @@ impl LookupBuilder {
     /// A cached item is _usable_ if its age is less than the sum of its TTL and its
     /// stale-while-revalidate period. Items beyond that age are unusably stale.
     pub fn execute(self) -> Result<Option<Found>, CacheError> {
+        let started = Instant::now();
         let cache_handle = handle::lookup(self.key, &self.options.as_handle_options())?;
         // force the lookup to await in the host, as we want synchronous behavior here.
-        cache_handle.wait()?;
+        let stats = LookupStats::wait(&cache_handle, started)?;
         if cache_handle.get_state().contains(CacheLookupState::FOUND) {
             Ok(Some(Found {
                 handle: Arc::new(cache_handle),
+                stats,
             }))
         } else {
             Ok(None)
@@ -181,6 +190,48 @@ impl LookupBuilder {
     }
 }
 
+/// Observations about how a lookup completed, for diagnosing [request
+/// collapsing](https://developer.fastly.com/learning/concepts/request-collapsing/) on frequently
+/// requested items.
+///
+/// These are available from [`Transaction::lookup_stats()`] and [`Found::lookup_stats()`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LookupStats {
+    wait_time: Duration,
+    had_to_wait: bool,
+}
+
+impl LookupStats {
+    /// Wait for a lookup to complete, observing whether it had to wait, and how long it took since
+    /// it was started.
+    fn wait(cache_handle: &CacheHandle, started: Instant) -> Result<Self, CacheError> {
+        let had_to_wait = !cache_handle.is_ready();
+        cache_handle.wait()?;
+        Ok(LookupStats {
+            wait_time: started.elapsed(),
+            had_to_wait,
+        })
+    }
+
+    /// The time from starting the lookup until it completed.
+    ///
+    /// For a transactional lookup that was collapsed, this includes the time spent waiting for
+    /// another transaction to insert or update the item.
+    pub fn wait_time(&self) -> Duration {
+        self.wait_time
+    }
+
+    /// Returns `true` if the lookup had not completed when it was first checked.
+    ///
+    /// This is the case when a transactional lookup is collapsed, waiting for another transaction
+    /// to insert or update the item. It is only a hint, though: the host may also take a moment to
+    /// complete a lookup that is not collapsed, including a non-transactional one, so
+    /// [`wait_time()`][Self::wait_time()] should be used to tell brief waits apart from long ones.
+    pub fn had_to_wait(&self) -> bool {
+        self.had_to_wait
+    }
+}
+
 /// A cached item returned by a lookup.
 ///
 /// This type can be used to [get the cached item as a stream][Found::to_stream()], and to retrieve
@@ -189,6 +240,7 @@ impl LookupBuilder {
 pub struct Found {
     // The `Arc` allows for a cache handle to be shared with `Transaction` in the transactional case.
     handle: Arc<CacheHandle>,
+    stats: LookupStats,
 }
 
 impl Found {
@@ -263,6 +315,19 @@ impl Found {
             .expect("`Found` is missing hits metadata")
     }
 
+    /// The current state of the lookup that found the cached item.
+    pub fn lookup_state(&self) -> CacheLookupState {
+        self.handle.get_state()
+    }
+
+    /// Observations about how the lookup that found the cached item completed.
+    ///
+    /// For an item found by [`TransactionInsertBuilder::execute_and_stream_back()`], these are
+    /// empty.
+    pub fn lookup_stats(&self) -> LookupStats {
+        self.stats
+    }
+
     /// Retrieves the entire cached item as a [`Body`] that can be read in a streaming fashion.
     ///
     #[doc = include_str!("../../docs/snippets/cache-found-multiple-streams.md")]
@@ -293,6 +358,27 @@ impl Found {
     }
 }
 
+impl fmt::Debug for Found {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Use the accessors of the handle that return `None` for missing metadata, rather than
+        // those of `Found` that expect it to be present. Like every accessor, they still panic if
+        // the host rejects the handle, which would be a bug in this crate.
+        let duration = |ns: Option<u64>| ns.map(Duration::from_nanos);
+        f.debug_struct("Found")
+            .field("state", &self.lookup_state())
+            .field("ttl", &duration(self.handle.get_max_age_ns()))
+            .field("age", &duration(self.handle.get_age_ns()))
+            .field(
+                "stale_while_revalidate",
+                &duration(self.handle.get_stale_while_revalidate_ns()),
+            )
+            .field("known_length", &self.known_length())
+            .field("hits", &self.handle.get_hits())
+            .field("lookup_stats", &self.stats)
+            .finish()
+    }
+}
+
 /// An owned variant of `HandleWriteOptions`.
 #[derive(Default)]
 struct WriteOptions {
@@ -580,6 +666,7 @@ impl InsertBuilder {
 /// ```
 pub struct Transaction {
     handle: Arc<CacheHandle>,
+    stats: LookupStats,
 }
 
 impl Transaction {
@@ -603,12 +690,28 @@ impl Transaction {
         if self.handle.get_state().contains(CacheLookupState::FOUND) {
             Some(Found {
                 handle: self.handle.clone(),
+                stats: self.stats,
             })
         } else {
             None
         }
     }
 
+    /// The current state of the lookup.
+    ///
+    /// The [`MUST_INSERT_OR_UPDATE`][CacheLookupState::MUST_INSERT_OR_UPDATE] flag is set while this
+    /// transaction client is the one elected, among any concurrent lookups of the same item, to
+    /// insert or update it.
+    pub fn lookup_state(&self) -> CacheLookupState {
+        self.handle.get_state()
+    }
+
+    /// Observations about how the lookup completed, such as whether it was collapsed with
+    /// concurrent lookups of the same item.
+    pub fn lookup_stats(&self) -> LookupStats {
+        self.stats
+    }
+
     /// Returns `true` if a usable cached item was not found, and this transaction client is
     /// expected to insert one.
     ///
@@ -697,6 +800,16 @@ impl Transaction {
     }
 }
 
+impl fmt::Debug for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Transaction")
+            .field("state", &self.lookup_state())
+            .field("lookup_stats", &self.stats)
+            .field("found", &self.found())
+            .finish()
+    }
+}
+
 /// A builder-style API for configuring a transactional lookup.
 pub struct TransactionLookupBuilder {
     key: CacheKey,
@@ -733,8 +846,7 @@ impl TransactionLookupBuilder {
     /// An option used only for testing, which avoids forcing an await when executing the lookup, so
     /// that tests can take advantage of the platform's asynchrony.
     ///
-    /// In the future, we'll provide a direct async SDK for transactions that will avoid the
-    /// need for this flag.
+    /// Programs should use [`execute_async()`][Self::execute_async()] instead.
     #[doc(hidden)]
     pub fn lazy_await(mut self) -> Self {
         self.lazy_await = true;
@@ -744,24 +856,93 @@ impl TransactionLookupBuilder {
     /// Perform the lookup, entering a [`Transaction`].
     ///
     /// Accessors like [`Transaction::found()`] can be used to determine the outcome of the lookup.
+    ///
+    /// This method blocks until the lookup has completed, which may involve waiting for another
+    /// transaction to insert the item. Use [`execute_async()`][Self::execute_async()] to perform
+    /// other work in the meantime.
     pub fn execute(self) -> Result<Transaction, CacheError> {
+        let started = Instant::now();
         let cache_handle = handle::transaction_lookup(self.key, &self.options.as_handle_options())?;
         // The underlying hostcall allows lookups to proceed asynchronously until "forced" to `await`
-        // by another hostcall, such as an accessor. At the moment, we only provide a synchronous
-        // interface to the low-level cache in the SDK, as we have not yet surfaced generic async
-        // operations in the Rust SDK. Hence, we want to force the underlying `await` here, to eagerly
+        // by another hostcall, such as an accessor. Force the underlying `await` here, to eagerly
         // retrieve any errors with the lookup, which allows subsequent accessors to be infallible.
         //
-        // In the future, we'll be able to provide an `async fn` version of `execute`, which will
-        // clean this up. In the meantime, we have a hidden `lazy_await` field used purely for the
-        // test suite, where a couple of tests rely on the underlying asynchrony in the platform.
-        if !self.lazy_await {
-            cache_handle.wait()?;
-        }
+        // The hidden `lazy_await` field is used purely for the test suite, where a couple of tests
+        // rely on the underlying asynchrony in the platform.
+        let stats = if self.lazy_await {
+            LookupStats::default()
+        } else {
+            LookupStats::wait(&cache_handle, started)?
+        };
         Ok(Transaction {
             handle: Arc::new(cache_handle),
+            stats,
         })
     }
+
+    /// Begin the lookup, returning a future that resolves to a [`Transaction`] once the lookup has
+    /// completed.
+    ///
+    /// The future must be run by [`async_io::block_on()`][crate::async_io::block_on()].
+    pub fn execute_async(self) -> PendingTransaction {
+        PendingTransaction {
+            lookup: Some(
+                handle::transaction_lookup(self.key, &self.options.as_handle_options())
+                    .map_err(CacheError::from),
+            ),
+            started: Instant::now(),
+            had_to_wait: None,
+        }
+    }
+}
+
+/// A transactional lookup that has not yet completed.
+///
+/// This `struct` is created by [`TransactionLookupBuilder::execute_async()`], and is a [`Future`]
+/// that resolves to the [`Transaction`].
+#[must_use = "futures do nothing unless polled"]
+pub struct PendingTransaction {
+    lookup: Option<Result<CacheHandle, CacheError>>,
+    started: Instant,
+    // Whether the lookup was pending when first polled, once it has been.
+    had_to_wait: Option<bool>,
+}
+
+impl Future for PendingTransaction {
+    type Output = Result<Transaction, CacheError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(Ok(cache_handle)) = &self.lookup {
+            let ready = cache_handle.poll_ready(cx);
+            self.had_to_wait.get_or_insert(ready.is_pending());
+            if ready.is_pending() {
+                return Poll::Pending;
+            }
+        }
+        let cache_handle = self
+            .lookup
+            .take()
+            .expect("`PendingTransaction` polled after completion")?;
+        // Surface any error with the lookup, so that the accessors of `Transaction` are infallible.
+        cache_handle.wait()?;
+        Poll::Ready(Ok(Transaction {
+            handle: Arc::new(cache_handle),
+            stats: LookupStats {
+                wait_time: self.started.elapsed(),
+                had_to_wait: self.had_to_wait.unwrap_or(false),
+            },
+        }))
+    }
+}
+
+impl AsyncItem for PendingTransaction {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        match &self.lookup {
+            Some(Ok(cache_handle)) => cache_handle.async_handle(),
+            // A lookup that failed to start resolves immediately with its error.
+            _ => AsyncHandle::from_u32(INVALID_CACHE_HANDLE),
+        }
+    }
 }
 
 /// A builder-style API for configuring a transactional cache insertion.
@@ -844,6 +1025,7 @@ impl TransactionInsertBuilder {
             body_handle.into(),
             Found {
                 handle: Arc::new(cache_handle),
+                stats: LookupStats::default(),
             },
         ))
     }
@@ -904,3 +1086,80 @@ impl TransactionUpdateBuilder {
         Ok(body_handle.into())
     }
 }
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{async_io::block_on, testing};
+    use std::io::Write;
+
+    #[test]
+    fn observes_transactional_lookups() {
+        testing::reset();
+        let tx = Transaction::lookup(CacheKey::from_static(b"key"))
+            .execute()
+            .unwrap();
+        assert_eq!(tx.lookup_state(), CacheLookupState::MUST_INSERT_OR_UPDATE);
+        assert!(!tx.lookup_stats().had_to_wait());
+        assert!(format!("{tx:?}").starts_with("Transaction { state: "));
+        let mut body = tx.insert(Duration::from_secs(60)).execute().unwrap();
+        body.write_all(b"hello").unwrap();
+        body.finish().unwrap();
+
+        let tx =
+            block_on(Transaction::lookup(CacheKey::from_static(b"key")).execute_async()).unwrap();
+        assert!(!tx.must_insert_or_update());
+        assert!(!tx.lookup_stats().had_to_wait());
+        let found = tx.found().unwrap();
+        assert_eq!(
+            found.lookup_state(),
+            CacheLookupState::FOUND | CacheLookupState::USABLE
+        );
+        assert_eq!(found.lookup_stats(), tx.lookup_stats());
+        let debug = format!("{found:?}");
+        assert!(debug.contains("ttl: Some(60s)"), "{debug}");
+        assert!(debug.contains("known_length: Some(5)"), "{debug}");
+    }
+
+    #[test]
+    fn collapses_lookups_onto_the_inserting_transaction() {
+        testing::reset();
+        let key = CacheKey::from_static(b"key");
+        let tx = Transaction::lookup(key.clone()).execute().unwrap();
+        assert!(tx.must_insert_or_update());
+
+        let mut pending = Transaction::lookup(key.clone()).execute_async();
+        let collapsed = block_on(async {
+            // The second lookup waits for the first transaction to insert the item.
+            let first_poll = std::future::poll_fn(|cx| {
+                Poll::Ready(Pin::new(&mut pending).poll(cx).is_pending())
+            })
+            .await;
+            assert!(first_poll);
+            let mut body = tx.insert(Duration::from_secs(60)).execute().unwrap();
+            body.write_all(b"hello").unwrap();
+            body.finish().unwrap();
+            pending.await.unwrap()
+        });
+        assert!(!collapsed.must_insert_or_update());
+        assert!(collapsed.lookup_stats().had_to_wait());
+        let found = collapsed.found().unwrap();
+        assert_eq!(found.to_stream().unwrap().into_string(), "hello");
+
+        // A transaction that gives up its obligation passes it on to a collapsed lookup.
+        let key = CacheKey::from_static(b"other");
+        let tx = Transaction::lookup(key.clone()).execute().unwrap();
+        let mut pending = Transaction::lookup(key).execute_async();
+        let next = block_on(async {
+            let first_poll = std::future::poll_fn(|cx| {
+                Poll::Ready(Pin::new(&mut pending).poll(cx).is_pending())
+            })
+            .await;
+            assert!(first_poll);
+            drop(tx);
+            pending.await.unwrap()
+        });
+        assert!(next.must_insert_or_update());
+        assert!(next.lookup_stats().had_to_wait());
+    }
+}
@@ -3,7 +3,9 @@ use fastly_shared::{FastlyStatus, INVALID_CACHE_HANDLE};
 use fastly_sys::fastly_cache::{self as sys, CacheHitCount};
 pub use fastly_sys::fastly_cache::{CacheDurationNs, CacheLookupState, CacheObjectLength};
 use std::ptr;
+use std::task::{Context, Poll};
 
+use crate::async_io::{AsyncHandle, AsyncItem};
 use crate::handle::{BodyHandle, RequestHandle, StreamingBodyHandle};
 
 /// A cache key consists of up to 4KiB of arbitrary bytes.
@@ -204,6 +206,12 @@ impl Drop for CacheHandle {
     }
 }
 
+impl AsyncItem for CacheHandle {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        AsyncHandle::from_u32(self.as_abi())
+    }
+}
+
 impl CacheHandle {
     const INVALID: Self = CacheHandle {
         cache_handle: INVALID_CACHE_HANDLE,
@@ -280,6 +288,12 @@ impl CacheHandle {
         unsafe { sys::get_state(self.as_abi(), &mut cache_lookup_state_out) }.result()
     }
 
+    /// Check whether the lookup has completed, and if not, arrange for the task in `cx` to be woken
+    /// once it has.
+    pub(crate) fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        crate::async_io::poll_ready(self.as_abi(), cx)
+    }
+
     pub fn get_state(&self) -> CacheLookupState {
         let mut cache_lookup_state_out = CacheLookupState::empty();
         unsafe { sys::get_state(self.as_abi(), &mut cache_lookup_state_out) }
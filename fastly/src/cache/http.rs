@@ -0,0 +1,1214 @@
+//! HTTP caching semantics on top of the [Core Cache API][core].
+//!
+//! This module interprets the caching headers of HTTP responses following [RFC
+//! 9111](https://www.rfc-editor.org/rfc/rfc9111), and uses the result to drive the transactional
+//! operations of the Core Cache API:
+//!
+//! * [`CachePolicy`] decides whether a response may be stored, and computes its freshness lifetime,
+//!   age, `stale-while-revalidate` period, `Vary` rule, and surrogate keys from the `Cache-Control`,
+//!   `Surrogate-Control`, `Expires`, `Date`, `Last-Modified`, `Age`, `Vary`, and `Surrogate-Key`
+//!   headers. It can then configure a [`Transaction::insert()`] or [`Transaction::update()`].
+//!
+//! * [`readthrough()`] performs an entire read-through cache lookup in one call: it serves fresh
+//!   responses from the cache, revalidates stale ones with a conditional request, and stores new
+//!   responses when their headers allow it.
+//!
+//! * [`revalidate()`] refreshes a stale response found by a [`Transaction`] with a conditional
+//!   request, for programs that perform their own lookups.
+//!
+//! ## Precedence of caching headers
+//!
+//! Fastly's `Surrogate-Control` header is intended for the edge cache rather than for browsers, so
+//! its directives take precedence over those of `Cache-Control`. The freshness lifetime of a response
+//! is taken from the first of the following that is present:
+//!
+//! 1. `Surrogate-Control: max-age`;
+//! 2. `Cache-Control: s-maxage`;
+//! 3. `Cache-Control: max-age`;
+//! 4. `Expires`, relative to `Date`;
+//! 5. a heuristic of 10% of the time since `Last-Modified`, for responses with a status code that
+//!    is cacheable by default.
+//!
+//! ## Limitations
+//!
+//! Only responses to `GET` requests are cached. Responses with `no-cache` are treated as having a
+//! freshness lifetime of zero, and the qualified forms of the `no-cache` and `private` directives
+//! are treated as their unqualified forms, as RFC 9111 permits.
+//!
+//! The `stale-while-revalidate` period only extends the time for which a stale response is kept in
+//! the cache. [`readthrough()`] revalidates a stale response before returning it, even within that
+//! period, rather than serving it and revalidating afterwards.
+//!
+//! `Set-Cookie` headers are never stored with a cached response, since they are meant only for the
+//! client the response was fetched for. They are still returned to that client.
+//!
+//! [`Transaction::insert()`]: core::Transaction::insert()
+//! [`Transaction::update()`]: core::Transaction::update()
+
+use super::core::{
+    self, CacheKey, Transaction, TransactionInsertBuilder, TransactionUpdateBuilder,
+};
+use crate::convert::ToBackend;
+use crate::http::header::{self, HeaderName, HeaderValue};
+use crate::http::{Method, StatusCode};
+use crate::{Request, Response};
+use bytes::Bytes;
+use fastly_shared::FastlyStatus;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime};
+
+pub(crate) mod range;
+
+/// Errors arising from HTTP cache operations.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum CacheError {
+    /// Operation failed due to a limit.
+    #[error("HTTP cache operation failed due to a limit")]
+    LimitExceeded,
+    /// An underlying Core Cache API operation found an invalid state.
+    ///
+    /// This should not arise during use of this API. If encountered, please report it as a bug.
+    #[error("invalid HTTP cache operation; please report this as a bug")]
+    InvalidOperation,
+    /// Cache operation is not supported.
+    #[error("unsupported HTTP cache operation")]
+    Unsupported,
+    /// An IO error occurred during an operation.
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The status and headers stored alongside a cached response could not be read.
+    ///
+    /// This can occur if the cache key is shared with items inserted by another API.
+    #[error("cached HTTP response metadata is invalid")]
+    InvalidMetadata,
+    /// An error occurred while running the closure argument of [`readthrough()`].
+    ///
+    /// This uses [`anyhow::Error`] to provide maximum flexibility in how the closure reports errors.
+    #[error("readthrough fetch error: {0}")]
+    Fetch(#[source] anyhow::Error),
+    /// An unknown error occurred.
+    #[error("unknown HTTP cache operation error; please report this as a bug: {0:?}")]
+    Other(FastlyStatus),
+}
+
+impl From<core::CacheError> for CacheError {
+    fn from(value: core::CacheError) -> Self {
+        match value {
+            core::CacheError::LimitExceeded => Self::LimitExceeded,
+            core::CacheError::InvalidOperation => Self::InvalidOperation,
+            core::CacheError::Unsupported => Self::Unsupported,
+            core::CacheError::Other(st) => Self::Other(st),
+        }
+    }
+}
+
+/// The `Surrogate-Control` header, which Fastly honors in preference to `Cache-Control`.
+const SURROGATE_CONTROL: HeaderName = HeaderName::from_static("surrogate-control");
+
+/// The `Surrogate-Key` header, which lists the surrogate keys used to purge a response.
+const SURROGATE_KEY: HeaderName = HeaderName::from_static("surrogate-key");
+
+/// Headers that describe a single connection, and so are never stored with a response.
+const HOP_BY_HOP_HEADERS: [HeaderName; 7] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// The directives of a `Cache-Control` or `Surrogate-Control` header.
+///
+/// Unrecognized directives are ignored. If a directive with a duration appears more than once, the
+/// shortest duration is used.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct CacheControl {
+    /// The `max-age` directive.
+    ///
+    /// An invalid value is treated as zero, so that the response is considered stale.
+    pub max_age: Option<Duration>,
+    /// The `s-maxage` directive.
+    ///
+    /// An invalid value is treated as zero, so that the response is considered stale.
+    pub s_maxage: Option<Duration>,
+    /// The `stale-while-revalidate` directive.
+    pub stale_while_revalidate: Option<Duration>,
+    /// The `stale-if-error` directive.
+    pub stale_if_error: Option<Duration>,
+    /// The `no-store` directive.
+    pub no_store: bool,
+    /// The `no-cache` directive, with or without a list of header names.
+    pub no_cache: bool,
+    /// The `private` directive, with or without a list of header names.
+    pub private: bool,
+    /// The `public` directive.
+    pub public: bool,
+    /// The `must-revalidate` directive.
+    pub must_revalidate: bool,
+    /// The `proxy-revalidate` directive.
+    pub proxy_revalidate: bool,
+}
+
+impl CacheControl {
+    /// Parse the directives from all of the values of a `Cache-Control` or `Surrogate-Control`
+    /// header.
+    ///
+    /// ```
+    /// # use fastly::cache::http::CacheControl;
+    /// # use fastly::http::HeaderValue;
+    /// # use std::time::Duration;
+    /// let value = HeaderValue::from_static("public, max-age=600, stale-while-revalidate=\"30\"");
+    /// let cc = CacheControl::parse([&value]);
+    /// assert!(cc.public);
+    /// assert_eq!(cc.max_age, Some(Duration::from_secs(600)));
+    /// assert_eq!(cc.stale_while_revalidate, Some(Duration::from_secs(30)));
+    /// ```
+    pub fn parse<'a>(values: impl IntoIterator<Item = &'a HeaderValue>) -> Self {
+        let mut cc = CacheControl::default();
+        for value in values {
+            // Directives are ASCII, so any other bytes can only be in arguments we do not read.
+            let value = String::from_utf8_lossy(value.as_bytes());
+            for directive in split_list(&value) {
+                let (name, arg) = match directive.split_once('=') {
+                    Some((name, arg)) => (name.trim(), Some(unquote(arg.trim()))),
+                    None => (directive, None),
+                };
+                let duration = || arg.as_deref().and_then(parse_delta_seconds);
+                match name.to_ascii_lowercase().as_str() {
+                    "max-age" => min_duration(&mut cc.max_age, duration().unwrap_or_default()),
+                    "s-maxage" => min_duration(&mut cc.s_maxage, duration().unwrap_or_default()),
+                    "stale-while-revalidate" => {
+                        if let Some(d) = duration() {
+                            min_duration(&mut cc.stale_while_revalidate, d);
+                        }
+                    }
+                    "stale-if-error" => {
+                        if let Some(d) = duration() {
+                            min_duration(&mut cc.stale_if_error, d);
+                        }
+                    }
+                    "no-store" => cc.no_store = true,
+                    "no-cache" => cc.no_cache = true,
+                    "private" => cc.private = true,
+                    "public" => cc.public = true,
+                    "must-revalidate" => cc.must_revalidate = true,
+                    "proxy-revalidate" => cc.proxy_revalidate = true,
+                    _ => {}
+                }
+            }
+        }
+        cc
+    }
+}
+
+/// Keep the shorter of an existing duration and a new one.
+fn min_duration(slot: &mut Option<Duration>, duration: Duration) {
+    *slot = Some(slot.map_or(duration, |d| d.min(duration)));
+}
+
+/// Split a comma-separated header value into its trimmed, non-empty elements, keeping commas
+/// within quoted strings.
+fn split_list(value: &str) -> Vec<&str> {
+    let mut elements = Vec::new();
+    let (mut start, mut in_quotes, mut escaped) = (0, false, false);
+    for (ix, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                elements.push(&value[start..ix]);
+                start = ix + 1;
+            }
+            _ => {}
+        }
+    }
+    elements.push(&value[start..]);
+    elements
+        .into_iter()
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .collect()
+}
+
+/// Remove the quotes and escapes from a quoted string, or return a token unchanged.
+fn unquote(s: &str) -> String {
+    match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        Some(inner) => {
+            let mut out = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                out.extend(if c == '\\' { chars.next() } else { Some(c) });
+            }
+            out
+        }
+        None => s.to_owned(),
+    }
+}
+
+/// Parse a number of seconds, as used by `max-age` and the `Age` header.
+///
+/// Values too large to represent saturate, as RFC 9111 requires.
+fn parse_delta_seconds(s: &str) -> Option<Duration> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(Duration::from_secs(s.parse().unwrap_or(u64::MAX)))
+}
+
+/// Parse an HTTP date in any of the formats of [RFC 9110 section
+/// 5.6.7](https://www.rfc-editor.org/rfc/rfc9110#section-5.6.7):
+///
+/// * IMF-fixdate: `Sun, 06 Nov 1994 08:49:37 GMT`;
+/// * RFC 850: `Sunday, 06-Nov-94 08:49:37 GMT`;
+/// * asctime: `Sun Nov  6 08:49:37 1994`.
+fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let tokens: Vec<&str> = s.split_ascii_whitespace().collect();
+    let (day, month, year, time) = match tokens.as_slice() {
+        [weekday, day, month, year, time, "GMT"] if weekday.ends_with(',') => {
+            (*day, *month, year.parse().ok()?, *time)
+        }
+        [weekday, date, time, "GMT"] if weekday.ends_with(',') => {
+            let mut parts = date.split('-');
+            let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+            // Two-digit years are interpreted as the nearest matching year in the past, which is
+            // in the previous century for years beyond those this API could see.
+            let year: i32 = year.parse().ok()?;
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            (day, month, year, *time)
+        }
+        [_, month, day, time, year] => (*day, *month, year.parse().ok()?, *time),
+        _ => return None,
+    };
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month = MONTHS.iter().position(|m| *m == month)? as u8 + 1;
+    let mut hms = time.split(':').map(|n| n.parse::<u8>().ok());
+    let (hour, minute, second) = (hms.next()??, hms.next()??, hms.next()??);
+    let date =
+        time::Date::from_calendar_date(year, time::Month::try_from(month).ok()?, day.parse().ok()?)
+            .ok()?;
+    let datetime = date.with_hms(hour, minute, second).ok()?.assume_utc();
+    Some(datetime.into())
+}
+
+/// Get the value of a header as an HTTP date.
+fn header_date(headers: &Headers, name: &HeaderName) -> Option<SystemTime> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_http_date)
+}
+
+/// Status codes that are cacheable by default, and so may be given a heuristic freshness lifetime.
+///
+/// See [RFC 9110 section 15.1](https://www.rfc-editor.org/rfc/rfc9110#section-15.1).
+fn is_heuristically_cacheable(status: StatusCode) -> bool {
+    matches!(
+        status.as_u16(),
+        200 | 203 | 204 | 206 | 300 | 301 | 308 | 404 | 405 | 410 | 414 | 501
+    )
+}
+
+/// The caching behavior of a response, as determined by its headers and those of its request.
+///
+/// ```no_run
+/// # use fastly::cache::core::{CacheKey, Transaction};
+/// # use fastly::cache::http::CachePolicy;
+/// # use fastly::Request;
+/// # fn f() -> Result<(), fastly::Error> {
+/// let req = Request::get("https://example.com/");
+/// let tx = Transaction::lookup(CacheKey::from_static(b"my_key")).execute()?;
+/// let mut resp = req.clone_without_body().send("example_backend")?;
+/// let policy = CachePolicy::new(&req, &resp);
+/// if tx.must_insert() && policy.is_storable() {
+///     let mut writer = policy.insert(tx).execute()?;
+///     writer.append(resp.take_body());
+///     writer.finish()?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct CachePolicy {
+    storable: bool,
+    freshness_lifetime: Duration,
+    age: Duration,
+    stale_while_revalidate: Duration,
+    vary: Vec<HeaderName>,
+    surrogate_keys: Vec<String>,
+}
+
+impl CachePolicy {
+    /// Determine the caching behavior of a response to the given request.
+    pub fn new(req: &Request, resp: &Response) -> Self {
+        Self::from_headers(
+            req.get_method(),
+            &Headers::from_request(req),
+            resp.get_status(),
+            &Headers::from_response(resp),
+            SystemTime::now(),
+        )
+    }
+
+    fn from_headers(
+        method: &Method,
+        req_headers: &Headers,
+        status: StatusCode,
+        resp_headers: &Headers,
+        now: SystemTime,
+    ) -> Self {
+        let req_cc = CacheControl::parse(req_headers.get_all(&header::CACHE_CONTROL));
+        let cc = CacheControl::parse(resp_headers.get_all(&header::CACHE_CONTROL));
+        let sc = CacheControl::parse(resp_headers.get_all(&SURROGATE_CONTROL));
+        let surrogate_max_age = sc.max_age.is_some();
+
+        let date = header_date(resp_headers, &header::DATE);
+        let explicit_lifetime = sc.max_age.or(cc.s_maxage).or(cc.max_age).or_else(|| {
+            resp_headers.get(&header::EXPIRES).map(|expires| {
+                let expires = expires.to_str().ok().and_then(parse_http_date);
+                // An invalid `Expires` header represents a time in the past.
+                let expires = expires.unwrap_or(SystemTime::UNIX_EPOCH);
+                expires
+                    .duration_since(date.unwrap_or(now))
+                    .unwrap_or_default()
+            })
+        });
+        let heuristic_lifetime = || {
+            let last_modified = header_date(resp_headers, &header::LAST_MODIFIED)?;
+            let since = date.unwrap_or(now).duration_since(last_modified).ok()?;
+            Some(since / 10)
+        };
+
+        let vary_values = resp_headers.get_all(&header::VARY).collect::<Vec<_>>();
+        let vary_names = vary_values
+            .iter()
+            .flat_map(|v| split_list(v.to_str().unwrap_or("*")))
+            .collect::<Vec<_>>();
+        let vary = vary_names
+            .iter()
+            .filter(|name| **name != "*")
+            .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+            .collect::<Vec<_>>();
+
+        let storable = *method == Method::GET
+            && !status.is_informational()
+            && status != StatusCode::NOT_MODIFIED
+            && status != StatusCode::PARTIAL_CONTENT
+            && !req_cc.no_store
+            && !cc.no_store
+            && !sc.no_store
+            // `private` is aimed at browsers, when the origin has also addressed the edge cache.
+            && (!cc.private || surrogate_max_age)
+            && (!req_headers.contains(&header::AUTHORIZATION)
+                || surrogate_max_age
+                || cc.public
+                || cc.must_revalidate
+                || cc.s_maxage.is_some())
+            // A `Vary: *` header, or one we cannot represent, means that no request can match.
+            && vary.len() == vary_names.len()
+            && (explicit_lifetime.is_some() || cc.public || is_heuristically_cacheable(status));
+
+        let freshness_lifetime = if cc.no_cache || sc.no_cache {
+            Duration::ZERO
+        } else {
+            explicit_lifetime
+                .or_else(|| {
+                    if cc.public || is_heuristically_cacheable(status) {
+                        heuristic_lifetime()
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or_default()
+        };
+
+        let age_value = resp_headers
+            .get(&header::AGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_delta_seconds)
+            .unwrap_or_default();
+        let apparent_age = date
+            .and_then(|date| now.duration_since(date).ok())
+            .unwrap_or_default();
+
+        let stale_while_revalidate = if cc.must_revalidate || cc.proxy_revalidate {
+            Duration::ZERO
+        } else {
+            sc.stale_while_revalidate
+                .or(cc.stale_while_revalidate)
+                .unwrap_or_default()
+        };
+
+        let surrogate_keys = resp_headers
+            .get_all(&SURROGATE_KEY)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(str::split_ascii_whitespace)
+            .map(str::to_owned)
+            .collect();
+
+        CachePolicy {
+            storable,
+            freshness_lifetime,
+            age: age_value.max(apparent_age),
+            stale_while_revalidate,
+            vary,
+            surrogate_keys,
+        }
+    }
+
+    /// Returns `true` if the response may be stored in the cache.
+    pub fn is_storable(&self) -> bool {
+        self.storable
+    }
+
+    /// The freshness lifetime of the response: the total time, including its current
+    /// [age][Self::age()], for which it may be served from the cache without revalidation.
+    pub fn ttl(&self) -> Duration {
+        self.freshness_lifetime
+    }
+
+    /// The current age of the response, from its `Age` and `Date` headers.
+    pub fn age(&self) -> Duration {
+        self.age
+    }
+
+    /// Returns `true` if the response is still fresh at its current age.
+    pub fn is_fresh(&self) -> bool {
+        self.age < self.freshness_lifetime
+    }
+
+    /// The time after the response becomes stale for which it may still be served while it is
+    /// revalidated.
+    ///
+    /// This extends the time for which the response is kept in the cache, but [`readthrough()`]
+    /// still revalidates the response before serving it once it is stale.
+    pub fn stale_while_revalidate(&self) -> Duration {
+        self.stale_while_revalidate
+    }
+
+    /// The request headers listed in the response's `Vary` header.
+    pub fn vary(&self) -> &[HeaderName] {
+        &self.vary
+    }
+
+    /// The surrogate keys listed in the response's `Surrogate-Key` header.
+    pub fn surrogate_keys(&self) -> &[String] {
+        &self.surrogate_keys
+    }
+
+    /// Returns `true` if the response would be usable from the cache for any amount of time, either
+    /// fresh or while being revalidated.
+    fn is_usable(&self) -> bool {
+        self.age < self.freshness_lifetime + self.stale_while_revalidate
+    }
+
+    /// Returns a [`TransactionInsertBuilder`] that inserts the response with this policy.
+    ///
+    /// The builder is configured with the freshness lifetime, age, `stale-while-revalidate`
+    /// period, `Vary` rule, and surrogate keys of the policy. This should only be called if
+    /// [`is_storable()`][Self::is_storable()] is `true`.
+    pub fn insert(&self, tx: Transaction) -> TransactionInsertBuilder {
+        tx.insert(self.freshness_lifetime)
+            .initial_age(self.age)
+            .stale_while_revalidate(self.stale_while_revalidate)
+            .vary_by(&self.vary)
+            .surrogate_keys(self.surrogate_keys.iter().map(String::as_str))
+    }
+
+    /// Returns a [`TransactionUpdateBuilder`] that freshens a stale cached response with this
+    /// policy, typically after a `304 Not Modified` response to a conditional request.
+    ///
+    /// The builder is configured in the same way as by [`insert()`][Self::insert()].
+    pub fn update(&self, tx: Transaction) -> TransactionUpdateBuilder {
+        tx.update(self.freshness_lifetime)
+            .age(self.age)
+            .stale_while_revalidate(self.stale_while_revalidate)
+            .vary_by(&self.vary)
+            .surrogate_keys(self.surrogate_keys.iter().map(String::as_str))
+    }
+}
+
+/// Perform a read-through cache lookup for a request, fetching and caching the response if
+/// necessary.
+///
+/// The cache key is derived from the URL of the request, and all of its headers are provided to the
+/// lookup so that responses with a `Vary` header are matched correctly. Then:
+///
+/// * A fresh cached response is returned without running `fetch`.
+///
+/// * A stale cached response is revalidated by running `fetch` with a conditional request, using
+///   the `ETag` and `Last-Modified` headers of the cached response. If the backend replies with
+///   `304 Not Modified`, the cached response is freshened and returned; otherwise the new response
+///   replaces it. This happens before the response is returned, including during the
+///   `stale-while-revalidate` period of the cached response; to serve the stale response first,
+///   perform the lookup with a [`Transaction`], send the [`cached_response()`] to the client, and
+///   then call [`revalidate()`].
+///
+/// * Otherwise, `fetch` is run with the request, and its response is stored in the cache if its
+///   [`CachePolicy`] allows it.
+///
+/// Requests other than `GET`, and requests with `Cache-Control: no-store` or `no-cache`, are passed
+/// to `fetch` without consulting the cache. Responses from the cache have an `Age` header.
+///
+/// The `fetch` closure should normally send the request with
+/// [`Request::set_pass()`][crate::Request::set_pass()], so that the response is not also cached by
+/// [`Request::send()`][crate::Request::send()]. Any error it returns is reported as
+/// [`CacheError::Fetch`].
+///
+/// ```no_run
+/// # use fastly::cache::http::readthrough;
+/// # use fastly::Request;
+/// # fn f() -> Result<(), fastly::Error> {
+/// let resp = readthrough(Request::from_client(), |mut req| {
+///     req.set_pass(true);
+///     Ok(req.send("example_backend")?)
+/// })?;
+/// resp.send_to_client();
+/// # Ok(())
+/// # }
+/// ```
+pub fn readthrough<F>(req: Request, fetch: F) -> Result<Response, CacheError>
+where
+    F: FnOnce(Request) -> Result<Response, anyhow::Error>,
+{
+    let req_cc = CacheControl::parse(req.get_header_all(header::CACHE_CONTROL));
+    if *req.get_method() != Method::GET || req_cc.no_store || req_cc.no_cache {
+        return fetch(req).map_err(CacheError::Fetch);
+    }
+
+    let mut lookup = Transaction::lookup(cache_key(&req));
+    for name in req.get_header_names() {
+        lookup = lookup.header_values(name, req.get_header_all(name));
+    }
+    let tx = lookup.execute()?;
+    let found = tx.found();
+    if !tx.must_insert_or_update() {
+        return match found {
+            Some(found) => cached_response(&found),
+            // Without the obligation to insert the response, someone else must have found it.
+            None => Err(CacheError::InvalidOperation),
+        };
+    }
+
+    if found.is_none() {
+        let resp = fetch(req.clone_without_body()).map_err(CacheError::Fetch)?;
+        return store(tx, &req, resp);
+    }
+    revalidate_with(tx, &req, fetch)
+}
+
+/// The validators of a cached response, which a conditional request uses to ask the backend whether
+/// the response has changed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct Validators {
+    /// The `ETag` header of the response, sent as `If-None-Match`.
+    pub etag: Option<HeaderValue>,
+    /// The `Last-Modified` header of the response, sent as `If-Modified-Since`.
+    pub last_modified: Option<HeaderValue>,
+}
+
+impl Validators {
+    /// Get the validators from the headers of a response.
+    pub fn from_response(resp: &Response) -> Self {
+        Self::from_headers(&Headers::from_response(resp))
+    }
+
+    /// Get the validators from the headers of a response cached by this module.
+    ///
+    /// Returns [`CacheError::InvalidMetadata`] if the item was not inserted by this module.
+    pub fn from_found(found: &core::Found) -> Result<Self, CacheError> {
+        let stored = StoredResponse::decode(&found.user_metadata())?;
+        Ok(Self::from_headers(&stored.headers))
+    }
+
+    fn from_headers(headers: &Headers) -> Self {
+        Validators {
+            etag: headers.get(&header::ETAG).cloned(),
+            last_modified: headers.get(&header::LAST_MODIFIED).cloned(),
+        }
+    }
+
+    /// Returns `true` if there are no validators, and so a conditional request cannot be made.
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Make a request conditional on the validators, by setting its `If-None-Match` and
+    /// `If-Modified-Since` headers.
+    pub fn apply(&self, req: &mut Request) {
+        if let Some(etag) = &self.etag {
+            req.set_header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            req.set_header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+}
+
+/// Revalidate a stale cached response with a conditional request to a backend.
+///
+/// This sends the request to the backend with [`Request::set_pass()`][crate::Request::set_pass()],
+/// and is otherwise the same as [`revalidate_with()`].
+///
+#[doc = include_str!("../../docs/snippets/backend-argument.md")]
+///
+/// ```no_run
+/// # use fastly::cache::core::Transaction;
+/// # use fastly::cache::http::revalidate;
+/// # use fastly::Request;
+/// # fn f(tx: Transaction) -> Result<(), fastly::Error> {
+/// let req = Request::get("https://example.com/video.mp4");
+/// if tx.found().is_some() && tx.must_insert_or_update() {
+///     let resp = revalidate(tx, &req, "example_backend")?;
+///     resp.send_to_client();
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn revalidate(
+    tx: Transaction,
+    req: &Request,
+    backend: impl ToBackend,
+) -> Result<Response, CacheError> {
+    revalidate_with(tx, req, |mut req| {
+        req.set_pass(true);
+        Ok(req.send(backend)?)
+    })
+}
+
+/// Revalidate a stale cached response, running the given closure to make a conditional request.
+///
+/// The transaction must have [found][Transaction::found()] a response cached by this module, and be
+/// obliged to [insert or update][Transaction::must_insert_or_update()] it; otherwise
+/// [`CacheError::InvalidOperation`] is returned.
+///
+/// The closure is run with a copy of `req` made conditional on the [`Validators`] of the cached
+/// response. Then:
+///
+/// * If the closure returns a `304 Not Modified` response, the headers of the cached response are
+///   updated with those of the `304` response, and its freshness lifetime and age are refreshed with
+///   [`Transaction::update()`]. The updated cached response is returned.
+///
+/// * Otherwise, the response returned by the closure replaces the cached response with
+///   [`Transaction::insert()`], if its [`CachePolicy`] allows it to be stored, and is returned.
+///
+/// Any error returned by the closure is reported as [`CacheError::Fetch`].
+pub fn revalidate_with<F>(tx: Transaction, req: &Request, fetch: F) -> Result<Response, CacheError>
+where
+    F: FnOnce(Request) -> Result<Response, anyhow::Error>,
+{
+    let found = match tx.found() {
+        Some(found) if tx.must_insert_or_update() => found,
+        _ => return Err(CacheError::InvalidOperation),
+    };
+    let stored = StoredResponse::decode(&found.user_metadata())?;
+    let mut cond_req = req.clone_without_body();
+    Validators::from_headers(&stored.headers).apply(&mut cond_req);
+    let resp = fetch(cond_req).map_err(CacheError::Fetch)?;
+    if resp.get_status() != StatusCode::NOT_MODIFIED {
+        return store(tx, req, resp);
+    }
+
+    // The stored response is still valid, so freshen it with the headers of the `304` response.
+    let mut stored = stored;
+    stored.headers.update(&Headers::from_response(&resp));
+    let policy = CachePolicy::from_headers(
+        req.get_method(),
+        &Headers::from_request(req),
+        stored.status,
+        &stored.headers,
+        SystemTime::now(),
+    );
+    if policy.is_storable() && policy.is_usable() {
+        policy.update(tx).user_metadata(stored.encode()).execute()?;
+    } else {
+        tx.cancel_insert_or_update()?;
+    }
+    let not_modified = resp;
+    let mut resp = stored.into_response();
+    // Cookies set by the `304` response are meant for this client, so pass them on unstored.
+    for cookie in not_modified.get_header_all(header::SET_COOKIE) {
+        resp.append_header(header::SET_COOKIE, cookie);
+    }
+    resp.set_body(found.to_stream()?);
+    resp.set_header(header::AGE, policy.age().as_secs().to_string());
+    Ok(resp)
+}
+
+/// Store a response fetched for a transaction if its policy allows, and return it.
+fn store(tx: Transaction, req: &Request, mut resp: Response) -> Result<Response, CacheError> {
+    let policy = CachePolicy::new(req, &resp);
+    if !policy.is_storable() || !policy.is_usable() {
+        tx.cancel_insert_or_update()?;
+        return Ok(resp);
+    }
+    let stored = StoredResponse::from_response(&resp);
+    let (mut writer, found) = policy
+        .insert(tx)
+        .user_metadata(stored.encode())
+        .execute_and_stream_back()?;
+    writer.append(resp.take_body());
+    writer.finish()?;
+    resp.set_body(found.to_stream()?);
+    Ok(resp)
+}
+
+/// Build a response from an item cached by this module, with an `Age` header.
+///
+/// Returns [`CacheError::InvalidMetadata`] if the item was not inserted by this module.
+pub fn cached_response(found: &core::Found) -> Result<Response, CacheError> {
+    let mut resp = StoredResponse::decode(&found.user_metadata())?.into_response();
+    resp.set_body(found.to_stream()?);
+    resp.set_header(header::AGE, found.age().as_secs().to_string());
+    Ok(resp)
+}
+
+/// The cache key for a request, which is a digest of its URL.
+fn cache_key(req: &Request) -> CacheKey {
+    let mut hasher = Sha256::new();
+    hasher.update(b"fastly::cache::http\0");
+    hasher.update(req.get_url_str());
+    CacheKey::copy_from_slice(&hasher.finalize())
+}
+
+/// A list of headers, in the order they appeared.
+///
+/// The same representation is used for the headers of requests, responses, and the metadata of
+/// cached responses.
+#[derive(Clone, Debug, Default)]
+struct Headers(Vec<(HeaderName, HeaderValue)>);
+
+impl Headers {
+    fn from_request(req: &Request) -> Self {
+        Self::from_names(req.get_header_names(), |name| req.get_header_all(name))
+    }
+
+    fn from_response(resp: &Response) -> Self {
+        Self::from_names(resp.get_header_names(), |name| resp.get_header_all(name))
+    }
+
+    fn from_names<'a, I>(
+        names: impl Iterator<Item = &'a HeaderName>,
+        values: impl Fn(&'a HeaderName) -> I,
+    ) -> Self
+    where
+        I: Iterator<Item = &'a HeaderValue>,
+    {
+        Headers(
+            names
+                .flat_map(|name| values(name).map(move |value| (name.clone(), value.clone())))
+                .collect(),
+        )
+    }
+
+    fn get(&self, name: &HeaderName) -> Option<&HeaderValue> {
+        self.0.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    fn get_all<'a>(&'a self, name: &'a HeaderName) -> impl Iterator<Item = &'a HeaderValue> {
+        self.0
+            .iter()
+            .filter(move |(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    fn contains(&self, name: &HeaderName) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Replace the headers that appear in `other` with their values from it, as required when a
+    /// stored response is freshened by a `304 Not Modified` response.
+    fn update(&mut self, other: &Headers) {
+        let replaced = |name: &HeaderName| {
+            *name != header::CONTENT_LENGTH && other.contains(name) && is_stored(name)
+        };
+        self.0.retain(|(name, _)| !replaced(name));
+        self.0
+            .extend(other.0.iter().filter(|(name, _)| replaced(name)).cloned());
+    }
+}
+
+/// Returns `true` if a header is stored with a cached response: it is not hop-by-hop, nor an `Age`
+/// header that is recomputed for each cached response, nor a `Set-Cookie` header that must not be
+/// replayed to other clients.
+fn is_stored(name: &HeaderName) -> bool {
+    *name != header::AGE && *name != header::SET_COOKIE && !HOP_BY_HOP_HEADERS.contains(name)
+}
+
+/// The status and headers of a cached response, which are stored as the user metadata of the
+/// cached item, with the body as its object.
+#[derive(Debug)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: Headers,
+}
+
+impl StoredResponse {
+    fn from_response(resp: &Response) -> Self {
+        let mut headers = Headers::from_response(resp);
+        headers.0.retain(|(name, _)| is_stored(name));
+        StoredResponse {
+            status: resp.get_status(),
+            headers,
+        }
+    }
+
+    /// Encode the status and headers as an HTTP/1-style header block: a line with the status code,
+    /// followed by a `name: value` line for each header.
+    fn encode(&self) -> Bytes {
+        let mut out = format!("{}\n", self.status.as_u16()).into_bytes();
+        for (name, value) in &self.headers.0 {
+            out.extend_from_slice(name.as_str().as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(value.as_bytes());
+            out.push(b'\n');
+        }
+        out.into()
+    }
+
+    fn decode(metadata: &[u8]) -> Result<Self, CacheError> {
+        let mut lines = metadata.split(|b| *b == b'\n');
+        let status = lines
+            .next()
+            .and_then(|line| StatusCode::from_bytes(line).ok())
+            .ok_or(CacheError::InvalidMetadata)?;
+        let mut headers = Headers::default();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let colon = line
+                .iter()
+                .position(|b| *b == b':')
+                .ok_or(CacheError::InvalidMetadata)?;
+            let name = HeaderName::from_bytes(&line[..colon]);
+            let value = &line[colon + 1..];
+            let value = &value[value.iter().take_while(|b| **b == b' ').count()..];
+            let value = HeaderValue::from_bytes(value);
+            match (name, value) {
+                (Ok(name), Ok(value)) => headers.0.push((name, value)),
+                _ => return Err(CacheError::InvalidMetadata),
+            }
+        }
+        Ok(StoredResponse { status, headers })
+    }
+
+    fn into_response(self) -> Response {
+        let mut resp = Response::from_status(self.status);
+        for (name, value) in self.headers.0 {
+            resp.append_header(name, value);
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> SystemTime {
+        parse_http_date(s).unwrap()
+    }
+
+    fn policy(req: &[(&str, &str)], status: u16, resp: &[(&str, &str)]) -> CachePolicy {
+        let headers = |list: &[(&str, &str)]| {
+            Headers(
+                list.iter()
+                    .map(|(n, v)| (n.parse().unwrap(), v.parse().unwrap()))
+                    .collect(),
+            )
+        };
+        CachePolicy::from_headers(
+            &Method::GET,
+            &headers(req),
+            StatusCode::from_u16(status).unwrap(),
+            &headers(resp),
+            date("Sun, 06 Nov 1994 08:49:37 GMT"),
+        )
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(date("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(date("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(date("Sun Nov  6 08:49:37 1994"), expected);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("0"), None);
+    }
+
+    #[test]
+    fn parses_cache_control() {
+        let value = HeaderValue::from_static(
+            "no-cache=\"Set-Cookie, X-Foo\", max-age=60, MAX-AGE=30, s-maxage=bogus, Private",
+        );
+        let cc = CacheControl::parse([&value]);
+        assert!(cc.no_cache && cc.private && !cc.public);
+        assert_eq!(cc.max_age, Some(Duration::from_secs(30)));
+        assert_eq!(cc.s_maxage, Some(Duration::ZERO));
+        assert_eq!(cc.stale_while_revalidate, None);
+    }
+
+    #[test]
+    fn freshness_lifetime_precedence() {
+        let expires = [
+            ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ("expires", "Sun, 06 Nov 1994 09:49:37 GMT"),
+        ];
+        assert_eq!(policy(&[], 200, &expires).ttl(), Duration::from_secs(3600));
+
+        let mut headers = expires.to_vec();
+        headers.push(("cache-control", "max-age=60"));
+        assert_eq!(policy(&[], 200, &headers).ttl(), Duration::from_secs(60));
+        headers.push(("cache-control", "s-maxage=120"));
+        assert_eq!(policy(&[], 200, &headers).ttl(), Duration::from_secs(120));
+        headers.push(("surrogate-control", "max-age=240"));
+        assert_eq!(policy(&[], 200, &headers).ttl(), Duration::from_secs(240));
+
+        let heuristic = [("last-modified", "Sun, 06 Nov 1994 07:49:37 GMT")];
+        assert_eq!(policy(&[], 200, &heuristic).ttl(), Duration::from_secs(360));
+        assert!(!policy(&[], 302, &heuristic).is_storable());
+
+        let invalid = [("expires", "0")];
+        assert!(policy(&[], 200, &invalid).is_storable());
+        assert_eq!(policy(&[], 200, &invalid).ttl(), Duration::ZERO);
+    }
+
+    #[test]
+    fn storability() {
+        let fresh = [("cache-control", "max-age=60")];
+        assert!(policy(&[], 200, &fresh).is_storable());
+        assert!(policy(&[], 500, &fresh).is_storable());
+        assert!(!policy(&[("cache-control", "no-store")], 200, &fresh).is_storable());
+        assert!(!policy(&[], 200, &[("cache-control", "max-age=60, no-store")]).is_storable());
+        assert!(!policy(&[], 200, &[("cache-control", "max-age=60, private")]).is_storable());
+        assert!(policy(
+            &[],
+            200,
+            &[
+                ("cache-control", "max-age=60, private"),
+                ("surrogate-control", "max-age=60")
+            ]
+        )
+        .is_storable());
+        assert!(!policy(&[("authorization", "secret")], 200, &fresh).is_storable());
+        assert!(policy(
+            &[("authorization", "secret")],
+            200,
+            &[("cache-control", "s-maxage=60")]
+        )
+        .is_storable());
+        assert!(!policy(&[], 200, &[("cache-control", "max-age=60"), ("vary", "*")]).is_storable());
+    }
+
+    #[test]
+    fn age_revalidation_and_metadata() {
+        let p = policy(
+            &[],
+            200,
+            &[
+                ("date", "Sun, 06 Nov 1994 08:49:07 GMT"),
+                ("age", "10"),
+                ("cache-control", "max-age=60, stale-while-revalidate=30"),
+                ("vary", "Accept-Encoding, accept-language"),
+                ("surrogate-key", "a  b"),
+            ],
+        );
+        assert_eq!(p.age(), Duration::from_secs(30));
+        assert!(p.is_fresh());
+        assert_eq!(p.stale_while_revalidate(), Duration::from_secs(30));
+        assert_eq!(p.vary(), [header::ACCEPT_ENCODING, header::ACCEPT_LANGUAGE]);
+        assert_eq!(p.surrogate_keys(), ["a", "b"]);
+
+        let p = policy(
+            &[],
+            200,
+            &[(
+                "cache-control",
+                "max-age=60, stale-while-revalidate=30, must-revalidate",
+            )],
+        );
+        assert_eq!(p.stale_while_revalidate(), Duration::ZERO);
+        let p = policy(&[], 200, &[("cache-control", "max-age=60, no-cache")]);
+        assert!(p.is_storable() && !p.is_fresh());
+    }
+
+    #[test]
+    fn stored_responses_round_trip() {
+        let mut headers = Headers::default();
+        headers
+            .0
+            .push((header::ETAG, HeaderValue::from_static("\"v1\"")));
+        headers
+            .0
+            .push((header::LINK, HeaderValue::from_static("</a>; rel=b: c")));
+        headers
+            .0
+            .push((header::LINK, HeaderValue::from_static("</d>")));
+        let stored = StoredResponse {
+            status: StatusCode::NOT_FOUND,
+            headers,
+        };
+        let decoded = StoredResponse::decode(&stored.encode()).unwrap();
+        assert_eq!(decoded.status, StatusCode::NOT_FOUND);
+        assert_eq!(decoded.headers.0, stored.headers.0);
+        assert!(matches!(
+            StoredResponse::decode(b"hello"),
+            Err(CacheError::InvalidMetadata)
+        ));
+
+        // Cookies set for one client are not cached for the others.
+        let resp = Response::new()
+            .with_header(header::ETAG, "\"v1\"")
+            .with_header(header::SET_COOKIE, "session=secret");
+        let stored =
+            StoredResponse::decode(&StoredResponse::from_response(&resp).encode()).unwrap();
+        assert!(stored.headers.get(&header::ETAG).is_some());
+        assert!(!stored.headers.contains(&header::SET_COOKIE));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn readthrough_stores_and_revalidates() {
+        use crate::testing;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        testing::reset();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        testing::register_backend("origin", move |req| {
+            log.borrow_mut()
+                .push(req.get_header_str("if-none-match").map(str::to_owned));
+            if req.get_header_str("if-none-match") == Some("\"v1\"") {
+                Response::from_status(StatusCode::NOT_MODIFIED)
+                    .with_header("cache-control", "max-age=60, stale-while-revalidate=60")
+                    .with_header("etag", "\"v1\"")
+            } else {
+                Response::from_body("hello")
+                    .with_header("cache-control", "max-age=60, stale-while-revalidate=60")
+                    .with_header("etag", "\"v1\"")
+                    .with_header("set-cookie", "session=first")
+            }
+        });
+        let get = || {
+            readthrough(Request::get("http://example.com/"), |req| {
+                Ok(req.send("origin")?)
+            })
+            .unwrap()
+        };
+
+        let mut miss = get();
+        assert_eq!(miss.get_header_str("set-cookie"), Some("session=first"));
+        assert_eq!(miss.take_body_str(), "hello");
+        testing::advance_clock(Duration::from_secs(30));
+        let mut hit = get();
+        assert_eq!(hit.get_header_str("age"), Some("30"));
+        assert!(!hit.contains_header("set-cookie"));
+        assert_eq!(hit.take_body_str(), "hello");
+        assert_eq!(*seen.borrow(), [None]);
+
+        // Once stale, the response is revalidated with a conditional request.
+        testing::advance_clock(Duration::from_secs(40));
+        let mut revalidated = get();
+        assert_eq!(revalidated.get_status(), StatusCode::OK);
+        assert_eq!(revalidated.take_body_str(), "hello");
+        assert_eq!(*seen.borrow(), [None, Some("\"v1\"".to_owned())]);
+        // The freshened response is served from the cache again.
+        assert_eq!(get().take_body_str(), "hello");
+        assert_eq!(seen.borrow().len(), 2);
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn readthrough_varies_and_skips_uncacheable() {
+        use crate::testing;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        testing::reset();
+        let fetches = Rc::new(Cell::new(0));
+        let count = fetches.clone();
+        testing::register_backend("origin", move |req| {
+            count.set(count.get() + 1);
+            let lang = req.get_header_str("accept-language").unwrap_or("en");
+            let cache_control = if req.get_path() == "/private" {
+                "private"
+            } else {
+                "max-age=60"
+            };
+            Response::from_body(lang.to_owned())
+                .with_header("cache-control", cache_control)
+                .with_header("vary", "accept-language")
+        });
+        let get = |path: &str, lang: &str| {
+            let req = Request::get(format!("http://example.com{}", path))
+                .with_header("accept-language", lang);
+            readthrough(req, |req| Ok(req.send("origin")?))
+                .unwrap()
+                .take_body_str()
+        };
+
+        assert_eq!(get("/", "fr"), "fr");
+        assert_eq!(get("/", "de"), "de");
+        assert_eq!(get("/", "fr"), "fr");
+        assert_eq!(fetches.get(), 2);
+        assert_eq!(get("/private", "fr"), "fr");
+        assert_eq!(get("/private", "fr"), "fr");
+        assert_eq!(fetches.get(), 4);
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn revalidate_replaces_modified_responses() {
+        use crate::testing;
+
+        testing::reset();
+        testing::register_backend("origin", |req| {
+            let version = if req.get_header_str("if-modified-since").is_some() {
+                "v2"
+            } else {
+                "v1"
+            };
+            Response::from_body(version)
+                .with_header("cache-control", "max-age=60, stale-while-revalidate=60")
+                .with_header("last-modified", "Sun, 06 Nov 1994 08:49:37 GMT")
+        });
+        let req = Request::get("http://example.com/");
+        let fetch = |req: Request| Ok(req.send("origin")?);
+        assert_eq!(
+            readthrough(req.clone_without_body(), fetch)
+                .unwrap()
+                .take_body_str(),
+            "v1"
+        );
+
+        let lookup = || Transaction::lookup(cache_key(&req)).execute().unwrap();
+        let fresh = lookup();
+        let validators = Validators::from_found(&fresh.found().unwrap()).unwrap();
+        assert_eq!(validators.etag, None);
+        assert!(validators.last_modified.is_some());
+        assert!(matches!(
+            revalidate(fresh, &req, "origin"),
+            Err(CacheError::InvalidOperation)
+        ));
+
+        testing::advance_clock(Duration::from_secs(90));
+        let stale = lookup();
+        assert!(stale.must_insert_or_update());
+        // The stale response can be served before it is revalidated.
+        let mut served = cached_response(&stale.found().unwrap()).unwrap();
+        assert_eq!(served.get_header_str("age"), Some("90"));
+        assert_eq!(served.take_body_str(), "v1");
+        assert_eq!(
+            revalidate(stale, &req, "origin").unwrap().take_body_str(),
+            "v2"
+        );
+        let found = lookup().found().unwrap().to_stream().unwrap();
+        assert_eq!(found.into_string(), "v2");
+    }
+}
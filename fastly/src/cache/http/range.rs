@@ -0,0 +1,398 @@
+//! Serving byte ranges of cached items, following [RFC 9110 section
+//! 14](https://www.rfc-editor.org/rfc/rfc9110#section-14).
+
+use super::{parse_http_date, StoredResponse, Validators};
+use crate::cache::core::{CacheError, Found};
+use crate::http::header::{self, HeaderValue};
+use crate::http::{Method, StatusCode};
+use crate::{Body, Request, Response};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+
+/// The most ranges that a `Range` header may name before it is ignored.
+///
+/// Each range of a `multipart/byteranges` body is streamed from the cache separately, so this bounds
+/// how much larger than the item a response can be, as [RFC 9110 section
+/// 14.2](https://www.rfc-editor.org/rfc/rfc9110#section-14.2) suggests.
+const MAX_RANGES: usize = 32;
+
+/// A range of bytes, with both ends inclusive.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct ByteRange {
+    first: u64,
+    last: u64,
+}
+
+impl ByteRange {
+    fn content_range(&self, len: u64) -> String {
+        format!("bytes {}-{}/{}", self.first, self.last, len)
+    }
+}
+
+/// The outcome of evaluating a `Range` header against an item of known length.
+#[derive(Debug, Eq, PartialEq)]
+enum Ranges {
+    /// The header is invalid, or uses a unit other than bytes, so the whole item is sent.
+    Ignored,
+    /// None of the ranges overlap the item.
+    Unsatisfiable,
+    /// The satisfiable ranges, in ascending order, with overlapping and adjacent ranges merged.
+    Satisfiable(Vec<ByteRange>),
+}
+
+/// Parse the digits of a range bound, saturating values too large to represent.
+fn parse_pos(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(s.parse().unwrap_or(u64::MAX))
+}
+
+/// Parse a `Range` header, and resolve its ranges against an item of the given length.
+///
+/// Ranges that start beyond the end of the item are dropped, and ranges that extend beyond it are
+/// shortened to fit. A header with more than [`MAX_RANGES`] ranges is ignored.
+fn parse_ranges(value: &str, len: u64) -> Ranges {
+    let Some((unit, specs)) = value.split_once('=') else {
+        return Ranges::Ignored;
+    };
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Ranges::Ignored;
+    }
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        count += 1;
+        if count > MAX_RANGES {
+            return Ranges::Ignored;
+        }
+        let Some((first, last)) = spec.split_once('-') else {
+            return Ranges::Ignored;
+        };
+        let (first, last) = (first.trim(), last.trim());
+        let range = if first.is_empty() {
+            // A suffix range, for the final bytes of the item.
+            let Some(suffix) = parse_pos(last) else {
+                return Ranges::Ignored;
+            };
+            (suffix > 0 && len > 0).then(|| ByteRange {
+                first: len.saturating_sub(suffix),
+                last: len - 1,
+            })
+        } else {
+            let Some(first) = parse_pos(first) else {
+                return Ranges::Ignored;
+            };
+            let last = if last.is_empty() {
+                u64::MAX
+            } else {
+                match parse_pos(last) {
+                    Some(last) if last >= first => last,
+                    _ => return Ranges::Ignored,
+                }
+            };
+            (first < len).then(|| ByteRange {
+                first,
+                last: last.min(len - 1),
+            })
+        };
+        ranges.extend(range);
+    }
+    if count == 0 {
+        Ranges::Ignored
+    } else if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Satisfiable(coalesce(ranges))
+    }
+}
+
+/// Sort ranges, and merge those that overlap or are adjacent, so that no byte is sent twice.
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.first);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(prev) if range.first <= prev.last.saturating_add(1) => {
+                prev.last = prev.last.max(range.last);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Returns `true` if an `If-Range` header matches the validators of the cached response, and so the
+/// `Range` header should be honored.
+///
+/// Entity tags must match using the strong comparison, and dates must match the `Last-Modified`
+/// header exactly.
+fn if_range_matches(if_range: &HeaderValue, validators: &Validators) -> bool {
+    let Ok(if_range) = if_range.to_str() else {
+        return false;
+    };
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        let strong = |tag: &str| !tag.starts_with("W/") && tag.starts_with('"');
+        match validators.etag.as_ref().and_then(|v| v.to_str().ok()) {
+            Some(etag) => strong(if_range) && strong(etag) && etag == if_range,
+            None => false,
+        }
+    } else {
+        let last_modified = validators
+            .last_modified
+            .as_ref()
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_http_date);
+        last_modified.is_some() && last_modified == parse_http_date(if_range)
+    }
+}
+
+/// A boundary for a `multipart/byteranges` body, which is derived from the ranges so that it is
+/// stable for identical requests.
+fn multipart_boundary(ranges: &[ByteRange], len: u64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(len.to_be_bytes());
+    for range in ranges {
+        hasher.update(range.first.to_be_bytes());
+        hasher.update(range.last.to_be_bytes());
+    }
+    let mut boundary = String::with_capacity(32);
+    for byte in &hasher.finalize()[..16] {
+        write!(boundary, "{:02x}", byte).expect("writing to a `String` cannot fail");
+    }
+    boundary
+}
+
+/// Build the response for a request to a cached item, honoring the `Range` header of the request.
+///
+/// See [`Response::from_cached_range()`] for details.
+pub(crate) fn from_cached_range(found: &Found, req: &Request) -> Result<Response, CacheError> {
+    // Items cached by the HTTP Cache API have their status and headers in the user metadata; other
+    // items are sent as plain `200 OK` responses.
+    let stored = StoredResponse::decode(&found.user_metadata()).ok();
+    let validators = stored
+        .as_ref()
+        .map(|s| Validators::from_headers(&s.headers))
+        .unwrap_or_default();
+    let (mut resp, content_type) = match stored {
+        Some(stored) => {
+            let content_type = stored.headers.get(&header::CONTENT_TYPE).cloned();
+            (stored.into_response(), content_type)
+        }
+        None => (Response::new(), None),
+    };
+    resp.set_header(header::AGE, found.age().as_secs().to_string());
+
+    // Ranges only apply to complete `200 OK` responses to `GET` requests.
+    let len = match found.known_length() {
+        Some(len) if resp.get_status() == StatusCode::OK => len,
+        _ => {
+            resp.set_body(found.to_stream()?);
+            return Ok(resp);
+        }
+    };
+    resp.set_header(header::ACCEPT_RANGES, "bytes");
+    let range = req.get_header(header::RANGE).and_then(|v| v.to_str().ok());
+    let if_range_ok = match req.get_header(header::IF_RANGE) {
+        Some(if_range) => if_range_matches(if_range, &validators),
+        None => true,
+    };
+    let ranges = match range {
+        Some(range) if *req.get_method() == Method::GET && if_range_ok => parse_ranges(range, len),
+        _ => Ranges::Ignored,
+    };
+
+    if ranges != Ranges::Ignored {
+        // The length of a partial body is set from the body itself.
+        resp.remove_header(header::CONTENT_LENGTH);
+    }
+    match ranges {
+        Ranges::Ignored => resp.set_body(found.to_stream()?),
+        Ranges::Unsatisfiable => {
+            resp.set_status(StatusCode::RANGE_NOT_SATISFIABLE);
+            resp.remove_header(header::CONTENT_TYPE);
+            resp.set_header(header::CONTENT_RANGE, format!("bytes */{}", len));
+        }
+        Ranges::Satisfiable(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            resp.set_status(StatusCode::PARTIAL_CONTENT);
+            resp.set_header(header::CONTENT_RANGE, range.content_range(len));
+            resp.set_body(found.to_stream_from_range(Some(range.first), Some(range.last))?);
+        }
+        Ranges::Satisfiable(ranges) => {
+            let boundary = multipart_boundary(&ranges, len);
+            let mut body = Body::new();
+            for range in &ranges {
+                let mut part = format!("\r\n--{}\r\n", boundary);
+                if let Some(content_type) = content_type.as_ref().and_then(|v| v.to_str().ok()) {
+                    write!(part, "Content-Type: {}\r\n", content_type)
+                        .expect("writing to a `String` cannot fail");
+                }
+                write!(part, "Content-Range: {}\r\n\r\n", range.content_range(len))
+                    .expect("writing to a `String` cannot fail");
+                body.append(Body::from(part));
+                body.append(found.to_stream_from_range(Some(range.first), Some(range.last))?);
+            }
+            body.append(Body::from(format!("\r\n--{}--\r\n", boundary)));
+            resp.set_status(StatusCode::PARTIAL_CONTENT);
+            resp.set_header(
+                header::CONTENT_TYPE,
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+            resp.set_body(body);
+        }
+    }
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(first: u64, last: u64) -> ByteRange {
+        ByteRange { first, last }
+    }
+
+    #[test]
+    fn parses_ranges() {
+        use Ranges::*;
+        assert_eq!(
+            parse_ranges("bytes=0-499", 1000),
+            Satisfiable(vec![range(0, 499)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=500-", 1000),
+            Satisfiable(vec![range(500, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=-100", 1000),
+            Satisfiable(vec![range(900, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=-2000", 1000),
+            Satisfiable(vec![range(0, 999)])
+        );
+        assert_eq!(
+            parse_ranges("Bytes = 0-0, 990-2000 ,2000-", 1000),
+            Satisfiable(vec![range(0, 0), range(990, 999)])
+        );
+        assert_eq!(parse_ranges("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(parse_ranges("bytes=5-1", 1000), Ignored);
+        assert_eq!(parse_ranges("bytes=a-b", 1000), Ignored);
+        assert_eq!(parse_ranges("items=0-1", 1000), Ignored);
+        assert_eq!(parse_ranges("bytes=", 1000), Ignored);
+    }
+
+    #[test]
+    fn merges_and_limits_ranges() {
+        use Ranges::*;
+        assert_eq!(
+            parse_ranges("bytes=0-,0-,-1000,0-", 1000),
+            Satisfiable(vec![range(0, 999)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=500-599,0-9,5-19,20-29", 1000),
+            Satisfiable(vec![range(0, 29), range(500, 599)])
+        );
+        let many = |n| format!("bytes={}", vec!["0-0"; n].join(","));
+        assert_eq!(
+            parse_ranges(&many(MAX_RANGES), 1000),
+            Satisfiable(vec![range(0, 0)])
+        );
+        assert_eq!(parse_ranges(&many(MAX_RANGES + 1), 1000), Ignored);
+    }
+
+    #[test]
+    fn matches_if_range() {
+        let validators = Validators {
+            etag: Some(HeaderValue::from_static("\"v1\"")),
+            last_modified: Some(HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT")),
+        };
+        let matches = |v| if_range_matches(&HeaderValue::from_static(v), &validators);
+        assert!(matches("\"v1\""));
+        assert!(!matches("W/\"v1\""));
+        assert!(!matches("\"v2\""));
+        assert!(matches("Sunday, 06-Nov-94 08:49:37 GMT"));
+        assert!(!matches("Sun, 06 Nov 1994 08:49:38 GMT"));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn serves_ranges_of_cached_items() {
+        use crate::cache::core::{insert, lookup, CacheKey};
+        use crate::testing;
+
+        testing::reset();
+        let key = CacheKey::from_static(b"segment");
+        let mut writer = insert(key.clone(), std::time::Duration::from_secs(60))
+            .known_length(10)
+            .execute()
+            .unwrap();
+        writer.write_bytes(b"0123456789");
+        writer.finish().unwrap();
+        let found = lookup(key).execute().unwrap().unwrap();
+        let get = |range: &str| {
+            let req = Request::get("http://example.com/").with_header(header::RANGE, range);
+            from_cached_range(&found, &req).unwrap()
+        };
+
+        let mut resp = get("bytes=2-4");
+        assert_eq!(resp.get_status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            resp.get_header_str(header::CONTENT_RANGE),
+            Some("bytes 2-4/10")
+        );
+        assert_eq!(resp.take_body_str(), "234");
+
+        let resp = get("bytes=10-");
+        assert_eq!(resp.get_status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(
+            resp.get_header_str(header::CONTENT_RANGE),
+            Some("bytes */10")
+        );
+
+        let mut resp = get("bytes=0-0,-2");
+        let content_type = resp
+            .get_header_str(header::CONTENT_TYPE)
+            .unwrap()
+            .to_owned();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        assert_eq!(
+            resp.take_body_str(),
+            format!(
+                "\r\n--{b}\r\nContent-Range: bytes 0-0/10\r\n\r\n0\
+                 \r\n--{b}\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+                 \r\n--{b}--\r\n",
+                b = boundary
+            )
+        );
+
+        let req = Request::get("http://example.com/")
+            .with_header(header::RANGE, "bytes=0-1")
+            .with_header(header::IF_RANGE, "\"v1\"");
+        let mut resp = from_cached_range(&found, &req).unwrap();
+        assert_eq!(resp.get_status(), StatusCode::OK);
+        assert_eq!(resp.take_body_str(), "0123456789");
+
+        // Cookies set by the response that was cached are not replayed with its ranges.
+        let key = CacheKey::from_static(b"response");
+        let origin = Response::new().with_header(header::SET_COOKIE, "session=secret");
+        let mut writer = insert(key.clone(), std::time::Duration::from_secs(60))
+            .known_length(10)
+            .user_metadata(StoredResponse::from_response(&origin).encode())
+            .execute()
+            .unwrap();
+        writer.write_bytes(b"0123456789");
+        writer.finish().unwrap();
+        let found = lookup(key).execute().unwrap().unwrap();
+        let req = Request::get("http://example.com/").with_header(header::RANGE, "bytes=0-1");
+        let mut resp = from_cached_range(&found, &req).unwrap();
+        assert_eq!(resp.get_status(), StatusCode::PARTIAL_CONTENT);
+        assert!(!resp.contains_header(header::SET_COOKIE));
+        assert_eq!(resp.take_body_str(), "01");
+    }
+}
@@ -17,12 +17,22 @@
 //! The Simple Cache API is implemented in terms of the [Core Cache API][core]. Items inserted with
 //! the Core Cache API can be read by the Simple Cache API, and vice versa. However, some metadata
 //! and advanced features like revalidation may be not be available via the Simple Cache API.
+//!
+//! ## Varying on request headers
+//!
+//! Entries can be varied on request headers, such as `Accept-Language` or `Accept-Encoding`, by
+//! naming them with [`InsertOptions::vary_by()`] and providing the request headers with
+//! [`LookupOptions`] when calling [`get_with_opts()`] or [`get_or_set_with_opts()`]. Each distinct
+//! combination of values for the varied headers gets its own entry under the same key.
 
+use bytes::Bytes;
 use fastly_shared::FastlyStatus;
+use http::header::{HeaderName, HeaderValue};
 use sha2::{Digest, Sha256};
 
+use crate::convert::{ToHeaderName, ToHeaderValue};
 use crate::http::purge::purge_surrogate_key;
-use crate::Body;
+use crate::{Body, Request};
 
 pub use super::core::CacheKey;
 use super::core::{self, Transaction};
@@ -84,12 +94,92 @@ impl From<core::CacheError> for CacheError {
 ///
 #[doc = include_str!("../../docs/snippets/key-argument.md")]
 pub fn get(key: impl Into<CacheKey>) -> Result<Option<Body>, CacheError> {
-    let Some(found) = core::lookup(key.into()).execute()? else {
+    get_with_opts(key, &LookupOptions::default())
+}
+
+/// Get the entry associated with the given cache key and request headers, if it exists.
+///
+/// The [`LookupOptions`] argument provides the request headers used to select among entries that
+/// were inserted with [`InsertOptions::vary_by()`].
+///
+/// ```no_run
+/// # use fastly::cache::simple::*;
+/// # let req = fastly::Request::get("https://example.com/");
+/// let opts = LookupOptions::new().request_headers(&req);
+/// if let Some(value) = get_with_opts("my_key", &opts).unwrap() {
+///     let cached_string = value.into_string();
+///     println!("the cached string was: {cached_string}");
+/// }
+/// ```
+///
+#[doc = include_str!("../../docs/snippets/key-argument.md")]
+pub fn get_with_opts(
+    key: impl Into<CacheKey>,
+    opts: &LookupOptions,
+) -> Result<Option<Body>, CacheError> {
+    let lookup = opts
+        .headers()
+        .fold(core::lookup(key.into()), |lookup, (name, values)| {
+            lookup.header_values(name, values)
+        });
+    let Some(found) = lookup.execute()? else {
         return Ok(None);
     };
     Ok(Some(found.to_stream()?))
 }
 
+/// Options for [`get_with_opts()`] and [`get_or_set_with_opts()`].
+#[derive(Clone, Debug, Default)]
+pub struct LookupOptions {
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl LookupOptions {
+    /// Create options with no request headers, equivalent to the options used by [`get()`] and
+    /// [`get_or_set_with()`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a request header value for the lookup.
+    ///
+    /// Values added for the same header name are all used for the lookup, in the order they were
+    /// added.
+    pub fn header(mut self, name: impl ToHeaderName, value: impl ToHeaderValue) -> Self {
+        self.headers.push((name.into_owned(), value.into_owned()));
+        self
+    }
+
+    /// Add all of the headers of the given request for the lookup.
+    ///
+    /// Only the headers named by an entry's [`InsertOptions::vary_by()`] list are used to select it,
+    /// so it is fine to include headers that are not varied on.
+    pub fn request_headers(mut self, req: &Request) -> Self {
+        self.headers.extend(
+            req.get_headers()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        self
+    }
+
+    /// Iterate over the distinct header names, along with all of the values for each.
+    fn headers(&self) -> impl Iterator<Item = (&HeaderName, Vec<&HeaderValue>)> {
+        self.headers
+            .iter()
+            .enumerate()
+            .filter(|(i, (name, _))| !self.headers[..*i].iter().any(|(n, _)| n == name))
+            .map(|(_, (name, _))| {
+                let values = self
+                    .headers
+                    .iter()
+                    .filter(|(n, _)| n == name)
+                    .map(|(_, value)| value)
+                    .collect();
+                (name, values)
+            })
+    }
+}
+
 /// Get the entry associated with the given cache key if it exists, or insert and return the
 /// specified entry.
 ///
@@ -110,13 +200,8 @@ pub fn get_or_set(
     value: impl Into<Body>,
     ttl: Duration,
 ) -> Result<Body, CacheError> {
-    get_or_set_with(key, || {
-        Ok(CacheEntry {
-            value: value.into(),
-            ttl,
-        })
-    })
-    .map(|opt| opt.expect("provided closure is infallible"))
+    get_or_set_with(key, || Ok(CacheEntry::new(value, ttl)))
+        .map(|opt| opt.expect("provided closure is infallible"))
 }
 
 /// The return type of the closure provided to [`get_or_set_with()`].
@@ -130,6 +215,72 @@ pub struct CacheEntry {
     pub ttl: Duration,
 }
 
+impl CacheEntry {
+    /// Create an entry with the given value and time-to-live.
+    ///
+    #[doc = include_str!("../../docs/snippets/body-argument.md")]
+    pub fn new(value: impl Into<Body>, ttl: Duration) -> Self {
+        Self {
+            value: value.into(),
+            ttl,
+        }
+    }
+}
+
+/// Options for the entry inserted by [`get_or_set_with_opts()`].
+#[derive(Clone, Debug, Default)]
+pub struct InsertOptions {
+    stale_while_revalidate: Duration,
+    vary_by: Vec<HeaderName>,
+    surrogate_keys: Vec<String>,
+    user_metadata: Bytes,
+}
+
+impl InsertOptions {
+    /// Create options with none set, equivalent to the options used by [`get_or_set_with()`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the period after the time-to-live during which the entry may still be returned.
+    ///
+    /// This is `Duration::ZERO` by default. The Simple Cache API does not revalidate entries, so a
+    /// stale entry is returned as-is until this period runs out.
+    pub fn stale_while_revalidate(mut self, duration: Duration) -> Self {
+        self.stale_while_revalidate = duration;
+        self
+    }
+
+    /// Set the request headers that must match for a lookup to return the entry.
+    ///
+    /// The values of these headers are taken from the [`LookupOptions`] given to
+    /// [`get_or_set_with_opts()`]; headers missing from the options only match lookups where they
+    /// are also missing.
+    pub fn vary_by(mut self, headers: impl IntoIterator<Item = impl ToHeaderName>) -> Self {
+        self.vary_by = headers.into_iter().map(|h| h.into_owned()).collect();
+        self
+    }
+
+    /// Add a surrogate key for the entry, which can be purged with
+    /// [`purge_surrogate_key()`][crate::http::purge::purge_surrogate_key()].
+    ///
+    /// The entry is always given the surrogate keys that [`purge()`] relies on, in addition to
+    /// these.
+    pub fn surrogate_key(mut self, key: impl Into<String>) -> Self {
+        self.surrogate_keys.push(key.into());
+        self
+    }
+
+    /// Set arbitrary user-defined metadata to store alongside the value.
+    ///
+    /// This can be read with [`Found::user_metadata()`][core::Found::user_metadata()] via the
+    /// [Core Cache API][core].
+    pub fn user_metadata(mut self, user_metadata: Bytes) -> Self {
+        self.user_metadata = user_metadata;
+        self
+    }
+}
+
 /// Get the entry associated with the given cache key if it exists, or insert and return an entry
 /// specified by running the given closure.
 ///
@@ -147,10 +298,7 @@ pub struct CacheEntry {
 /// # use fastly::cache::simple::*;
 /// # use std::time::Duration;
 /// let value = get_or_set_with("my_key", || {
-///     Ok(CacheEntry {
-///         value: "hello!".into(),
-///         ttl: Duration::from_secs(60),
-///     })
+///     Ok(CacheEntry::new("hello!", Duration::from_secs(60)))
 /// })
 /// .unwrap()
 /// .expect("closure always returns `Ok`, so we have a value");
@@ -176,11 +324,62 @@ pub fn get_or_set_with<F>(
     key: impl Into<CacheKey>,
     make_entry: F,
 ) -> Result<Option<Body>, CacheError>
+where
+    F: FnOnce() -> Result<CacheEntry, anyhow::Error>,
+{
+    get_or_set_with_opts(
+        key,
+        &LookupOptions::default(),
+        &InsertOptions::default(),
+        make_entry,
+    )
+}
+
+/// Get the entry associated with the given cache key and request headers if it exists, or insert
+/// and return an entry specified by running the given closure.
+///
+/// This behaves like [`get_or_set_with()`], except that the [`LookupOptions`] argument provides
+/// the request headers used to select among entries, and to associate with an entry inserted with
+/// [`InsertOptions::vary_by()`]. The [`InsertOptions`] argument applies only if the closure is run.
+///
+#[doc = include_str!("../../docs/snippets/key-argument.md")]
+///
+/// ## Example varying on a request header
+///
+/// ```no_run
+/// # use fastly::cache::simple::*;
+/// # use fastly::http::header;
+/// # use std::time::Duration;
+/// # let req = fastly::Request::get("https://example.com/");
+/// let lookup_opts = LookupOptions::new().request_headers(&req);
+/// let insert_opts = InsertOptions::new().vary_by([header::ACCEPT_LANGUAGE]);
+/// let value = get_or_set_with_opts("greeting", &lookup_opts, &insert_opts, || {
+///     let greeting = match req.get_header_str(header::ACCEPT_LANGUAGE) {
+///         Some(lang) if lang.starts_with("fr") => "bonjour!",
+///         _ => "hello!",
+///     };
+///     Ok(CacheEntry::new(greeting, Duration::from_secs(60)))
+/// })
+/// .unwrap()
+/// .expect("closure always returns `Ok`, so we have a value");
+/// ```
+pub fn get_or_set_with_opts<F>(
+    key: impl Into<CacheKey>,
+    lookup_opts: &LookupOptions,
+    insert_opts: &InsertOptions,
+    make_entry: F,
+) -> Result<Option<Body>, CacheError>
 where
     F: FnOnce() -> Result<CacheEntry, anyhow::Error>,
 {
     let key = key.into();
-    let lookup_tx = Transaction::lookup(key.clone()).execute()?;
+    let lookup_tx = lookup_opts
+        .headers()
+        .fold(
+            Transaction::lookup(key.clone()),
+            |lookup, (name, values)| lookup.header_values(name, values),
+        )
+        .execute()?;
     if !lookup_tx.must_insert_or_update() {
         if let Some(found) = lookup_tx.found() {
             // the value is already present, so just return it
@@ -193,13 +392,22 @@ where
     // run the user-provided closure to produce the entry, tagging it as a user error if something
     // goes wrong
     let CacheEntry { value, ttl } = make_entry().map_err(CacheError::GetOrSet)?;
+    let purge_keys = [
+        surrogate_key_for_cache_key(&key, PurgeScope::Pop),
+        surrogate_key_for_cache_key(&key, PurgeScope::Global),
+    ];
     // perform a standard insert-and-read-back
     let (mut insert_body, found) = lookup_tx
         .insert(ttl)
-        .surrogate_keys([
-            surrogate_key_for_cache_key(&key, PurgeScope::Pop).as_str(),
-            surrogate_key_for_cache_key(&key, PurgeScope::Global).as_str(),
-        ])
+        .stale_while_revalidate(insert_opts.stale_while_revalidate)
+        .vary_by(&insert_opts.vary_by)
+        .surrogate_keys(
+            purge_keys
+                .iter()
+                .chain(&insert_opts.surrogate_keys)
+                .map(String::as_str),
+        )
+        .user_metadata(insert_opts.user_metadata.clone())
         .execute_and_stream_back()?;
     insert_body.append(value.into());
     insert_body.finish()?;
@@ -337,3 +545,62 @@ fn surrogate_key_for_cache_key(key: &CacheKey, scope: PurgeScope) -> String {
     }
     sk_str
 }
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::http::header;
+    use crate::testing;
+
+    fn greet(lang: Option<&str>, greeting: &'static str) -> Option<String> {
+        let mut opts = LookupOptions::new().header("x-unrelated", "1");
+        if let Some(lang) = lang {
+            opts = opts.header(header::ACCEPT_LANGUAGE, lang);
+        }
+        let insert_opts = InsertOptions::new().vary_by([header::ACCEPT_LANGUAGE]);
+        get_or_set_with_opts("greeting", &opts, &insert_opts, || {
+            Ok(CacheEntry {
+                value: greeting.into(),
+                ttl: Duration::from_secs(60),
+            })
+        })
+        .unwrap()
+        .map(Body::into_string)
+    }
+
+    #[test]
+    fn entries_vary_on_request_headers() {
+        testing::reset();
+        assert_eq!(greet(Some("fr"), "bonjour").unwrap(), "bonjour");
+        assert_eq!(greet(Some("en"), "hello").unwrap(), "hello");
+        assert_eq!(greet(Some("fr"), "salut").unwrap(), "bonjour");
+        assert_eq!(greet(None, "hi").unwrap(), "hi");
+
+        let req = Request::get("http://example.com/").with_header(header::ACCEPT_LANGUAGE, "en");
+        let opts = LookupOptions::new().request_headers(&req);
+        let value = get_with_opts("greeting", &opts).unwrap().unwrap();
+        assert_eq!(value.into_string(), "hello");
+        let opts = LookupOptions::new().header(header::ACCEPT_LANGUAGE, "de");
+        assert!(get_with_opts("greeting", &opts).unwrap().is_none());
+    }
+
+    #[test]
+    fn entries_store_options() {
+        testing::reset();
+        let insert_opts = InsertOptions::new()
+            .stale_while_revalidate(Duration::from_secs(10))
+            .surrogate_key("my-key")
+            .user_metadata(Bytes::from_static(b"meta"));
+        get_or_set_with_opts("key", &LookupOptions::new(), &insert_opts, || {
+            Ok(CacheEntry::new("value", Duration::from_secs(60)))
+        })
+        .unwrap();
+        let found = core::lookup(CacheKey::from_static(b"key"))
+            .execute()
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.stale_while_revalidate(), Duration::from_secs(10));
+        assert_eq!(found.user_metadata(), Bytes::from_static(b"meta"));
+        assert_eq!(found.to_stream().unwrap().into_string(), "value");
+    }
+}
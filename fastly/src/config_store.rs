@@ -2,8 +2,17 @@
 
 pub(crate) mod handle;
 
+use crate::error::ValueError;
+use crate::memo::Memo;
 use handle::ConfigStoreHandle;
 pub use handle::{LookupError, OpenError};
+use serde::de::DeserializeOwned;
+use std::str::FromStr;
+
+/// Derive [`FromConfigStore`] for a struct with named fields.
+///
+/// See the [`FromConfigStore`] trait for the attributes this accepts.
+pub use fastly_macros::FromConfigStore;
 
 /// Maximum Edge Config Store value size.
 ///
@@ -131,4 +140,324 @@ impl ConfigStore {
             .contains(key)
             .unwrap_or_else(|e| panic!("lookup for key `{}` failed: {}", key, e))
     }
+
+    /// Lookup a value in this config store, and parse it with [`FromStr`].
+    ///
+    /// Returns `Ok(None)` if no entry with the given key was found, and an error naming the key if
+    /// the lookup failed or the value could not be parsed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fastly::ConfigStore;
+    /// # let config_store = ConfigStore::open("settings");
+    /// let max_retries: u32 = config_store.get_parsed("max_retries")?.unwrap_or(3);
+    /// # Ok::<(), fastly::error::ValueError>(())
+    /// ```
+    pub fn get_parsed<T>(&self, key: &str) -> Result<Option<T>, ValueError>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.get_with(key, |value| value.parse::<T>().map_err(Into::into))
+    }
+
+    /// Lookup a value in this config store, and deserialize it from JSON.
+    ///
+    /// Returns `Ok(None)` if no entry with the given key was found, and an error naming the key if
+    /// the lookup failed or the value could not be deserialized.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fastly::ConfigStore;
+    /// # let config_store = ConfigStore::open("settings");
+    /// let allowed_origins: Vec<String> =
+    ///     config_store.get_json("allowed_origins")?.unwrap_or_default();
+    /// # Ok::<(), fastly::error::ValueError>(())
+    /// ```
+    pub fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, ValueError> {
+        self.get_with(key, |value| serde_json::from_str(value).map_err(Into::into))
+    }
+
+    fn get_with<T>(
+        &self,
+        key: &str,
+        decode: impl FnOnce(&str) -> Result<T, crate::Error>,
+    ) -> Result<Option<T>, ValueError> {
+        decode_value(key, self.try_get(key), decode)
+    }
+
+    /// Load a [`FromConfigStore`] type from the entries of this config store.
+    pub fn load<T: FromConfigStore>(&self) -> Result<T, ValueError> {
+        T::from_config_store(self)
+    }
+
+    /// Wrap this config store so that its lookups are remembered for the rest of the invocation.
+    ///
+    /// See [`CachedConfigStore`] for details.
+    pub fn cached(self) -> CachedConfigStore {
+        CachedConfigStore::new(self)
+    }
+}
+
+fn decode_value<T>(
+    key: &str,
+    lookup: Result<Option<String>, LookupError>,
+    decode: impl FnOnce(&str) -> Result<T, crate::Error>,
+) -> Result<Option<T>, ValueError> {
+    match lookup {
+        Ok(Some(value)) => decode(&value)
+            .map(Some)
+            .map_err(|e| ValueError::invalid(key, e)),
+        Ok(None) => Ok(None),
+        Err(e) => Err(ValueError::lookup(key, e)),
+    }
+}
+
+/// A [`ConfigStore`] that remembers the result of each lookup for the rest of the invocation.
+///
+/// The first read of a key goes to the host, and later reads of the same key are answered from
+/// memory, including reads of keys that were not found. Failed lookups are not remembered. Keys
+/// that are known to be needed can be looked up up front with
+/// [`prefetch()`][CachedConfigStore::prefetch()].
+///
+/// The number of reads of each key, and the number of lookups that went to the host, are kept so
+/// that hot keys can be found.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use fastly::ConfigStore;
+/// let routes = ConfigStore::open("routes").cached();
+/// routes.prefetch(["origin", "shield", "timeout_ms"])?;
+///
+/// let origin = routes.get("origin");
+/// let timeout_ms: u64 = routes.get_parsed("timeout_ms")?.unwrap_or(1000);
+///
+/// for (key, reads) in routes.lookup_counts() {
+///     println!("{key}: {reads}");
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct CachedConfigStore {
+    store: ConfigStore,
+    memo: Memo<String>,
+}
+
+impl CachedConfigStore {
+    /// Wrap a config store so that its lookups are remembered.
+    pub fn new(store: ConfigStore) -> Self {
+        Self {
+            store,
+            memo: Memo::new(),
+        }
+    }
+
+    /// Open a config store, given its name, and wrap it so that its lookups are remembered.
+    ///
+    /// # Panics
+    ///
+    /// This panics for the same reasons as [`ConfigStore::open()`].
+    pub fn open(name: &str) -> Self {
+        Self::new(ConfigStore::open(name))
+    }
+
+    /// Try to open a config store, given its name, and wrap it so that its lookups are remembered.
+    pub fn try_open(name: &str) -> Result<Self, OpenError> {
+        ConfigStore::try_open(name).map(Self::new)
+    }
+
+    /// Lookup a value in this config store.
+    ///
+    /// # Panics
+    ///
+    /// This may panic for any of the reasons that [`CachedConfigStore::try_get`] would return an
+    /// error.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.try_get(key)
+            .unwrap_or_else(|e| panic!("lookup for key `{}` failed: {}", key, e))
+    }
+
+    /// Try to lookup a value in this config store.
+    ///
+    /// Only the first successful lookup of a key goes to the host.
+    pub fn try_get(&self, key: &str) -> Result<Option<String>, LookupError> {
+        self.memo.get(key, |key| self.store.try_get(key))
+    }
+
+    /// Return true if the config store contains an entry with the given key.
+    ///
+    /// # Panics
+    ///
+    /// This may panic for any of the reasons that [`CachedConfigStore::try_get`] would return an
+    /// error.
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Lookup a value in this config store, and parse it with [`FromStr`].
+    ///
+    /// See [`ConfigStore::get_parsed()`].
+    pub fn get_parsed<T>(&self, key: &str) -> Result<Option<T>, ValueError>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        decode_value(key, self.try_get(key), |value| {
+            value.parse::<T>().map_err(Into::into)
+        })
+    }
+
+    /// Lookup a value in this config store, and deserialize it from JSON.
+    ///
+    /// See [`ConfigStore::get_json()`].
+    pub fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, ValueError> {
+        decode_value(key, self.try_get(key), |value| {
+            serde_json::from_str(value).map_err(Into::into)
+        })
+    }
+
+    /// Look up each of the given keys that has not been looked up already.
+    ///
+    /// Prefetched keys are not counted as read until they are read.
+    pub fn prefetch<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> Result<(), LookupError> {
+        for key in keys {
+            self.memo.prefetch(key, |key| self.store.try_get(key))?;
+        }
+        Ok(())
+    }
+
+    /// The number of times each key has been read, most read first.
+    pub fn lookup_counts(&self) -> Vec<(String, u64)> {
+        self.memo.reads()
+    }
+
+    /// The number of lookups that have gone to the host.
+    pub fn host_lookups(&self) -> u64 {
+        self.memo.host_lookups()
+    }
+
+    /// Unwrap the underlying config store, discarding any remembered lookups.
+    pub fn into_inner(self) -> ConfigStore {
+        self.store
+    }
+}
+
+/// Types that can be loaded from the entries of a config store.
+///
+/// This is usually derived with `#[derive(FromConfigStore)]`, which loads each field of a struct
+/// from the entry with the same key. Values are parsed with [`FromStr`] unless the field is marked
+/// `#[config_store(json)]`, in which case they are deserialized from JSON. Fields of type
+/// `Option<T>` are `None` when the key is missing, and fields marked `#[config_store(default)]`
+/// take their [`Default`] value. Any other missing key is an error naming the key.
+///
+/// The name of the store for [`load()`][FromConfigStore::load()] is given with
+/// `#[config_store(name = "...")]` on the struct, and the key for a field can be changed with
+/// `#[config_store(rename = "...")]`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fastly::config_store::FromConfigStore;
+///
+/// #[derive(FromConfigStore)]
+/// #[config_store(name = "settings")]
+/// struct Settings {
+///     greeting: String,
+///     #[config_store(rename = "max-retries", default)]
+///     max_retries: u32,
+///     #[config_store(json)]
+///     allowed_origins: Vec<String>,
+///     banner: Option<String>,
+/// }
+///
+/// let settings = Settings::load()?;
+/// # Ok::<(), fastly::error::ValueError>(())
+/// ```
+pub trait FromConfigStore: Sized {
+    /// The name of the config store that [`load()`][FromConfigStore::load()] reads from.
+    const STORE_NAME: &'static str;
+
+    /// Load a value from the entries of the given config store.
+    fn from_config_store(store: &ConfigStore) -> Result<Self, ValueError>;
+
+    /// Open the config store named by [`STORE_NAME`][FromConfigStore::STORE_NAME], and load a
+    /// value from its entries.
+    fn load() -> Result<Self, ValueError> {
+        let store = ConfigStore::try_open(Self::STORE_NAME).map_err(|e| ValueError::Open {
+            name: Self::STORE_NAME.to_owned(),
+            source: e.into(),
+        })?;
+        Self::from_config_store(&store)
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[derive(Debug, FromConfigStore)]
+    #[config_store(name = "settings")]
+    struct Settings {
+        greeting: String,
+        #[config_store(rename = "max-retries", default)]
+        max_retries: u32,
+        #[config_store(json)]
+        origins: Vec<String>,
+        banner: Option<String>,
+    }
+
+    #[test]
+    fn loads_typed_values() {
+        testing::reset();
+        testing::insert_config_store_entry("settings", "greeting", "hello");
+        testing::insert_config_store_entry("settings", "origins", r#"["a", "b"]"#);
+        let settings = Settings::load().unwrap();
+        assert_eq!(settings.greeting, "hello");
+        assert_eq!(settings.max_retries, 0);
+        assert_eq!(settings.origins, ["a", "b"]);
+        assert_eq!(settings.banner, None);
+
+        testing::insert_config_store_entry("settings", "max-retries", "three");
+        let err = Settings::load().unwrap_err();
+        assert!(matches!(&err, ValueError::Invalid { key, .. } if key == "max-retries"));
+        assert_eq!(
+            err.to_string(),
+            "invalid value for key `max-retries`: invalid digit found in string"
+        );
+
+        testing::create_config_store("empty");
+        let err = ConfigStore::open("empty").load::<Settings>().unwrap_err();
+        assert_eq!(err.to_string(), "no value found for key `greeting`");
+    }
+
+    #[test]
+    fn cached_lookups() {
+        testing::reset();
+        testing::insert_config_store_entry("routes", "origin", "a.example.com");
+        testing::insert_config_store_entry("routes", "timeout_ms", "250");
+        let routes = ConfigStore::open("routes").cached();
+        routes.prefetch(["origin", "shield"]).unwrap();
+        assert_eq!(routes.host_lookups(), 2);
+        assert!(routes.lookup_counts().iter().all(|(_, reads)| *reads == 0));
+
+        testing::insert_config_store_entry("routes", "origin", "b.example.com");
+        testing::insert_config_store_entry("routes", "shield", "iad");
+        for _ in 0..3 {
+            assert_eq!(routes.get("origin").as_deref(), Some("a.example.com"));
+            assert!(!routes.contains("shield"));
+        }
+        assert_eq!(routes.get_parsed::<u64>("timeout_ms").unwrap(), Some(250));
+        assert_eq!(routes.host_lookups(), 3);
+        assert_eq!(
+            routes.lookup_counts(),
+            [
+                ("origin".to_owned(), 3),
+                ("shield".to_owned(), 3),
+                ("timeout_ms".to_owned(), 1),
+            ]
+        );
+    }
 }
@@ -102,6 +102,76 @@ impl BufferSizeError {
     }
 }
 
+/// An error looking up, decoding, or encoding a typed value in a store.
+///
+/// This is returned by typed accessors such as
+/// [`ConfigStore::get_json()`][crate::ConfigStore::get_json()] and
+/// [`KVStore::lookup_json()`][crate::KVStore::lookup_json()], and by implementations of
+/// [`FromConfigStore`][crate::config_store::FromConfigStore]. Each variant names the store or key it
+/// concerns, so that the message points at the offending entry.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ValueError {
+    /// The store could not be opened.
+    #[error("could not open store `{name}`: {source}")]
+    Open {
+        /// The name of the store.
+        name: String,
+        /// The reason the store could not be opened.
+        #[source]
+        source: Error,
+    },
+    /// No value was found for a required key.
+    #[error("no value found for key `{key}`")]
+    Missing {
+        /// The key that was looked up.
+        key: String,
+    },
+    /// The lookup of the key failed.
+    #[error("lookup for key `{key}` failed: {source}")]
+    Lookup {
+        /// The key that was looked up.
+        key: String,
+        /// The reason the lookup failed.
+        #[source]
+        source: Error,
+    },
+    /// The insert of a value for the key failed.
+    #[error("insert for key `{key}` failed: {source}")]
+    Insert {
+        /// The key of the value.
+        key: String,
+        /// The reason the insert failed.
+        #[source]
+        source: Error,
+    },
+    /// The value of the key could not be decoded as, or encoded from, the requested type.
+    #[error("invalid value for key `{key}`: {source}")]
+    Invalid {
+        /// The key of the value.
+        key: String,
+        /// The reason the value is invalid.
+        #[source]
+        source: Error,
+    },
+}
+
+impl ValueError {
+    pub(crate) fn lookup(key: &str, source: impl Into<Error>) -> Self {
+        ValueError::Lookup {
+            key: key.to_owned(),
+            source: source.into(),
+        }
+    }
+
+    pub(crate) fn invalid(key: &str, source: impl Into<Error>) -> Self {
+        ValueError::Invalid {
+            key: key.to_owned(),
+            source: source.into(),
+        }
+    }
+}
+
 #[non_exhaustive]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, thiserror::Error)]
 /// `HandleError` is for errors that might arise when using the low level handle
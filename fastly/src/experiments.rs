@@ -0,0 +1,576 @@
+//! A/B experiments with sticky, signed assignment cookies.
+//!
+//! An [`Experiment`] has a name and a set of weighted [`Variant`]s, each of which can send
+//! requests to a different backend or path. Experiments can be built in code, or deserialized from
+//! JSON, for example with [`ConfigStore::get_json()`][crate::ConfigStore::get_json()]:
+//!
+//! ```json
+//! {
+//!     "name": "checkout",
+//!     "variants": [
+//!         { "name": "control", "weight": 90 },
+//!         { "name": "one_page", "weight": 10, "backend": "checkout_v2", "path": "/v2/checkout" }
+//!     ]
+//! }
+//! ```
+//!
+//! An [`Assigner`] assigns client requests to variants. A client seen for the first time is
+//! assigned by the same stable hash as [feature flag rollouts][crate::flags::bucket()], so the
+//! assignment is reproducible from the client's bucketing key. The assignment is then kept in a
+//! cookie signed with the assigner's key, and later requests with a valid cookie keep their
+//! variant. Each new assignment writes one JSON exposure line to the assigner's log endpoint, if it
+//! has one.
+//!
+//! An [`Assignment`] adds a header naming the variant to the backend request, and adds the variant
+//! to the request's [cache key][crate::experimental::RequestCacheKey], so that responses cached for
+//! one variant are not served to clients in another. The assignment must therefore be applied to
+//! the request before it is sent.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use fastly::experiments::{Assigner, Experiment, Variant};
+//! use fastly::log::Endpoint;
+//! use fastly::Request;
+//!
+//! let experiment = Experiment::new("checkout")
+//!     .with_variant(Variant::new("control", 90))
+//!     .with_variant(Variant::new("one_page", 10).with_path("/v2/checkout"));
+//! let assigner = Assigner::new(b"signing key".to_vec())
+//!     .with_log_endpoint(Endpoint::from_name("exposures"));
+//!
+//! let mut req = Request::from_client();
+//! let assignment = assigner.assign(&experiment, &req).unwrap();
+//! assignment.apply_to_request(&mut req);
+//! let backend = assignment.variant().backend().unwrap_or("origin");
+//! let mut resp = req.send(backend)?;
+//! assignment.apply_to_response(&mut resp);
+//! resp.send_to_client();
+//! # Ok::<(), fastly::Error>(())
+//! ```
+
+use crate::experimental::RequestCacheKey;
+use crate::flags::{bucket, BUCKETS};
+use crate::http::header::{HeaderName, HOST};
+use crate::http::request::CacheKeyGen;
+use crate::http::{Cookie, SameSite};
+use crate::log::Endpoint;
+use crate::secret_store::plaintext::ct_eq;
+use crate::secret_store::HmacKey;
+use crate::{Request, Response};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::Duration;
+
+/// An experiment with a set of weighted variants.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Experiment {
+    #[serde(deserialize_with = "deserialize_name")]
+    name: String,
+    variants: Vec<Variant>,
+}
+
+impl Experiment {
+    /// Create an experiment with no variants.
+    ///
+    /// The name is used in the names of the experiment's header and cookie, so it should only
+    /// contain letters, numbers, dashes (-) and underscores (_). Requests are not assigned to
+    /// experiments with other names, and deserializing such an experiment fails.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            variants: Vec::new(),
+        }
+    }
+
+    /// Add a variant to this experiment.
+    pub fn with_variant(mut self, variant: Variant) -> Self {
+        self.variants.push(variant);
+        self
+    }
+
+    /// The name of this experiment.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The variants of this experiment.
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+
+    /// The name of the header that carries the variant on backend requests.
+    pub fn header_name(&self) -> String {
+        format!("x-experiment-{}", self.name.to_ascii_lowercase())
+    }
+
+    /// The name of the cookie that keeps a client's variant.
+    pub fn cookie_name(&self) -> String {
+        format!("exp_{}", self.name)
+    }
+
+    fn variant(&self, name: &str) -> Option<&Variant> {
+        self.variants
+            .iter()
+            .find(|variant| variant.name == name && variant.weight > 0)
+    }
+
+    fn variant_for_bucket(&self, bucket: u16) -> Option<&Variant> {
+        let total: u64 = self.variants.iter().map(|v| u64::from(v.weight)).sum();
+        let mut point = u64::from(bucket) * total / u64::from(BUCKETS);
+        for variant in &self.variants {
+            match point.checked_sub(u64::from(variant.weight)) {
+                Some(rest) => point = rest,
+                None => return Some(variant),
+            }
+        }
+        None
+    }
+}
+
+/// A variant of an [`Experiment`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct Variant {
+    #[serde(deserialize_with = "deserialize_name")]
+    name: String,
+    #[serde(default = "default_weight")]
+    weight: u32,
+    #[serde(default)]
+    backend: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// Returns `true` if a name can be used in the header and cookie of an assignment.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn deserialize_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    if !is_valid_name(&name) {
+        return Err(D::Error::custom(format!(
+            "invalid name `{name}`: expected letters, numbers, dashes and underscores"
+        )));
+    }
+    Ok(name)
+}
+
+impl Variant {
+    /// Create a variant with the given name and weight.
+    ///
+    /// A variant is assigned to new clients in proportion to its weight out of the total weight of
+    /// the experiment's variants. A variant with a weight of zero is no longer assigned, and
+    /// clients that had it are reassigned.
+    ///
+    /// The name is kept in the assignment cookie, so it should only contain letters, numbers,
+    /// dashes (-) and underscores (_). Requests are not assigned to variants with other names, and
+    /// deserializing such a variant fails.
+    pub fn new(name: impl Into<String>, weight: u32) -> Self {
+        Self {
+            name: name.into(),
+            weight,
+            backend: None,
+            path: None,
+        }
+    }
+
+    /// Send requests for this variant to the named backend.
+    pub fn with_backend(mut self, backend: impl Into<String>) -> Self {
+        self.backend = Some(backend.into());
+        self
+    }
+
+    /// Rewrite the path of requests for this variant.
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// The name of this variant.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The weight of this variant.
+    pub fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    /// The backend for requests in this variant, if it has one.
+    pub fn backend(&self) -> Option<&str> {
+        self.backend.as_deref()
+    }
+
+    /// The path for requests in this variant, if it has one.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+}
+
+/// Assigns client requests to the variants of experiments.
+pub struct Assigner {
+    key: HmacKey,
+    log_endpoint: Option<Endpoint>,
+    bucket_cookie: Option<String>,
+    cookie_max_age: Duration,
+}
+
+impl Assigner {
+    /// Create an assigner that signs its cookies with the given key.
+    ///
+    /// The key should be kept secret, for example in a
+    /// [`SecretStore`][crate::secret_store::SecretStore], since anyone who has it can choose their
+    /// own variants. See [`Secret::hmac_key()`][crate::secret_store::Secret::hmac_key()].
+    pub fn new(key: impl Into<HmacKey>) -> Self {
+        Self {
+            key: key.into(),
+            log_endpoint: None,
+            bucket_cookie: None,
+            cookie_max_age: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+
+    /// Write an exposure line to the given endpoint for each assignment.
+    pub fn with_log_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.log_endpoint = Some(endpoint);
+        self
+    }
+
+    /// Assign new clients by the value of the cookie with the given name, when the request has it.
+    ///
+    /// Clients without the cookie are assigned by their IP address.
+    pub fn bucket_by_cookie(mut self, name: impl Into<String>) -> Self {
+        self.bucket_cookie = Some(name.into());
+        self
+    }
+
+    /// Set how long assignment cookies last. The default is 30 days.
+    pub fn with_cookie_max_age(mut self, max_age: Duration) -> Self {
+        self.cookie_max_age = max_age;
+        self
+    }
+
+    /// Assign a request to a variant of the given experiment.
+    ///
+    /// A request with a valid assignment cookie for a variant that is still assigned keeps that
+    /// variant. Otherwise, the request is assigned by its bucketing key, and requests with neither
+    /// a bucketing cookie nor a client IP address are all assigned alike.
+    ///
+    /// Returns `None` if the experiment has no variants with a weight above zero, or if the name of
+    /// the experiment or of the assigned variant is not valid in a header and cookie.
+    pub fn assign(&self, experiment: &Experiment, req: &Request) -> Option<Assignment> {
+        let kept = req
+            .get_cookie(&experiment.cookie_name())
+            .and_then(|value| value.split_once('.'))
+            .filter(|(variant, signature)| {
+                ct_eq(
+                    self.signature(experiment, variant).as_bytes(),
+                    signature.as_bytes(),
+                )
+            })
+            .and_then(|(variant, _)| experiment.variant(variant));
+        let (variant, is_new) = match kept {
+            Some(variant) => (variant, false),
+            None => {
+                let key = self
+                    .bucket_cookie
+                    .as_deref()
+                    .and_then(|name| req.get_cookie(name).map(str::to_owned))
+                    .or_else(|| req.get_client_ip_addr().map(|ip| ip.to_string()))
+                    .unwrap_or_default();
+                let variant = experiment.variant_for_bucket(bucket(experiment.name(), &key))?;
+                (variant, true)
+            }
+        };
+        if !is_valid_name(&experiment.name) || !is_valid_name(&variant.name) {
+            return None;
+        }
+
+        let assignment = Assignment {
+            header_name: HeaderName::from_bytes(experiment.header_name().as_bytes()).ok()?,
+            cookie: Cookie::new(
+                experiment.cookie_name(),
+                format!(
+                    "{}.{}",
+                    variant.name,
+                    self.signature(experiment, &variant.name)
+                ),
+            )
+            .with_max_age(self.cookie_max_age)
+            .with_path("/")
+            .with_secure(true)
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax),
+            experiment: experiment.name.clone(),
+            variant: variant.clone(),
+            is_new,
+        };
+        if assignment.is_new {
+            self.log_exposure(&assignment, req);
+        }
+        Some(assignment)
+    }
+
+    fn signature(&self, experiment: &Experiment, variant: &str) -> String {
+        let mut message = Vec::with_capacity(experiment.name.len() + variant.len() + 1);
+        message.extend_from_slice(experiment.name.as_bytes());
+        message.push(0);
+        message.extend_from_slice(variant.as_bytes());
+        let mac = self.key.sign(&message);
+        let mut signature = String::with_capacity(32);
+        for b in &mac[..16] {
+            write!(&mut signature, "{b:02x}").expect("writing to a String is infallible");
+        }
+        signature
+    }
+
+    fn log_exposure(&self, assignment: &Assignment, req: &Request) {
+        if let Some(endpoint) = &self.log_endpoint {
+            let line = serde_json::json!({
+                "experiment": assignment.experiment,
+                "variant": assignment.variant.name,
+                "new": assignment.is_new,
+                "client_ip": req.get_client_ip_addr().map(|ip| ip.to_string()),
+                "url": req.get_url_str(),
+            });
+            // Exposure logging is best effort, and shouldn't fail the request.
+            let _ = endpoint.clone().write_all(line.to_string().as_bytes());
+        }
+    }
+}
+
+/// The variant of an experiment that a request was assigned to.
+#[derive(Clone, Debug)]
+pub struct Assignment {
+    experiment: String,
+    variant: Variant,
+    is_new: bool,
+    header_name: HeaderName,
+    cookie: Cookie,
+}
+
+impl Assignment {
+    /// The name of the experiment.
+    pub fn experiment(&self) -> &str {
+        &self.experiment
+    }
+
+    /// The variant the request was assigned to.
+    pub fn variant(&self) -> &Variant {
+        &self.variant
+    }
+
+    /// Return true if the request did not already have a valid assignment cookie.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// Add the variant header to a backend request, rewrite its path if the variant has one, and
+    /// add the variant to its cache key.
+    ///
+    /// The cache key is computed when the request is sent, from the cache key the request already
+    /// had, or otherwise from its URL and `Host` header. Later changes to the request before it is
+    /// sent are therefore still part of the key.
+    pub fn apply_to_request(&self, req: &mut Request) {
+        req.set_header(&self.header_name, self.variant.name.as_str());
+        if let Some(path) = &self.variant.path {
+            req.set_path(path);
+        }
+        let previous = req.cache_key.take();
+        let experiment = self.experiment.clone();
+        let variant = self.variant.name.clone();
+        req.set_cache_key_fn(move |req| {
+            let mut sha = Sha256::new();
+            match &previous {
+                Some(CacheKeyGen::Set(key)) => sha.update(key),
+                Some(CacheKeyGen::Lazy(f)) => sha.update(f(req)),
+                None => {
+                    sha.update(req.get_url_str());
+                    sha.update(b"\x00");
+                    if let Some(host) = req.get_header(HOST) {
+                        sha.update(host.as_bytes());
+                    }
+                }
+            }
+            sha.update(b"\x00");
+            sha.update(&experiment);
+            sha.update(b"\x00");
+            sha.update(&variant);
+            sha.finalize().into()
+        });
+    }
+
+    /// Set the assignment cookie on a response if the assignment is new.
+    pub fn apply_to_response(&self, resp: &mut Response) {
+        if self.is_new {
+            resp.set_cookie(self.cookie.clone());
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::http::header::{COOKIE, SET_COOKIE};
+    use crate::testing;
+
+    fn experiment() -> Experiment {
+        serde_json::from_str(
+            r#"{"name": "checkout", "variants": [
+                {"name": "control", "weight": 1},
+                {"name": "one_page", "weight": 1, "backend": "v2", "path": "/v2"}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn client(cookie: Option<&str>) -> Request {
+        testing::reset();
+        let mut req = Request::get("http://example.com/checkout");
+        if let Some(cookie) = cookie {
+            req.set_header(COOKIE, cookie);
+        }
+        testing::set_client_request(req);
+        Request::from_client()
+    }
+
+    #[test]
+    fn assignments_are_sticky() {
+        let experiment = experiment();
+        let mut req = client(Some("visitor=1"));
+        let assigner =
+            Assigner::new(b"key".to_vec()).with_log_endpoint(Endpoint::from_name("exposures"));
+        let first = assigner.assign(&experiment, &req).unwrap();
+        assert!(first.is_new());
+        let expected = experiment.variant_for_bucket(bucket("checkout", "127.0.0.1"));
+        assert_eq!(Some(first.variant()), expected);
+
+        first.apply_to_request(&mut req);
+        assert_eq!(
+            req.get_header_str("x-experiment-checkout"),
+            Some(first.variant().name())
+        );
+        let mut resp = Response::new();
+        first.apply_to_response(&mut resp);
+        let set_cookie = resp.get_header_str(SET_COOKIE).unwrap().to_owned();
+        let entries = testing::log_entries("exposures");
+        assert_eq!(entries.len(), 1);
+        let exposure: serde_json::Value = serde_json::from_slice(&entries[0]).unwrap();
+        assert_eq!(exposure["variant"], first.variant().name());
+        assert_eq!(exposure["new"], true);
+
+        // Pick the other variant, and sign it with the same key.
+        let other = experiment
+            .variants()
+            .iter()
+            .find(|v| *v != first.variant())
+            .unwrap();
+        let cookie = format!(
+            "exp_checkout={}.{}",
+            other.name(),
+            assigner.signature(&experiment, other.name())
+        );
+        let req = client(Some(&cookie));
+        let kept = assigner.assign(&experiment, &req).unwrap();
+        assert!(!kept.is_new());
+        assert_eq!(kept.variant(), other);
+        assert!(testing::log_entries("exposures").is_empty());
+        let mut resp = Response::new();
+        kept.apply_to_response(&mut resp);
+        assert!(resp.get_header(SET_COOKIE).is_none());
+
+        // A forged cookie is ignored.
+        let forged = format!(
+            "exp_checkout={}.00000000000000000000000000000000",
+            other.name()
+        );
+        let req = client(Some(&forged));
+        let reassigned = assigner.assign(&experiment, &req).unwrap();
+        assert!(reassigned.is_new());
+        assert_eq!(Some(reassigned.variant()), expected);
+        assert!(set_cookie.starts_with(&format!("exp_checkout={}.", reassigned.variant().name())));
+    }
+
+    fn cache_key(req: &Request) -> [u8; 32] {
+        match req.cache_key.as_ref().unwrap() {
+            CacheKeyGen::Lazy(f) => f(req),
+            CacheKeyGen::Set(key) => *key,
+        }
+    }
+
+    #[test]
+    fn variants_have_their_own_cache_keys() {
+        let experiment = experiment();
+        let assigner = Assigner::new(b"key".to_vec());
+        let keys: Vec<_> = experiment
+            .variants()
+            .iter()
+            .map(|variant| {
+                let cookie = format!(
+                    "exp_checkout={}.{}",
+                    variant.name(),
+                    assigner.signature(&experiment, variant.name())
+                );
+                let req = client(Some(&cookie));
+                let assignment = assigner.assign(&experiment, &req).unwrap();
+                assert_eq!(assignment.variant(), variant);
+                let mut backend_req = Request::get("http://example.com/checkout");
+                assignment.apply_to_request(&mut backend_req);
+                cache_key(&backend_req)
+            })
+            .collect();
+        assert_ne!(keys[0], keys[1]);
+
+        // An existing cache key is kept apart per variant too.
+        let req = client(None);
+        let assignment = assigner.assign(&experiment, &req).unwrap();
+        let mut with_key = Request::get("http://example.com/checkout").with_cache_key([7; 32]);
+        assignment.apply_to_request(&mut with_key);
+        let mut without_key = Request::get("http://example.com/checkout");
+        assignment.apply_to_request(&mut without_key);
+        assert_ne!(cache_key(&with_key), cache_key(&without_key));
+        assert_ne!(cache_key(&with_key), [7; 32]);
+    }
+
+    #[test]
+    fn weights() {
+        let experiment = Experiment::new("e")
+            .with_variant(Variant::new("a", 3))
+            .with_variant(Variant::new("off", 0))
+            .with_variant(Variant::new("b", 1));
+        assert_eq!(experiment.variant_for_bucket(0).unwrap().name(), "a");
+        assert_eq!(experiment.variant_for_bucket(7499).unwrap().name(), "a");
+        assert_eq!(experiment.variant_for_bucket(7500).unwrap().name(), "b");
+        assert_eq!(experiment.variant_for_bucket(9999).unwrap().name(), "b");
+        assert!(experiment.variant("off").is_none());
+        assert!(Experiment::new("empty").variant_for_bucket(0).is_none());
+    }
+
+    #[test]
+    fn invalid_names() {
+        let req = client(None);
+        let assigner = Assigner::new(b"key".to_vec());
+        let experiment = Experiment::new("check out").with_variant(Variant::new("control", 1));
+        assert!(assigner.assign(&experiment, &req).is_none());
+        let experiment = Experiment::new("checkout").with_variant(Variant::new("a;b", 1));
+        assert!(assigner.assign(&experiment, &req).is_none());
+
+        let err = serde_json::from_str::<Experiment>(
+            r#"{"name": "checkout", "variants": [{"name": "one page"}]}"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid name `one page`"), "{err}");
+        assert!(serde_json::from_str::<Experiment>(r#"{"name": "", "variants": []}"#).is_err());
+    }
+}
@@ -0,0 +1,386 @@
+//! Feature flags and percentage rollouts, defined in a config store.
+//!
+//! Each entry of the config store defines one flag. The key is the name of the flag, and the value
+//! is a JSON object with any of these fields:
+//!
+//! ```json
+//! {
+//!     "enabled": true,
+//!     "rollout": 25,
+//!     "countries": ["US", "CA"],
+//!     "asns": [54113],
+//!     "override_header": "x-new-checkout",
+//!     "override_cookie": "new_checkout"
+//! }
+//! ```
+//!
+//! A flag is evaluated for a client request in this order:
+//!
+//! 1. A flag with no entry in the store is off.
+//! 2. If the request has the `override_header` header or the `override_cookie` cookie set to `on`,
+//!    `true` or `1`, the flag is on, and if it is set to `off`, `false` or `0`, the flag is off.
+//! 3. A flag with `"enabled": false` is off. Flags are enabled by default.
+//! 4. If `countries` or `asns` are given, the flag is off unless the client's
+//!    [geographic data][crate::geo] matches one of them.
+//! 5. The flag is on for `rollout` percent of clients, which defaults to 100.
+//!
+//! Clients are assigned to a rollout [`bucket()`] by a stable hash of the flag name and a bucketing
+//! key, so the same client gets the same answer for a flag on every request, and the clients that
+//! get a flag are independent of those that get any other flag. The bucketing key is the value of
+//! the cookie given to [`Flags::bucket_by_cookie()`] if the request has it, and the client IP
+//! address otherwise.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use fastly::flags::Flags;
+//! use fastly::Request;
+//!
+//! let flags = Flags::open("flags")?.bucket_by_cookie("visitor_id");
+//! let req = Request::from_client();
+//! if flags.is_enabled("new_checkout", &req) {
+//!     // ...
+//! }
+//! # Ok::<(), fastly::config_store::OpenError>(())
+//! ```
+
+use crate::config_store::{CachedConfigStore, ConfigStore, OpenError};
+use crate::error::ValueError;
+use crate::geo::geo_lookup;
+use crate::http::header::HeaderName;
+use crate::Request;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+/// The number of rollout buckets. Each bucket is one hundredth of a percent of clients.
+pub const BUCKETS: u16 = 10_000;
+
+/// Feature flags defined in a config store.
+///
+/// Flag definitions are read from the store at most once per invocation. See the [module
+/// documentation][self] for the format of the definitions and how they are evaluated.
+pub struct Flags {
+    store: CachedConfigStore,
+    bucket_cookie: Option<String>,
+}
+
+impl Flags {
+    /// Read flag definitions from the given config store.
+    pub fn new(store: ConfigStore) -> Self {
+        Self {
+            store: store.cached(),
+            bucket_cookie: None,
+        }
+    }
+
+    /// Open the config store with the given name, and read flag definitions from it.
+    pub fn open(name: &str) -> Result<Self, OpenError> {
+        ConfigStore::try_open(name).map(Self::new)
+    }
+
+    /// Assign clients to rollout buckets by the value of the cookie with the given name, when the
+    /// request has it.
+    ///
+    /// Clients without the cookie are assigned by their IP address.
+    pub fn bucket_by_cookie(mut self, name: impl Into<String>) -> Self {
+        self.bucket_cookie = Some(name.into());
+        self
+    }
+
+    /// Return true if the given flag is on for the given request.
+    ///
+    /// A flag with a definition that could not be read is off. Use
+    /// [`evaluate()`][Flags::evaluate()] to tell the two apart.
+    pub fn is_enabled(&self, flag: &str, req: &Request) -> bool {
+        self.evaluate(flag, req)
+            .map(|evaluation| evaluation.is_enabled())
+            .unwrap_or(false)
+    }
+
+    /// Evaluate the given flag for the given request.
+    ///
+    /// Returns an error naming the flag if its definition could not be read.
+    pub fn evaluate(&self, flag: &str, req: &Request) -> Result<Evaluation, ValueError> {
+        let definition: Definition = match self.store.get_json(flag)? {
+            Some(definition) => definition,
+            None => return Ok(Evaluation::new(false, Reason::Missing)),
+        };
+
+        if let Some(enabled) = definition.overridden(req) {
+            return Ok(Evaluation::new(enabled, Reason::Override));
+        }
+        if !definition.enabled {
+            return Ok(Evaluation::new(false, Reason::Disabled));
+        }
+        if !definition.targets(req) {
+            return Ok(Evaluation::new(false, Reason::NotTargeted));
+        }
+
+        let threshold = (definition.rollout.clamp(0.0, 100.0) * 100.0).round() as u16;
+        if threshold >= BUCKETS {
+            return Ok(Evaluation::new(true, Reason::FullRollout));
+        }
+        let key = self
+            .bucket_cookie
+            .as_deref()
+            .and_then(|name| req.get_cookie(name).map(str::to_owned))
+            .or_else(|| req.get_client_ip_addr().map(|ip| ip.to_string()));
+        Ok(match key {
+            Some(key) => {
+                let bucket = bucket(flag, &key);
+                Evaluation::new(bucket < threshold, Reason::Rollout { bucket })
+            }
+            None => Evaluation::new(false, Reason::NoBucketingKey),
+        })
+    }
+
+    /// The number of times each flag has been evaluated, most evaluated first.
+    pub fn evaluation_counts(&self) -> Vec<(String, u64)> {
+        self.store.lookup_counts()
+    }
+}
+
+/// The rollout bucket of a bucketing key for a flag, from `0` up to [`BUCKETS`].
+///
+/// A flag with a rollout of `p` percent is on for keys in buckets below `p * 100`. This is the
+/// bucketing used by [`Flags`], for code that needs to agree with it.
+pub fn bucket(flag: &str, key: &str) -> u16 {
+    let mut sha = Sha256::new();
+    sha.update(flag.as_bytes());
+    sha.update([0]);
+    sha.update(key.as_bytes());
+    let digest = sha.finalize();
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(prefix) % u64::from(BUCKETS)) as u16
+}
+
+/// The result of evaluating a flag for a request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Evaluation {
+    enabled: bool,
+    reason: Reason,
+}
+
+impl Evaluation {
+    fn new(enabled: bool, reason: Reason) -> Self {
+        Self { enabled, reason }
+    }
+
+    /// Return true if the flag is on.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Why the flag is on or off.
+    pub fn reason(&self) -> Reason {
+        self.reason
+    }
+}
+
+/// Why a flag is on or off for a request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Reason {
+    /// The flag has no definition in the store.
+    Missing,
+    /// The request overrides the flag with a header or cookie.
+    Override,
+    /// The flag is disabled.
+    Disabled,
+    /// The client is not in any of the flag's countries or ASNs.
+    NotTargeted,
+    /// The flag is rolled out to all clients.
+    FullRollout,
+    /// The flag is partially rolled out, and the client is in the given bucket.
+    Rollout {
+        /// The client's rollout bucket, from [`bucket()`].
+        bucket: u16,
+    },
+    /// The flag is partially rolled out, and the request has neither a bucketing cookie nor a
+    /// client IP address.
+    NoBucketingKey,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct Definition {
+    enabled: bool,
+    rollout: f64,
+    countries: Vec<String>,
+    asns: Vec<u32>,
+    #[serde(deserialize_with = "deserialize_header_name")]
+    override_header: Option<HeaderName>,
+    override_cookie: Option<String>,
+}
+
+impl Default for Definition {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rollout: 100.0,
+            countries: Vec::new(),
+            asns: Vec::new(),
+            override_header: None,
+            override_cookie: None,
+        }
+    }
+}
+
+impl Definition {
+    fn overridden(&self, req: &Request) -> Option<bool> {
+        let header = self
+            .override_header
+            .as_ref()
+            .and_then(|name| req.get_header(name))
+            .and_then(|value| value.to_str().ok());
+        let cookie = self
+            .override_cookie
+            .as_deref()
+            .and_then(|name| req.get_cookie(name));
+        header
+            .and_then(parse_override)
+            .or_else(|| cookie.and_then(parse_override))
+    }
+
+    fn targets(&self, req: &Request) -> bool {
+        if self.countries.is_empty() && self.asns.is_empty() {
+            return true;
+        }
+        let geo = match req.get_client_ip_addr().and_then(geo_lookup) {
+            Some(geo) => geo,
+            None => return false,
+        };
+        self.countries
+            .iter()
+            .any(|country| country.eq_ignore_ascii_case(geo.country_code()))
+            || self.asns.contains(&geo.as_number())
+    }
+}
+
+fn deserialize_header_name<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<HeaderName>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|name| HeaderName::from_bytes(name.as_bytes()).map_err(D::Error::custom))
+        .transpose()
+}
+
+fn parse_override(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "on" | "true" | "1" => Some(true),
+        "off" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::http::header::{HeaderValue, COOKIE};
+    use crate::testing;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const GEO: &str = r#"{
+        "as_name": "fastly", "as_number": 54113, "area_code": 0, "city": "seattle",
+        "conn_speed": "broadband", "conn_type": "wired", "continent": "NA",
+        "country_code": "US", "country_code3": "USA", "country_name": "united states",
+        "latitude": 47.6, "longitude": -122.3, "metro_code": 0, "postal_code": "98101",
+        "proxy_description": "?", "proxy_type": "?", "region": "WA", "utc_offset": -800
+    }"#;
+
+    fn client(cookie: Option<&str>) -> Request {
+        testing::reset();
+        testing::insert_config_store_entry("flags", "off", r#"{"enabled": false}"#);
+        testing::insert_config_store_entry("flags", "everyone", "{}");
+        testing::insert_config_store_entry("flags", "canada", r#"{"countries": ["ca"]}"#);
+        testing::insert_config_store_entry("flags", "fastly", r#"{"asns": [54113]}"#);
+        testing::insert_config_store_entry(
+            "flags",
+            "half",
+            r#"{"rollout": 50, "override_header": "x-half", "override_cookie": "half"}"#,
+        );
+        testing::insert_config_store_entry("flags", "broken", "{");
+        testing::insert_config_store_entry(
+            "flags",
+            "bad_header",
+            r#"{"override_header": "x half"}"#,
+        );
+        testing::set_geo(IpAddr::V4(Ipv4Addr::LOCALHOST), GEO);
+        let mut req = Request::get("http://example.com/");
+        if let Some(cookie) = cookie {
+            req.set_header(COOKIE, cookie);
+        }
+        testing::set_client_request(req);
+        Request::from_client()
+    }
+
+    #[test]
+    fn evaluates_definitions() {
+        let req = client(None);
+        let flags = Flags::open("flags").unwrap();
+        let reason = |flag| flags.evaluate(flag, &req).unwrap().reason();
+        assert_eq!(reason("missing"), Reason::Missing);
+        assert_eq!(reason("off"), Reason::Disabled);
+        assert_eq!(reason("everyone"), Reason::FullRollout);
+        assert_eq!(reason("canada"), Reason::NotTargeted);
+        assert!(flags.is_enabled("everyone", &req));
+        assert!(flags.is_enabled("fastly", &req));
+        assert!(!flags.is_enabled("canada", &req));
+        assert!(!flags.is_enabled("broken", &req));
+        assert!(matches!(
+            flags.evaluate("broken", &req),
+            Err(ValueError::Invalid { key, .. }) if key == "broken"
+        ));
+        assert!(!flags.is_enabled("bad_header", &req));
+        assert!(matches!(
+            flags.evaluate("bad_header", &req),
+            Err(ValueError::Invalid { key, .. }) if key == "bad_header"
+        ));
+
+        let bucket = bucket("half", "127.0.0.1");
+        assert_eq!(reason("half"), Reason::Rollout { bucket });
+        assert_eq!(flags.is_enabled("half", &req), bucket < 5000);
+    }
+
+    #[test]
+    fn overrides_and_bucketing() {
+        let req = client(Some("visitor=abc; half=off"));
+        let flags = Flags::open("flags").unwrap().bucket_by_cookie("visitor");
+        let evaluation = flags.evaluate("half", &req).unwrap();
+        assert_eq!(evaluation.reason(), Reason::Override);
+        assert!(!evaluation.is_enabled());
+
+        let req = client(Some("visitor=abc"));
+        let flags = Flags::open("flags").unwrap().bucket_by_cookie("visitor");
+        let bucket = bucket("half", "abc");
+        assert_eq!(
+            flags.evaluate("half", &req).unwrap().reason(),
+            Reason::Rollout { bucket }
+        );
+        assert_eq!(flags.evaluation_counts(), [("half".to_owned(), 1)]);
+
+        let req = client(None).with_header("x-half", "ON");
+        let flags = Flags::open("flags").unwrap();
+        assert!(flags.is_enabled("half", &req));
+
+        // An override header that is not visible ASCII is ignored.
+        let value = HeaderValue::from_bytes(b"on\xff").unwrap();
+        let req = client(Some("half=on")).with_header("x-half", value);
+        let flags = Flags::open("flags").unwrap();
+        assert_eq!(
+            flags.evaluate("half", &req).unwrap(),
+            Evaluation::new(true, Reason::Override)
+        );
+    }
+
+    #[test]
+    fn buckets_are_stable_and_spread() {
+        assert_eq!(bucket("flag", "key"), bucket("flag", "key"));
+        let in_rollout = (0..1000)
+            .filter(|i| bucket("flag", &i.to_string()) < 2500)
+            .count();
+        assert!((200..300).contains(&in_rollout), "{in_rollout}");
+    }
+}
@@ -0,0 +1,488 @@
+//! Composable request handlers.
+//!
+//! A [`Handler`] turns a [`Request`] into a [`Response`]. Any function or closure with the same
+//! signature as a `#[fastly::main]` function is a handler, and so is a
+//! [`Router`].
+//!
+//! A [`Layer`] wraps a handler in another handler, to share cross-cutting behavior like logging,
+//! authentication, CORS or security headers between services. Layers can be built from closures
+//! with [`before_request()`], [`after_response()`], [`map_error()`] and [`around()`], or defined
+//! as types that implement [`Layer`] for any handler, so that they can be published as crates.
+//!
+//! With the `tower` feature, handlers can also be used as
+//! [`tower_service::Service`](https://docs.rs/tower-service)s, and vice versa.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use fastly::handler::{after_response, before_request, map_error, Handler};
+//! use fastly::{Error, Request, Response};
+//! use std::ops::ControlFlow;
+//!
+//! fn app(req: Request) -> Result<Response, Error> {
+//!     Ok(req.send("origin")?)
+//! }
+//!
+//! #[fastly::main]
+//! fn main(req: Request) -> Result<Response, Error> {
+//!     app.with_layer(before_request(|req: Request| {
+//!         if req.contains_header("authorization") {
+//!             ControlFlow::Continue(req)
+//!         } else {
+//!             ControlFlow::Break(Response::from_status(401))
+//!         }
+//!     }))
+//!     .with_layer(after_response(|resp: Response| {
+//!         resp.with_header("strict-transport-security", "max-age=31536000")
+//!     }))
+//!     .with_layer(map_error(|e: Error| {
+//!         Response::from_status(502).with_body(e.to_string())
+//!     }))
+//!     .handle(req)
+//! }
+//! ```
+
+use crate::router::Router;
+use crate::{Error, Request, Response};
+use std::ops::ControlFlow;
+
+/// Something that turns a request into a response.
+pub trait Handler {
+    /// Handle a request.
+    fn handle(&self, req: Request) -> Result<Response, Error>;
+
+    /// Wrap this handler in a layer.
+    ///
+    /// Layers added later wrap those added earlier, so they see requests first and responses
+    /// last.
+    fn with_layer<L: Layer<Self>>(self, layer: L) -> L::Handler
+    where
+        Self: Sized,
+    {
+        layer.layer(self)
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(Request) -> Result<Response, Error>,
+{
+    fn handle(&self, req: Request) -> Result<Response, Error> {
+        self(req)
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, req: Request) -> Result<Response, Error> {
+        Router::handle(self, req)
+    }
+}
+
+/// Wraps a handler in another handler.
+pub trait Layer<H> {
+    /// The wrapping handler.
+    type Handler: Handler;
+
+    /// Wrap a handler.
+    fn layer(self, inner: H) -> Self::Handler;
+}
+
+/// A layer that runs a function on requests before the inner handler.
+///
+/// The function returns either [`ControlFlow::Continue`] with the request to pass on, or
+/// [`ControlFlow::Break`] with a response to return without calling the inner handler.
+pub fn before_request<F>(f: F) -> BeforeRequestLayer<F>
+where
+    F: Fn(Request) -> ControlFlow<Response, Request>,
+{
+    BeforeRequestLayer { f }
+}
+
+/// A layer that runs a function on the responses of the inner handler.
+///
+/// The function is not run when the inner handler returns an error.
+pub fn after_response<F>(f: F) -> AfterResponseLayer<F>
+where
+    F: Fn(Response) -> Response,
+{
+    AfterResponseLayer { f }
+}
+
+/// A layer that turns errors from the inner handler into responses.
+pub fn map_error<F>(f: F) -> MapErrorLayer<F>
+where
+    F: Fn(Error) -> Response,
+{
+    MapErrorLayer { f }
+}
+
+/// A layer that runs a function in place of the inner handler, passing it the inner handler to
+/// call.
+///
+/// This is the most general layer: the function can change the request, decide whether to call
+/// the inner handler, and change the response or error.
+pub fn around<F>(f: F) -> AroundLayer<F>
+where
+    F: Fn(Request, &dyn Handler) -> Result<Response, Error>,
+{
+    AroundLayer { f }
+}
+
+/// A layer returned by [`before_request()`].
+#[derive(Clone, Debug)]
+pub struct BeforeRequestLayer<F> {
+    f: F,
+}
+
+impl<H, F> Layer<H> for BeforeRequestLayer<F>
+where
+    H: Handler,
+    F: Fn(Request) -> ControlFlow<Response, Request>,
+{
+    type Handler = BeforeRequest<H, F>;
+
+    fn layer(self, inner: H) -> Self::Handler {
+        BeforeRequest { inner, f: self.f }
+    }
+}
+
+/// A handler wrapped by a [`BeforeRequestLayer`].
+#[derive(Clone, Debug)]
+pub struct BeforeRequest<H, F> {
+    inner: H,
+    f: F,
+}
+
+impl<H, F> Handler for BeforeRequest<H, F>
+where
+    H: Handler,
+    F: Fn(Request) -> ControlFlow<Response, Request>,
+{
+    fn handle(&self, req: Request) -> Result<Response, Error> {
+        match (self.f)(req) {
+            ControlFlow::Continue(req) => self.inner.handle(req),
+            ControlFlow::Break(resp) => Ok(resp),
+        }
+    }
+}
+
+/// A layer returned by [`after_response()`].
+#[derive(Clone, Debug)]
+pub struct AfterResponseLayer<F> {
+    f: F,
+}
+
+impl<H, F> Layer<H> for AfterResponseLayer<F>
+where
+    H: Handler,
+    F: Fn(Response) -> Response,
+{
+    type Handler = AfterResponse<H, F>;
+
+    fn layer(self, inner: H) -> Self::Handler {
+        AfterResponse { inner, f: self.f }
+    }
+}
+
+/// A handler wrapped by an [`AfterResponseLayer`].
+#[derive(Clone, Debug)]
+pub struct AfterResponse<H, F> {
+    inner: H,
+    f: F,
+}
+
+impl<H, F> Handler for AfterResponse<H, F>
+where
+    H: Handler,
+    F: Fn(Response) -> Response,
+{
+    fn handle(&self, req: Request) -> Result<Response, Error> {
+        self.inner.handle(req).map(&self.f)
+    }
+}
+
+/// A layer returned by [`map_error()`].
+#[derive(Clone, Debug)]
+pub struct MapErrorLayer<F> {
+    f: F,
+}
+
+impl<H, F> Layer<H> for MapErrorLayer<F>
+where
+    H: Handler,
+    F: Fn(Error) -> Response,
+{
+    type Handler = MapError<H, F>;
+
+    fn layer(self, inner: H) -> Self::Handler {
+        MapError { inner, f: self.f }
+    }
+}
+
+/// A handler wrapped by a [`MapErrorLayer`].
+#[derive(Clone, Debug)]
+pub struct MapError<H, F> {
+    inner: H,
+    f: F,
+}
+
+impl<H, F> Handler for MapError<H, F>
+where
+    H: Handler,
+    F: Fn(Error) -> Response,
+{
+    fn handle(&self, req: Request) -> Result<Response, Error> {
+        Ok(self.inner.handle(req).unwrap_or_else(&self.f))
+    }
+}
+
+/// A layer returned by [`around()`].
+#[derive(Clone, Debug)]
+pub struct AroundLayer<F> {
+    f: F,
+}
+
+impl<H, F> Layer<H> for AroundLayer<F>
+where
+    H: Handler,
+    F: Fn(Request, &dyn Handler) -> Result<Response, Error>,
+{
+    type Handler = Around<H, F>;
+
+    fn layer(self, inner: H) -> Self::Handler {
+        Around { inner, f: self.f }
+    }
+}
+
+/// A handler wrapped by an [`AroundLayer`].
+#[derive(Clone, Debug)]
+pub struct Around<H, F> {
+    inner: H,
+    f: F,
+}
+
+impl<H, F> Handler for Around<H, F>
+where
+    H: Handler,
+    F: Fn(Request, &dyn Handler) -> Result<Response, Error>,
+{
+    fn handle(&self, req: Request) -> Result<Response, Error> {
+        (self.f)(req, &self.inner)
+    }
+}
+
+#[cfg(feature = "tower")]
+pub use self::tower::{FromService, IntoService};
+
+#[cfg(feature = "tower")]
+mod tower {
+    use super::Handler;
+    use crate::async_io::block_on;
+    use crate::{Error, Request, Response};
+    use std::cell::RefCell;
+    use std::future::{poll_fn, ready, Ready};
+    use std::task::{Context, Poll};
+    use tower_service::Service;
+
+    /// A [`Handler`] used as a [`tower_service::Service`].
+    #[derive(Clone, Debug)]
+    pub struct IntoService<H> {
+        handler: H,
+    }
+
+    impl<H: Handler> IntoService<H> {
+        /// Use a handler as a service.
+        pub fn new(handler: H) -> Self {
+            Self { handler }
+        }
+
+        /// Take the handler back.
+        pub fn into_inner(self) -> H {
+            self.handler
+        }
+    }
+
+    impl<H: Handler> Service<Request> for IntoService<H> {
+        type Response = Response;
+        type Error = Error;
+        type Future = Ready<Result<Response, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request) -> Self::Future {
+            ready(self.handler.handle(req))
+        }
+    }
+
+    /// A [`tower_service::Service`] used as a [`Handler`].
+    ///
+    /// Compute@Edge programs handle requests synchronously, so the service is driven with
+    /// [`block_on()`][crate::async_io::block_on()]. Its futures may wait on asynchronous
+    /// operations in the host, such as pending requests, but must not depend on an async runtime
+    /// to wake them.
+    #[derive(Debug)]
+    pub struct FromService<S> {
+        service: RefCell<S>,
+    }
+
+    impl<S> FromService<S> {
+        /// Use a service as a handler.
+        pub fn new(service: S) -> Self {
+            Self {
+                service: RefCell::new(service),
+            }
+        }
+
+        /// Take the service back.
+        pub fn into_inner(self) -> S {
+            self.service.into_inner()
+        }
+    }
+
+    impl<S> Handler for FromService<S>
+    where
+        S: Service<Request, Response = Response>,
+        S::Error: Into<Error>,
+    {
+        fn handle(&self, req: Request) -> Result<Response, Error> {
+            let mut service = self.service.borrow_mut();
+            block_on(async {
+                poll_fn(|cx| service.poll_ready(cx))
+                    .await
+                    .map_err(Into::into)?;
+                service.call(req).await.map_err(Into::into)
+            })
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn echo(req: Request) -> Result<Response, Error> {
+        match req.get_path() {
+            "/fail" => Err(anyhow::anyhow!("failed")),
+            path => Ok(Response::from_body(path.to_owned())),
+        }
+    }
+
+    #[test]
+    fn layers() {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let (first, second) = (order.clone(), order.clone());
+        let handler = echo
+            .with_layer(after_response(move |resp: Response| {
+                first.borrow_mut().push("after inner");
+                resp.with_header("x-inner", "1")
+            }))
+            .with_layer(after_response(move |resp: Response| {
+                second.borrow_mut().push("after outer");
+                resp
+            }))
+            .with_layer(before_request(|req: Request| {
+                if req.get_path() == "/denied" {
+                    ControlFlow::Break(Response::from_status(403))
+                } else {
+                    ControlFlow::Continue(req.with_path("/rewritten"))
+                }
+            }))
+            .with_layer(map_error(|e: Error| {
+                Response::from_status(502).with_body(e.to_string())
+            }));
+
+        let resp = handler
+            .handle(Request::get("http://example.com/a"))
+            .unwrap();
+        assert_eq!(resp.get_header_str("x-inner"), Some("1"));
+        assert_eq!(resp.into_body_str(), "/rewritten");
+        assert_eq!(*order.borrow(), ["after inner", "after outer"]);
+
+        let resp = handler.handle(Request::get("http://example.com/denied"));
+        assert_eq!(resp.unwrap().get_status(), 403);
+
+        let handler = echo.with_layer(map_error(|e: Error| {
+            Response::from_status(502).with_body(e.to_string())
+        }));
+        let resp = handler
+            .handle(Request::get("http://example.com/fail"))
+            .unwrap();
+        assert_eq!(resp.get_status(), 502);
+        assert_eq!(resp.into_body_str(), "failed");
+    }
+
+    #[test]
+    fn around_and_routers() {
+        let mut router = Router::new();
+        router.get("/users/:id", |_, params| {
+            Ok(Response::from_body(params.get("id").unwrap().to_owned()))
+        });
+        let handler = router.with_layer(around(|req: Request, inner: &dyn Handler| {
+            let mut resp = inner.handle(req)?;
+            let status = resp.get_status().as_u16().to_string();
+            resp.set_header("x-status", status);
+            Ok(resp)
+        }));
+        let resp = handler
+            .handle(Request::get("http://example.com/users/7"))
+            .unwrap();
+        assert_eq!(resp.get_header_str("x-status"), Some("200"));
+        let resp = handler
+            .handle(Request::get("http://example.com/missing"))
+            .unwrap();
+        assert_eq!(resp.get_header_str("x-status"), Some("404"));
+
+        let boxed: Box<dyn Handler> = Box::new(handler);
+        assert!(boxed
+            .handle(Request::get("http://example.com/users/1"))
+            .is_ok());
+    }
+
+    #[cfg(feature = "tower")]
+    #[test]
+    fn tower_services() {
+        use std::task::{Context, Poll};
+        use tower_service::Service;
+
+        let mut service = IntoService::new(echo);
+        let resp = service.call(Request::get("http://example.com/svc"));
+        assert_eq!(resp.into_inner().unwrap().into_body_str(), "/svc");
+        let handler = FromService::new(service);
+        let resp = handler.handle(Request::get("http://example.com/handler"));
+        assert_eq!(resp.unwrap().into_body_str(), "/handler");
+        assert!(handler
+            .handle(Request::get("http://example.com/fail"))
+            .is_err());
+
+        // A service that is not ready yet is polled again once it wakes.
+        struct Warming(u32);
+
+        impl Service<Request> for Warming {
+            type Response = Response;
+            type Error = Error;
+            type Future = std::future::Ready<Result<Response, Error>>;
+
+            fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+                if self.0 == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                self.0 -= 1;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+
+            fn call(&mut self, req: Request) -> Self::Future {
+                std::future::ready(echo(req))
+            }
+        }
+
+        let handler = FromService::new(Warming(2));
+        let resp = handler.handle(Request::get("http://example.com/warm"));
+        assert_eq!(resp.unwrap().into_body_str(), "/warm");
+        assert_eq!(handler.into_inner().0, 0);
+    }
+}
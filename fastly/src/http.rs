@@ -1,6 +1,9 @@
 //! Compute@Edge HTTP interfaces.
 
 pub mod body;
+#[cfg(feature = "http1")]
+mod compat;
+pub mod cookie;
 pub mod purge;
 #[macro_use]
 pub(crate) mod response;
@@ -9,6 +12,7 @@ pub mod request;
 pub use ::http::{Method, StatusCode, Version};
 #[doc(inline)]
 pub use body::Body;
+pub use cookie::{Cookie, SameSite};
 pub use fastly_shared::{FramingHeadersMode, HttpKeepaliveMode};
 pub use header::{HeaderName, HeaderValue};
 #[doc(inline)]
@@ -4,9 +4,13 @@ pub(crate) mod handle;
 pub(crate) mod streaming;
 
 use self::handle::BodyHandle;
+use crate::async_io;
 use std::fmt::Debug;
+use std::future::Future;
 use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::mem::{self, ManuallyDrop};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 pub use streaming::StreamingBody;
 
@@ -350,6 +354,80 @@ impl Body {
     }
 }
 
+impl Body {
+    /// Read some bytes from the body into the given buffer, once data is available.
+    ///
+    /// This is an asynchronous equivalent of [`Read::read()`], for bodies whose data is still
+    /// arriving, such as a response body streaming from a backend. The returned future resolves to
+    /// the number of bytes read, which is `0` at the end of the body. It must be run by
+    /// [`async_io::block_on()`].
+    pub fn read_async<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadAsync<'a> {
+        ReadAsync { body: self, buf }
+    }
+}
+
+/// A future that reads from a [`Body`] once data is available.
+///
+/// This `struct` is created by [`Body::read_async()`].
+#[must_use = "futures do nothing unless polled"]
+pub struct ReadAsync<'a> {
+    body: &'a mut Body,
+    buf: &'a mut [u8],
+}
+
+impl Future for ReadAsync<'_> {
+    type Output = std::io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // Data already buffered can be read without waiting on the host.
+        if this.body.reader.buffer().is_empty() {
+            if let Err(e) = this.body.writer.flush() {
+                return Poll::Ready(Err(e));
+            }
+            let handle = unsafe { this.body.writer.get_ref().as_u32() };
+            if async_io::poll_ready(handle, cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(this.body.read(this.buf))
+    }
+}
+
+/// Reads the body as a stream of data frames, with each frame holding whatever data is available
+/// once the host has some ready.
+///
+/// This lets a [`Body`] be used with adapters built on the `http-body` crate. It is only available
+/// with the `http1` feature.
+#[cfg(feature = "http1")]
+impl http_body::Body for Body {
+    type Data = bytes::Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        if this.reader.buffer().is_empty() {
+            if let Err(e) = this.writer.flush() {
+                return Poll::Ready(Some(Err(e)));
+            }
+            let handle = unsafe { this.writer.get_ref().as_u32() };
+            if async_io::poll_ready(handle, cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        let chunk = match this.fill_buf() {
+            Ok([]) => return Poll::Ready(None),
+            Ok(buf) => bytes::Bytes::copy_from_slice(buf),
+            Err(e) => return Poll::Ready(Some(Err(e))),
+        };
+        this.consume(chunk.len());
+        Poll::Ready(Some(Ok(http_body::Frame::data(chunk))))
+    }
+}
+
 // For these trait implementations we only implement the methods that the underlying buffered
 // adaptors implement; the default implementations for the others will behave the same.
 //
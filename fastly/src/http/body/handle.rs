@@ -2,11 +2,12 @@
 
 use crate::{
     abi::{self, FastlyStatus},
+    async_io::{AsyncHandle, AsyncItem},
     error::{HandleError, HandleKind},
 };
 use fastly_shared::BodyWriteEnd;
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::{
+    cell::Cell,
     io::{BufReader, Read, Write},
     mem::ManuallyDrop,
 };
@@ -25,8 +26,10 @@ pub struct BodyHandle {
     pub(super) handle: u32,
 }
 
-/// A flag representing whether or not we have taken the client body.
-pub(crate) static GOT_CLIENT_BODY: AtomicBool = AtomicBool::new(false);
+thread_local! {
+    /// A flag representing whether or not we have taken the client body.
+    pub(crate) static GOT_CLIENT_BODY: Cell<bool> = const { Cell::new(false) };
+}
 
 impl BodyHandle {
     /// An invalid body handle.
@@ -83,7 +86,7 @@ impl BodyHandle {
     ///
     /// This will panic if the flag has already been set by someone else.
     pub(crate) fn set_got_client() {
-        if GOT_CLIENT_BODY.swap(true, Ordering::SeqCst) {
+        if GOT_CLIENT_BODY.with(|got| got.replace(true)) {
             panic!("cannot get more than one handle to the client body per execution");
         }
     }
@@ -293,6 +296,12 @@ impl Write for BodyHandle {
     }
 }
 
+impl AsyncItem for BodyHandle {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        AsyncHandle::from_u32(self.handle)
+    }
+}
+
 impl Drop for BodyHandle {
     fn drop(&mut self) {
         if self.is_valid() {
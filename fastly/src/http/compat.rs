@@ -0,0 +1,210 @@
+//! Conversions between the `http` 0.2 types used throughout this crate and their `http` 1.x
+//! counterparts.
+//!
+//! The two versions of these types have the same representation, so the conversions only fail if
+//! one side contains something that the other can't: for example, a header value is valid in both
+//! versions, so it is converted with `expect()`.
+
+use http::header::{HeaderName, HeaderValue};
+use http::{HeaderMap, Method, StatusCode, Version};
+use url::Url;
+
+pub(crate) fn method_to_v1(method: &Method) -> http1::Method {
+    http1::Method::from_bytes(method.as_str().as_bytes())
+        .expect("Method to http 1.x conversion shouldn't fail, but did")
+}
+
+pub(crate) fn method_from_v1(method: &http1::Method) -> Method {
+    Method::from_bytes(method.as_str().as_bytes())
+        .expect("Method from http 1.x conversion shouldn't fail, but did")
+}
+
+pub(crate) fn status_to_v1(status: StatusCode) -> http1::StatusCode {
+    http1::StatusCode::from_u16(status.as_u16())
+        .expect("StatusCode to http 1.x conversion shouldn't fail, but did")
+}
+
+pub(crate) fn status_from_v1(status: http1::StatusCode) -> StatusCode {
+    StatusCode::from_u16(status.as_u16())
+        .expect("StatusCode from http 1.x conversion shouldn't fail, but did")
+}
+
+pub(crate) fn version_to_v1(version: Version) -> http1::Version {
+    match version {
+        Version::HTTP_09 => http1::Version::HTTP_09,
+        Version::HTTP_10 => http1::Version::HTTP_10,
+        Version::HTTP_2 => http1::Version::HTTP_2,
+        Version::HTTP_3 => http1::Version::HTTP_3,
+        _ => http1::Version::HTTP_11,
+    }
+}
+
+pub(crate) fn version_from_v1(version: http1::Version) -> Version {
+    match version {
+        http1::Version::HTTP_09 => Version::HTTP_09,
+        http1::Version::HTTP_10 => Version::HTTP_10,
+        http1::Version::HTTP_2 => Version::HTTP_2,
+        http1::Version::HTTP_3 => Version::HTTP_3,
+        _ => Version::HTTP_11,
+    }
+}
+
+pub(crate) fn uri_to_v1(uri: &str) -> http1::Uri {
+    uri.parse()
+        .expect("Url to http 1.x Uri conversion shouldn't fail, but did")
+}
+
+/// Convert a URI to an absolute URL, resolving a URI in origin form against the `Host` header.
+pub(crate) fn url_from_v1(uri: &http1::Uri, host: Option<&http1::HeaderValue>) -> Option<Url> {
+    if uri.scheme().is_some() {
+        return Url::parse(&uri.to_string()).ok();
+    }
+    let path = uri.path_and_query()?.as_str();
+    if !path.starts_with('/') {
+        return None;
+    }
+    // Only accept a plain host and port, so that the header can't change the path of the URL.
+    let host: http1::uri::Authority = host?.to_str().ok()?.parse().ok()?;
+    if host.as_str().contains('@') {
+        return None;
+    }
+    Url::parse(&format!("https://{host}{path}")).ok()
+}
+
+/// Convert a header map, keeping repeated headers in order and preserving which values are
+/// marked as sensitive.
+pub(crate) fn headers_to_v1(headers: &HeaderMap) -> http1::HeaderMap {
+    let mut out = http1::HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let name = http1::HeaderName::from_bytes(name.as_str().as_bytes())
+            .expect("HeaderName to http 1.x conversion shouldn't fail, but did");
+        let mut new_value = http1::HeaderValue::from_bytes(value.as_bytes())
+            .expect("HeaderValue to http 1.x conversion shouldn't fail, but did");
+        new_value.set_sensitive(value.is_sensitive());
+        out.append(name, new_value);
+    }
+    out
+}
+
+/// Convert a header map from `http` 1.x; the inverse of [`headers_to_v1()`].
+pub(crate) fn headers_from_v1(headers: &http1::HeaderMap) -> HeaderMap {
+    let mut out = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_str().as_bytes())
+            .expect("HeaderName from http 1.x conversion shouldn't fail, but did");
+        let mut new_value = HeaderValue::from_bytes(value.as_bytes())
+            .expect("HeaderValue from http 1.x conversion shouldn't fail, but did");
+        new_value.set_sensitive(value.is_sensitive());
+        out.append(name, new_value);
+    }
+    out
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use crate::{testing, Body, Request, Response};
+    use bytes::Bytes;
+    use http_body::Body as _;
+    use std::pin::Pin;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct TraceId(u64);
+
+    #[test]
+    fn request_round_trip() {
+        testing::reset();
+        let req = Request::post("https://example.com/a/b?c=d")
+            .with_header("x-one", "1")
+            .with_header("x-many", "a")
+            .with_header("x-many", "b")
+            .with_body("payload");
+
+        let mut v1: http1::Request<Body> = req.into();
+        assert_eq!(v1.method(), http1::Method::POST);
+        assert_eq!(v1.uri(), "https://example.com/a/b?c=d");
+        assert_eq!(v1.version(), http1::Version::HTTP_11);
+        assert_eq!(v1.headers()["x-one"], "1");
+        let many: Vec<_> = v1.headers().get_all("x-many").iter().collect();
+        assert_eq!(many, ["a", "b"]);
+        v1.extensions_mut().insert(TraceId(7));
+        *v1.method_mut() = http1::Method::PUT;
+
+        let mut req = Request::try_from(v1).unwrap();
+        assert_eq!(req.get_method(), http::Method::PUT);
+        assert_eq!(req.get_url_str(), "https://example.com/a/b?c=d");
+        assert_eq!(req.get_header_all_str("x-many"), ["a", "b"]);
+        assert_eq!(req.clone_with_body().take_body_str(), "payload");
+
+        // Extensions survive a trip through `fastly::Request`, including clones of it.
+        let v1: http1::Request<Body> = req.clone_without_body().into();
+        assert_eq!(v1.extensions().get(), Some(&TraceId(7)));
+        let (_, body) = http1::Request::from(req).into_parts();
+        assert_eq!(body.into_string(), "payload");
+    }
+
+    #[test]
+    fn origin_form_uris() {
+        testing::reset();
+        let v1 = |uri: &str, host: Option<&str>| {
+            let mut req = http1::Request::get(uri);
+            if let Some(host) = host {
+                req = req.header(http1::header::HOST, host);
+            }
+            req.body(Body::new()).unwrap()
+        };
+        let req = Request::try_from(v1("/a/b?c=d", Some("example.com:8443"))).unwrap();
+        assert_eq!(req.get_url_str(), "https://example.com:8443/a/b?c=d");
+        let req = Request::try_from(v1("http://other.com/", Some("example.com"))).unwrap();
+        assert_eq!(req.get_url_str(), "http://other.com/");
+
+        assert!(Request::try_from(v1("/a", None)).is_err());
+        assert!(Request::try_from(v1("/a", Some("evil.com/x?"))).is_err());
+        assert!(Request::try_from(v1("/a", Some("user@example.com"))).is_err());
+        let err = Request::try_from(v1("*", Some("example.com"))).unwrap_err();
+        assert!(err.to_string().contains("`*`"));
+    }
+
+    #[test]
+    fn response_round_trip() {
+        testing::reset();
+        let resp = Response::from_status(404)
+            .with_header("x-one", "1")
+            .with_body("missing");
+
+        let mut v1: http1::Response<Body> = resp.into();
+        assert_eq!(v1.status(), http1::StatusCode::NOT_FOUND);
+        assert_eq!(v1.headers()["x-one"], "1");
+        v1.extensions_mut().insert(TraceId(9));
+        v1.headers_mut().insert(
+            http1::header::CACHE_CONTROL,
+            http1::HeaderValue::from_static("no-store"),
+        );
+
+        let mut resp = Response::from(v1);
+        assert_eq!(resp.get_status(), 404);
+        assert_eq!(resp.get_header_str("cache-control"), Some("no-store"));
+        assert_eq!(resp.take_body_str(), "missing");
+
+        let v1 = http1::Response::from(resp);
+        assert_eq!(v1.extensions().get(), Some(&TraceId(9)));
+    }
+
+    #[test]
+    fn http_body_frames() {
+        testing::reset();
+        let mut body = Body::from("hello, ");
+        body.write_str("world");
+        assert!(!body.is_end_stream());
+
+        let mut data = Vec::new();
+        crate::async_io::block_on(async {
+            while let Some(frame) =
+                std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await
+            {
+                let chunk: Bytes = frame.unwrap().into_data().unwrap();
+                data.extend_from_slice(&chunk);
+            }
+        });
+        assert_eq!(data, b"hello, world");
+    }
+}
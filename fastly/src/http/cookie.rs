@@ -0,0 +1,492 @@
+//! HTTP cookies.
+//!
+//! Cookies sent by a client are read from a [`Request`][crate::Request] with
+//! [`get_cookie()`][crate::Request::get_cookie()] and
+//! [`get_cookies()`][crate::Request::get_cookies()], and can be removed or rewritten before the
+//! request is forwarded to a backend. Cookies are set on a [`Response`][crate::Response] with
+//! [`set_cookie()`][crate::Response::set_cookie()], which takes a [`Cookie`] built with its
+//! attributes, and the `Set-Cookie` headers of a backend response are parsed by
+//! [`get_cookies()`][crate::Response::get_cookies()].
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// A cookie set by a `Set-Cookie` response header.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fastly::http::{Cookie, SameSite};
+/// use std::time::Duration;
+///
+/// let cookie = Cookie::new("session", "abc123")
+///     .with_path("/")
+///     .with_max_age(Duration::from_secs(3600))
+///     .with_secure(true)
+///     .with_http_only(true)
+///     .with_same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "session=abc123; Max-Age=3600; Path=/; Secure; HttpOnly; SameSite=Lax"
+/// );
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    max_age: Option<Duration>,
+    expires: Option<String>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+    partitioned: bool,
+}
+
+impl Cookie {
+    /// Create a cookie with the given name and value, and no attributes.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the name or value can't be sent in a cookie. Use
+    /// [`try_new()`][Self::try_new()] for names and values that aren't known to be valid.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self::try_new(name, value).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Create a cookie with the given name and value, and no attributes, if they can be sent in a
+    /// cookie.
+    ///
+    /// As defined by [RFC 6265](https://www.rfc-editor.org/rfc/rfc6265#section-4.1.1), the name
+    /// must be a non-empty HTTP token, and the value must only contain printable ASCII characters
+    /// other than spaces, double quotes, commas, semicolons and backslashes. The value may be
+    /// wrapped in double quotes.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use fastly::http::cookie::{Cookie, CookieParseError};
+    ///
+    /// assert!(Cookie::try_new("theme", "dark").is_ok());
+    /// assert_eq!(
+    ///     Cookie::try_new("theme", "dark; Domain=evil.example"),
+    ///     Err(CookieParseError::InvalidValue)
+    /// );
+    /// ```
+    pub fn try_new(
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Self, CookieParseError> {
+        let (name, value) = (name.into(), value.into());
+        validate(&name, &value)?;
+        Ok(Self {
+            name,
+            value,
+            max_age: None,
+            expires: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+            partitioned: false,
+        })
+    }
+
+    /// Create a cookie that removes the cookie with the given name from the client.
+    ///
+    /// The cookie has an empty value and a `Max-Age` of zero. The path and domain must match those
+    /// of the cookie being removed.
+    pub fn removal(name: impl Into<String>) -> Self {
+        Self::new(name, "").with_max_age(Duration::ZERO)
+    }
+
+    /// Parse the value of a `Set-Cookie` header.
+    ///
+    /// Attribute names are matched without regard to case, and unknown attributes are ignored. The
+    /// name and value must be valid as for [`try_new()`][Self::try_new()].
+    pub fn parse(set_cookie: &str) -> Result<Self, CookieParseError> {
+        let mut parts = set_cookie.split(';');
+        let (name, value) = parts
+            .next()
+            .and_then(|pair| pair.split_once('='))
+            .ok_or(CookieParseError::MissingValue)?;
+        let mut cookie = Self::try_new(name.trim(), value.trim())?;
+        for attribute in parts {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "max-age" => {
+                    if let Ok(secs) = value.parse::<i64>() {
+                        cookie.max_age = Some(Duration::from_secs(secs.max(0) as u64));
+                    }
+                }
+                "expires" => cookie.expires = Some(value.to_owned()),
+                "domain" => cookie.domain = Some(value.trim_start_matches('.').to_owned()),
+                "path" => cookie.path = Some(value.to_owned()),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                "samesite" => cookie.same_site = value.parse().ok(),
+                "partitioned" => cookie.partitioned = true,
+                _ => {}
+            }
+        }
+        Ok(cookie)
+    }
+
+    /// Set how long the cookie lasts, with the `Max-Age` attribute.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Set the domain of the cookie, with the `Domain` attribute.
+    pub fn with_domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Set the path of the cookie, with the `Path` attribute.
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Set whether the cookie is only sent over HTTPS, with the `Secure` attribute.
+    pub fn with_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Set whether the cookie is hidden from scripts, with the `HttpOnly` attribute.
+    pub fn with_http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Set when the cookie is sent with cross-site requests, with the `SameSite` attribute.
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Set whether the cookie is kept in partitioned storage, with the `Partitioned` attribute.
+    ///
+    /// Browsers only accept partitioned cookies that are also [secure][Cookie::with_secure()].
+    pub fn with_partitioned(mut self, partitioned: bool) -> Self {
+        self.partitioned = partitioned;
+        self
+    }
+
+    /// Return true if `other` is for the same cookie as this one: one with the same name, domain
+    /// and path, which a client would replace with it.
+    pub(crate) fn is_same_cookie(&self, other: &Cookie) -> bool {
+        self.name == other.name
+            && self.path == other.path
+            && match (&self.domain, &other.domain) {
+                (Some(a), Some(b)) => a
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(b.trim_start_matches('.')),
+                (a, b) => a == b,
+            }
+    }
+
+    /// The name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The `Max-Age` of the cookie, if it has one. A negative `Max-Age` is read as zero.
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    /// The `Expires` date of the cookie, as it was given, if it has one.
+    pub fn expires(&self) -> Option<&str> {
+        self.expires.as_deref()
+    }
+
+    /// The `Domain` of the cookie, if it has one.
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    /// The `Path` of the cookie, if it has one.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Return true if the cookie has the `Secure` attribute.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Return true if the cookie has the `HttpOnly` attribute.
+    pub fn is_http_only(&self) -> bool {
+        self.http_only
+    }
+
+    /// The `SameSite` attribute of the cookie, if it has one.
+    pub fn same_site(&self) -> Option<SameSite> {
+        self.same_site
+    }
+
+    /// Return true if the cookie has the `Partitioned` attribute.
+    pub fn is_partitioned(&self) -> bool {
+        self.partitioned
+    }
+}
+
+/// Formats the cookie as the value of a `Set-Cookie` header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = &self.expires {
+            write!(f, "; Expires={expires}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={same_site}")?;
+        }
+        if self.partitioned {
+            f.write_str("; Partitioned")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cookie {
+    type Err = CookieParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SameSite {
+    /// The cookie is only sent with same-site requests.
+    Strict,
+    /// The cookie is also sent when navigating to the site from another site.
+    Lax,
+    /// The cookie is sent with all requests. Browsers require such cookies to be secure.
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+impl FromStr for SameSite {
+    type Err = CookieParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(SameSite::Strict),
+            "lax" => Ok(SameSite::Lax),
+            "none" => Ok(SameSite::None),
+            _ => Err(CookieParseError::InvalidSameSite),
+        }
+    }
+}
+
+/// Errors parsing a `Set-Cookie` header, or creating a cookie.
+#[derive(Clone, Copy, Debug, Error, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum CookieParseError {
+    /// The header does not start with a `name=value` pair.
+    #[error("cookie has no value")]
+    MissingValue,
+    /// The cookie name is empty.
+    #[error("cookie has no name")]
+    MissingName,
+    /// The cookie name is not an HTTP token.
+    #[error("invalid cookie name")]
+    InvalidName,
+    /// The cookie value contains characters that are not allowed in cookies.
+    #[error("invalid cookie value")]
+    InvalidValue,
+    /// The `SameSite` attribute is not `Strict`, `Lax` or `None`.
+    #[error("invalid SameSite attribute")]
+    InvalidSameSite,
+}
+
+/// Check that a cookie name is a token, and a cookie value is made of `cookie-octet`s, as defined
+/// by RFC 6265.
+pub(crate) fn validate(name: &str, value: &str) -> Result<(), CookieParseError> {
+    if name.is_empty() {
+        return Err(CookieParseError::MissingName);
+    }
+    let is_tchar = |b: u8| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b);
+    if !name.bytes().all(is_tchar) {
+        return Err(CookieParseError::InvalidName);
+    }
+    let unquoted = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    let is_cookie_octet =
+        |b: u8| matches!(b, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e);
+    if !unquoted.bytes().all(is_cookie_octet) {
+        return Err(CookieParseError::InvalidValue);
+    }
+    Ok(())
+}
+
+/// The `name=value` pairs of `Cookie` request headers.
+pub(crate) fn pairs<'a>(
+    headers: impl Iterator<Item = &'a str>,
+) -> impl Iterator<Item = (&'a str, &'a str)> {
+    headers
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .map(|(name, value)| (name.trim(), value.trim()))
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::http::header::{HeaderValue, COOKIE, SET_COOKIE};
+    use crate::{Request, Response};
+
+    #[test]
+    fn request_cookies() {
+        let mut req = Request::get("http://example.com/")
+            .with_header(COOKIE, "a=1; b=2")
+            .with_header("cookie", "c=3");
+        assert_eq!(req.get_cookie("b"), Some("2"));
+        assert_eq!(req.get_cookie("c"), Some("3"));
+        assert_eq!(req.get_cookie("d"), None);
+        assert_eq!(
+            req.get_cookies().collect::<Vec<_>>(),
+            [("a", "1"), ("b", "2"), ("c", "3")]
+        );
+
+        assert_eq!(req.remove_cookie("b"), Some("2".to_owned()));
+        assert_eq!(req.remove_cookie("b"), None);
+        req.set_cookie("a", "10");
+        req.set_cookie("d", "4");
+        assert_eq!(req.get_header_all_str(COOKIE), ["a=10; c=3; d=4"]);
+
+        req.retain_cookies(|name, _| name == "c");
+        assert_eq!(req.get_header_str(COOKIE), Some("c=3"));
+        req.retain_cookies(|_, _| false);
+        assert!(req.get_header(COOKIE).is_none());
+
+        // Cookies without a value and headers that are not UTF-8 are kept when rewriting.
+        let mut req = Request::get("http://example.com/")
+            .with_header(COOKIE, "a=1; flag")
+            .with_header(COOKIE, HeaderValue::from_bytes(b"b=\xff; c=3").unwrap());
+        assert_eq!(req.get_cookie("c"), Some("3"));
+        req.remove_cookie("a");
+        req.set_cookie("d", "4");
+        assert_eq!(
+            req.get_header(COOKIE).unwrap().as_bytes(),
+            b"flag; b=\xff; c=3; d=4"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid cookie value")]
+    fn request_cookie_values_are_checked() {
+        Request::get("http://example.com/").set_cookie("a", "1; b=2");
+    }
+
+    #[test]
+    fn response_cookies() {
+        let mut resp = Response::new();
+        resp.append_header(
+            SET_COOKIE,
+            "id=a3fWa; Expires=Wed, 21 Oct 2015 07:28:00 GMT; domain=.example.com; \
+             secure; HttpOnly; SameSite=strict; Unknown=1",
+        );
+        resp.append_header(SET_COOKIE, "=nameless");
+        resp.append_header(SET_COOKIE, "old=1; Max-Age=-5");
+        let mut resp = resp.with_cookie(Cookie::new("new", "2").with_partitioned(true));
+
+        let cookies = resp.get_cookies();
+        assert_eq!(cookies.len(), 3);
+        let id = &cookies[0];
+        assert_eq!((id.name(), id.value()), ("id", "a3fWa"));
+        assert_eq!(id.expires(), Some("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(id.domain(), Some("example.com"));
+        assert!(id.is_secure() && id.is_http_only());
+        assert_eq!(id.same_site(), Some(SameSite::Strict));
+        assert_eq!(cookies[1].max_age(), Some(Duration::ZERO));
+        assert!(cookies[2].is_partitioned());
+
+        // Only a cookie with the same name, domain and path is replaced.
+        resp.set_cookie(Cookie::removal("old").with_path("/"));
+        assert_eq!(resp.get_header_all_str(SET_COOKIE).len(), 5);
+        resp.set_cookie(Cookie::removal("old"));
+        resp.set_cookie(Cookie::new("id", "b").with_domain("Example.com"));
+        assert_eq!(resp.get_header_all_str(SET_COOKIE).len(), 5);
+        assert_eq!(
+            resp.get_cookie("old").unwrap().to_string(),
+            "old=; Max-Age=0"
+        );
+        assert!(resp.remove_cookie("old").is_some());
+        assert!(resp.get_cookie("old").is_none());
+        assert_eq!(
+            resp.get_header_all_str(SET_COOKIE),
+            [
+                "=nameless",
+                "new=2; Partitioned",
+                "id=b; Domain=Example.com"
+            ]
+        );
+
+        assert_eq!(
+            Cookie::parse("novalue"),
+            Err(CookieParseError::MissingValue)
+        );
+        assert_eq!(Cookie::parse(" =x"), Err(CookieParseError::MissingName));
+        assert_eq!(Cookie::parse("a b=x"), Err(CookieParseError::InvalidName));
+        assert_eq!(Cookie::parse("a=x y"), Err(CookieParseError::InvalidValue));
+        assert!(Cookie::try_new("quoted", "\"x\"").is_ok());
+        assert_eq!(
+            Cookie::try_new("a", "1; Domain=evil.example"),
+            Err(CookieParseError::InvalidValue)
+        );
+        assert_eq!(
+            Cookie::try_new("a\r\nb", "1"),
+            Err(CookieParseError::InvalidName)
+        );
+        let round_trip = Cookie::new("x", "y")
+            .with_domain("example.com")
+            .with_same_site(SameSite::None)
+            .with_secure(true);
+        assert_eq!(round_trip.to_string().parse::<Cookie>(), Ok(round_trip));
+    }
+}
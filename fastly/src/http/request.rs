@@ -2,13 +2,17 @@
 
 use self::handle::{ContentEncodings, RequestHandle};
 use super::body::{self, Body, StreamingBody};
+#[cfg(feature = "http1")]
+use super::compat;
+use super::cookie;
 use super::response::{handles_to_response, FastlyResponseMetadata, Response};
+use crate::auth::aws::{self, AwsCredentials, AwsSigningError};
 use crate::convert::{Borrowable, ToBackend, ToHeaderName, ToHeaderValue, ToMethod, ToUrl};
 use crate::error::{ensure, BufferSizeError, Error};
 use crate::handle::BodyHandle;
 use crate::limits::{self, RequestLimits};
 use fastly_shared::{CacheOverride, ClientCertVerifyResult, FramingHeadersMode};
-use http::header::{HeaderName, HeaderValue};
+use http::header::{self, HeaderName, HeaderValue};
 use http::{HeaderMap, Method, Version};
 use mime::Mime;
 use serde::de::DeserializeOwned;
@@ -101,6 +105,9 @@ pub struct Request {
     framing_headers_mode: FramingHeadersMode,
     // Overridden via experimental::RequestCacheKey
     pub(crate) cache_key: Option<CacheKeyGen>,
+    // Carried over from `http` 1.x requests, so that they survive a round trip
+    #[cfg(feature = "http1")]
+    extensions: http1::Extensions,
 }
 
 #[derive(Clone)]
@@ -192,6 +199,8 @@ impl Request {
             auto_decompress_response: ContentEncodings::empty(),
             framing_headers_mode: FramingHeadersMode::Automatic,
             cache_key: None,
+            #[cfg(feature = "http1")]
+            extensions: http1::Extensions::new(),
         }
     }
 
@@ -228,6 +237,8 @@ impl Request {
             auto_decompress_response: self.auto_decompress_response,
             framing_headers_mode: self.framing_headers_mode,
             cache_key: self.cache_key.clone(),
+            #[cfg(feature = "http1")]
+            extensions: self.extensions.clone(),
         }
     }
 
@@ -1602,6 +1613,192 @@ impl Request {
             .map(|hdr| String::from_utf8_lossy(hdr.as_bytes()).into_owned())
     }
 
+    /// Get the value of the cookie with the given name from the request's `Cookie` headers.
+    ///
+    /// If the cookie appears more than once, the first value is returned. Cookies that are not
+    /// valid UTF-8 are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fastly::Request;
+    /// let req = Request::get("https://example.com").with_header("cookie", "a=1; b=2");
+    /// assert_eq!(req.get_cookie("b"), Some("2"));
+    /// assert_eq!(req.get_cookie("c"), None);
+    /// ```
+    pub fn get_cookie(&self, name: &str) -> Option<&str> {
+        self.get_cookies()
+            .find(|(cookie, _)| *cookie == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Get an iterator over the `(name, value)` pairs of the request's `Cookie` headers, in order.
+    ///
+    /// Cookies that are not valid UTF-8 are skipped.
+    pub fn get_cookies(&self) -> impl Iterator<Item = (&str, &str)> {
+        cookie::pairs(
+            self.headers
+                .get_all(header::COOKIE)
+                .into_iter()
+                .flat_map(|value| value.as_bytes().split(|&b| b == b';'))
+                .filter_map(|part| std::str::from_utf8(part).ok()),
+        )
+    }
+
+    /// Set the value of a cookie in the request's `Cookie` headers, replacing any existing values
+    /// for the same name.
+    ///
+    /// The request's cookies are rewritten into a single `Cookie` header.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fastly::Request;
+    /// let mut req = Request::get("https://example.com").with_header("cookie", "a=1; b=2");
+    /// req.set_cookie("a", "3");
+    /// assert_eq!(req.get_header_str("cookie"), Some("a=3; b=2"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This panics if the name or value can't be sent in a cookie, as for
+    /// [`Cookie::new()`][crate::http::Cookie::new()].
+    pub fn set_cookie(&mut self, name: &str, value: &str) {
+        cookie::validate(name, value).unwrap_or_else(|e| panic!("{e}"));
+        let mut found = false;
+        self.rewrite_cookies(|cookie, old| {
+            if cookie != name {
+                Some(old.to_owned())
+            } else if !found {
+                found = true;
+                Some(value.to_owned())
+            } else {
+                None
+            }
+        });
+        if !found {
+            let mut cookies = self
+                .remove_header(header::COOKIE)
+                .map(|value| value.as_bytes().to_vec())
+                .unwrap_or_default();
+            if !cookies.is_empty() {
+                cookies.extend_from_slice(b"; ");
+            }
+            cookies.extend_from_slice(format!("{name}={value}").as_bytes());
+            let value = HeaderValue::from_bytes(&cookies)
+                .expect("a valid cookie added to a valid header value is a valid header value");
+            self.set_header(header::COOKIE, value);
+        }
+    }
+
+    /// Remove the cookie with the given name from the request's `Cookie` headers, and return its
+    /// value if it was present.
+    ///
+    /// This is useful to keep cookies that are only meant for Compute@Edge from being forwarded to
+    /// a backend. The request's cookies are rewritten into a single `Cookie` header.
+    pub fn remove_cookie(&mut self, name: &str) -> Option<String> {
+        let removed = self.get_cookie(name)?.to_owned();
+        self.retain_cookies(|cookie, _| cookie != name);
+        Some(removed)
+    }
+
+    /// Keep only the cookies for which `f` returns `true` in the request's `Cookie` headers.
+    ///
+    /// The remaining cookies are rewritten into a single `Cookie` header, which is removed if none
+    /// remain.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fastly::Request;
+    /// let mut req = Request::get("https://example.com").with_header("cookie", "a=1; _ga=2");
+    /// req.retain_cookies(|name, _| !name.starts_with('_'));
+    /// assert_eq!(req.get_header_str("cookie"), Some("a=1"));
+    /// ```
+    pub fn retain_cookies(&mut self, mut f: impl FnMut(&str, &str) -> bool) {
+        self.rewrite_cookies(|name, value| f(name, value).then(|| value.to_owned()));
+    }
+
+    /// Rewrite the request's `Cookie` headers into a single header, replacing the value of each
+    /// `name=value` pair with the one returned by `f`, or removing the pair if it returns `None`.
+    ///
+    /// Other parts of the headers, such as cookies without a value and bytes that are not valid
+    /// UTF-8, are kept as they are.
+    fn rewrite_cookies(&mut self, mut f: impl FnMut(&str, &str) -> Option<String>) {
+        let mut cookies = Vec::new();
+        for header in self.headers.get_all(header::COOKIE) {
+            for part in header.as_bytes().split(|&b| b == b';') {
+                let part = part.trim_ascii();
+                if part.is_empty() {
+                    continue;
+                }
+                let rewritten = match std::str::from_utf8(part)
+                    .ok()
+                    .and_then(|p| p.split_once('='))
+                {
+                    Some((name, value)) => match f(name.trim(), value.trim()) {
+                        Some(value) => Cow::Owned(format!("{}={value}", name.trim()).into_bytes()),
+                        None => continue,
+                    },
+                    None => Cow::Borrowed(part),
+                };
+                if !cookies.is_empty() {
+                    cookies.extend_from_slice(b"; ");
+                }
+                cookies.extend_from_slice(&rewritten);
+            }
+        }
+        if cookies.is_empty() {
+            self.remove_header(header::COOKIE);
+        } else {
+            let value = HeaderValue::from_bytes(&cookies)
+                .expect("cookies from valid header values are a valid header value");
+            self.set_header(header::COOKIE, value);
+        }
+    }
+
+    /// Sign this request for an AWS service with [Signature Version
+    /// 4](https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_aws-signing.html).
+    ///
+    /// This adds `Authorization` and `x-amz-date` headers, and an `x-amz-security-token` header if
+    /// the credentials have a session token. For the `s3` service, it also adds the
+    /// `x-amz-content-sha256` header that S3 requires. Any previous signature is replaced.
+    ///
+    /// The method, URL, and the `Host`, `Content-MD5`, `Content-Type`, `Range` and `x-amz-*`
+    /// headers are signed, so they must not be changed after signing. Sign the request just before
+    /// sending it. The `Host` header is set to the host of the URL if it names a different host,
+    /// for example when a client request is forwarded to a bucket.
+    ///
+    /// The body is read into memory to compute its SHA-256 hash, and then restored. To avoid this
+    /// for a body that will be streamed, set the `x-amz-content-sha256` header to
+    /// [`UNSIGNED_PAYLOAD`][crate::auth::aws::UNSIGNED_PAYLOAD] before signing.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fastly::Request;
+    /// use fastly::auth::aws::AwsCredentials;
+    ///
+    /// let credentials = AwsCredentials::new("AKIDEXAMPLE", b"secret".to_vec());
+    /// let mut req = Request::get("https://my-bucket.s3.us-east-1.amazonaws.com/index.html");
+    /// req.sign_aws_sigv4("us-east-1", "s3", &credentials)?;
+    /// # Ok::<(), fastly::auth::aws::AwsSigningError>(())
+    /// ```
+    pub fn sign_aws_sigv4(
+        &mut self,
+        region: &str,
+        service: &str,
+        credentials: &AwsCredentials,
+    ) -> Result<(), AwsSigningError> {
+        aws::sign(
+            self,
+            region,
+            service,
+            credentials,
+            std::time::SystemTime::now(),
+        )
+    }
+
     /// Builder-style equivalent of [`set_method()`][`Self::set_method()`].
     pub fn with_method(mut self, method: impl ToMethod) -> Self {
         self.set_method(method);
@@ -2295,7 +2492,7 @@ impl Request {
 
 /// Anything that we need to make a full roundtrip through the `http` types that doesn't have a more
 /// concrete corresponding type.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 struct FastlyExts {
     cache_override: CacheOverride,
     is_from_client: bool,
@@ -2346,10 +2543,78 @@ impl From<http::Request<Body>> for Request {
             auto_decompress_response,
             framing_headers_mode,
             cache_key,
+            #[cfg(feature = "http1")]
+            extensions: http1::Extensions::new(),
         }
     }
 }
 
+#[cfg(feature = "http1")]
+impl From<Request> for http1::Request<Body> {
+    fn from(from: Request) -> Self {
+        let mut req = http1::Request::new(from.body.unwrap_or_else(Body::new));
+        *req.extensions_mut() = from.extensions;
+        req.extensions_mut().insert(FastlyExts {
+            cache_override: from.cache_override,
+            is_from_client: from.is_from_client,
+            auto_decompress_response: from.auto_decompress_response,
+            framing_headers_mode: from.framing_headers_mode,
+            cache_key: from.cache_key,
+        });
+        *req.headers_mut() = compat::headers_to_v1(&from.headers);
+        *req.method_mut() = compat::method_to_v1(&from.method);
+        *req.uri_mut() = compat::uri_to_v1(from.url.as_str());
+        *req.version_mut() = compat::version_to_v1(from.version);
+        req
+    }
+}
+
+/// Convert an `http` 1.x request.
+///
+/// A URI in origin form, such as `/path?query`, is resolved against the request's `Host` header,
+/// with the `https` scheme. This fails for a URI in origin form without a valid `Host` header, and
+/// for URIs in authority or asterisk form.
+#[cfg(feature = "http1")]
+impl TryFrom<http1::Request<Body>> for Request {
+    type Error = UriError;
+
+    fn try_from(from: http1::Request<Body>) -> Result<Self, UriError> {
+        let (mut parts, body) = from.into_parts();
+        let url = compat::url_from_v1(&parts.uri, parts.headers.get(http1::header::HOST))
+            .ok_or_else(|| UriError {
+                uri: parts.uri.to_string(),
+            })?;
+        let FastlyExts {
+            cache_override,
+            is_from_client,
+            auto_decompress_response,
+            framing_headers_mode,
+            cache_key,
+        } = parts.extensions.remove().unwrap_or_default();
+        Ok(Request {
+            version: compat::version_from_v1(parts.version),
+            method: compat::method_from_v1(&parts.method),
+            url,
+            headers: compat::headers_from_v1(&parts.headers),
+            body: Some(body),
+            cache_override,
+            is_from_client,
+            auto_decompress_response,
+            framing_headers_mode,
+            cache_key,
+            extensions: parts.extensions,
+        })
+    }
+}
+
+/// An error converting an `http` 1.x request whose URI can't be made into an absolute URL.
+#[cfg(feature = "http1")]
+#[derive(Debug, Error)]
+#[error("request URI `{uri}` is not absolute, and can't be resolved against a `Host` header")]
+pub struct UriError {
+    uri: String,
+}
+
 /// The reason that a request sent to a backend failed.
 #[non_exhaustive]
 #[derive(Debug, Error)]
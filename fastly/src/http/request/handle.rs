@@ -10,9 +10,9 @@ use bytes::{BufMut, BytesMut};
 use http::header::{HeaderName, HeaderValue};
 use http::{Method, Version};
 use lazy_static::lazy_static;
+use std::cell::Cell;
 use std::mem::ManuallyDrop;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use std::sync::atomic::{AtomicBool, Ordering};
 use url::Url;
 
 // This import is just to get `Request` into scope for intradoc linking.
@@ -46,8 +46,10 @@ pub struct RequestHandle {
     handle: u32,
 }
 
-/// A flag representing whether or not the request has been taken from the client.
-pub(crate) static GOT_CLIENT_REQ: AtomicBool = AtomicBool::new(false);
+thread_local! {
+    /// A flag representing whether or not the request has been taken from the client.
+    pub(crate) static GOT_CLIENT_REQ: Cell<bool> = const { Cell::new(false) };
+}
 
 impl RequestHandle {
     /// An invalid handle.
@@ -96,7 +98,7 @@ impl RequestHandle {
     ///
     /// This will panic if the flag has already been set by someone else.
     pub(crate) fn set_got_client() {
-        if GOT_CLIENT_REQ.swap(true, Ordering::SeqCst) {
+        if GOT_CLIENT_REQ.with(|got| got.replace(true)) {
             panic!("cannot get more than one handle to the client request per execution",);
         }
     }
@@ -1,9 +1,13 @@
 pub use fastly_shared::CacheOverride;
 
 use super::SendError;
+use crate::async_io::{self, AsyncHandle, AsyncItem};
 use crate::http::response::{handles_to_response, FastlyResponseMetadata};
 use crate::{Request, Response};
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 pub mod handle;
 pub use handle::{select_handles, PendingRequestHandle, PollHandleResult};
@@ -14,6 +18,9 @@ pub use handle::{select_handles, PendingRequestHandle, PollHandleResult};
 /// A handle can be evaluated using [`PendingRequest::poll()`], [`PendingRequest::wait()`], or
 /// [`select`]. It can also be discarded if the request was sent for effects it might have, and the
 /// response is unimportant.
+///
+/// A pending request is also a [`Future`] that resolves to the result of the request, and can be
+/// awaited within a future run by [`async_io::block_on()`].
 pub struct PendingRequest {
     /// The handle to the pending asynchronous request.
     handle: PendingRequestHandle,
@@ -78,6 +85,31 @@ impl PendingRequest {
     }
 }
 
+impl AsyncItem for PendingRequest {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        AsyncHandle::from_u32(self.handle.as_u32())
+    }
+}
+
+impl Future for PendingRequest {
+    type Output = Result<Response, SendError>;
+
+    /// # Panics
+    ///
+    #[doc = include_str!("../../../docs/snippets/panics-responselimits.md")]
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        assert!(
+            !self.handle.is_invalid(),
+            "`PendingRequest` polled after completion"
+        );
+        if async_io::poll_ready(self.handle.as_u32(), cx).is_pending() {
+            return Poll::Pending;
+        }
+        let handle = std::mem::replace(&mut self.handle, PendingRequestHandle::INVALID);
+        Poll::Ready(Self::new(handle, self.metadata.clone()).wait())
+    }
+}
+
 /// The result of a call to [`PendingRequest::poll()`].
 pub enum PollResult {
     /// The request is still in progress, and can be polled again.
@@ -2,6 +2,7 @@ pub use fastly_shared::CacheOverride;
 
 use super::PendingRequest;
 use crate::abi;
+use crate::async_io::{AsyncHandle, AsyncItem};
 use crate::error::Error;
 use crate::handle::{BodyHandle, ResponseHandle};
 use crate::http::request::SendErrorCause;
@@ -136,6 +137,12 @@ impl PendingRequestHandle {
     }
 }
 
+impl AsyncItem for PendingRequestHandle {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        AsyncHandle::from_u32(self.handle)
+    }
+}
+
 /// The result of a call to [`PendingRequestHandle::poll()`].
 pub enum PollHandleResult {
     /// The request is still in progress, and can be polled again using the given handle.
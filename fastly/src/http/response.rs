@@ -6,6 +6,9 @@ pub(crate) use self::handle::handles_to_response;
 
 use self::handle::ResponseHandle;
 use super::body::{self, Body, StreamingBody};
+#[cfg(feature = "http1")]
+use super::compat;
+use super::cookie::Cookie;
 use super::Request;
 use crate::backend::Backend;
 use crate::convert::{Borrowable, ToHeaderName, ToHeaderValue, ToStatusCode};
@@ -93,6 +96,9 @@ pub struct Response {
     fastly_metadata: Option<FastlyResponseMetadata>,
     framing_headers_mode: FramingHeadersMode,
     http_keepalive_mode: HttpKeepaliveMode,
+    // Carried over from `http` 1.x responses, so that they survive a round trip
+    #[cfg(feature = "http1")]
+    extensions: http1::Extensions,
 }
 
 impl Response {
@@ -108,6 +114,8 @@ impl Response {
             fastly_metadata: None,
             framing_headers_mode: FramingHeadersMode::Automatic,
             http_keepalive_mode: HttpKeepaliveMode::Automatic,
+            #[cfg(feature = "http1")]
+            extensions: http1::Extensions::new(),
         }
     }
 
@@ -145,6 +153,8 @@ impl Response {
             fastly_metadata: self.fastly_metadata.clone(),
             framing_headers_mode: self.framing_headers_mode,
             http_keepalive_mode: self.http_keepalive_mode,
+            #[cfg(feature = "http1")]
+            extensions: self.extensions.clone(),
         }
     }
 
@@ -226,6 +236,50 @@ impl Response {
         Self::new().with_status(status)
     }
 
+    /// Create a response for a request to a cached item, serving the byte ranges named by the
+    /// request's `Range` header.
+    ///
+    /// The body of the response is streamed from the cache. If the item was cached by the [HTTP
+    /// Cache API][crate::cache::http], the response has the status and headers it was cached with;
+    /// otherwise it is a `200 OK` response. Its `Age` header is set from the age of the item.
+    ///
+    /// Ranges are only served for `GET` requests to `200 OK` responses whose
+    /// [length][crate::cache::core::Found::known_length()] is known. Then:
+    ///
+    /// - A single range is sent as a `206 Partial Content` response with a `Content-Range` header.
+    ///   Suffix ranges such as `bytes=-500` and open-ended ranges such as `bytes=500-` are supported.
+    ///
+    /// - Multiple ranges are sent as a `206 Partial Content` response with a
+    ///   `multipart/byteranges` body. Overlapping and adjacent ranges are merged first, and the parts
+    ///   are sent in ascending order.
+    ///
+    /// - If none of the ranges overlap the item, the response is `416 Range Not Satisfiable`, with
+    ///   a `Content-Range: bytes */<length>` header and no body.
+    ///
+    /// - If the `Range` header is invalid or names more than 32 ranges, or the request has an
+    ///   `If-Range` header that does not match the `ETag` or `Last-Modified` header of the cached
+    ///   response, the whole item is sent.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fastly::cache::core::{lookup, CacheKey};
+    /// # use fastly::{Request, Response};
+    /// # fn f() -> Result<(), fastly::Error> {
+    /// let req = Request::from_client();
+    /// if let Some(found) = lookup(CacheKey::from(req.get_path().to_owned())).execute()? {
+    ///     Response::from_cached_range(&found, &req)?.send_to_client();
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_cached_range(
+        found: &crate::cache::core::Found,
+        req: &Request,
+    ) -> Result<Self, crate::cache::core::CacheError> {
+        crate::cache::http::range::from_cached_range(found, req)
+    }
+
     /// Create a 303 See Other response with the given value as the `Location` header.
     ///
     /// # Examples
@@ -1354,6 +1408,88 @@ impl Response {
             .map(|hdr| String::from_utf8_lossy(hdr.as_bytes()).into_owned())
     }
 
+    /// Set a cookie with a `Set-Cookie` header, replacing any `Set-Cookie` headers for a cookie
+    /// with the same name, domain and path.
+    ///
+    /// Cookies with the same name but a different domain or path are separate cookies to a
+    /// client, so their headers are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use fastly::Response;
+    /// use fastly::http::Cookie;
+    /// let mut resp = Response::new();
+    /// resp.set_cookie(Cookie::new("theme", "dark").with_path("/"));
+    /// assert_eq!(resp.get_header_str("set-cookie"), Some("theme=dark; Path=/"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This panics if the formatted cookie is not a valid header value, for example if it contains
+    /// a newline.
+    pub fn set_cookie(&mut self, cookie: Cookie) {
+        self.remove_cookies_where(|old| old.is_same_cookie(&cookie));
+        self.append_header(header::SET_COOKIE, cookie.to_string());
+    }
+
+    /// Builder-style equivalent of [`set_cookie()`][`Self::set_cookie()`].
+    pub fn with_cookie(mut self, cookie: Cookie) -> Self {
+        self.set_cookie(cookie);
+        self
+    }
+
+    /// Parse the response's `Set-Cookie` headers, in order.
+    ///
+    /// Headers that are not valid UTF-8, or can't be parsed, are skipped.
+    pub fn get_cookies(&self) -> Vec<Cookie> {
+        self.headers
+            .get_all(header::SET_COOKIE)
+            .into_iter()
+            .filter_map(|value| value.to_str().ok()?.parse().ok())
+            .collect()
+    }
+
+    /// Parse the last `Set-Cookie` header for the cookie with the given name, if there is one.
+    pub fn get_cookie(&self, name: &str) -> Option<Cookie> {
+        self.get_cookies()
+            .into_iter()
+            .rev()
+            .find(|cookie| cookie.name() == name)
+    }
+
+    /// Remove the `Set-Cookie` headers for the cookie with the given name, and return the last of
+    /// them if there were any.
+    pub fn remove_cookie(&mut self, name: &str) -> Option<Cookie> {
+        self.remove_cookies_where(|cookie| cookie.name() == name)
+    }
+
+    fn remove_cookies_where(&mut self, mut f: impl FnMut(&Cookie) -> bool) -> Option<Cookie> {
+        let mut removed = None;
+        let kept: Vec<HeaderValue> = self
+            .headers
+            .get_all(header::SET_COOKIE)
+            .into_iter()
+            .filter(
+                |value| match value.to_str().ok().map(str::parse::<Cookie>) {
+                    Some(Ok(cookie)) if f(&cookie) => {
+                        removed = Some(cookie);
+                        false
+                    }
+                    _ => true,
+                },
+            )
+            .cloned()
+            .collect();
+        if removed.is_some() {
+            self.headers.remove(header::SET_COOKIE);
+            for value in kept {
+                self.headers.append(header::SET_COOKIE, value);
+            }
+        }
+        removed
+    }
+
     /// Builder-style equivalent of [`set_status()`][`Self::set_status()`].
     pub fn with_status(mut self, status: impl ToStatusCode) -> Self {
         self.set_status(status);
@@ -1767,7 +1903,7 @@ impl Response {
 
 /// Anything that we need to make a full roundtrip through the `http` types that doesn't have a more
 /// concrete corresponding type.
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 struct FastlyExts {
     fastly_metadata: Option<FastlyResponseMetadata>,
     framing_headers_mode: FramingHeadersMode,
@@ -1801,10 +1937,57 @@ impl From<http::Response<Body>> for Response {
             fastly_metadata: fastly_exts.fastly_metadata,
             framing_headers_mode: fastly_exts.framing_headers_mode,
             http_keepalive_mode: fastly_exts.http_keepalive_mode,
+            #[cfg(feature = "http1")]
+            extensions: http1::Extensions::new(),
+        }
+    }
+}
+
+#[cfg(feature = "http1")]
+impl From<Response> for http1::Response<Body> {
+    fn from(from: Response) -> Self {
+        let mut resp = http1::Response::new(from.body.unwrap_or_else(Body::new));
+        *resp.extensions_mut() = from.extensions;
+        resp.extensions_mut().insert(FastlyExts {
+            fastly_metadata: from.fastly_metadata,
+            framing_headers_mode: from.framing_headers_mode,
+            http_keepalive_mode: from.http_keepalive_mode,
+        });
+        *resp.headers_mut() = compat::headers_to_v1(&from.headers);
+        *resp.status_mut() = compat::status_to_v1(from.status);
+        *resp.version_mut() = compat::version_to_v1(from.version);
+        resp
+    }
+}
+
+#[cfg(feature = "http1")]
+impl From<http1::Response<Body>> for Response {
+    fn from(from: http1::Response<Body>) -> Self {
+        let (mut parts, body) = from.into_parts();
+        let fastly_exts: FastlyExts = parts.extensions.remove().unwrap_or_default();
+        Response {
+            version: compat::version_from_v1(parts.version),
+            status: compat::status_from_v1(parts.status),
+            headers: compat::headers_from_v1(&parts.headers),
+            body: Some(body),
+            fastly_metadata: fastly_exts.fastly_metadata,
+            framing_headers_mode: fastly_exts.framing_headers_mode,
+            http_keepalive_mode: fastly_exts.http_keepalive_mode,
+            extensions: parts.extensions,
         }
     }
 }
 
+/// Convert an error into a `500 Internal Server Error` response, with the error message as its body.
+///
+/// This is the response sent to the client when a [`#[fastly::main]`][crate::main] function returns
+/// an error.
+impl From<crate::Error> for Response {
+    fn from(e: crate::Error) -> Self {
+        Response::from_body(e.to_string()).with_status(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
 /// Additional Fastly-specific metadata for responses.
 #[derive(Debug)]
 pub(crate) struct FastlyResponseMetadata {
@@ -1886,16 +2069,16 @@ macro_rules! panic_with_status {
     }};
 }
 
+thread_local! {
+    /// A flag representing whether or not we have sent a response to the client.
+    pub(crate) static SENT_CLIENT_RESP: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
 /// Make sure a single response is sent to the downstream request
 #[doc(hidden)]
 pub(crate) fn assert_single_downstream_response_is_sent(panic_on_multiple_send: bool) {
-    use std::sync::atomic::{AtomicBool, Ordering};
-
-    /// A flag representing whether or not we have sent a response to the client.
-    static SENT: AtomicBool = AtomicBool::new(false);
-
     // Set our sent flag, and panic if we have already sent a response.
-    if SENT.swap(true, Ordering::SeqCst) && panic_on_multiple_send {
+    if SENT_CLIENT_RESP.with(|sent| sent.replace(true)) && panic_on_multiple_send {
         panic!("cannot send more than one client response per execution");
     }
 }
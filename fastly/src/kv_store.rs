@@ -2,10 +2,23 @@
 //!
 //! [blog]: https://www.fastly.com/blog/introducing-the-compute-edge-kv-store-global-persistent-storage-for-compute-functions
 
+use bytes::Bytes;
+use fastly_shared::FastlyStatus;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::async_io::{self, AsyncHandle, AsyncItem};
+use crate::error::ValueError;
 use crate::Body;
 
-pub use self::handle::KVStoreError;
-use self::handle::StoreHandle;
+use self::handle::{
+    FoundItem, InsertOptions, ListOptions, PendingInsertHandle, PendingLookupHandle, StoreHandle,
+};
+pub use self::handle::{InsertMode, KVStoreError};
 
 // TODO ACF 2022-10-10: this module is temporarily public for the large kv preview.
 #[doc(hidden)]
@@ -90,4 +103,604 @@ impl KVStore {
     pub fn insert(&mut self, key: &str, value: impl Into<Body>) -> Result<(), KVStoreError> {
         self.handle.insert(key, value.into().into_handle())
     }
+
+    /// Start inserting a value into the KV Store, without waiting for the insert to complete.
+    ///
+    /// This behaves like [`insert()`][Self::insert()], except that the outcome of the insert is
+    /// reported by the returned [`PendingInsert`].
+    pub fn insert_async(
+        &mut self,
+        key: &str,
+        value: impl Into<Body>,
+    ) -> Result<PendingInsert, KVStoreError> {
+        self.build_insert().execute_async(key, value)
+    }
+
+    /// Look up a value in the KV Store, and deserialize it from JSON.
+    ///
+    /// Returns `Ok(None)` if the key was not found, and an error naming the key if the lookup failed
+    /// or the value could not be deserialized.
+    ///
+    /// ```no_run
+    /// # use fastly::KVStore;
+    /// #[derive(serde::Deserialize)]
+    /// struct Profile {
+    ///     name: String,
+    /// }
+    ///
+    /// # let store = KVStore::open("my-store").unwrap().unwrap();
+    /// if let Some(profile) = store.lookup_json::<Profile>("user/1")? {
+    ///     println!("hello, {}", profile.name);
+    /// }
+    /// # Ok::<(), fastly::error::ValueError>(())
+    /// ```
+    pub fn lookup_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, ValueError> {
+        match self.lookup_bytes(key) {
+            Ok(Some(value)) => serde_json::from_slice(&value)
+                .map(Some)
+                .map_err(|e| ValueError::invalid(key, e)),
+            Ok(None) => Ok(None),
+            Err(e) => Err(ValueError::lookup(key, e)),
+        }
+    }
+
+    /// Serialize a value as JSON, and insert it into the KV Store.
+    ///
+    /// If the store already contained a value for this key, it will be overwritten.
+    pub fn insert_json<T: Serialize + ?Sized>(
+        &mut self,
+        key: &str,
+        value: &T,
+    ) -> Result<(), ValueError> {
+        let value = serde_json::to_vec(value).map_err(|e| ValueError::invalid(key, e))?;
+        self.insert(key, value).map_err(|e| ValueError::Insert {
+            key: key.to_owned(),
+            source: e.into(),
+        })
+    }
+
+    /// Look up a value in the KV Store, along with its metadata and generation.
+    ///
+    /// Returns `Ok(Some(LookupResponse))` if the value is found, and `Ok(None)` if the key was not
+    /// found or is expired.
+    ///
+    /// ```no_run
+    /// # use fastly::KVStore;
+    /// # let store = KVStore::open("my-store").unwrap().unwrap();
+    /// if let Some(item) = store.lookup_with_metadata("my-key").unwrap() {
+    ///     println!("generation {} has metadata {:?}", item.current_generation(), item.metadata());
+    ///     let value = item.into_body().into_string();
+    /// }
+    /// ```
+    pub fn lookup_with_metadata(&self, key: &str) -> Result<Option<LookupResponse>, KVStoreError> {
+        Ok(self
+            .handle
+            .lookup_with_metadata(key.as_bytes())?
+            .map(LookupResponse::from_found))
+    }
+
+    /// Start looking up a value in the KV Store, without waiting for the lookup to complete.
+    ///
+    /// The returned [`PendingLookup`] can be waited on, checked with
+    /// [`is_ready()`][AsyncItem::is_ready()], passed to [`async_io::select()`], or awaited within a
+    /// future run by [`async_io::block_on()`]. This allows several lookups, inserts, and backend
+    /// requests to be in flight at once:
+    ///
+    /// ```no_run
+    /// # use fastly::{Error, KVStore, Request};
+    /// # fn f() -> Result<(), Error> {
+    /// let store = KVStore::open("my-store")?.unwrap();
+    /// let lookups = ["a", "b", "c"].map(|key| store.lookup_async(key));
+    /// let resp = Request::get("https://example.com/").send_async("origin")?;
+    /// for lookup in lookups {
+    ///     if let Some(item) = lookup?.wait()? {
+    ///         println!("{}", item.into_body().into_string());
+    ///     }
+    /// }
+    /// let resp = resp.wait()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn lookup_async(&self, key: &str) -> Result<PendingLookup, KVStoreError> {
+        Ok(PendingLookup {
+            handle: Some(self.handle.lookup_async(key.as_bytes())?),
+        })
+    }
+
+    /// Begin an insert with options such as metadata, a time to live, or an [`InsertMode`].
+    ///
+    /// ```no_run
+    /// # use fastly::kv_store::{KVStore, KVStoreError};
+    /// # use std::time::Duration;
+    /// # let mut store = KVStore::open("my-store").unwrap().unwrap();
+    /// // Optimistically increment a counter, unless another request changed it first.
+    /// let item = store.lookup_with_metadata("count").unwrap().unwrap();
+    /// let generation = item.current_generation();
+    /// let count: u64 = item.into_body().into_string().parse().unwrap();
+    /// let result = store
+    ///     .build_insert()
+    ///     .if_generation_match(generation)
+    ///     .time_to_live(Duration::from_secs(3600))
+    ///     .execute("count", (count + 1).to_string());
+    /// if let Err(KVStoreError::PreconditionFailed) = result {
+    ///     println!("the counter was changed concurrently");
+    /// }
+    /// ```
+    pub fn build_insert(&mut self) -> InsertBuilder<'_> {
+        InsertBuilder {
+            store: self,
+            options: InsertOptions::default(),
+        }
+    }
+
+    /// Delete a value from the KV Store.
+    ///
+    /// Returns `Err(KVStoreError::ItemNotFound)` if the key was not found or is expired.
+    pub fn delete(&mut self, key: &str) -> Result<(), KVStoreError> {
+        self.handle.delete(key)
+    }
+
+    /// Begin listing the keys in the KV Store, in lexicographic order.
+    ///
+    /// Keys are listed a page at a time. Pass the [`next_cursor()`][ListPage::next_cursor()] of
+    /// one page to [`ListBuilder::cursor()`] to list the next one.
+    ///
+    /// ```no_run
+    /// # use fastly::KVStore;
+    /// # let store = KVStore::open("my-store").unwrap().unwrap();
+    /// let mut cursor = None;
+    /// loop {
+    ///     let mut list = store.list().prefix("user/");
+    ///     if let Some(cursor) = cursor {
+    ///         list = list.cursor(cursor);
+    ///     }
+    ///     let page = list.execute().unwrap();
+    ///     for key in page.keys() {
+    ///         println!("{key}");
+    ///     }
+    ///     match page.next_cursor() {
+    ///         Some(next) => cursor = Some(next.to_owned()),
+    ///         None => break,
+    ///     }
+    /// }
+    /// ```
+    pub fn list(&self) -> ListBuilder<'_> {
+        ListBuilder {
+            store: self,
+            options: ListOptions::default(),
+        }
+    }
+}
+
+/// A value found in a KV Store, along with its metadata and generation.
+///
+/// This is returned by [`KVStore::lookup_with_metadata()`].
+#[derive(Debug)]
+pub struct LookupResponse {
+    body: Body,
+    metadata: Option<Bytes>,
+    generation: u64,
+}
+
+impl LookupResponse {
+    fn from_found((body_handle, metadata, generation): FoundItem) -> Self {
+        LookupResponse {
+            body: body_handle.into(),
+            metadata,
+            generation,
+        }
+    }
+
+    /// Get the value.
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+
+    /// Get the metadata stored alongside the value, if any.
+    pub fn metadata(&self) -> Option<&[u8]> {
+        self.metadata.as_deref()
+    }
+
+    /// Get the generation of the value.
+    ///
+    /// Each insert gives a value a new generation. Pass this to
+    /// [`InsertBuilder::if_generation_match()`] to only insert if the value has not changed since
+    /// it was looked up.
+    pub fn current_generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+/// A builder-style API for configuring an insert into a KV Store.
+///
+/// This is returned by [`KVStore::build_insert()`].
+pub struct InsertBuilder<'a> {
+    store: &'a mut KVStore,
+    options: InsertOptions,
+}
+
+impl InsertBuilder<'_> {
+    /// Sets how the value is combined with any existing value.
+    ///
+    /// The mode is [`InsertMode::Overwrite`] by default.
+    pub fn mode(mut self, mode: InsertMode) -> Self {
+        self.options.mode = mode;
+        self
+    }
+
+    /// Sets metadata to store alongside the value.
+    pub fn metadata(mut self, metadata: impl Into<Bytes>) -> Self {
+        self.options.metadata = Some(metadata.into());
+        self
+    }
+
+    /// Sets how long the item lives before it expires.
+    ///
+    /// The time to live is rounded down to whole seconds. Items do not expire by default.
+    pub fn time_to_live(mut self, ttl: Duration) -> Self {
+        self.options.time_to_live = Some(ttl);
+        self
+    }
+
+    /// Only insert the value if the current value for the key has the given generation, as
+    /// returned by [`LookupResponse::current_generation()`].
+    ///
+    /// If the generation does not match, or the key has no value, the insert fails with
+    /// [`KVStoreError::PreconditionFailed`].
+    pub fn if_generation_match(mut self, generation: u64) -> Self {
+        self.options.if_generation_match = Some(generation);
+        self
+    }
+
+    /// Perform the insert.
+    ///
+    /// The value may be provided as any type that can be converted to [`Body`], and its size must
+    /// be known, as for [`KVStore::insert()`].
+    pub fn execute(self, key: &str, value: impl Into<Body>) -> Result<(), KVStoreError> {
+        self.store
+            .handle
+            .insert_with_options(key, value.into().into_handle(), &self.options)
+    }
+
+    /// Start the insert, without waiting for it to complete.
+    ///
+    /// The outcome of the insert, including any precondition failure, is reported by the returned
+    /// [`PendingInsert`].
+    pub fn execute_async(
+        self,
+        key: &str,
+        value: impl Into<Body>,
+    ) -> Result<PendingInsert, KVStoreError> {
+        let handle =
+            self.store
+                .handle
+                .insert_async(key, value.into().into_handle(), &self.options)?;
+        Ok(PendingInsert {
+            handle: Some(handle),
+        })
+    }
+}
+
+/// A KV Store lookup that may not have completed yet.
+///
+/// This is returned by [`KVStore::lookup_async()`]. It is also a [`Future`] that resolves to the
+/// result of the lookup, and can be awaited within a future run by [`async_io::block_on()`].
+#[must_use = "the lookup result is only available through `wait()` or by awaiting it"]
+pub struct PendingLookup {
+    // `None` once the lookup has been awaited.
+    handle: Option<PendingLookupHandle>,
+}
+
+impl PendingLookup {
+    /// Block until the lookup completes.
+    ///
+    /// Returns `Ok(Some(LookupResponse))` if the value is found, and `Ok(None)` if the key was not
+    /// found or is expired.
+    pub fn wait(mut self) -> Result<Option<LookupResponse>, KVStoreError> {
+        let handle = self
+            .handle
+            .take()
+            .expect("`PendingLookup` waited on after completion");
+        Ok(handle.wait()?.map(LookupResponse::from_found))
+    }
+}
+
+impl AsyncItem for PendingLookup {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        match &self.handle {
+            Some(handle) => handle.async_handle(),
+            None => AsyncHandle::from_u32(fastly_shared::INVALID_KV_LOOKUP_HANDLE),
+        }
+    }
+}
+
+impl Future for PendingLookup {
+    type Output = Result<Option<LookupResponse>, KVStoreError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let handle = self
+            .handle
+            .as_ref()
+            .expect("`PendingLookup` polled after completion");
+        if async_io::poll_ready(handle.as_u32(), cx).is_pending() {
+            return Poll::Pending;
+        }
+        let handle = self.handle.take().expect("checked above");
+        Poll::Ready(Ok(handle.wait()?.map(LookupResponse::from_found)))
+    }
+}
+
+/// A KV Store insert that may not have completed yet.
+///
+/// This is returned by [`KVStore::insert_async()`] and [`InsertBuilder::execute_async()`]. It is
+/// also a [`Future`] that resolves to the outcome of the insert, and can be awaited within a future
+/// run by [`async_io::block_on()`].
+#[must_use = "the outcome of the insert is only available through `wait()` or by awaiting it"]
+pub struct PendingInsert {
+    // `None` once the insert has been awaited.
+    handle: Option<PendingInsertHandle>,
+}
+
+impl PendingInsert {
+    /// Block until the insert completes.
+    pub fn wait(mut self) -> Result<(), KVStoreError> {
+        self.handle
+            .take()
+            .expect("`PendingInsert` waited on after completion")
+            .wait()
+    }
+}
+
+impl AsyncItem for PendingInsert {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        match &self.handle {
+            Some(handle) => handle.async_handle(),
+            None => AsyncHandle::from_u32(fastly_shared::INVALID_KV_INSERT_HANDLE),
+        }
+    }
+}
+
+impl Future for PendingInsert {
+    type Output = Result<(), KVStoreError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let handle = self
+            .handle
+            .as_ref()
+            .expect("`PendingInsert` polled after completion");
+        if async_io::poll_ready(handle.as_u32(), cx).is_pending() {
+            return Poll::Pending;
+        }
+        Poll::Ready(self.handle.take().expect("checked above").wait())
+    }
+}
+
+/// A builder-style API for configuring a listing of the keys in a KV Store.
+///
+/// This is returned by [`KVStore::list()`].
+pub struct ListBuilder<'a> {
+    store: &'a KVStore,
+    options: ListOptions,
+}
+
+impl ListBuilder<'_> {
+    /// Only list keys that start with the given prefix.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.options.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Sets the largest number of keys to list in the page.
+    ///
+    /// The limit is 100 by default, and at most 1000.
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.options.limit = Some(limit);
+        self
+    }
+
+    /// Continue listing from the cursor returned with a previous page.
+    pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+        self.options.cursor = Some(cursor.into());
+        self
+    }
+
+    /// List a page of keys.
+    pub fn execute(self) -> Result<ListPage, KVStoreError> {
+        #[derive(Deserialize)]
+        struct Page {
+            data: Vec<String>,
+            meta: Meta,
+        }
+        #[derive(Deserialize)]
+        struct Meta {
+            next_cursor: Option<String>,
+        }
+
+        let body = self.store.handle.list(&self.options)?.into_bytes();
+        let page: Page = serde_json::from_slice(&body)
+            .map_err(|_| KVStoreError::Unexpected(FastlyStatus::ERROR))?;
+        Ok(ListPage {
+            keys: page.data,
+            next_cursor: page.meta.next_cursor,
+        })
+    }
+}
+
+/// A page of keys listed from a KV Store.
+///
+/// This is returned by [`ListBuilder::execute()`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListPage {
+    keys: Vec<String>,
+    next_cursor: Option<String>,
+}
+
+impl ListPage {
+    /// The keys in this page, in lexicographic order.
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Take the keys in this page.
+    pub fn into_keys(self) -> Vec<String> {
+        self.keys
+    }
+
+    /// The cursor to list the next page of keys, or `None` if this is the last page.
+    pub fn next_cursor(&self) -> Option<&str> {
+        self.next_cursor.as_deref()
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::async_io::{block_on, join, join_all, select};
+    use crate::testing::{self, Backends, Reply};
+    use crate::{Request, Response};
+
+    fn open() -> KVStore {
+        testing::reset();
+        testing::create_kv_store("store");
+        KVStore::open("store").unwrap().unwrap()
+    }
+
+    #[test]
+    fn inserts_with_metadata_and_ttl() {
+        let mut store = open();
+        store
+            .build_insert()
+            .metadata("meta")
+            .time_to_live(Duration::from_secs(60))
+            .execute("key", "value")
+            .unwrap();
+        let item = store.lookup_with_metadata("key").unwrap().unwrap();
+        assert_eq!(item.metadata(), Some(&b"meta"[..]));
+        assert_eq!(item.into_body().into_string(), "value");
+
+        testing::advance_clock(Duration::from_secs(60));
+        assert!(store.lookup_with_metadata("key").unwrap().is_none());
+        assert_eq!(store.delete("key"), Err(KVStoreError::ItemNotFound));
+    }
+
+    #[test]
+    fn insert_modes_and_generations() {
+        let mut store = open();
+        store.insert("key", "b").unwrap();
+        store
+            .build_insert()
+            .mode(InsertMode::Append)
+            .execute("key", "c")
+            .unwrap();
+        store
+            .build_insert()
+            .mode(InsertMode::Prepend)
+            .execute("key", "a")
+            .unwrap();
+        let add = store
+            .build_insert()
+            .mode(InsertMode::Add)
+            .execute("key", "x");
+        assert_eq!(add, Err(KVStoreError::PreconditionFailed));
+
+        let item = store.lookup_with_metadata("key").unwrap().unwrap();
+        assert_eq!(item.metadata(), None);
+        let generation = item.current_generation();
+        assert_eq!(item.into_body().into_string(), "abc");
+        store
+            .build_insert()
+            .if_generation_match(generation)
+            .execute("key", "new")
+            .unwrap();
+        let stale = store
+            .build_insert()
+            .if_generation_match(generation)
+            .execute("key", "newer");
+        assert_eq!(stale, Err(KVStoreError::PreconditionFailed));
+        assert_eq!(store.lookup_str("key").unwrap().as_deref(), Some("new"));
+
+        store.delete("key").unwrap();
+        assert_eq!(store.lookup_str("key").unwrap(), None);
+    }
+
+    #[test]
+    fn lists_pages_of_keys() {
+        let mut store = open();
+        for key in ["a/1", "a/2", "a/\"3\"", "b/1"] {
+            store.insert(key, "").unwrap();
+        }
+        let page = store.list().prefix("a/").limit(2).execute().unwrap();
+        assert_eq!(page.keys(), ["a/\"3\"", "a/1"]);
+        let cursor = page.next_cursor().unwrap();
+        let page = store.list().prefix("a/").cursor(cursor).execute().unwrap();
+        assert_eq!(page.keys(), ["a/2"]);
+        assert_eq!(page.next_cursor(), None);
+        assert_eq!(store.list().execute().unwrap().into_keys().len(), 4);
+        assert!(matches!(
+            store.list().limit(0).execute(),
+            Err(KVStoreError::BadRequest)
+        ));
+    }
+
+    #[test]
+    fn lookups_and_inserts_run_alongside_requests() {
+        let mut store = open();
+        store.insert("a", "1").unwrap();
+        store.insert("b", "2").unwrap();
+        Backends::new().reply(
+            "origin",
+            Reply::from(Response::from_body("origin")).with_delay(Duration::from_millis(100)),
+        );
+        let insert = store.insert_async("c", "3").unwrap();
+        let lookups: Vec<_> = ["a", "b", "missing"]
+            .iter()
+            .map(|key| store.lookup_async(key).unwrap())
+            .collect();
+        let pending = Request::get("http://example.com/")
+            .send_async("origin")
+            .unwrap();
+        assert!(insert.is_ready());
+        assert_eq!(
+            select([pending.async_handle(), lookups[0].async_handle()], None),
+            Some(1)
+        );
+
+        let ((found, inserted), resp) = block_on(join(join(join_all(lookups), insert), pending));
+        let values: Vec<_> = found
+            .into_iter()
+            .map(|item| item.unwrap().map(|item| item.into_body().into_string()))
+            .collect();
+        assert_eq!(values, [Some("1".to_owned()), Some("2".to_owned()), None]);
+        inserted.unwrap();
+        assert_eq!(resp.unwrap().take_body_str(), "origin");
+        assert_eq!(testing::now(), Duration::from_millis(100));
+
+        let item = store.lookup_async("c").unwrap().wait().unwrap().unwrap();
+        assert_eq!(item.into_body().into_string(), "3");
+        let add = store
+            .build_insert()
+            .mode(InsertMode::Add)
+            .execute_async("c", "4")
+            .unwrap();
+        assert_eq!(add.wait(), Err(KVStoreError::PreconditionFailed));
+    }
+
+    #[test]
+    fn json_values() {
+        let mut store = open();
+        store.insert_json("list", &[1, 2, 3]).unwrap();
+        assert_eq!(
+            store.lookup_str("list").unwrap().as_deref(),
+            Some("[1,2,3]")
+        );
+        assert_eq!(
+            store.lookup_json::<Vec<u32>>("list").unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert!(store.lookup_json::<u32>("missing").unwrap().is_none());
+        let err = store.lookup_json::<String>("list").unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("invalid value for key `list`: "));
+    }
 }
@@ -1,7 +1,16 @@
 //! Safe abstractions around the KV Store FFI.
-use fastly_shared::{FastlyStatus, INVALID_BODY_HANDLE, INVALID_KV_STORE_HANDLE};
-use fastly_sys::fastly_kv_store as sys;
+use bytes::{Bytes, BytesMut};
+use fastly_shared::{
+    FastlyStatus, INVALID_BODY_HANDLE, INVALID_KV_DELETE_HANDLE, INVALID_KV_INSERT_HANDLE,
+    INVALID_KV_LIST_HANDLE, INVALID_KV_LOOKUP_HANDLE, INVALID_KV_STORE_HANDLE,
+};
+use fastly_sys::fastly_kv_store::{
+    self as sys, DeleteConfig, DeleteConfigOptions, InsertConfig, InsertConfigOptions, KvError,
+    ListConfig, ListConfigOptions, LookupConfig, LookupConfigOptions,
+};
+use std::time::Duration;
 
+use crate::async_io::{AsyncHandle, AsyncItem};
 use crate::handle::BodyHandle;
 
 /// Errors that can arise during KV Store operations.
@@ -27,6 +36,25 @@ pub enum KVStoreError {
     /// No KV Store by this name exists.
     #[error("KV Store {0:?} not found")]
     KVStoreNotFound(String),
+    /// The item to delete was not found.
+    #[error("KV Store item not found")]
+    ItemNotFound,
+    /// An insert did not meet its preconditions.
+    ///
+    /// This arises when [`InsertMode::Add`] is used for a key that already has a value, or when
+    /// the generation given to `if_generation_match` does not match the current value.
+    #[error("KV Store insert precondition failed")]
+    PreconditionFailed,
+    /// The options of the operation were not valid, such as a list limit of zero or a malformed
+    /// cursor.
+    #[error("Invalid KV Store request")]
+    BadRequest,
+    /// The value or its metadata was too large.
+    #[error("KV Store item too large")]
+    PayloadTooLarge,
+    /// The operation was rate limited.
+    #[error("Too many KV Store requests")]
+    TooManyRequests,
     /// Some unexpected error occurred.
     #[error("Unexpected KV Store error: {0:?}")]
     Unexpected(FastlyStatus),
@@ -38,6 +66,130 @@ impl From<FastlyStatus> for KVStoreError {
     }
 }
 
+impl KVStoreError {
+    /// Convert the outcome reported by a KV Store hostcall into a result.
+    fn from_kv_error(kv_error: KvError) -> Result<(), Self> {
+        match kv_error {
+            KvError::Ok => Ok(()),
+            KvError::NotFound => Err(KVStoreError::ItemNotFound),
+            KvError::PreconditionFailed => Err(KVStoreError::PreconditionFailed),
+            KvError::BadRequest => Err(KVStoreError::BadRequest),
+            KvError::PayloadTooLarge => Err(KVStoreError::PayloadTooLarge),
+            KvError::TooManyRequests => Err(KVStoreError::TooManyRequests),
+            KvError::InternalError | KvError::Uninitialized => {
+                Err(KVStoreError::Unexpected(FastlyStatus::ERROR))
+            }
+        }
+    }
+}
+
+/// How an insert combines the new value with any existing value for the key.
+///
+/// This type is marked as non-exhaustive because more variants may be added over time.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum InsertMode {
+    /// Replace any existing value. This is the default.
+    #[default]
+    Overwrite,
+    /// Only insert the value if the key does not already have one.
+    Add,
+    /// Append the value to any existing value.
+    Append,
+    /// Prepend the value to any existing value.
+    Prepend,
+}
+
+impl From<InsertMode> for sys::InsertMode {
+    fn from(mode: InsertMode) -> Self {
+        match mode {
+            InsertMode::Overwrite => sys::InsertMode::Overwrite,
+            InsertMode::Add => sys::InsertMode::Add,
+            InsertMode::Append => sys::InsertMode::Append,
+            InsertMode::Prepend => sys::InsertMode::Prepend,
+        }
+    }
+}
+
+/// Options for [`StoreHandle::insert_with_options()`].
+#[derive(Clone, Debug, Default)]
+pub struct InsertOptions {
+    /// How to combine the value with any existing value.
+    pub mode: InsertMode,
+    /// Metadata to store alongside the value.
+    pub metadata: Option<Bytes>,
+    /// How long the item lives before it expires, rounded down to whole seconds.
+    pub time_to_live: Option<Duration>,
+    /// Only insert if the current value of the key has this generation.
+    pub if_generation_match: Option<u64>,
+}
+
+impl InsertOptions {
+    fn as_abi(&self) -> (InsertConfigOptions, InsertConfig) {
+        let mut mask = InsertConfigOptions::empty();
+        let mut config = InsertConfig {
+            mode: self.mode.into(),
+            ..InsertConfig::default()
+        };
+        if let Some(metadata) = &self.metadata {
+            mask.insert(InsertConfigOptions::METADATA);
+            config.metadata = metadata.as_ptr();
+            config.metadata_len = metadata.len().try_into().unwrap_or(u32::MAX);
+        }
+        if let Some(ttl) = self.time_to_live {
+            mask.insert(InsertConfigOptions::TIME_TO_LIVE_SEC);
+            config.time_to_live_sec = ttl.as_secs().try_into().unwrap_or(u32::MAX);
+        }
+        if let Some(generation) = self.if_generation_match {
+            mask.insert(InsertConfigOptions::IF_GENERATION_MATCH);
+            config.if_generation_match = generation;
+        }
+        (mask, config)
+    }
+}
+
+/// Options for [`StoreHandle::list()`].
+#[derive(Clone, Debug, Default)]
+pub struct ListOptions {
+    /// Only list keys that start with this prefix.
+    pub prefix: Option<String>,
+    /// The largest number of keys to list.
+    pub limit: Option<u32>,
+    /// The cursor returned with the previous page of keys.
+    pub cursor: Option<String>,
+}
+
+impl ListOptions {
+    fn as_abi(&self) -> (ListConfigOptions, ListConfig) {
+        let mut mask = ListConfigOptions::empty();
+        let mut config = ListConfig::default();
+        if let Some(prefix) = &self.prefix {
+            mask.insert(ListConfigOptions::PREFIX);
+            config.prefix = prefix.as_ptr();
+            config.prefix_len = prefix.len().try_into().unwrap_or(u32::MAX);
+        }
+        if let Some(limit) = self.limit {
+            mask.insert(ListConfigOptions::LIMIT);
+            config.limit = limit;
+        }
+        if let Some(cursor) = &self.cursor {
+            mask.insert(ListConfigOptions::CURSOR);
+            config.cursor = cursor.as_ptr();
+            config.cursor_len = cursor.len().try_into().unwrap_or(u32::MAX);
+        }
+        (mask, config)
+    }
+}
+
+/// Map the status of a KV Store hostcall to an error.
+fn status_error(st: FastlyStatus) -> KVStoreError {
+    match st {
+        FastlyStatus::BADF => KVStoreError::InvalidKVStoreHandle,
+        FastlyStatus::INVAL => KVStoreError::InvalidKey,
+        _ => st.into(),
+    }
+}
+
 /// A handle to a key-value store that is open for lookups and inserting.
 #[derive(Debug, Eq, Hash, PartialEq)]
 #[repr(transparent)]
@@ -57,7 +209,7 @@ impl StoreHandle {
     /// Open a handle to the KV Store with the given name.
     pub fn open(name: &str) -> Result<Option<StoreHandle>, KVStoreError> {
         let mut store_handle_out = INVALID_KV_STORE_HANDLE;
-        let status = unsafe { sys::open(name.as_ptr(), name.len(), &mut store_handle_out) };
+        let status = unsafe { sys::open_v2(name.as_ptr(), name.len(), &mut store_handle_out) };
         status.result().map_err(|st| match st {
             FastlyStatus::INVAL => KVStoreError::KVStoreNotFound(name.to_owned()),
             _ => st.into(),
@@ -76,34 +228,225 @@ impl StoreHandle {
     /// Returns `Ok(Some(BodyHandle))` if a value is found, and `Ok(None)` if the key was not
     /// found or is expired.
     pub fn lookup(&self, key: impl AsRef<[u8]>) -> Result<Option<BodyHandle>, KVStoreError> {
-        let mut body_handle_out = INVALID_BODY_HANDLE;
-        let key = key.as_ref();
-        let status =
-            unsafe { sys::lookup(self.as_u32(), key.as_ptr(), key.len(), &mut body_handle_out) };
-        status.result().map_err(|st| match st {
-            FastlyStatus::BADF => KVStoreError::InvalidKVStoreHandle,
-            FastlyStatus::INVAL => KVStoreError::InvalidKey,
-            _ => st.into(),
-        })?;
-        if body_handle_out == INVALID_BODY_HANDLE {
-            Ok(None)
-        } else {
-            Ok(Some(unsafe { BodyHandle::from_u32(body_handle_out) }))
-        }
+        Ok(self.lookup_with_metadata(key)?.map(|(body, _, _)| body))
     }
 
     /// Insert a value into the KV Store.
     ///
     /// If the KV Store already contains a value for this key, it will be overwritten.
     pub fn insert(&mut self, key: impl AsRef<str>, value: BodyHandle) -> Result<(), KVStoreError> {
+        self.insert_with_options(key, value, &InsertOptions::default())
+    }
+
+    /// Look up a value in the KV Store, along with its metadata and generation.
+    ///
+    /// Returns `Ok(None)` if the key was not found or is expired.
+    pub fn lookup_with_metadata(
+        &self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<FoundItem>, KVStoreError> {
+        self.lookup_async(key)?.wait()
+    }
+
+    /// Start looking up a value in the KV Store, without waiting for the lookup to complete.
+    pub fn lookup_async(&self, key: impl AsRef<[u8]>) -> Result<PendingLookupHandle, KVStoreError> {
+        let key = key.as_ref();
+        let mut lookup_handle_out = INVALID_KV_LOOKUP_HANDLE;
+        let status = unsafe {
+            sys::lookup_v2(
+                self.as_u32(),
+                key.as_ptr(),
+                key.len(),
+                LookupConfigOptions::empty(),
+                &LookupConfig::default(),
+                &mut lookup_handle_out,
+            )
+        };
+        status.result().map_err(status_error)?;
+        Ok(PendingLookupHandle {
+            handle: lookup_handle_out,
+        })
+    }
+
+    /// Insert a value into the KV Store with the given options.
+    pub fn insert_with_options(
+        &mut self,
+        key: impl AsRef<str>,
+        value: BodyHandle,
+        options: &InsertOptions,
+    ) -> Result<(), KVStoreError> {
+        self.insert_async(key, value, options)?.wait()
+    }
+
+    /// Start inserting a value into the KV Store with the given options, without waiting for the
+    /// insert to complete.
+    pub fn insert_async(
+        &mut self,
+        key: impl AsRef<str>,
+        value: BodyHandle,
+        options: &InsertOptions,
+    ) -> Result<PendingInsertHandle, KVStoreError> {
         let key = key.as_ref();
-        let status =
-            unsafe { sys::insert(self.as_u32(), key.as_ptr(), key.len(), value.into_u32()) };
+        let (mask, config) = options.as_abi();
+        let mut insert_handle_out = INVALID_KV_INSERT_HANDLE;
+        let status = unsafe {
+            sys::insert_v2(
+                self.as_u32(),
+                key.as_ptr(),
+                key.len(),
+                value.into_u32(),
+                mask,
+                &config,
+                &mut insert_handle_out,
+            )
+        };
+        status.result().map_err(status_error)?;
+        Ok(PendingInsertHandle {
+            handle: insert_handle_out,
+        })
+    }
+
+    /// Delete a value from the KV Store.
+    ///
+    /// Returns `Err(KVStoreError::ItemNotFound)` if the key was not found or is expired.
+    pub fn delete(&mut self, key: impl AsRef<str>) -> Result<(), KVStoreError> {
+        let key = key.as_ref();
+        let mut delete_handle_out = INVALID_KV_DELETE_HANDLE;
+        let status = unsafe {
+            sys::delete_v2(
+                self.as_u32(),
+                key.as_ptr(),
+                key.len(),
+                DeleteConfigOptions::empty(),
+                &DeleteConfig::default(),
+                &mut delete_handle_out,
+            )
+        };
+        status.result().map_err(status_error)?;
+        let mut kv_error_out = KvError::Uninitialized;
+        let status = unsafe { sys::pending_delete_wait_v2(delete_handle_out, &mut kv_error_out) };
+        status.result().map_err(status_error)?;
+        KVStoreError::from_kv_error(kv_error_out)
+    }
+
+    /// List a page of the keys in the KV Store.
+    ///
+    /// The page is returned as a JSON document of the form
+    /// `{"data": [keys...], "meta": {"next_cursor": ...}}`, where `next_cursor` is absent on the
+    /// last page.
+    pub fn list(&self, options: &ListOptions) -> Result<BodyHandle, KVStoreError> {
+        let (mask, config) = options.as_abi();
+        let mut list_handle_out = INVALID_KV_LIST_HANDLE;
+        let status = unsafe { sys::list_v2(self.as_u32(), mask, &config, &mut list_handle_out) };
         status.result().map_err(|st| match st {
-            FastlyStatus::BADF => KVStoreError::InvalidKVStoreHandle,
-            FastlyStatus::INVAL => KVStoreError::InvalidKey,
-            _ => st.into(),
+            FastlyStatus::INVAL => KVStoreError::BadRequest,
+            st => status_error(st),
         })?;
-        Ok(())
+        let mut body_handle_out = INVALID_BODY_HANDLE;
+        let mut kv_error_out = KvError::Uninitialized;
+        let status = unsafe {
+            sys::pending_list_wait_v2(list_handle_out, &mut body_handle_out, &mut kv_error_out)
+        };
+        status.result().map_err(status_error)?;
+        KVStoreError::from_kv_error(kv_error_out)?;
+        Ok(unsafe { BodyHandle::from_u32(body_handle_out) })
+    }
+}
+
+/// A value found by a lookup, along with its metadata, if any, and its generation.
+pub type FoundItem = (BodyHandle, Option<Bytes>, u64);
+
+/// A handle to a KV Store lookup that may not have completed yet.
+#[derive(Debug, Eq, Hash, PartialEq)]
+#[repr(transparent)]
+pub struct PendingLookupHandle {
+    handle: u32,
+}
+
+impl PendingLookupHandle {
+    /// Get the underlying representation of the handle.
+    ///
+    /// This should only be used when calling the raw ABI directly, and care should be taken not to
+    /// reuse or alias handle values.
+    pub fn as_u32(&self) -> u32 {
+        self.handle
+    }
+
+    /// Block until the lookup completes.
+    ///
+    /// Returns `Ok(None)` if the key was not found or is expired.
+    pub fn wait(self) -> Result<Option<FoundItem>, KVStoreError> {
+        const INITIAL_CAPACITY: usize = 2 * 1024;
+        let mut metadata = BytesMut::with_capacity(INITIAL_CAPACITY);
+        loop {
+            let mut body_handle_out = INVALID_BODY_HANDLE;
+            let mut nwritten_out = 0;
+            let mut generation_out = 0;
+            let mut kv_error_out = KvError::Uninitialized;
+            let status = unsafe {
+                sys::pending_lookup_wait_v2(
+                    self.handle,
+                    &mut body_handle_out,
+                    metadata.as_mut_ptr(),
+                    metadata.capacity(),
+                    &mut nwritten_out,
+                    &mut generation_out,
+                    &mut kv_error_out,
+                )
+            };
+            match status {
+                // The metadata did not fit, but the length we need was written to `nwritten_out`.
+                FastlyStatus::BUFLEN if nwritten_out > metadata.capacity() => {
+                    metadata.reserve(nwritten_out);
+                    continue;
+                }
+                status => status.result().map_err(status_error)?,
+            }
+            match KVStoreError::from_kv_error(kv_error_out) {
+                Err(KVStoreError::ItemNotFound) => return Ok(None),
+                result => result?,
+            }
+            let body = unsafe { BodyHandle::from_u32(body_handle_out) };
+            unsafe { metadata.set_len(nwritten_out) };
+            let metadata = Some(metadata.freeze()).filter(|metadata| !metadata.is_empty());
+            return Ok(Some((body, metadata, generation_out.into())));
+        }
+    }
+}
+
+impl AsyncItem for PendingLookupHandle {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        AsyncHandle::from_u32(self.handle)
+    }
+}
+
+/// A handle to a KV Store insert that may not have completed yet.
+#[derive(Debug, Eq, Hash, PartialEq)]
+#[repr(transparent)]
+pub struct PendingInsertHandle {
+    handle: u32,
+}
+
+impl PendingInsertHandle {
+    /// Get the underlying representation of the handle.
+    ///
+    /// This should only be used when calling the raw ABI directly, and care should be taken not to
+    /// reuse or alias handle values.
+    pub fn as_u32(&self) -> u32 {
+        self.handle
+    }
+
+    /// Block until the insert completes.
+    pub fn wait(self) -> Result<(), KVStoreError> {
+        let mut kv_error_out = KvError::Uninitialized;
+        let status = unsafe { sys::pending_insert_wait_v2(self.handle, &mut kv_error_out) };
+        status.result().map_err(status_error)?;
+        KVStoreError::from_kv_error(kv_error_out)
+    }
+}
+
+impl AsyncItem for PendingInsertHandle {
+    fn async_handle(&self) -> AsyncHandle<'_> {
+        AsyncHandle::from_u32(self.handle)
     }
 }
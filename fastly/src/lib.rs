@@ -14,6 +14,12 @@
 //! Compute@Edge](https://developer.fastly.com/learning/compute/rust) at the Fastly Developer Hub.
 mod abi;
 
+// Let the code generated by this crate's derive macros name it as `fastly` in its own tests.
+#[cfg(test)]
+extern crate self as fastly;
+
+pub mod async_io;
+pub mod auth;
 pub mod backend;
 pub mod cache;
 pub mod config_store;
@@ -21,15 +27,24 @@ pub mod convert;
 pub mod dictionary;
 pub mod error;
 pub mod experimental;
+pub mod experiments;
+pub mod flags;
 pub mod geo;
 pub mod handle;
+pub mod handler;
 pub mod http;
 pub mod kv_store;
 pub mod limits;
 pub mod log;
+mod memo;
 pub mod mime;
 pub mod object_store;
+pub mod router;
 pub mod secret_store;
+#[cfg(feature = "session")]
+pub mod session;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 pub use crate::backend::Backend;
 #[doc(inline)]
@@ -59,3 +74,26 @@ pub use fastly_macros::main;
 pub fn init() {
     unsafe { abi::fastly_abi::init(abi::FASTLY_ABI_VERSION) };
 }
+
+/// A value that a [`#[fastly::main]`][crate::main] function may return, which is converted into the
+/// response sent to the client.
+///
+/// This is implemented for [`Response`], and for `Result<Response, E>` where `E` converts into a
+/// [`Response`].
+#[doc(hidden)]
+pub trait MainOutput {
+    /// Convert the value into the response sent to the client.
+    fn into_response(self) -> Response;
+}
+
+impl MainOutput for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl<E: Into<Response>> MainOutput for Result<Response, E> {
+    fn into_response(self) -> Response {
+        self.unwrap_or_else(Into::into)
+    }
+}
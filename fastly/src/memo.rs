@@ -0,0 +1,87 @@
+//! Memoized store lookups, shared by the cached config and secret store wrappers.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+/// The results of lookups made against a store, keyed by name.
+///
+/// Misses are remembered as well as hits, so a key is only looked up on the host once. Failed
+/// lookups are not remembered, and will be retried the next time the key is read.
+pub(crate) struct Memo<V> {
+    entries: RefCell<HashMap<String, Entry<V>>>,
+    host_lookups: Cell<u64>,
+}
+
+struct Entry<V> {
+    value: Option<V>,
+    reads: u64,
+}
+
+impl<V: Clone> Memo<V> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: RefCell::new(HashMap::new()),
+            host_lookups: Cell::new(0),
+        }
+    }
+
+    /// Read a key, calling `fetch` to look it up on the host if it hasn't been seen before.
+    pub(crate) fn get<E>(
+        &self,
+        key: &str,
+        fetch: impl FnOnce(&str) -> Result<Option<V>, E>,
+    ) -> Result<Option<V>, E> {
+        if let Some(entry) = self.entries.borrow_mut().get_mut(key) {
+            entry.reads += 1;
+            return Ok(entry.value.clone());
+        }
+        let value = self.fetch(key, fetch)?;
+        let mut entries = self.entries.borrow_mut();
+        let entry = entries
+            .entry(key.to_owned())
+            .or_insert(Entry { value, reads: 0 });
+        entry.reads += 1;
+        Ok(entry.value.clone())
+    }
+
+    /// Look up a key on the host if it hasn't been seen before, without counting it as a read.
+    pub(crate) fn prefetch<E>(
+        &self,
+        key: &str,
+        fetch: impl FnOnce(&str) -> Result<Option<V>, E>,
+    ) -> Result<(), E> {
+        if !self.entries.borrow().contains_key(key) {
+            let value = self.fetch(key, fetch)?;
+            self.entries
+                .borrow_mut()
+                .insert(key.to_owned(), Entry { value, reads: 0 });
+        }
+        Ok(())
+    }
+
+    fn fetch<E>(
+        &self,
+        key: &str,
+        fetch: impl FnOnce(&str) -> Result<Option<V>, E>,
+    ) -> Result<Option<V>, E> {
+        let value = fetch(key)?;
+        self.host_lookups.set(self.host_lookups.get() + 1);
+        Ok(value)
+    }
+
+    pub(crate) fn host_lookups(&self) -> u64 {
+        self.host_lookups.get()
+    }
+
+    /// The number of reads of each key, most read first.
+    pub(crate) fn reads(&self) -> Vec<(String, u64)> {
+        let mut reads: Vec<_> = self
+            .entries
+            .borrow()
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.reads))
+            .collect();
+        reads.sort_by(|(a_key, a), (b_key, b)| b.cmp(a).then_with(|| a_key.cmp(b_key)));
+        reads
+    }
+}
@@ -0,0 +1,572 @@
+//! Routing of requests to handlers by method and path.
+//!
+//! A [`Router`] holds routes made of a method, a path template and a handler. Path templates are
+//! made of `/`-separated segments, each of which is one of:
+//!
+//! - a literal, such as `users`, which matches only itself,
+//! - a parameter, such as `:id`, which matches any single non-empty segment,
+//! - a wildcard, such as `*path` or `*`, which matches the rest of the path, including any further
+//!   `/`. A wildcard must be the last segment.
+//!
+//! When several routes match a path, the most specific wins: at the first segment where they
+//! differ, literals win over parameters, and parameters win over wildcards.
+//!
+//! Requests for a path that matches no route get a `404 Not Found` response, and requests whose
+//! path matches routes for other methods get a `405 Method Not Allowed` response with an `Allow`
+//! header. `HEAD` requests are handled by `GET` routes when there is no `HEAD` route.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use fastly::router::{Next, Params, Router};
+//! use fastly::{Error, Request, Response};
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct UserPath {
+//!     id: u64,
+//! }
+//!
+//! fn get_user(req: Request, params: &Params) -> Result<Response, Error> {
+//!     let path: UserPath = params.parse()?;
+//!     Ok(Response::from_body(format!("user {}", path.id)))
+//! }
+//!
+//! fn require_admin(req: Request, next: Next) -> Result<Response, Error> {
+//!     if req.get_header_str("x-admin-token") != Some("secret") {
+//!         return Ok(Response::from_status(403));
+//!     }
+//!     next.run(req)
+//! }
+//!
+//! #[fastly::main]
+//! fn main(req: Request) -> Result<Response, Error> {
+//!     let mut router = Router::new();
+//!     router.get("/users/:id", get_user);
+//!     router
+//!         .delete("/users/:id", |_, _| Ok(Response::from_status(204)))
+//!         .with_middleware(require_admin);
+//!     router.get("/static/*path", |req, _| Ok(req.send("assets")?));
+//!     router.handle(req)
+//! }
+//! ```
+
+use crate::http::header::ALLOW;
+use crate::http::{Method, StatusCode};
+use crate::{Error, Request, Response};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+
+type Handler = Box<dyn Fn(Request, &Params) -> Result<Response, Error>>;
+type Middleware = Box<dyn Fn(Request, Next<'_>) -> Result<Response, Error>>;
+
+/// Routes requests to handlers by method and path.
+///
+/// See the [module documentation][self] for the syntax of path templates.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    middleware: Vec<Middleware>,
+    fallback: Option<Handler>,
+}
+
+impl Router {
+    /// A router with no routes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a route for the given method and path template.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path template has a wildcard that is not its last segment, or a parameter or
+    /// wildcard with an empty name other than the anonymous wildcard `*`.
+    pub fn route(
+        &mut self,
+        method: Method,
+        template: &str,
+        handler: impl Fn(Request, &Params) -> Result<Response, Error> + 'static,
+    ) -> &mut Route {
+        self.routes.push(Route {
+            method,
+            template: Template::parse(template),
+            handler: Box::new(handler),
+            middleware: Vec::new(),
+        });
+        self.routes.last_mut().expect("route was just added")
+    }
+
+    /// Add a route for `GET` (and `HEAD`) requests. See [`Router::route()`].
+    pub fn get(
+        &mut self,
+        template: &str,
+        handler: impl Fn(Request, &Params) -> Result<Response, Error> + 'static,
+    ) -> &mut Route {
+        self.route(Method::GET, template, handler)
+    }
+
+    /// Add a route for `POST` requests. See [`Router::route()`].
+    pub fn post(
+        &mut self,
+        template: &str,
+        handler: impl Fn(Request, &Params) -> Result<Response, Error> + 'static,
+    ) -> &mut Route {
+        self.route(Method::POST, template, handler)
+    }
+
+    /// Add a route for `PUT` requests. See [`Router::route()`].
+    pub fn put(
+        &mut self,
+        template: &str,
+        handler: impl Fn(Request, &Params) -> Result<Response, Error> + 'static,
+    ) -> &mut Route {
+        self.route(Method::PUT, template, handler)
+    }
+
+    /// Add a route for `PATCH` requests. See [`Router::route()`].
+    pub fn patch(
+        &mut self,
+        template: &str,
+        handler: impl Fn(Request, &Params) -> Result<Response, Error> + 'static,
+    ) -> &mut Route {
+        self.route(Method::PATCH, template, handler)
+    }
+
+    /// Add a route for `DELETE` requests. See [`Router::route()`].
+    pub fn delete(
+        &mut self,
+        template: &str,
+        handler: impl Fn(Request, &Params) -> Result<Response, Error> + 'static,
+    ) -> &mut Route {
+        self.route(Method::DELETE, template, handler)
+    }
+
+    /// Add middleware that runs for every request, before any route middleware.
+    ///
+    /// Router middleware also runs for requests that match no route, in which case
+    /// [`Next::params()`] is empty and [`Next::run()`] returns the `404` or `405` response.
+    /// Middleware runs in the order it is added.
+    pub fn with_middleware(
+        &mut self,
+        middleware: impl Fn(Request, Next<'_>) -> Result<Response, Error> + 'static,
+    ) -> &mut Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Handle requests that match no route with the given handler, rather than with a `404 Not
+    /// Found` response.
+    pub fn with_fallback(
+        &mut self,
+        handler: impl Fn(Request, &Params) -> Result<Response, Error> + 'static,
+    ) -> &mut Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Route a request to its handler, and return the handler's response.
+    pub fn handle(&self, req: Request) -> Result<Response, Error> {
+        let path = req.get_path().to_owned();
+        let mut allowed: Vec<&Method> = Vec::new();
+        let mut best: Option<(&Route, Params, Vec<u8>)> = None;
+        for route in &self.routes {
+            let Some((params, rank)) = route.template.matches(&path) else {
+                continue;
+            };
+            allowed.push(&route.method);
+            if !route.allows(req.get_method(), &self.routes, &path) {
+                continue;
+            }
+            match &best {
+                Some((_, _, best)) if *best <= rank => {}
+                _ => best = Some((route, params, rank)),
+            }
+        }
+
+        let empty = Params::default();
+        match best {
+            Some((route, params, _)) => {
+                let endpoint = |req| {
+                    Next {
+                        middleware: &route.middleware,
+                        params: &params,
+                        endpoint: &|req| (route.handler)(req, &params),
+                    }
+                    .run(req)
+                };
+                Next {
+                    middleware: &self.middleware,
+                    params: &params,
+                    endpoint: &endpoint,
+                }
+                .run(req)
+            }
+            None if allowed.is_empty() => Next {
+                middleware: &self.middleware,
+                params: &empty,
+                endpoint: &|req| match &self.fallback {
+                    Some(fallback) => fallback(req, &empty),
+                    None => Ok(Response::from_status(StatusCode::NOT_FOUND)),
+                },
+            }
+            .run(req),
+            None => {
+                if allowed.contains(&&Method::GET) {
+                    allowed.push(&Method::HEAD);
+                }
+                allowed.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+                allowed.dedup();
+                let allow = allowed
+                    .iter()
+                    .map(|method| method.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                Next {
+                    middleware: &self.middleware,
+                    params: &empty,
+                    endpoint: &|_| {
+                        Ok(Response::from_status(StatusCode::METHOD_NOT_ALLOWED)
+                            .with_header(ALLOW, allow.as_str()))
+                    },
+                }
+                .run(req)
+            }
+        }
+    }
+}
+
+/// A route added to a [`Router`].
+pub struct Route {
+    method: Method,
+    template: Template,
+    handler: Handler,
+    middleware: Vec<Middleware>,
+}
+
+impl Route {
+    /// Add middleware that runs only for requests to this route, after any router middleware.
+    ///
+    /// Middleware runs in the order it is added.
+    pub fn with_middleware(
+        &mut self,
+        middleware: impl Fn(Request, Next<'_>) -> Result<Response, Error> + 'static,
+    ) -> &mut Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Whether this route handles requests with the given method. `GET` routes also handle `HEAD`
+    /// requests, unless the path has a `HEAD` route.
+    fn allows(&self, method: &Method, routes: &[Route], path: &str) -> bool {
+        self.method == method
+            || (method == Method::HEAD
+                && self.method == Method::GET
+                && !routes.iter().any(|route| {
+                    route.method == Method::HEAD && route.template.matches(path).is_some()
+                }))
+    }
+}
+
+/// The rest of the middleware chain, and the handler, after a middleware.
+pub struct Next<'a> {
+    middleware: &'a [Middleware],
+    params: &'a Params,
+    endpoint: &'a dyn Fn(Request) -> Result<Response, Error>,
+}
+
+impl Next<'_> {
+    /// The parameters captured from the request path.
+    pub fn params(&self) -> &Params {
+        self.params
+    }
+
+    /// Run the rest of the chain on a request, and return the response.
+    pub fn run(self, req: Request) -> Result<Response, Error> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware(
+                req,
+                Next {
+                    middleware: rest,
+                    ..self
+                },
+            ),
+            None => (self.endpoint)(req),
+        }
+    }
+}
+
+/// Parameters captured from a request path by a route's template.
+///
+/// Values are percent-decoded. A wildcard's value is the rest of the path, without a leading `/`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Params {
+    params: Vec<(String, String)>,
+}
+
+impl Params {
+    /// The value of the named parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Iterate over the names and values of the parameters, in template order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Parse the parameters into a type that implements [`serde::Deserialize`].
+    ///
+    /// Values are parsed as with [`Request::get_query()`], so fields can be strings, numbers or
+    /// other types that deserialize from strings.
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, serde_urlencoded::de::Error> {
+        let encoded =
+            serde_urlencoded::to_string(&self.params).expect("string pairs can always be encoded");
+        serde_urlencoded::from_str(&encoded)
+    }
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(Option<String>),
+}
+
+struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    fn parse(template: &str) -> Self {
+        let parts: Vec<&str> = template.trim_start_matches('/').split('/').collect();
+        let segments = parts
+            .iter()
+            .enumerate()
+            .map(|(i, part)| {
+                if let Some(name) = part.strip_prefix(':') {
+                    assert!(!name.is_empty(), "empty parameter name in `{template}`");
+                    Segment::Param(name.to_owned())
+                } else if let Some(name) = part.strip_prefix('*') {
+                    assert!(
+                        i == parts.len() - 1,
+                        "wildcard is not the last segment of `{template}`"
+                    );
+                    Segment::Wildcard((!name.is_empty()).then(|| name.to_owned()))
+                } else {
+                    Segment::Literal((*part).to_owned())
+                }
+            })
+            .collect();
+        Self { segments }
+    }
+
+    /// Match a path, returning the captured parameters and a rank where lower is more specific.
+    fn matches(&self, path: &str) -> Option<(Params, Vec<u8>)> {
+        let mut params = Vec::new();
+        let mut rank = Vec::with_capacity(self.segments.len());
+        let mut rest = Some(path.trim_start_matches('/'));
+        for segment in &self.segments {
+            let (part, remainder) = match rest {
+                Some(rest) => match rest.split_once('/') {
+                    Some((part, remainder)) => (part, Some(remainder)),
+                    None => (rest, None),
+                },
+                None if matches!(segment, Segment::Wildcard(_)) => ("", None),
+                None => return None,
+            };
+            match segment {
+                Segment::Literal(literal) if percent_decode(part) == literal.as_str() => {
+                    rank.push(0)
+                }
+                Segment::Literal(_) => return None,
+                Segment::Param(_) if part.is_empty() => return None,
+                Segment::Param(name) => {
+                    params.push((name.clone(), percent_decode(part).into_owned()));
+                    rank.push(1);
+                }
+                Segment::Wildcard(name) => {
+                    if let Some(name) = name {
+                        let value = rest.unwrap_or_default();
+                        params.push((name.clone(), percent_decode(value).into_owned()));
+                    }
+                    rank.push(2);
+                    return Some((Params { params }, rank));
+                }
+            }
+            rest = remainder;
+        }
+        rest.is_none().then_some((Params { params }, rank))
+    }
+}
+
+fn percent_decode(input: &str) -> Cow<'_, str> {
+    if !input.contains('%') {
+        return Cow::Borrowed(input);
+    }
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = (bytes[i] == b'%')
+            .then(|| input.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match decoded {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&out).into_owned())
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn body(handler: &'static str) -> impl Fn(Request, &Params) -> Result<Response, Error> {
+        move |_, params| {
+            let params: Vec<String> = params.iter().map(|(n, v)| format!("{n}={v}")).collect();
+            Ok(Response::from_body(format!(
+                "{handler} {}",
+                params.join(" ")
+            )))
+        }
+    }
+
+    fn call(router: &Router, method: Method, path: &str) -> Response {
+        let req = Request::new(method, format!("http://example.com{path}"));
+        router.handle(req).unwrap()
+    }
+
+    #[test]
+    fn matching() {
+        let mut router = Router::new();
+        router.get("/", body("root"));
+        router.get("/users/:id", body("user"));
+        router.get("/users/me", body("me"));
+        router.get("/users/:id/files/*path", body("files"));
+        router.get("/assets/*", body("assets"));
+        router.post("/users", body("create"));
+
+        let text = |method, path| call(&router, method, path).into_body_str();
+        assert_eq!(text(Method::GET, "/"), "root ");
+        assert_eq!(text(Method::GET, "/users/42"), "user id=42");
+        assert_eq!(text(Method::GET, "/users/me"), "me ");
+        assert_eq!(text(Method::GET, "/users/a%20b"), "user id=a b");
+        assert_eq!(
+            text(Method::GET, "/users/7/files/a/b.txt"),
+            "files id=7 path=a/b.txt"
+        );
+        assert_eq!(text(Method::GET, "/users/7/files"), "files id=7 path=");
+        assert_eq!(text(Method::GET, "/assets/css/site.css"), "assets ");
+        assert_eq!(text(Method::HEAD, "/users/42"), "user id=42");
+        assert_eq!(text(Method::POST, "/users"), "create ");
+
+        let resp = call(&router, Method::GET, "/users/42/other");
+        assert_eq!(resp.get_status(), StatusCode::NOT_FOUND);
+        let resp = call(&router, Method::GET, "/users/");
+        assert_eq!(resp.get_status(), StatusCode::NOT_FOUND);
+        let resp = call(&router, Method::DELETE, "/users/42");
+        assert_eq!(resp.get_status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(resp.get_header_str(ALLOW), Some("GET, HEAD"));
+        let resp = call(&router, Method::PUT, "/users");
+        assert_eq!(resp.get_header_str(ALLOW), Some("POST"));
+
+        router.with_fallback(body("fallback"));
+        let resp = call(&router, Method::GET, "/nowhere");
+        assert_eq!(resp.into_body_str(), "fallback ");
+    }
+
+    #[test]
+    fn typed_params() {
+        #[derive(Deserialize)]
+        struct Path {
+            org: String,
+            id: u64,
+        }
+        let params = Params {
+            params: vec![
+                ("org".to_owned(), "acme & co".to_owned()),
+                ("id".to_owned(), "42".to_owned()),
+            ],
+        };
+        let path: Path = params.parse().unwrap();
+        assert_eq!((path.org.as_str(), path.id), ("acme & co", 42));
+        assert_eq!(params.get("id"), Some("42"));
+        let bad = Params {
+            params: vec![
+                ("org".to_owned(), "x".to_owned()),
+                ("id".to_owned(), "x".to_owned()),
+            ],
+        };
+        assert!(bad.parse::<Path>().is_err());
+    }
+
+    #[test]
+    fn middleware() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut router = Router::new();
+        let router_log = log.clone();
+        router.with_middleware(move |req, next| {
+            router_log
+                .borrow_mut()
+                .push(format!("router {:?}", next.params().get("id")));
+            let resp = next.run(req)?;
+            Ok(resp.with_header("x-router", "1"))
+        });
+        router.get("/public/:id", body("public"));
+        let route_log = log.clone();
+        router
+            .get("/admin/:id", body("admin"))
+            .with_middleware(move |req, next| {
+                route_log.borrow_mut().push("admin".to_owned());
+                if req.get_header_str("x-admin").is_none() {
+                    return Ok(Response::from_status(StatusCode::FORBIDDEN));
+                }
+                next.run(req)
+            });
+
+        let resp = call(&router, Method::GET, "/public/1");
+        assert_eq!(resp.get_header_str("x-router"), Some("1"));
+        let resp = call(&router, Method::GET, "/admin/2");
+        assert_eq!(resp.get_status(), StatusCode::FORBIDDEN);
+        assert_eq!(resp.get_header_str("x-router"), Some("1"));
+        let req = Request::get("http://example.com/admin/3").with_header("x-admin", "yes");
+        assert_eq!(router.handle(req).unwrap().into_body_str(), "admin id=3");
+        let resp = call(&router, Method::GET, "/missing");
+        assert_eq!(resp.get_status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.get_header_str("x-router"), Some("1"));
+
+        assert_eq!(
+            *log.borrow(),
+            [
+                "router Some(\"1\")",
+                "router Some(\"2\")",
+                "admin",
+                "router Some(\"3\")",
+                "admin",
+                "router None",
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "wildcard is not the last segment")]
+    fn wildcard_must_be_last() {
+        Router::new().get("/*path/more", body("bad"));
+    }
+}
@@ -1,7 +1,8 @@
 //! Low-level Compute@Edge Secret Store interfaces.
 
+use super::SecretBytes;
 use crate::abi;
-use bytes::{Bytes, BytesMut};
+use bytes::Bytes;
 use fastly_shared::FastlyStatus;
 
 /// A low-level interface to Secret Store.
@@ -118,6 +119,15 @@ impl SecretHandle {
     // TODO KTM 22-11-01: Assign a distinct `ReadError` error enum to this method, that will let us
     // provide some helpful error messages that don't overlap with looking up secrets from a store.
     pub fn plaintext(&self) -> Result<Bytes, LookupError> {
+        self.plaintext_vec().map(Bytes::from)
+    }
+
+    /// Return the plaintext value of this secret, in a buffer that is zeroed when it is dropped.
+    pub fn plaintext_zeroizing(&self) -> Result<SecretBytes, LookupError> {
+        self.plaintext_vec().map(SecretBytes::from)
+    }
+
+    fn plaintext_vec(&self) -> Result<Vec<u8>, LookupError> {
         use crate::limits::INITIAL_SECRET_PLAINTEXT_BUF_SIZE;
 
         if self.is_invalid() {
@@ -125,7 +135,7 @@ impl SecretHandle {
         }
 
         // Allocate a mutable byte buffer for our secret's contents.
-        let mut plaintext_buf = BytesMut::zeroed(INITIAL_SECRET_PLAINTEXT_BUF_SIZE);
+        let mut plaintext_buf = vec![0; INITIAL_SECRET_PLAINTEXT_BUF_SIZE];
         let mut nwritten = 0usize;
 
         // Attempt to read the secret's plaintext contents into the buffer.
@@ -140,10 +150,11 @@ impl SecretHandle {
 
         // If the provided buffer was not large enough to fit the plaintext, reserve additional
         // capacity observing the number of bytes needed, in the `nwritten` value. Then, set
-        // `nwritten` back to 0 and try again.
+        // `nwritten` back to 0 and try again. Nothing was written to the first buffer, so it can be
+        // dropped as it is.
         let status = match status {
             FastlyStatus::BUFLEN if nwritten != 0 => {
-                plaintext_buf.resize(nwritten, 0);
+                plaintext_buf = vec![0; nwritten];
                 nwritten = 0;
                 unsafe {
                     abi::fastly_secret_store::plaintext(
@@ -159,12 +170,10 @@ impl SecretHandle {
 
         match status.result() {
             Ok(()) => {
-                // Freeze the bytes, being sure to set the length to reflect the number of bytes
-                // written into the buffer by the host.
-                unsafe {
-                    plaintext_buf.set_len(nwritten);
-                }
-                Ok(plaintext_buf.freeze())
+                // Set the length to reflect the number of bytes written into the buffer by the
+                // host.
+                plaintext_buf.truncate(nwritten);
+                Ok(plaintext_buf)
             }
             Err(FastlyStatus::BADF) => Err(LookupError::InvalidSecretHandle),
             Err(FastlyStatus::ERROR) => Err(LookupError::Unexpected(FastlyStatus::ERROR)),
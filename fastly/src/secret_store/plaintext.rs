@@ -0,0 +1,298 @@
+//! Zeroizing secret buffers, and the typed values that can be parsed from them.
+
+use base64ct::{Base64, Base64Unpadded, Base64Url, Base64UrlUnpadded, Encoding};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::ops::Deref;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// A buffer of secret bytes that is overwritten with zeros when it is dropped.
+///
+/// Unlike the [`Bytes`][bytes::Bytes] returned by [`Secret::plaintext()`][super::Secret::plaintext()],
+/// the contents of this buffer do not outlive it in memory. It can't be cloned, and its [`Debug`]
+/// output does not include its contents.
+pub struct SecretBytes {
+    buf: Vec<u8>,
+}
+
+impl SecretBytes {
+    /// The secret bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(buf: Vec<u8>) -> Self {
+        Self { buf }
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl std::fmt::Debug for SecretBytes {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "SecretBytes([REDACTED; {}])", self.buf.len())
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        // Overwrite the whole allocation, not just the initialized part, with volatile writes so
+        // that they aren't optimized away as dead stores.
+        let ptr = self.buf.as_mut_ptr();
+        for i in 0..self.buf.capacity() {
+            unsafe { ptr.add(i).write_volatile(0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// A key for HMAC-SHA256 signatures.
+pub struct HmacKey {
+    key: SecretBytes,
+}
+
+impl HmacKey {
+    /// Use the given bytes as an HMAC-SHA256 key.
+    pub fn new(key: impl Into<SecretBytes>) -> Self {
+        Self { key: key.into() }
+    }
+
+    fn mac(&self, message: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(message);
+        mac
+    }
+
+    /// Sign a message.
+    pub fn sign(&self, message: &[u8]) -> [u8; 32] {
+        self.mac(message).finalize().into_bytes().into()
+    }
+
+    /// Return true if `tag` is the signature of a message.
+    ///
+    /// The tag may be truncated to its leading bytes, but to no fewer than 16. The comparison
+    /// takes the same time wherever the tag differs.
+    pub fn verify(&self, message: &[u8], tag: &[u8]) -> bool {
+        tag.len() >= 16 && self.mac(message).verify_truncated_left(tag).is_ok()
+    }
+}
+
+impl From<Vec<u8>> for HmacKey {
+    fn from(key: Vec<u8>) -> Self {
+        Self::new(key)
+    }
+}
+
+impl From<&[u8]> for HmacKey {
+    fn from(key: &[u8]) -> Self {
+        Self::new(key.to_vec())
+    }
+}
+
+impl std::fmt::Debug for HmacKey {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("HmacKey").finish_non_exhaustive()
+    }
+}
+
+/// A PEM-encoded block, such as a private key.
+#[derive(Debug)]
+pub struct PemBlock {
+    label: String,
+    der: SecretBytes,
+}
+
+impl PemBlock {
+    /// The label of the block, such as `PRIVATE KEY` or `EC PRIVATE KEY`.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// The DER-encoded contents of the block.
+    pub fn der(&self) -> &[u8] {
+        &self.der
+    }
+
+    /// Take the DER-encoded contents of the block.
+    pub fn into_der(self) -> SecretBytes {
+        self.der
+    }
+}
+
+/// A JSON Web Key Set, as defined by [RFC 7517](https://www.rfc-editor.org/rfc/rfc7517).
+///
+/// The [`Debug`] output of a key set only includes the public fields of each key.
+#[derive(Debug, Deserialize)]
+pub struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+impl JwkSet {
+    /// The keys in this set.
+    pub fn keys(&self) -> &[Jwk] {
+        &self.keys
+    }
+
+    /// Find the key with the given key ID.
+    pub fn find(&self, kid: &str) -> Option<&Jwk> {
+        self.keys.iter().find(|key| key.kid.as_deref() == Some(kid))
+    }
+}
+
+/// A JSON Web Key.
+#[derive(Deserialize)]
+pub struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    alg: Option<String>,
+    #[serde(default, rename = "use")]
+    key_use: Option<String>,
+    #[serde(flatten)]
+    params: BTreeMap<String, serde_json::Value>,
+}
+
+impl Jwk {
+    /// The key type, such as `RSA`, `EC` or `oct`.
+    pub fn kty(&self) -> &str {
+        &self.kty
+    }
+
+    /// The key ID.
+    pub fn kid(&self) -> Option<&str> {
+        self.kid.as_deref()
+    }
+
+    /// The algorithm the key is meant for, such as `RS256`.
+    pub fn alg(&self) -> Option<&str> {
+        self.alg.as_deref()
+    }
+
+    /// The intended use of the key, such as `sig` or `enc`.
+    pub fn key_use(&self) -> Option<&str> {
+        self.key_use.as_deref()
+    }
+
+    /// Any other parameter of the key, such as `n` or `d`.
+    pub fn param(&self, name: &str) -> Option<&serde_json::Value> {
+        self.params.get(name)
+    }
+}
+
+impl std::fmt::Debug for Jwk {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("Jwk")
+            .field("kty", &self.kty)
+            .field("kid", &self.kid)
+            .field("alg", &self.alg)
+            .field("use", &self.key_use)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Errors parsing the plaintext of a secret.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The secret is not valid base64.
+    #[error("invalid base64")]
+    Base64,
+
+    /// The secret does not contain a PEM private key block.
+    #[error("no PEM private key found")]
+    MissingPrivateKey,
+
+    /// The secret contains a PEM block that could not be decoded.
+    #[error("malformed PEM block: {0}")]
+    Pem(&'static str),
+
+    /// The secret is not a valid JSON Web Key Set.
+    #[error("invalid JWK set: {0}")]
+    Jwk(#[from] serde_json::Error),
+}
+
+pub(crate) fn decode_base64(input: &[u8]) -> Result<SecretBytes, ParseError> {
+    let input = SecretBytes::from(
+        input
+            .iter()
+            .copied()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect::<Vec<_>>(),
+    );
+    let url_safe = input.iter().any(|&c| c == b'-' || c == b'_');
+    let mut out = SecretBytes::from(vec![0; input.len() / 4 * 3 + 3]);
+    let decode = match (url_safe, input.ends_with(b"=")) {
+        (false, true) => Base64::decode,
+        (false, false) => Base64Unpadded::decode,
+        (true, true) => Base64Url::decode,
+        (true, false) => Base64UrlUnpadded::decode,
+    };
+    let len = decode(&input, &mut out.buf)
+        .map_err(|_| ParseError::Base64)?
+        .len();
+    out.buf.truncate(len);
+    Ok(out)
+}
+
+/// Encode bytes as lowercase hex.
+pub(crate) fn encode_hex(input: &[u8]) -> String {
+    let mut out = String::with_capacity(input.len() * 2);
+    for b in input {
+        write!(&mut out, "{b:02x}").expect("writing to a String is infallible");
+    }
+    out
+}
+
+pub(crate) fn parse_private_key(pem: &[u8]) -> Result<PemBlock, ParseError> {
+    let text = std::str::from_utf8(pem).map_err(|_| ParseError::Pem("not UTF-8"))?;
+    // The secret may hold other blocks, such as a certificate chain, so find the private key and
+    // decode only that block.
+    let (start, label) = text
+        .match_indices("-----BEGIN ")
+        .find_map(|(start, _)| {
+            let rest = &text[start + "-----BEGIN ".len()..];
+            let label = &rest[..rest.find("-----")?];
+            label.ends_with("PRIVATE KEY").then_some((start, label))
+        })
+        .ok_or(ParseError::MissingPrivateKey)?;
+    let end_line = format!("-----END {label}-----");
+    let end = text[start..]
+        .find(&end_line)
+        .ok_or(ParseError::Pem("missing END line"))?
+        + start
+        + end_line.len();
+    let mut der = SecretBytes::from(vec![0; pem.len() / 4 * 3]);
+    let len = pem_rfc7468::decode(&pem[start..end], &mut der.buf)
+        .map_err(|_| ParseError::Pem("invalid encoding"))?
+        .1
+        .len();
+    der.buf.truncate(len);
+    Ok(PemBlock {
+        label: label.to_owned(),
+        der,
+    })
+}
+
+/// Compare two byte strings in time that doesn't depend on where they differ.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
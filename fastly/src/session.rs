@@ -0,0 +1,524 @@
+//! Encrypted cookie sessions.
+//!
+//! This module is only available with the `session` feature.
+//!
+//! A [`SessionStore`] keeps a serde-serializable session in a cookie that is encrypted and
+//! authenticated with ChaCha20-Poly1305, so clients can neither read nor change it. Keys are
+//! usually loaded from a [`SecretStore`], and more than one can be given so that keys can be
+//! rotated: sessions are always saved with the first key, and loaded with any of them.
+//!
+//! Browsers limit cookies to 4 KB. Saving a larger session is an error, unless the store has a
+//! [KV Store fallback][SessionStore::with_kv_fallback()], in which case the encrypted session is
+//! kept in the KV Store and the cookie holds a random ID for it.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use fastly::secret_store::SecretStore;
+//! use fastly::session::SessionStore;
+//! use fastly::{Request, Response};
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Default, Deserialize, Serialize)]
+//! struct Session {
+//!     user_id: Option<u64>,
+//! }
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let secrets = SecretStore::open("secrets")?;
+//! let sessions = SessionStore::from_secret_store("session", &secrets, &["session_key_v2", "session_key"])?;
+//!
+//! let req = Request::from_client();
+//! let mut session: Session = sessions.load(&req)?.unwrap_or_default();
+//! session.user_id = Some(42);
+//!
+//! let mut resp = Response::from_body("hello");
+//! sessions.save(&session, &mut resp)?;
+//! resp.send_to_client();
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::http::{Cookie, SameSite};
+use crate::kv_store::{KVStore, KVStoreError};
+use crate::secret_store::plaintext::decode_base64;
+use crate::secret_store::{LookupError, Secret, SecretBytes, SecretStore};
+use crate::{Request, Response};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+/// The largest cookie, including its attributes, that browsers are required to accept.
+pub const MAX_COOKIE_LEN: usize = 4096;
+
+const NONCE_LEN: usize = 12;
+const INLINE_PREFIX: &str = "c.";
+const KV_PREFIX: &str = "k.";
+
+/// A key for encrypting sessions.
+pub struct SessionKey {
+    key: SecretBytes,
+}
+
+impl SessionKey {
+    /// The length of a key, in bytes.
+    pub const LEN: usize = 32;
+
+    /// Use the given bytes as a key. There must be exactly [`SessionKey::LEN`] of them.
+    pub fn new(key: impl Into<SecretBytes>) -> Result<Self, SessionError> {
+        let key = key.into();
+        if key.len() != Self::LEN {
+            return Err(SessionError::InvalidKey);
+        }
+        Ok(Self { key })
+    }
+
+    /// Use the plaintext of a secret as a key.
+    ///
+    /// The secret may either be the [`SessionKey::LEN`] bytes of the key, or their base64
+    /// encoding.
+    pub fn from_secret(secret: &Secret) -> Result<Self, SessionError> {
+        let plaintext = secret.plaintext_zeroizing();
+        if plaintext.len() == Self::LEN {
+            return Self::new(plaintext);
+        }
+        Self::new(decode_base64(&plaintext).map_err(|_| SessionError::InvalidKey)?)
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+}
+
+impl std::fmt::Debug for SessionKey {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("SessionKey").finish_non_exhaustive()
+    }
+}
+
+/// Sessions kept in an encrypted cookie.
+pub struct SessionStore {
+    cookie_name: String,
+    keys: Vec<SessionKey>,
+    max_age: Duration,
+    path: String,
+    domain: Option<String>,
+    kv: Option<RefCell<KVStore>>,
+}
+
+impl SessionStore {
+    /// Keep sessions in the cookie with the given name, encrypted with the given key.
+    ///
+    /// Sessions last for a day by default.
+    pub fn new(cookie_name: impl Into<String>, key: SessionKey) -> Self {
+        Self {
+            cookie_name: cookie_name.into(),
+            keys: vec![key],
+            max_age: Duration::from_secs(24 * 60 * 60),
+            path: "/".to_owned(),
+            domain: None,
+            kv: None,
+        }
+    }
+
+    /// Keep sessions in the cookie with the given name, encrypted with keys from a secret store.
+    ///
+    /// The first of the named secrets that is found is used to save sessions, and all of them are
+    /// used to load sessions. List the newest key first, and keep old keys listed until the
+    /// sessions saved with them have expired. See [`SessionKey::from_secret()`] for the format of
+    /// each secret.
+    pub fn from_secret_store(
+        cookie_name: impl Into<String>,
+        store: &SecretStore,
+        names: &[&str],
+    ) -> Result<Self, SessionError> {
+        let mut keys = Vec::with_capacity(names.len());
+        for name in names {
+            if let Some(secret) = store.try_get(name)? {
+                keys.push(SessionKey::from_secret(&secret)?);
+            }
+        }
+        let mut keys = keys.into_iter();
+        let mut sessions = Self::new(cookie_name, keys.next().ok_or(SessionError::NoKey)?);
+        sessions.keys.extend(keys);
+        Ok(sessions)
+    }
+
+    /// Also load sessions that were saved with the given key.
+    pub fn with_previous_key(mut self, key: SessionKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    /// Set how long sessions last after they are saved.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Set the `Path` of the session cookie. The default is `/`.
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Set the `Domain` of the session cookie.
+    pub fn with_domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Keep sessions that are too large for a cookie in the given KV Store.
+    ///
+    /// The encrypted session is inserted with a time to live of the session's max age. Each save
+    /// of an oversized session inserts a new entry; old entries are left to expire.
+    pub fn with_kv_fallback(mut self, store: KVStore) -> Self {
+        self.kv = Some(RefCell::new(store));
+        self
+    }
+
+    /// Load the session from a request's cookie.
+    ///
+    /// Returns `Ok(None)` if the request has no session cookie, or if the cookie has expired, was
+    /// not encrypted with any of the store's keys, or has been tampered with. Returns an error if
+    /// the session could not be read from the KV Store or deserialized.
+    pub fn load<T: DeserializeOwned>(&self, req: &Request) -> Result<Option<T>, SessionError> {
+        self.load_at(req, SystemTime::now())
+    }
+
+    fn load_at<T: DeserializeOwned>(
+        &self,
+        req: &Request,
+        now: SystemTime,
+    ) -> Result<Option<T>, SessionError> {
+        let value = match req.get_cookie(&self.cookie_name) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        let sealed = if let Some(encoded) = value.strip_prefix(INLINE_PREFIX) {
+            decode_base64(encoded.as_bytes()).ok()
+        } else if let Some(id) = value.strip_prefix(KV_PREFIX) {
+            match &self.kv {
+                Some(kv) => kv
+                    .borrow()
+                    .lookup_bytes(&kv_key(id))?
+                    .map(SecretBytes::from),
+                None => None,
+            }
+        } else {
+            None
+        };
+        let plaintext = match sealed.and_then(|sealed| self.open(&sealed)) {
+            Some(plaintext) => plaintext,
+            None => return Ok(None),
+        };
+        let envelope: Envelope<T> = serde_json::from_slice(&plaintext)?;
+        if unix_secs(now) >= envelope.exp {
+            return Ok(None);
+        }
+        Ok(Some(envelope.data))
+    }
+
+    /// Save a session in a cookie on a response, replacing any session cookie already set on it.
+    ///
+    /// Returns [`SessionError::TooLarge`] if the cookie would be larger than [`MAX_COOKIE_LEN`]
+    /// and the store has no KV Store fallback.
+    pub fn save<T: Serialize>(&self, session: &T, resp: &mut Response) -> Result<(), SessionError> {
+        self.save_at(session, resp, SystemTime::now())
+    }
+
+    fn save_at<T: Serialize>(
+        &self,
+        session: &T,
+        resp: &mut Response,
+        now: SystemTime,
+    ) -> Result<(), SessionError> {
+        let envelope = Envelope {
+            exp: unix_secs(now) + self.max_age.as_secs(),
+            data: session,
+        };
+        let plaintext = SecretBytes::from(serde_json::to_vec(&envelope)?);
+        let sealed = self.seal(&plaintext)?;
+
+        let cookie = self.cookie(format!(
+            "{INLINE_PREFIX}{}",
+            Base64UrlUnpadded::encode_string(&sealed)
+        ));
+        let len = cookie.to_string().len();
+        let cookie = if len <= MAX_COOKIE_LEN {
+            cookie
+        } else if let Some(kv) = &self.kv {
+            let id = Base64UrlUnpadded::encode_string(&random::<16>()?);
+            kv.borrow_mut()
+                .build_insert()
+                .time_to_live(self.max_age)
+                .execute(&kv_key(&id), sealed)?;
+            self.cookie(format!("{KV_PREFIX}{id}"))
+        } else {
+            return Err(SessionError::TooLarge { len });
+        };
+        resp.set_cookie(cookie);
+        Ok(())
+    }
+
+    /// Remove the session cookie from the client, and the session from the KV Store if it was
+    /// kept there.
+    ///
+    /// The KV Store entry named by the cookie is only deleted if it holds a session that can be
+    /// decrypted with one of the store's keys.
+    pub fn clear(&self, req: &Request, resp: &mut Response) -> Result<(), SessionError> {
+        let kv_id = req
+            .get_cookie(&self.cookie_name)
+            .and_then(|value| value.strip_prefix(KV_PREFIX));
+        if let (Some(kv), Some(id)) = (&self.kv, kv_id) {
+            let key = kv_key(id);
+            let sealed = kv.borrow().lookup_bytes(&key)?;
+            if sealed.is_some_and(|sealed| self.open(&sealed).is_some()) {
+                kv.borrow_mut().delete(&key)?;
+            }
+        }
+        let mut removal = Cookie::removal(self.cookie_name.as_str()).with_path(self.path.as_str());
+        if let Some(domain) = &self.domain {
+            removal = removal.with_domain(domain.as_str());
+        }
+        resp.set_cookie(removal);
+        Ok(())
+    }
+
+    fn cookie(&self, value: String) -> Cookie {
+        let cookie = Cookie::new(self.cookie_name.as_str(), value)
+            .with_max_age(self.max_age)
+            .with_path(self.path.as_str())
+            .with_secure(true)
+            .with_http_only(true)
+            .with_same_site(SameSite::Lax);
+        match &self.domain {
+            Some(domain) => cookie.with_domain(domain.as_str()),
+            None => cookie,
+        }
+    }
+
+    /// Encrypt a session with the current key, binding it to the cookie name.
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, SessionError> {
+        let nonce = random::<NONCE_LEN>()?;
+        let payload = Payload {
+            msg: plaintext,
+            aad: self.cookie_name.as_bytes(),
+        };
+        let ciphertext = self.keys[0]
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| SessionError::Encrypt)?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt a session with any of the keys.
+    fn open(&self, sealed: &[u8]) -> Option<SecretBytes> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.keys.iter().find_map(|key| {
+            let payload = Payload {
+                msg: ciphertext,
+                aad: self.cookie_name.as_bytes(),
+            };
+            key.cipher()
+                .decrypt(Nonce::from_slice(nonce), payload)
+                .ok()
+                .map(SecretBytes::from)
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Envelope<T> {
+    exp: u64,
+    data: T,
+}
+
+fn kv_key(id: &str) -> String {
+    format!("session/{id}")
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn random<const N: usize>() -> Result<[u8; N], SessionError> {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).map_err(|_| SessionError::Random)?;
+    Ok(bytes)
+}
+
+/// Errors loading or saving sessions.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SessionError {
+    /// A session key is not [`SessionKey::LEN`] bytes long.
+    #[error("session keys must be {} bytes", SessionKey::LEN)]
+    InvalidKey,
+
+    /// None of the named session keys were found in the secret store.
+    #[error("no session key found")]
+    NoKey,
+
+    /// A session key could not be looked up.
+    #[error("session key lookup failed: {0}")]
+    KeyLookup(#[from] LookupError),
+
+    /// The session cookie would be too large, and there is no KV Store fallback.
+    #[error("session cookie of {len} bytes is too large")]
+    TooLarge {
+        /// The length of the cookie, in bytes.
+        len: usize,
+    },
+
+    /// The session could not be serialized or deserialized.
+    #[error("invalid session: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// The session could not be read from or written to the KV Store.
+    #[error("session KV Store operation failed: {0}")]
+    KvStore(#[from] KVStoreError),
+
+    /// A random nonce or ID could not be generated.
+    #[error("could not generate random bytes")]
+    Random,
+
+    /// The session could not be encrypted.
+    #[error("session could not be encrypted")]
+    Encrypt,
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::http::header::{COOKIE, SET_COOKIE};
+    use crate::testing;
+
+    #[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+    struct Session {
+        user: String,
+        cart: Vec<u32>,
+    }
+
+    fn key(byte: u8) -> SessionKey {
+        SessionKey::new(vec![byte; SessionKey::LEN]).unwrap()
+    }
+
+    fn save(sessions: &SessionStore, session: &Session, now: SystemTime) -> Request {
+        let mut resp = Response::new();
+        sessions.save_at(session, &mut resp, now).unwrap();
+        let cookie = resp.get_cookie("session").unwrap();
+        assert!(cookie.is_secure() && cookie.is_http_only());
+        Request::get("http://example.com/")
+            .with_header(COOKIE, format!("{}={}", cookie.name(), cookie.value()))
+    }
+
+    #[test]
+    fn round_trip_rotation_and_expiry() {
+        let now = SystemTime::now();
+        let session = Session {
+            user: "ferris".to_owned(),
+            cart: vec![1, 2, 3],
+        };
+        let old = SessionStore::new("session", key(1)).with_max_age(Duration::from_secs(60));
+        let req = save(&old, &session, now);
+        assert_eq!(old.load_at(&req, now).unwrap(), Some(session));
+
+        // Sessions saved with a previous key can still be loaded.
+        let rotated = SessionStore::new("session", key(2)).with_previous_key(key(1));
+        assert!(rotated.load_at::<Session>(&req, now).unwrap().is_some());
+        let other = SessionStore::new("session", key(2));
+        assert!(other.load_at::<Session>(&req, now).unwrap().is_none());
+
+        let later = now + Duration::from_secs(60);
+        assert!(old.load_at::<Session>(&req, later).unwrap().is_none());
+
+        // Tampering with the cookie invalidates it.
+        let value = req.get_cookie("session").unwrap();
+        let mut tampered = value.as_bytes().to_vec();
+        let last = tampered.len() - 1;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        let req = Request::get("http://example.com/").with_header(
+            COOKIE,
+            format!("session={}", String::from_utf8(tampered).unwrap()),
+        );
+        assert!(old.load_at::<Session>(&req, now).unwrap().is_none());
+    }
+
+    #[test]
+    fn keys_from_secret_store() {
+        testing::reset();
+        testing::insert_secret("secrets", "key_v1", [7; 32]);
+        testing::insert_secret(
+            "secrets",
+            "key_v2",
+            Base64UrlUnpadded::encode_string(&[9; 32]).as_bytes(),
+        );
+        testing::insert_secret("secrets", "short", b"abc");
+        let secrets = SecretStore::open("secrets").unwrap();
+
+        let sessions =
+            SessionStore::from_secret_store("session", &secrets, &["key_v3", "key_v2", "key_v1"])
+                .unwrap();
+        assert_eq!(sessions.keys.len(), 2);
+        assert_eq!(&*sessions.keys[0].key, &[9; 32]);
+        assert!(matches!(
+            SessionStore::from_secret_store("session", &secrets, &["key_v3"]),
+            Err(SessionError::NoKey)
+        ));
+        assert!(matches!(
+            SessionStore::from_secret_store("session", &secrets, &["short"]),
+            Err(SessionError::InvalidKey)
+        ));
+    }
+
+    #[test]
+    fn oversized_sessions() {
+        testing::reset();
+        testing::create_kv_store("sessions");
+        let now = SystemTime::now();
+        let session = Session {
+            user: "x".repeat(5000),
+            cart: vec![],
+        };
+        let sessions = SessionStore::new("session", key(1));
+        let mut resp = Response::new();
+        assert!(matches!(
+            sessions.save_at(&session, &mut resp, now),
+            Err(SessionError::TooLarge { .. })
+        ));
+
+        let kv = KVStore::open("sessions").unwrap().unwrap();
+        let sessions = sessions.with_kv_fallback(kv);
+        let req = save(&sessions, &session, now);
+        assert!(req.get_cookie("session").unwrap().starts_with(KV_PREFIX));
+        assert_eq!(sessions.load_at(&req, now).unwrap(), Some(session));
+
+        let mut resp = Response::new();
+        sessions.clear(&req, &mut resp).unwrap();
+        assert_eq!(
+            resp.get_header_str(SET_COOKIE),
+            Some("session=; Max-Age=0; Path=/")
+        );
+        assert!(sessions.load_at::<Session>(&req, now).unwrap().is_none());
+
+        // An entry that is not a session is not deleted.
+        let mut kv = KVStore::open("sessions").unwrap().unwrap();
+        kv.insert(&kv_key("other"), "not a session").unwrap();
+        let req = Request::get("http://example.com/").with_header(COOKIE, "session=k.other");
+        sessions.clear(&req, &mut Response::new()).unwrap();
+        assert!(kv.lookup_bytes(&kv_key("other")).unwrap().is_some());
+    }
+}
@@ -0,0 +1,218 @@
+//! Run Compute@Edge programs natively under `cargo test`.
+//!
+//! With the `testing` feature enabled, every hostcall made by this crate is served by an in-process
+//! mock of the Compute@Edge host, rather than by imports that only link inside the WebAssembly
+//! host. Requests, responses, and bodies are kept in memory, and requests sent to a backend are
+//! answered by a fake backend registered with [`Backends`] or [`register_backend()`].
+//!
+//! Each thread has its own host, so tests running in parallel do not observe each other's state.
+//! Call [`reset()`] at the start of a test to make sure it starts from a clean host.
+//!
+//! ```no_run
+//! use fastly::{testing, Request, Response};
+//!
+//! fn handler(req: Request) -> Response {
+//!     req.send("origin").unwrap()
+//! }
+//!
+//! testing::reset();
+//! testing::register_backend("origin", |req| {
+//!     Response::from_body(format!("hello from {}", req.get_path()))
+//! });
+//! let mut resp = handler(Request::get("http://example.com/greeting"));
+//! assert_eq!(resp.take_body_str(), "hello from /greeting");
+//! ```
+//!
+//! To test a whole program, set the client request with [`set_client_request()`], call the
+//! function annotated with [`#[fastly::main]`][`crate::main`] or the code it wraps, and inspect the
+//! response sent to the client with [`take_client_response()`].
+pub use self::backends::{Backends, Reply};
+
+use crate::handle::{BodyHandle, ResponseHandle};
+use crate::http::body::handle::GOT_CLIENT_BODY;
+use crate::http::request::handle::GOT_CLIENT_REQ;
+use crate::http::response::SENT_CLIENT_RESP;
+use crate::{Request, Response};
+use std::net::IpAddr;
+use std::time::Duration;
+
+mod backends;
+
+/// Reset the current thread's host to its initial state.
+///
+/// This removes all backends, stores, and log entries, and allows the client request to be taken
+/// and a client response to be sent again.
+///
+/// Handles created before the reset are no longer valid. Dropping values that hold them, such as a
+/// [`Request`] or [`Body`][`crate::Body`] from a previous test, is harmless.
+pub fn reset() {
+    fastly_sys::testing::reset();
+    GOT_CLIENT_REQ.with(|got| got.set(false));
+    GOT_CLIENT_BODY.with(|got| got.set(false));
+    SENT_CLIENT_RESP.with(|sent| sent.set(false));
+}
+
+/// The current time on the host's clock.
+///
+/// The clock starts at zero when the thread's host is created, and only moves forward when
+/// [`advance_clock()`] is called.
+pub fn now() -> Duration {
+    fastly_sys::testing::now()
+}
+
+/// Move the host's clock forward.
+pub fn advance_clock(by: Duration) {
+    fastly_sys::testing::advance_clock(by)
+}
+
+/// Set the request returned by [`Request::from_client()`].
+///
+/// The original header names reported for the client request are the names of the headers of
+/// `req`. If this function is not called, the client request is an empty `GET` request for
+/// `http://localhost/`.
+pub fn set_client_request(req: Request) {
+    let (req_handle, body_handle) = req.into_handles();
+    let body_handle = body_handle.unwrap_or_else(BodyHandle::new);
+    fastly_sys::testing::set_client_request(req_handle.into_u32(), body_handle.into_u32());
+}
+
+/// Set the IP address returned by [`Request::get_client_ip_addr()`].
+///
+/// The default is `127.0.0.1`.
+pub fn set_client_ip_addr(addr: IpAddr) {
+    fastly_sys::testing::set_client_ip_addr(addr)
+}
+
+/// Set the value returned by [`Request::fastly_key_is_valid()`].
+///
+/// The default is `false`.
+pub fn set_fastly_key_is_valid(is_valid: bool) {
+    fastly_sys::testing::set_fastly_key_is_valid(is_valid)
+}
+
+/// Take the response sent to the client, if one has been sent.
+///
+/// The body of the response contains everything written to it so far. If the response was sent
+/// with [`Response::stream_to_client()`], the body only contains data that has been flushed from
+/// the [`StreamingBody`][`crate::http::body::StreamingBody`].
+pub fn take_client_response() -> Option<Response> {
+    let (resp_handle, body_handle) = fastly_sys::testing::take_client_response()?;
+    let mut resp = ResponseHandle::INVALID;
+    *resp.as_u32_mut() = resp_handle;
+    // Safety: the mock host has just given us ownership of the handle.
+    let body = unsafe { BodyHandle::from_u32(body_handle) };
+    let resp = Response::from_handles(resp, body).expect("client response exceeds response limits");
+    Some(resp)
+}
+
+/// Register a backend with the given name, replacing any existing backend of the same name.
+///
+/// Requests sent to the backend are passed to `handler`, and the response it returns is sent back to
+/// the program without delay. This is shorthand for [`Backends::handle()`], which also allows
+/// handlers to delay their responses or fail requests.
+pub fn register_backend(name: &str, handler: impl FnMut(Request) -> Response + 'static) {
+    Backends::new().handle(name, handler);
+}
+
+/// Remove a backend registered with [`register_backend()`] or [`Backends`].
+pub fn remove_backend(name: &str) {
+    fastly_sys::testing::remove_backend(name)
+}
+
+/// Set an entry in a [`ConfigStore`][`crate::ConfigStore`], creating the store if it does not
+/// exist.
+pub fn insert_config_store_entry(store: &str, key: &str, value: &str) {
+    fastly_sys::testing::insert_config_store_entry(store, key, value)
+}
+
+/// Create an empty [`ConfigStore`][`crate::ConfigStore`], if it does not already exist.
+pub fn create_config_store(store: &str) {
+    fastly_sys::testing::create_config_store(store)
+}
+
+/// Set an entry in a [`KVStore`][`crate::KVStore`], creating the store if it does not exist.
+pub fn insert_kv_store_entry(store: &str, key: &str, value: impl AsRef<[u8]>) {
+    fastly_sys::testing::insert_kv_store_entry(store, key, value.as_ref())
+}
+
+/// Create an empty [`KVStore`][`crate::KVStore`], if it does not already exist.
+pub fn create_kv_store(store: &str) {
+    fastly_sys::testing::create_kv_store(store)
+}
+
+/// Get the value of an entry in a [`KVStore`][`crate::KVStore`].
+///
+/// This is useful for checking the values written by a program.
+pub fn kv_store_entry(store: &str, key: &str) -> Option<Vec<u8>> {
+    fastly_sys::testing::kv_store_entry(store, key)
+}
+
+/// Set a secret in a [`SecretStore`][`crate::SecretStore`], creating the store if it does not
+/// exist.
+pub fn insert_secret(store: &str, name: &str, plaintext: impl AsRef<[u8]>) {
+    fastly_sys::testing::insert_secret(store, name, plaintext.as_ref())
+}
+
+/// Set the geolocation data returned by [`geo_lookup()`][`crate::geo::geo_lookup()`] for an IP
+/// address.
+///
+/// `json` must be a JSON object in the format returned by the Compute@Edge host, for example
+/// `{"as_name": "Fastly, Inc", "as_number": 54113, "city": "Seattle", ...}`.
+pub fn set_geo(addr: IpAddr, json: &str) {
+    fastly_sys::testing::set_geo(addr, json)
+}
+
+/// The messages written to a log endpoint, in order.
+pub fn log_entries(endpoint: &str) -> Vec<Vec<u8>> {
+    fastly_sys::testing::log_entries(endpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ConfigStore;
+
+    #[test]
+    fn backend_round_trip() {
+        reset();
+        register_backend("origin", |mut req| {
+            let body = req.take_body_str();
+            Response::from_body(format!("{} {}", req.get_path(), body))
+                .with_header("x-origin", "yes")
+        });
+        let mut resp = Request::post("http://example.com/echo")
+            .with_body("ping")
+            .send("origin")
+            .unwrap();
+        assert_eq!(resp.get_header_str("x-origin"), Some("yes"));
+        assert_eq!(resp.take_body_str(), "/echo ping");
+        assert!(Request::get("http://example.com/").send("missing").is_err());
+    }
+
+    #[test]
+    fn client_request_and_response() {
+        reset();
+        set_client_request(Request::get("http://example.com/hello").with_header("x-test", "1"));
+        let req = Request::from_client();
+        assert_eq!(req.get_path(), "/hello");
+        assert_eq!(req.get_header_str("x-test"), Some("1"));
+        Response::from_body("world").send_to_client();
+
+        let mut resp = take_client_response().unwrap();
+        assert_eq!(resp.take_body_str(), "world");
+        assert!(take_client_response().is_none());
+
+        // A reset allows another execution on the same thread.
+        reset();
+        let _ = Request::from_client();
+    }
+
+    #[test]
+    fn config_store() {
+        reset();
+        insert_config_store_entry("settings", "greeting", "hello");
+        let store = ConfigStore::open("settings");
+        assert_eq!(store.get("greeting").as_deref(), Some("hello"));
+        assert_eq!(store.get("missing"), None);
+    }
+}
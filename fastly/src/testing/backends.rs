@@ -0,0 +1,271 @@
+//! Fake backends.
+
+use crate::handle::{BodyHandle, RequestHandle};
+use crate::http::request::SendErrorCause;
+use crate::{Body, Request, Response};
+use fastly_shared::FastlyStatus;
+use fastly_sys::testing::BackendReply;
+use http::{HeaderMap, StatusCode, Version};
+use std::time::Duration;
+
+/// The fake backends of the current thread's host.
+///
+/// Requests sent to a registered backend, whether with [`Request::send()`],
+/// [`Request::send_async()`], or [`Request::send_async_streaming()`], are answered by the backend's
+/// handler, and the result is delivered through [`PendingRequest::poll()`][poll],
+/// [`PendingRequest::wait()`][wait], and [`select()`][select] as it would be by the Compute@Edge
+/// host.
+///
+/// Handlers reply with a [`Reply`], which can delay the response on the host's clock, or fail the
+/// request with a [`SendErrorCause`] or a timeout.
+///
+/// ```no_run
+/// use fastly::http::request::{select, SendErrorCause};
+/// use fastly::testing::{Backends, Reply};
+/// use fastly::{Request, Response};
+/// use std::time::Duration;
+///
+/// Backends::new()
+///     .reply("primary", Reply::error(SendErrorCause::Incomplete))
+///     .reply(
+///         "secondary",
+///         Reply::from(Response::from_body("ok")).with_delay(Duration::from_millis(50)),
+///     )
+///     .handle("echo", |mut req: Request| Response::from_body(req.take_body()));
+///
+/// let primary = Request::get("http://example.com/").send_async("primary").unwrap();
+/// let secondary = Request::get("http://example.com/").send_async("secondary").unwrap();
+/// let (first, _rest) = select(vec![primary, secondary]);
+/// assert!(first.is_err());
+/// ```
+///
+/// Registrations are made on the current thread's host, and are removed by
+/// [`reset()`][crate::testing::reset].
+///
+/// [poll]: crate::http::request::PendingRequest::poll
+/// [wait]: crate::http::request::PendingRequest::wait
+/// [select]: crate::http::request::select
+#[derive(Debug, Default)]
+pub struct Backends {
+    _private: (),
+}
+
+impl Backends {
+    /// Get the fake backends of the current thread's host.
+    pub fn new() -> Self {
+        Backends { _private: () }
+    }
+
+    /// Register a backend whose requests are answered by `handler`, replacing any existing backend
+    /// of the same name.
+    ///
+    /// The handler runs on the same thread and host as the program, so it can read the same stores
+    /// and send requests to other backends. The backend reports its host as `name`, and uses TLS on
+    /// port 443.
+    pub fn handle<R: Into<Reply>>(
+        &self,
+        name: &str,
+        mut handler: impl FnMut(Request) -> R + 'static,
+    ) -> &Self {
+        fastly_sys::testing::register_backend(
+            name,
+            Box::new(move |req_handle, body_handle| {
+                let mut req = RequestHandle::INVALID;
+                *req.as_u32_mut() = req_handle;
+                // Safety: the mock host has just given us ownership of the handle.
+                let body = unsafe { BodyHandle::from_u32(body_handle) };
+                let req = Request::from_handles(req, Some(body))
+                    .expect("backend request exceeds request limits");
+                handler(req).into().into_backend_reply()
+            }),
+        );
+        self
+    }
+
+    /// Register a backend that answers every request with the same reply, replacing any existing
+    /// backend of the same name.
+    pub fn reply(&self, name: &str, reply: impl Into<Reply>) -> &Self {
+        let reply = reply.into();
+        self.handle(name, move |_| reply.clone())
+    }
+
+    /// Set the first byte timeout of a registered backend.
+    ///
+    /// Replies delayed by more than this fail with [`SendErrorCause::Generic`] once the timeout
+    /// has elapsed. The default is 15 seconds.
+    pub fn first_byte_timeout(&self, name: &str, timeout: Duration) -> &Self {
+        fastly_sys::testing::set_first_byte_timeout(name, timeout);
+        self
+    }
+
+    /// Remove a backend.
+    ///
+    /// Requests sent to a backend that does not exist fail immediately.
+    pub fn remove(&self, name: &str) -> &Self {
+        fastly_sys::testing::remove_backend(name);
+        self
+    }
+}
+
+/// The reply of a fake backend to a request.
+///
+/// A reply is either a response, an error, or a timeout, and may be delayed on the host's clock. It
+/// is delivered to the program as soon as the program waits for it, advancing the clock by the
+/// delay, or when the program polls for it after the delay has elapsed.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    outcome: Outcome,
+    delay: Duration,
+}
+
+#[derive(Clone, Debug)]
+enum Outcome {
+    Response {
+        status: StatusCode,
+        version: Version,
+        headers: HeaderMap,
+        body: Vec<u8>,
+    },
+    Error(FastlyStatus),
+    Timeout,
+}
+
+impl Reply {
+    /// A reply that fails the request with the given cause.
+    ///
+    /// The program receives the same [`SendErrorCause`] variant, except for
+    /// [`SendErrorCause::BufferSize`] and [`SendErrorCause::Generic`], which are both received as
+    /// [`SendErrorCause::Generic`].
+    pub fn error(cause: SendErrorCause) -> Self {
+        let status = match cause {
+            SendErrorCause::Invalid => FastlyStatus::HTTPINVALID,
+            SendErrorCause::Incomplete => FastlyStatus::HTTPINCOMPLETE,
+            SendErrorCause::InvalidStatus => FastlyStatus::HTTPINVALIDSTATUS,
+            SendErrorCause::HeadTooLarge => FastlyStatus::HTTPHEADTOOLARGE,
+            SendErrorCause::BufferSize(_) | SendErrorCause::Generic(_) => FastlyStatus::ERROR,
+        };
+        Reply {
+            outcome: Outcome::Error(status),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// A reply that never arrives, so that the request fails once the first byte timeout of the
+    /// backend has elapsed.
+    pub fn timeout() -> Self {
+        Reply {
+            outcome: Outcome::Timeout,
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Delay the reply by the given duration.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    fn into_backend_reply(self) -> BackendReply {
+        let result = match self.outcome {
+            Outcome::Response {
+                status,
+                version,
+                headers,
+                body,
+            } => {
+                let mut resp = Response::new().with_status(status).with_version(version);
+                for (name, value) in headers.iter() {
+                    resp.append_header(name, value);
+                }
+                let (resp_handle, body_handle) = resp.with_body(body).into_handles();
+                Ok((resp_handle.into_u32(), body_handle.into_u32()))
+            }
+            Outcome::Error(status) => Err(status),
+            Outcome::Timeout => {
+                return BackendReply {
+                    result: Err(FastlyStatus::ERROR),
+                    latency: Duration::MAX,
+                }
+            }
+        };
+        BackendReply {
+            result,
+            latency: self.delay,
+        }
+    }
+}
+
+impl From<Response> for Reply {
+    fn from(resp: Response) -> Self {
+        let resp: http::Response<Body> = resp.into();
+        let (parts, body) = resp.into_parts();
+        Reply {
+            outcome: Outcome::Response {
+                status: parts.status,
+                version: parts.version,
+                headers: parts.headers,
+                body: body.into_bytes(),
+            },
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::request::{select, PollResult};
+    use crate::testing::{now, reset};
+
+    #[test]
+    fn delayed_replies_complete_in_order() {
+        reset();
+        Backends::new()
+            .reply(
+                "slow",
+                Reply::from(Response::from_body("slow")).with_delay(Duration::from_millis(200)),
+            )
+            .reply(
+                "fast",
+                Reply::from(Response::from_body("fast")).with_delay(Duration::from_millis(50)),
+            );
+        let slow = Request::get("http://example.com/")
+            .send_async("slow")
+            .unwrap();
+        let fast = Request::get("http://example.com/")
+            .send_async("fast")
+            .unwrap();
+        let slow = match slow.poll() {
+            PollResult::Pending(slow) => slow,
+            PollResult::Done(_) => panic!("slow backend replied early"),
+        };
+
+        let (first, rest) = select(vec![slow, fast]);
+        assert_eq!(first.unwrap().take_body_str(), "fast");
+        assert_eq!(now(), Duration::from_millis(50));
+
+        let mut second = rest.into_iter().next().unwrap().wait().unwrap();
+        assert_eq!(second.take_body_str(), "slow");
+        assert_eq!(now(), Duration::from_millis(200));
+    }
+
+    #[test]
+    fn errors_and_timeouts() {
+        reset();
+        Backends::new()
+            .reply("invalid", Reply::error(SendErrorCause::Invalid))
+            .reply("hung", Reply::timeout())
+            .first_byte_timeout("hung", Duration::from_secs(1));
+
+        let err = Request::get("http://example.com/")
+            .send("invalid")
+            .unwrap_err();
+        assert!(matches!(err.root_cause(), SendErrorCause::Invalid));
+
+        let err = Request::get("http://example.com/")
+            .send("hung")
+            .unwrap_err();
+        assert!(matches!(err.root_cause(), SendErrorCause::Generic(_)));
+        assert_eq!(now(), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,107 @@
+//! Tests of the programs generated by the `#[fastly::main]` attribute.
+#![cfg(feature = "testing")]
+
+use fastly::http::StatusCode;
+use fastly::{testing, Error, Request, Response};
+
+/// Run a generated `main()` for a client request, and return the response it sent to the client.
+fn run(main: fn() -> Result<(), Error>, req: Request) -> Response {
+    testing::reset();
+    testing::set_client_request(req);
+    main().unwrap();
+    testing::take_client_response().expect("main sent a response")
+}
+
+mod plain_response {
+    use super::*;
+
+    #[fastly::main]
+    fn handle(req: Request) -> Response {
+        Response::from_body(format!("hello from {}", req.get_path()))
+    }
+
+    #[test]
+    fn sends_the_response() {
+        let mut resp = run(main, Request::get("http://example.com/plain"));
+        assert_eq!(resp.get_status(), StatusCode::OK);
+        assert_eq!(resp.take_body_str(), "hello from /plain");
+    }
+}
+
+mod result {
+    use super::*;
+
+    #[fastly::main]
+    fn handle(req: Request) -> Result<Response, Error> {
+        match req.get_path() {
+            "/ok" => Ok(Response::from_body("ok")),
+            _ => Err(Error::msg("no route")),
+        }
+    }
+
+    #[test]
+    fn sends_the_response_or_a_500_for_errors() {
+        let mut resp = run(main, Request::get("http://example.com/ok"));
+        assert_eq!(resp.take_body_str(), "ok");
+
+        let mut resp = run(main, Request::get("http://example.com/missing"));
+        assert_eq!(resp.get_status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(resp.take_body_str(), "no route");
+    }
+}
+
+mod async_fn {
+    use super::*;
+
+    #[fastly::main]
+    async fn handle(req: Request) -> Result<Response, Error> {
+        Ok(req.send_async("origin")?.await?)
+    }
+
+    #[test]
+    fn runs_to_completion() {
+        testing::reset();
+        testing::register_backend("origin", |req| {
+            Response::from_body(format!("origin saw {}", req.get_path()))
+        });
+        testing::set_client_request(Request::get("http://example.com/async"));
+        main().unwrap();
+        let mut resp = testing::take_client_response().unwrap();
+        assert_eq!(resp.take_body_str(), "origin saw /async");
+    }
+}
+
+mod error_handler {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Teapot;
+
+    fn handle_error(_: Teapot) -> Response {
+        Response::from_status(StatusCode::IM_A_TEAPOT).with_body("short and stout")
+    }
+
+    // `Teapot` does not convert into a `Response`, so this only compiles with the error handler.
+    #[fastly::main(error_handler = "handle_error")]
+    fn handle(req: Request) -> Result<Response, Teapot> {
+        match req.get_path() {
+            "/ok" => Ok(Response::from_body("ok")),
+            _ => Err(Teapot),
+        }
+    }
+
+    #[test]
+    fn handles_errors() {
+        let mut resp = run(main, Request::get("http://example.com/ok"));
+        assert_eq!(resp.take_body_str(), "ok");
+
+        let mut resp = run(main, Request::get("http://example.com/brew"));
+        assert_eq!(resp.get_status(), StatusCode::IM_A_TEAPOT);
+        assert_eq!(resp.take_body_str(), "short and stout");
+    }
+}
+
+#[test]
+fn compile_errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/main_*.rs");
+}
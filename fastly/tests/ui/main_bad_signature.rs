@@ -0,0 +1,4 @@
+#[fastly::main]
+fn main(req: fastly::Request, extra: u32) -> fastly::Response {
+    fastly::Response::from_body(format!("{} {}", req.get_path(), extra))
+}
@@ -0,0 +1,8 @@
+fn handle_error(e: fastly::Error) -> fastly::Response {
+    fastly::Response::from_body(e.to_string())
+}
+
+#[fastly::main(error_handler = 42)]
+fn main(req: fastly::Request) -> Result<fastly::Response, fastly::Error> {
+    Ok(fastly::Response::from_body(req.into_body()))
+}
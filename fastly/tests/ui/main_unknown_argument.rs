@@ -0,0 +1,4 @@
+#[fastly::main(handler = "handle_error")]
+fn main(req: fastly::Request) -> fastly::Response {
+    fastly::Response::from_body(req.into_body())
+}